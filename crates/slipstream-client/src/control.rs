@@ -0,0 +1,233 @@
+use crate::dns::{
+    normalize_dual_stack_addr, refresh_resolver_path, resolve_resolvers, QueryEncoder,
+    ResolverState,
+};
+use crate::metrics::mode_label;
+use crate::runtime::fetch_path_quality;
+use crate::shaping::ShapingConfig;
+use crate::streams::ClientState;
+use serde_json::{json, Map, Value};
+use slipstream_core::control::ControlRequest;
+use slipstream_core::{parse_host_port, resolve_host_port, AddressKind};
+use slipstream_ffi::cnx_time;
+use slipstream_ffi::picoquic::{picoquic_abandon_path, picoquic_cnx_t};
+use slipstream_ffi::{ResolverMode, ResolverSpec};
+use tracing::info;
+
+/// Event loop state a control request may read or change.
+pub(crate) struct ControlContext<'a, 'e> {
+    pub(crate) cnx: *mut picoquic_cnx_t,
+    pub(crate) state: &'a mut ClientState,
+    pub(crate) resolvers: &'a mut Vec<ResolverState>,
+    pub(crate) encoder: &'a QueryEncoder<'e>,
+    pub(crate) mtu: u32,
+    pub(crate) shaping: &'a ShapingConfig,
+    pub(crate) debug_poll: bool,
+    pub(crate) idle: bool,
+}
+
+pub(crate) fn handle_control(
+    request: &ControlRequest,
+    ctx: &mut ControlContext<'_, '_>,
+) -> Result<Value, String> {
+    match request.command.as_str() {
+        "status" => {
+            let mut active = 0;
+            for resolver in ctx.resolvers.iter_mut() {
+                if refresh_resolver_path(ctx.cnx, resolver) {
+                    active += 1;
+                }
+            }
+            Ok(json!({
+                "connected": ctx.state.is_ready(),
+                "streams": ctx.state.streams_len(),
+                "paths": ctx.resolvers.len(),
+                "active_paths": active,
+                "idle": ctx.idle,
+            }))
+        }
+        "paths" => {
+            let paths = ctx
+                .resolvers
+                .iter_mut()
+                .map(|resolver| path_info(ctx.cnx, resolver))
+                .collect();
+            Ok(Value::Array(paths))
+        }
+        "streams" => {
+            let now = unsafe { cnx_time(ctx.cnx) };
+            let streams = ctx
+                .state
+                .stream_infos()
+                .into_iter()
+                .map(|info| {
+                    json!({
+                        "id": info.stream_id,
+                        "state": info.state,
+                        "tx_bytes": info.tx_bytes,
+                        "rx_bytes": info.rx_bytes,
+                        "queued_bytes": info.queued_bytes,
+                        "age_secs": now.saturating_sub(info.opened_at) / 1_000_000,
+                    })
+                })
+                .collect();
+            Ok(Value::Array(streams))
+        }
+        "close-stream" => {
+            let stream_id = request.u64_arg("id")?;
+            if !ctx.state.close_stream(ctx.cnx, stream_id) {
+                return Err(format!("No open stream {}", stream_id));
+            }
+            info!("Closed stream {} from the control socket", stream_id);
+            Ok(Value::Null)
+        }
+        "add-resolver" => add_resolver(request, ctx),
+        "remove-resolver" => remove_resolver(request, ctx),
+        other => Err(unknown_command(other)),
+    }
+}
+
+/// Answers requests while `--lazy` waits for the first TCP connection.
+pub(crate) fn handle_control_disconnected(request: &ControlRequest) -> Result<Value, String> {
+    match request.command.as_str() {
+        "status" => Ok(json!({
+            "connected": false,
+            "streams": 0,
+            "paths": 0,
+            "active_paths": 0,
+            "idle": false,
+        })),
+        "paths" | "streams" => Ok(Value::Array(Vec::new())),
+        "close-stream" | "add-resolver" | "remove-resolver" => {
+            Err("The tunnel is not connected".to_string())
+        }
+        other => Err(unknown_command(other)),
+    }
+}
+
+fn unknown_command(command: &str) -> String {
+    format!(
+        "Unknown command {}; expected status, paths, streams, close-stream, add-resolver or remove-resolver",
+        command
+    )
+}
+
+fn path_info(cnx: *mut picoquic_cnx_t, resolver: &mut ResolverState) -> Value {
+    let active = refresh_resolver_path(cnx, resolver);
+    let mut errors = Map::new();
+    for (kind, count) in resolver.response_errors.entries() {
+        if count > 0 {
+            errors.insert(kind.to_string(), Value::from(count));
+        }
+    }
+    let quality = active.then(|| {
+        let quality = fetch_path_quality(cnx, resolver);
+        json!({
+            "rtt_us": quality.rtt,
+            "cwnd": quality.cwin,
+            "bytes_in_transit": quality.bytes_in_transit,
+            "pacing_rate": quality.pacing_rate,
+            "packets_sent": quality.sent,
+            "packets_lost": quality.lost,
+        })
+    });
+    json!({
+        "resolver": resolver.addr.to_string(),
+        "mode": mode_label(resolver.mode),
+        "active": active,
+        "path_id": resolver.path_id,
+        "path_mtu": resolver.path_mtu,
+        "pending_polls": resolver.pending_polls,
+        "inflight_polls": resolver.inflight_poll_ids.len(),
+        "queries": resolver.debug.queries_sent,
+        "responses": resolver.debug.dns_responses,
+        "polls": resolver.debug.polls_sent,
+        "response_errors": errors,
+        "quality": quality,
+    })
+}
+
+fn add_resolver(
+    request: &ControlRequest,
+    ctx: &mut ControlContext<'_, '_>,
+) -> Result<Value, String> {
+    let resolver = parse_host_port(request.str_arg("resolver")?, 53, AddressKind::Resolver)
+        .map_err(|err| err.to_string())?;
+    let mode = match request.opt_str_arg("mode")? {
+        None | Some("recursive") => ResolverMode::Recursive,
+        Some("authoritative") => ResolverMode::Authoritative,
+        Some(other) => {
+            return Err(format!(
+                "Unknown mode {}; expected recursive or authoritative",
+                other
+            ))
+        }
+    };
+    let mut added = resolve_resolvers(
+        &[ResolverSpec { resolver, mode }],
+        ctx.mtu,
+        ctx.shaping,
+        ctx.debug_poll,
+    )
+    .map_err(|err| err.to_string())?;
+    let mut new_resolver = added.remove(0);
+    if ctx
+        .resolvers
+        .iter()
+        .any(|existing| existing.addr == new_resolver.addr)
+    {
+        return Err(format!("Resolver {} is already in use", new_resolver.addr));
+    }
+    let mut addrs: Vec<_> = ctx.resolvers.iter().map(|resolver| resolver.addr).collect();
+    addrs.push(new_resolver.addr);
+    ctx.encoder
+        .domains()
+        .validate(&addrs)
+        .map_err(|err| err.to_string())?;
+    // resolve_resolvers treats its first entry as the primary path.
+    new_resolver.added = false;
+    new_resolver.path_id = -1;
+    new_resolver.unique_path_id = None;
+    info!(
+        "Adding resolver {} ({}) from the control socket",
+        new_resolver.addr,
+        mode_label(mode)
+    );
+    let addr = new_resolver.addr.to_string();
+    ctx.resolvers.push(new_resolver);
+    Ok(json!({ "resolver": addr }))
+}
+
+fn remove_resolver(
+    request: &ControlRequest,
+    ctx: &mut ControlContext<'_, '_>,
+) -> Result<Value, String> {
+    let wanted = parse_host_port(request.str_arg("resolver")?, 53, AddressKind::Resolver)
+        .map_err(|err| err.to_string())?;
+    let addr = resolve_host_port(&wanted).map_err(|err| err.to_string())?;
+    let addr = normalize_dual_stack_addr(addr);
+    let index = ctx
+        .resolvers
+        .iter()
+        .position(|resolver| resolver.addr == addr)
+        .ok_or_else(|| format!("No resolver {}", addr))?;
+    if index == 0 {
+        return Err("The first resolver carries the connection and cannot be removed".to_string());
+    }
+    let resolver = &mut ctx.resolvers[index];
+    if refresh_resolver_path(ctx.cnx, resolver) {
+        let Some(unique_path_id) = resolver.unique_path_id else {
+            return Err(format!(
+                "The path to {} is still being set up; try again",
+                resolver.addr
+            ));
+        };
+        let now = unsafe { cnx_time(ctx.cnx) };
+        unsafe {
+            picoquic_abandon_path(ctx.cnx, unique_path_id, 0, std::ptr::null(), now);
+        }
+    }
+    let resolver = ctx.resolvers.remove(index);
+    info!("Removed resolver {} from the control socket", resolver.addr);
+    Ok(json!({ "resolver": resolver.addr.to_string() }))
+}
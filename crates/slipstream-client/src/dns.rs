@@ -1,12 +1,18 @@
 mod debug;
+mod domains;
+mod mtu;
 mod path;
 mod poll;
+mod query;
 mod resolver;
 mod response;
 
 pub(crate) use debug::maybe_report_debug;
+pub(crate) use domains::{resolve_domains, DomainRotation};
+pub(crate) use mtu::{send_mtu_probes, PathMtuProbe};
 pub(crate) use path::{add_paths, refresh_resolver_path, resolver_mode_to_c};
 pub(crate) use poll::{expire_inflight_polls, send_poll_queries};
+pub(crate) use query::QueryEncoder;
 pub(crate) use resolver::{
     normalize_dual_stack_addr, reset_resolver_path, resolve_resolvers,
     sockaddr_storage_to_socket_addr, ResolverState,
@@ -1,4 +1,5 @@
 use crate::pacing::PacingBudgetSnapshot;
+use slipstream_dns::ResponseError;
 use tracing::debug;
 
 use super::resolver::ResolverState;
@@ -14,7 +15,10 @@ pub(crate) struct DebugMetrics {
     pub(crate) enqueued_bytes: u64,
     pub(crate) send_packets: u64,
     pub(crate) send_bytes: u64,
+    pub(crate) queries_sent: u64,
+    pub(crate) recv_bytes: u64,
     pub(crate) polls_sent: u64,
+    pub(crate) idle_polls: u64,
     pub(crate) last_enqueue_at: u64,
     pub(crate) last_report_dns: u64,
     pub(crate) last_report_zero: u64,
@@ -23,6 +27,7 @@ pub(crate) struct DebugMetrics {
     pub(crate) last_report_send_packets: u64,
     pub(crate) last_report_send_bytes: u64,
     pub(crate) last_report_polls: u64,
+    pub(crate) last_report_idle_polls: u64,
 }
 
 impl DebugMetrics {
@@ -36,7 +41,10 @@ impl DebugMetrics {
             enqueued_bytes: 0,
             send_packets: 0,
             send_bytes: 0,
+            queries_sent: 0,
+            recv_bytes: 0,
             polls_sent: 0,
+            idle_polls: 0,
             last_enqueue_at: 0,
             last_report_dns: 0,
             last_report_zero: 0,
@@ -45,10 +53,39 @@ impl DebugMetrics {
             last_report_send_packets: 0,
             last_report_send_bytes: 0,
             last_report_polls: 0,
+            last_report_idle_polls: 0,
         }
     }
 }
 
+/// Responses that did not carry a QUIC packet, by [`ResponseError::kind`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ResponseErrorCounters {
+    counts: [u64; ResponseError::KINDS.len()],
+}
+
+impl ResponseErrorCounters {
+    pub(crate) fn record(&mut self, err: &ResponseError) {
+        let kind = err.kind();
+        if let Some(index) = ResponseError::KINDS.iter().position(|name| *name == kind) {
+            self.counts[index] = self.counts[index].saturating_add(1);
+        }
+    }
+
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        ResponseError::KINDS.into_iter().zip(self.counts)
+    }
+
+    /// Non-zero counters as `name=count` pairs, or an empty string.
+    pub(crate) fn summary(&self) -> String {
+        self.entries()
+            .filter(|(_, count)| *count > 0)
+            .map(|(name, count)| format!("{}={}", name, count))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
 pub(crate) fn maybe_report_debug(
     resolver: &mut ResolverState,
     now: u64,
@@ -56,8 +93,11 @@ pub(crate) fn maybe_report_debug(
     pending_polls: usize,
     inflight_polls: usize,
     pacing_snapshot: Option<PacingBudgetSnapshot>,
+    idle_interval_us: Option<u64>,
 ) {
     let label = resolver.label();
+    let response_errors = resolver.response_errors.summary();
+    let shaping = resolver.shaping.counters.summary();
     let debug = &mut resolver.debug;
     if !debug.enabled {
         return;
@@ -98,8 +138,29 @@ pub(crate) fn maybe_report_debug(
     } else {
         String::new()
     };
+    let idle_summary = if let Some(interval_us) = idle_interval_us {
+        format!(
+            " idle_interval_ms={} idle_polls+={}",
+            interval_us / 1_000,
+            debug
+                .idle_polls
+                .saturating_sub(debug.last_report_idle_polls)
+        )
+    } else {
+        String::new()
+    };
+    let errors_summary = if response_errors.is_empty() {
+        String::new()
+    } else {
+        format!(" response_errors={}", response_errors)
+    };
+    let shaping_summary = if shaping.is_empty() {
+        String::new()
+    } else {
+        format!(" shaping={}", shaping)
+    };
     debug!(
-        "debug: {} dns+={} send_pkts+={} send_bytes+={} polls+={} zero_send+={} zero_send_streams+={} streams={} enqueued+={} last_enqueue_ms={} pending_polls={} inflight_polls={}{}",
+        "debug: {} dns+={} send_pkts+={} send_bytes+={} polls+={} zero_send+={} zero_send_streams+={} streams={} enqueued+={} last_enqueue_ms={} pending_polls={} inflight_polls={}{}{}{}{}",
         label,
         dns_delta,
         send_pkt_delta,
@@ -112,7 +173,10 @@ pub(crate) fn maybe_report_debug(
         enqueue_ms,
         pending_polls,
         inflight_polls,
-        pacing_summary
+        pacing_summary,
+        idle_summary,
+        errors_summary,
+        shaping_summary
     );
     debug.last_report_at = now;
     debug.last_report_dns = debug.dns_responses;
@@ -122,4 +186,5 @@ pub(crate) fn maybe_report_debug(
     debug.last_report_send_packets = debug.send_packets;
     debug.last_report_send_bytes = debug.send_bytes;
     debug.last_report_polls = debug.polls_sent;
+    debug.last_report_idle_polls = debug.idle_polls;
 }
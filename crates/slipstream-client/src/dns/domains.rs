@@ -0,0 +1,184 @@
+use crate::error::ClientError;
+use slipstream_core::resolve_host_port;
+use slipstream_ffi::DomainSpec;
+use std::net::SocketAddr;
+
+use super::resolver::normalize_dual_stack_addr;
+
+/// A tunnel domain as configured with `--domain`.
+#[derive(Debug, Clone)]
+pub(crate) struct TunnelDomain<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) weight: u32,
+    /// Only queries to this resolver use the domain.
+    pub(crate) resolver: Option<SocketAddr>,
+}
+
+pub(crate) fn resolve_domains(
+    domains: &[DomainSpec],
+) -> Result<Vec<TunnelDomain<'_>>, ClientError> {
+    domains
+        .iter()
+        .map(|spec| {
+            let resolver = match &spec.resolver {
+                Some(resolver) => Some(normalize_dual_stack_addr(
+                    resolve_host_port(resolver).map_err(|err| ClientError::new(err.to_string()))?,
+                )),
+                None => None,
+            };
+            Ok(TunnelDomain {
+                name: &spec.domain,
+                weight: spec.weight,
+                resolver,
+            })
+        })
+        .collect()
+}
+
+/// Picks the domain for each query with smooth weighted round-robin.
+///
+/// Queries to a resolver use the domains pinned to it, or the unpinned domains when
+/// none are. Equal weights give plain round-robin.
+pub(crate) struct DomainRotation<'a> {
+    domains: Vec<TunnelDomain<'a>>,
+    current: Vec<i64>,
+}
+
+impl<'a> DomainRotation<'a> {
+    pub(crate) fn new(domains: Vec<TunnelDomain<'a>>) -> Result<Self, ClientError> {
+        if domains.is_empty() {
+            return Err(ClientError::new("At least one domain is required"));
+        }
+        if let Some(domain) = domains.iter().find(|domain| domain.weight == 0) {
+            return Err(ClientError::new(format!(
+                "Domain {} must have a weight of at least 1",
+                domain.name
+            )));
+        }
+        Ok(Self {
+            current: vec![0; domains.len()],
+            domains,
+        })
+    }
+
+    pub(crate) fn names(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.domains.iter().map(|domain| domain.name)
+    }
+
+    /// Checks that pins name configured resolvers and every resolver has a domain.
+    pub(crate) fn validate(&self, resolvers: &[SocketAddr]) -> Result<(), ClientError> {
+        for domain in &self.domains {
+            if let Some(pinned) = domain.resolver {
+                if !resolvers.contains(&pinned) {
+                    return Err(ClientError::new(format!(
+                        "Domain {} is pinned to {}, which is not a configured resolver",
+                        domain.name, pinned
+                    )));
+                }
+            }
+        }
+        for resolver in resolvers {
+            if self.longest_for(*resolver).is_none() {
+                return Err(ClientError::new(format!(
+                    "No domain can be used with resolver {}; the others are pinned elsewhere",
+                    resolver
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn usable(&self, dest: SocketAddr) -> impl Iterator<Item = usize> + '_ {
+        let pinned = self
+            .domains
+            .iter()
+            .any(|domain| domain.resolver == Some(dest));
+        let wanted = if pinned { Some(dest) } else { None };
+        self.domains
+            .iter()
+            .enumerate()
+            .filter(move |(_, domain)| domain.resolver == wanted)
+            .map(|(index, _)| index)
+    }
+
+    /// Longest domain queries to `dest` may use; it bounds what fits in one QNAME.
+    pub(crate) fn longest_for(&self, dest: SocketAddr) -> Option<&'a str> {
+        self.usable(dest)
+            .map(|index| self.domains[index].name)
+            .max_by_key(|name| name.len())
+    }
+
+    /// Domain for the next query to `dest`.
+    pub(crate) fn next(&mut self, dest: SocketAddr) -> Option<&'a str> {
+        let usable: Vec<usize> = self.usable(dest).collect();
+        let total: i64 = usable
+            .iter()
+            .map(|index| self.domains[*index].weight as i64)
+            .sum();
+        let mut best: Option<usize> = None;
+        for index in usable {
+            self.current[index] += self.domains[index].weight as i64;
+            if best.is_none_or(|best| self.current[index] > self.current[best]) {
+                best = Some(index);
+            }
+        }
+        let best = best?;
+        self.current[best] -= total;
+        Some(self.domains[best].name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DomainRotation, TunnelDomain};
+    use std::net::SocketAddr;
+
+    fn domain(name: &str, weight: u32, resolver: Option<SocketAddr>) -> TunnelDomain<'_> {
+        TunnelDomain {
+            name,
+            weight,
+            resolver,
+        }
+    }
+
+    #[test]
+    fn rotates_by_weight() {
+        let dest: SocketAddr = "192.0.2.1:53".parse().expect("addr");
+        let mut rotation = DomainRotation::new(vec![
+            domain("a.example.com", 3, None),
+            domain("b.example.net", 1, None),
+        ])
+        .expect("rotation");
+        let picks: Vec<&str> = (0..8).filter_map(|_| rotation.next(dest)).collect();
+        let count_a = picks
+            .iter()
+            .filter(|name| **name == "a.example.com")
+            .count();
+        assert_eq!(count_a, 6);
+        // Smooth: the light domain is not starved until the end of a cycle.
+        assert!(picks[..4].contains(&"b.example.net"));
+    }
+
+    #[test]
+    fn honors_resolver_pins() {
+        let pinned: SocketAddr = "192.0.2.1:53".parse().expect("addr");
+        let other: SocketAddr = "192.0.2.2:53".parse().expect("addr");
+        let mut rotation = DomainRotation::new(vec![
+            domain("shared.example.com", 1, None),
+            domain("pinned.example.com", 1, Some(pinned)),
+        ])
+        .expect("rotation");
+        for _ in 0..4 {
+            assert_eq!(rotation.next(pinned), Some("pinned.example.com"));
+            assert_eq!(rotation.next(other), Some("shared.example.com"));
+        }
+        assert!(rotation.validate(&[pinned, other]).is_ok());
+
+        let missing: SocketAddr = "192.0.2.3:53".parse().expect("addr");
+        assert!(rotation.validate(&[other]).is_err());
+        let only_pinned = DomainRotation::new(vec![domain("pinned.example.com", 1, Some(pinned))])
+            .expect("rotation");
+        assert!(only_pinned.validate(&[pinned, missing]).is_err());
+        assert!(DomainRotation::new(vec![domain("zero.example.com", 0, None)]).is_err());
+    }
+}
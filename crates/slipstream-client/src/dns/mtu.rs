@@ -0,0 +1,383 @@
+use crate::error::ClientError;
+use crate::transport::DnsTransport;
+use slipstream_dns::{
+    build_probe_qname, encode_query, max_response_payload_len, ProbeKind, ProbeReply, ProbeRequest,
+    QueryParams, ResponseError, CLASS_IN, PROBE_CNX_ID_LEN, RR_TXT,
+};
+
+use super::resolver::ResolverState;
+
+// Probe plan; see docs/protocol.md for details.
+// QNAME lengths to try, longest first; the first one answered is the upstream limit.
+const QNAME_LADDER: [usize; 6] = [253, 220, 190, 160, 130, 100];
+// DNS response sizes to request, smallest first; stops at the first failure.
+const RESPONSE_LADDER: [usize; 5] = [512, 768, 1024, 1232, 1452];
+const PROBE_ATTEMPTS: u32 = 2;
+const PROBE_TIMEOUT_US: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Upstream(usize),
+    Downstream(usize),
+    Report,
+    Done,
+}
+
+struct Outstanding {
+    id: u16,
+    request: ProbeRequest,
+    sent_at: u64,
+}
+
+/// Discovers how long a QNAME and how large a response one resolver passes through.
+///
+/// Runs a single probe at a time next to the QUIC traffic of the path. The upstream
+/// limit shrinks the client's send MTU for the path; the downstream limit is reported
+/// to the server, which sizes its answers for the path accordingly.
+pub(crate) struct PathMtuProbe {
+    domain: String,
+    stage: Stage,
+    attempts: u32,
+    outstanding: Option<Outstanding>,
+    next_nonce: u32,
+    max_qname_len: Option<usize>,
+    max_response_len: Option<usize>,
+    finish_reported: bool,
+}
+
+impl PathMtuProbe {
+    pub(crate) fn new(domain: &str, seed: u32) -> Self {
+        Self {
+            domain: domain.to_string(),
+            stage: Stage::Upstream(0),
+            attempts: 0,
+            outstanding: None,
+            next_nonce: seed,
+            max_qname_len: None,
+            max_response_len: None,
+            finish_reported: false,
+        }
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    /// Returns true once, after the last probe finished.
+    pub(crate) fn take_finished(&mut self) -> bool {
+        if self.is_done() && !self.finish_reported {
+            self.finish_reported = true;
+            return true;
+        }
+        false
+    }
+
+    /// Longest QNAME answered through the resolver, once known.
+    pub(crate) fn max_qname_len(&self) -> Option<usize> {
+        self.max_qname_len
+    }
+
+    /// Largest DNS response received through the resolver, once known.
+    pub(crate) fn max_response_len(&self) -> Option<usize> {
+        self.max_response_len
+    }
+
+    pub(crate) fn is_probe_response(&self, id: u16) -> bool {
+        self.outstanding
+            .as_ref()
+            .is_some_and(|outstanding| outstanding.id == id)
+    }
+
+    /// Returns the QNAME of the next probe if one is due, recording it under DNS `id`.
+    ///
+    /// The report waits until `cnx_id`, the server's connection ID on the path, is known.
+    pub(crate) fn next_probe(
+        &mut self,
+        id: u16,
+        now: u64,
+        cnx_id: Option<[u8; PROBE_CNX_ID_LEN]>,
+    ) -> Result<Option<String>, ClientError> {
+        if let Some(outstanding) = &self.outstanding {
+            if now.saturating_sub(outstanding.sent_at) < PROBE_TIMEOUT_US {
+                return Ok(None);
+            }
+            self.outstanding = None;
+            self.fail(false);
+        }
+        let (kind, qname_len, message_len) = match self.stage {
+            Stage::Upstream(index) => (ProbeKind::Echo, QNAME_LADDER[index], None),
+            Stage::Downstream(index) => (ProbeKind::Echo, 0, Some(RESPONSE_LADDER[index])),
+            Stage::Report if cnx_id.is_some() => (ProbeKind::ReportDownstream, 0, None),
+            Stage::Report | Stage::Done => return Ok(None),
+        };
+        self.next_nonce = self.next_nonce.wrapping_add(1);
+        let mut request = ProbeRequest {
+            kind,
+            nonce: self.next_nonce,
+            response_len: 0,
+            cnx_id: None,
+        };
+        let qname = build_probe_qname(&request, &self.domain, qname_len)
+            .map_err(|err| ClientError::new(err.to_string()))?;
+        match (kind, message_len) {
+            (ProbeKind::ReportDownstream, _) => {
+                request.response_len = self.max_response_len.unwrap_or(0) as u16;
+                request.cnx_id = cnx_id;
+            }
+            (_, Some(message_len)) => {
+                request.response_len = max_response_payload_len(message_len, &qname) as u16;
+            }
+            _ => {}
+        }
+        // Echo probe labels have a fixed size, so the QNAME length does not change;
+        // reports are never padded.
+        let qname = build_probe_qname(&request, &self.domain, qname_len)
+            .map_err(|err| ClientError::new(err.to_string()))?;
+        self.outstanding = Some(Outstanding {
+            id,
+            request,
+            sent_at: now,
+        });
+        Ok(Some(qname))
+    }
+
+    /// Feeds the decoded response to the outstanding probe.
+    pub(crate) fn on_response(&mut self, decoded: Result<Vec<u8>, ResponseError>, size: usize) {
+        let Some(outstanding) = self.outstanding.take() else {
+            return;
+        };
+        let answered = match &decoded {
+            Ok(payload) => ProbeReply::decode(payload)
+                .is_some_and(|reply| reply.nonce == outstanding.request.nonce),
+            Err(_) => false,
+        };
+        if !answered {
+            // A truncated answer will not get better on retry.
+            self.fail(matches!(decoded, Err(ResponseError::Truncated)));
+            return;
+        }
+        self.attempts = 0;
+        self.stage = match self.stage {
+            Stage::Upstream(index) => {
+                self.max_qname_len = Some(QNAME_LADDER[index]);
+                Stage::Downstream(0)
+            }
+            Stage::Downstream(index) => {
+                self.max_response_len = Some(size.min(RESPONSE_LADDER[index]));
+                if index + 1 < RESPONSE_LADDER.len() {
+                    Stage::Downstream(index + 1)
+                } else {
+                    Stage::Report
+                }
+            }
+            Stage::Report | Stage::Done => Stage::Done,
+        };
+    }
+
+    fn fail(&mut self, final_attempt: bool) {
+        self.attempts += 1;
+        if !final_attempt && self.attempts < PROBE_ATTEMPTS {
+            return;
+        }
+        self.attempts = 0;
+        self.stage = match self.stage {
+            Stage::Upstream(index) if index + 1 < QNAME_LADDER.len() => Stage::Upstream(index + 1),
+            // No probe got through at all; the server may predate probes.
+            Stage::Upstream(_) => Stage::Done,
+            Stage::Downstream(_) if self.max_response_len.is_some() => Stage::Report,
+            Stage::Downstream(_) | Stage::Report | Stage::Done => Stage::Done,
+        };
+    }
+}
+
+/// Sends due MTU probes on every established path.
+pub(crate) async fn send_mtu_probes<T: DnsTransport>(
+    transport: &T,
+    dns_id: &mut u16,
+    resolvers: &mut [ResolverState],
+    now: u64,
+) -> Result<(), ClientError> {
+    for resolver in resolvers.iter_mut() {
+        if !resolver.added {
+            continue;
+        }
+        let Some(probe) = resolver.mtu_probe.as_mut() else {
+            continue;
+        };
+        let Some(qname) = probe.next_probe(*dns_id, now, resolver.server_cnx_id)? else {
+            continue;
+        };
+        let query = encode_query(&QueryParams {
+            id: *dns_id,
+            qname: &qname,
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+        })
+        .map_err(|err| ClientError::new(err.to_string()))?;
+        *dns_id = dns_id.wrapping_add(1);
+        transport
+            .send_to(&query, resolver.addr)
+            .await
+            .map_err(|err| ClientError::new(err.to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{send_mtu_probes, PathMtuProbe, Stage, PROBE_TIMEOUT_US};
+    use crate::dns::{handle_dns_response, resolve_resolvers, DnsResponseContext};
+    use crate::shaping::ShapingConfig;
+    use crate::transport::{memory_transport, DnsTransport};
+    use slipstream_core::{AddressFamily, HostPort};
+    use slipstream_dns::{
+        decode_query, encode_response, ProbeKind, ProbeReply, ResponseError, ResponseMode,
+        ResponseParams,
+    };
+    use slipstream_ffi::{socket_addr_to_storage, ResolverMode, ResolverSpec};
+
+    const DOMAIN: &str = "tunnel.example.com";
+
+    fn reply_for(probe: &PathMtuProbe) -> Vec<u8> {
+        let outstanding = probe.outstanding.as_ref().expect("outstanding probe");
+        ProbeReply {
+            id: outstanding.id,
+            nonce: outstanding.request.nonce,
+            stamp: 1,
+            qname: String::new(),
+        }
+        .encode(outstanding.request.response_len as usize)
+    }
+
+    #[test]
+    fn walks_qname_and_response_ladders() {
+        let mut probe = PathMtuProbe::new(DOMAIN, 7);
+        let mut now = 0;
+
+        // 253 and 220 are dropped twice each, 190 gets through.
+        for _ in 0..4 {
+            let qname = probe
+                .next_probe(1, now, None)
+                .expect("probe")
+                .expect("qname");
+            assert!(qname.len() > 200);
+            now += PROBE_TIMEOUT_US;
+        }
+        let qname = probe
+            .next_probe(2, now, None)
+            .expect("probe")
+            .expect("qname");
+        assert_eq!(qname.trim_end_matches('.').len(), 190);
+        assert!(probe.is_probe_response(2));
+        probe.on_response(Ok(reply_for(&probe)), 100);
+        assert_eq!(probe.max_qname_len(), Some(190));
+
+        // 512 and 768 fit, 1024 comes back truncated.
+        for size in [512, 768] {
+            probe
+                .next_probe(3, now, None)
+                .expect("probe")
+                .expect("qname");
+            probe.on_response(Ok(reply_for(&probe)), size);
+        }
+        probe
+            .next_probe(4, now, None)
+            .expect("probe")
+            .expect("qname");
+        probe.on_response(Err(ResponseError::Truncated), 60);
+        assert_eq!(probe.max_response_len(), Some(768));
+        assert_eq!(probe.stage, Stage::Report);
+
+        // The report waits for the path's connection ID.
+        assert_eq!(probe.next_probe(5, now, None).expect("probe"), None);
+        let cnx_id = Some([9; 8]);
+        probe
+            .next_probe(5, now, cnx_id)
+            .expect("probe")
+            .expect("qname");
+        let request = probe.outstanding.as_ref().expect("report").request;
+        assert_eq!(request.kind, ProbeKind::ReportDownstream);
+        assert_eq!(request.response_len, 768);
+        assert_eq!(request.cnx_id, cnx_id);
+        probe.on_response(Ok(reply_for(&probe)), 80);
+        assert!(probe.take_finished());
+        assert!(!probe.take_finished());
+        assert_eq!(probe.next_probe(6, now, cnx_id).expect("probe"), None);
+    }
+
+    #[test]
+    fn gives_up_when_no_probe_is_answered() {
+        let mut probe = PathMtuProbe::new(DOMAIN, 7);
+        let mut now = 0;
+        while !probe.is_done() {
+            probe.next_probe(1, now, None).expect("probe");
+            assert_eq!(probe.next_probe(1, now + 1, None).expect("probe"), None);
+            now += PROBE_TIMEOUT_US;
+        }
+        assert_eq!(probe.max_qname_len(), None);
+        assert_eq!(probe.max_response_len(), None);
+    }
+
+    #[tokio::test]
+    async fn probes_round_trip_over_a_transport() {
+        let specs = [ResolverSpec {
+            resolver: HostPort {
+                host: "192.0.2.1".to_string(),
+                port: 53,
+                family: AddressFamily::V4,
+            },
+            mode: ResolverMode::Recursive,
+        }];
+        let mut resolvers =
+            resolve_resolvers(&specs, 900, &ShapingConfig::default(), false).expect("resolvers");
+        resolvers[0].mtu_probe = Some(PathMtuProbe::new(DOMAIN, 7));
+        let resolver_addr = resolvers[0].addr;
+        let (transport, mut network) = memory_transport("[::]:0".parse().expect("addr"));
+
+        let mut dns_id = 1;
+        send_mtu_probes(&transport, &mut dns_id, &mut resolvers, 0)
+            .await
+            .expect("send probes");
+        let (query, dest) = network.queries.try_recv().expect("probe query");
+        assert_eq!(dest, resolver_addr);
+        assert_eq!(dns_id, 2);
+
+        let decoded = decode_query(&query, DOMAIN).expect("decode probe");
+        let probe = decoded.probe.expect("probe request");
+        assert_eq!(probe.kind, ProbeKind::Echo);
+        let reply = ProbeReply {
+            id: decoded.id,
+            nonce: probe.nonce,
+            stamp: 1,
+            qname: decoded.question.name.clone(),
+        };
+        let response = encode_response(&ResponseParams {
+            id: decoded.id,
+            rd: decoded.rd,
+            cd: decoded.cd,
+            question: &decoded.question,
+            payload: Some(&reply.encode(probe.response_len as usize)),
+            rcode: None,
+        })
+        .expect("encode response");
+        network.respond(&response, dest);
+
+        let mut buf = [0u8; 2048];
+        let (size, peer) = transport.recv_from(&mut buf).await.expect("recv");
+        let local_addr_storage = socket_addr_to_storage(transport.local_addr().expect("addr"));
+        let mut ctx = DnsResponseContext {
+            quic: std::ptr::null_mut(),
+            local_addr_storage: &local_addr_storage,
+            resolvers: &mut resolvers,
+            response_mode: ResponseMode::Strict,
+        };
+        // Probe answers are consumed before anything reaches picoquic.
+        handle_dns_response(&buf[..size], peer, &mut ctx).expect("handle response");
+        let probe = resolvers[0].mtu_probe.as_ref().expect("probe");
+        assert_eq!(probe.max_qname_len(), Some(253));
+    }
+}
@@ -1,11 +1,11 @@
 use crate::error::ClientError;
 use crate::net::{Sockaddr, SockaddrStorage};
 use slipstream_ffi::picoquic::{
-    picoquic_cnx_t, picoquic_current_time, picoquic_get_path_addr, picoquic_probe_new_path_ex,
+    picoquic_cnx_t, picoquic_get_path_addr, picoquic_probe_new_path_ex,
     slipstream_find_path_id_by_addr, slipstream_get_path_id_from_unique,
     slipstream_set_default_path_mode,
 };
-use slipstream_ffi::ResolverMode;
+use slipstream_ffi::{cnx_time, ResolverMode};
 use tracing::{info, warn};
 
 use super::resolver::{reset_resolver_path, ResolverState};
@@ -57,7 +57,7 @@ pub(crate) fn add_paths(
     if ret != 0 {
         return Ok(());
     }
-    let now = unsafe { picoquic_current_time() };
+    let now = unsafe { cnx_time(cnx) };
     let primary_mode = resolvers[0].mode;
     let mut default_mode = primary_mode;
 
@@ -87,17 +87,17 @@ pub(crate) fn add_paths(
         if ret == 0 && path_id >= 0 {
             resolver.added = true;
             resolver.path_id = path_id;
-            info!("Added path {}", resolver.addr);
+            info!(resolver = %resolver.addr, "Added path");
             continue;
         }
         resolver.probe_attempts = resolver.probe_attempts.saturating_add(1);
         let delay = path_probe_backoff(resolver.probe_attempts);
         resolver.next_probe_at = now.saturating_add(delay);
         warn!(
-            "Failed adding path {} (attempt {}), retrying in {}ms",
-            resolver.addr,
-            resolver.probe_attempts,
-            delay / 1000
+            resolver = %resolver.addr,
+            attempt = resolver.probe_attempts,
+            retry_ms = delay / 1000,
+            "Failed adding path"
         );
     }
 
@@ -1,13 +1,13 @@
 use crate::error::ClientError;
-use slipstream_dns::{build_qname, encode_query, QueryParams, CLASS_IN, RR_TXT};
+use slipstream_core::udp_batch::SendBatch;
 use slipstream_ffi::picoquic::{
-    picoquic_cnx_t, picoquic_current_time, picoquic_prepare_packet_ex, slipstream_request_poll,
+    picoquic_cnx_t, picoquic_prepare_packet_ex, slipstream_request_poll,
 };
-use slipstream_ffi::{ClientConfig, ResolverMode};
+use slipstream_ffi::{cnx_time, ResolverMode};
 use std::collections::HashMap;
-use tokio::net::UdpSocket as TokioUdpSocket;
 
 use super::path::refresh_resolver_path;
+use super::query::QueryEncoder;
 use super::resolver::{normalize_dual_stack_addr, sockaddr_storage_to_socket_addr, ResolverState};
 use crate::net::SockaddrStorage;
 
@@ -30,10 +30,10 @@ pub(crate) fn expire_inflight_polls(inflight_poll_ids: &mut HashMap<u16, u64>, n
 }
 
 #[allow(clippy::too_many_arguments)]
-pub(crate) async fn send_poll_queries(
+pub(crate) fn send_poll_queries(
     cnx: *mut picoquic_cnx_t,
-    udp: &TokioUdpSocket,
-    config: &ClientConfig<'_>,
+    batch: &mut SendBatch,
+    encoder: &mut QueryEncoder<'_>,
     local_addr_storage: &mut SockaddrStorage,
     dns_id: &mut u16,
     resolver: &mut ResolverState,
@@ -47,7 +47,11 @@ pub(crate) async fn send_poll_queries(
     *remaining = 0;
 
     while remaining_count > 0 {
-        let current_time = unsafe { picoquic_current_time() };
+        let current_time = unsafe { cnx_time(cnx) };
+        if !resolver.shaping.admit(current_time) {
+            *remaining = remaining_count;
+            break;
+        }
         unsafe {
             slipstream_request_poll(cnx);
         }
@@ -84,28 +88,18 @@ pub(crate) async fn send_poll_queries(
         resolver.debug.send_packets = resolver.debug.send_packets.saturating_add(1);
         resolver.debug.send_bytes = resolver.debug.send_bytes.saturating_add(send_length as u64);
         resolver.debug.polls_sent = resolver.debug.polls_sent.saturating_add(1);
-
-        let poll_id = *dns_id;
-        let qname = build_qname(&send_buf[..send_length], config.domain)
-            .map_err(|err| ClientError::new(err.to_string()))?;
-        let params = QueryParams {
-            id: poll_id,
-            qname: &qname,
-            qtype: RR_TXT,
-            qclass: CLASS_IN,
-            rd: true,
-            cd: false,
-            qdcount: 1,
-            is_query: true,
-        };
-        *dns_id = dns_id.wrapping_add(1);
-        let packet = encode_query(&params).map_err(|err| ClientError::new(err.to_string()))?;
+        resolver.note_sent_packet(&send_buf[..send_length]);
 
         let dest = sockaddr_storage_to_socket_addr(&addr_to)?;
         let dest = normalize_dual_stack_addr(dest);
-        udp.send_to(&packet, dest)
-            .await
-            .map_err(|err| ClientError::new(err.to_string()))?;
+        let queries = encoder.encode(dns_id, dest, &send_buf[..send_length])?;
+        // Only the final segment's response can carry data for this poll.
+        let poll_id = dns_id.wrapping_sub(1);
+
+        for packet in &queries {
+            batch.push(packet, dest);
+            resolver.debug.queries_sent = resolver.debug.queries_sent.saturating_add(1);
+        }
         if resolver.mode == ResolverMode::Authoritative {
             resolver.inflight_poll_ids.insert(poll_id, current_time);
         }
@@ -0,0 +1,149 @@
+use crate::error::ClientError;
+use slipstream_dns::{
+    build_qname_with_layout, build_segment_qname, encode_query, max_payload_len_for_layout,
+    max_payload_len_for_name_len, max_segment_payload_len, LabelLayout, QueryParams, SegmentHeader,
+    CLASS_IN, MAX_SEGMENTS, RR_TXT, SEGMENT_PACKET_ID_MASK,
+};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+
+use super::domains::DomainRotation;
+
+/// Wraps outbound QUIC packets into DNS queries using the configured label layout.
+///
+/// Packets larger than one query are split into segments when segmentation is enabled.
+/// Sizes are computed for the longest domain, so any domain can carry any packet.
+pub(crate) struct QueryEncoder<'a> {
+    domains: DomainRotation<'a>,
+    layout: LabelLayout,
+    sequence: u32,
+    max_payload_len: usize,
+    segment_len: Option<usize>,
+    session: u32,
+    next_packet_id: u32,
+}
+
+impl<'a> QueryEncoder<'a> {
+    pub(crate) fn new(
+        domains: DomainRotation<'a>,
+        layout: LabelLayout,
+    ) -> Result<Self, ClientError> {
+        let mut max_payload_len = usize::MAX;
+        for domain in domains.names() {
+            let len = max_payload_len_for_layout(domain, &layout)
+                .map_err(|err| ClientError::new(err.to_string()))?;
+            max_payload_len = max_payload_len.min(len);
+        }
+        Ok(Self {
+            domains,
+            layout,
+            sequence: 0,
+            max_payload_len,
+            segment_len: None,
+            // The server keys reassembly on the session, so clients sharing it never collide.
+            session: RandomState::new().build_hasher().finish() as u32,
+            next_packet_id: 0,
+        })
+    }
+
+    /// Largest QUIC packet that fits in a single query.
+    pub(crate) fn max_payload_len(&self) -> usize {
+        self.max_payload_len
+    }
+
+    /// Largest QUIC packet whose query QNAME stays within `max_name_len` characters.
+    pub(crate) fn max_payload_len_for_name_len(&self, max_name_len: usize) -> usize {
+        self.domains
+            .names()
+            .map(|domain| {
+                max_payload_len_for_name_len(domain, &self.layout, max_name_len).unwrap_or(0)
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn domains(&self) -> &DomainRotation<'a> {
+        &self.domains
+    }
+
+    /// Enables segmentation when `mtu` exceeds a single query and returns the usable MTU.
+    pub(crate) fn enable_segmentation(&mut self, mtu: usize) -> Result<usize, ClientError> {
+        if mtu <= self.max_payload_len {
+            self.segment_len = None;
+            return Ok(mtu);
+        }
+        let mut segment_len = usize::MAX;
+        for domain in self.domains.names() {
+            let len = max_segment_payload_len(domain, &self.layout)
+                .map_err(|err| ClientError::new(err.to_string()))?;
+            segment_len = segment_len.min(len);
+        }
+        if segment_len == 0 {
+            return Err(ClientError::new(
+                "Domain name is too long for segmented DNS transport",
+            ));
+        }
+        self.segment_len = Some(segment_len);
+        Ok(mtu.min(segment_len * MAX_SEGMENTS))
+    }
+
+    /// Encodes `payload` into one or more queries to `dest`, assigning consecutive DNS ids.
+    ///
+    /// All segments of a packet use the same domain.
+    pub(crate) fn encode(
+        &mut self,
+        dns_id: &mut u16,
+        dest: SocketAddr,
+        payload: &[u8],
+    ) -> Result<Vec<Vec<u8>>, ClientError> {
+        let domain = self
+            .domains
+            .next(dest)
+            .ok_or_else(|| ClientError::new(format!("No domain for resolver {}", dest)))?;
+        let segment_len = match self.segment_len {
+            Some(segment_len) if payload.len() > self.max_payload_len => segment_len,
+            _ => {
+                let qname = build_qname_with_layout(payload, domain, &self.layout, self.sequence)
+                    .map_err(|err| ClientError::new(err.to_string()))?;
+                return Ok(vec![self.encode_qname(dns_id, &qname)?]);
+            }
+        };
+
+        let count = payload.len().div_ceil(segment_len);
+        if count > MAX_SEGMENTS {
+            return Err(ClientError::new("QUIC packet needs too many DNS segments"));
+        }
+        let packet_id = self.next_packet_id;
+        self.next_packet_id = self.next_packet_id.wrapping_add(1) & SEGMENT_PACKET_ID_MASK;
+        let mut queries = Vec::with_capacity(count);
+        for (index, segment) in payload.chunks(segment_len).enumerate() {
+            let header = SegmentHeader {
+                session: self.session,
+                packet_id,
+                index: index as u8,
+                count: count as u8,
+            };
+            let qname = build_segment_qname(segment, &header, domain, &self.layout, self.sequence)
+                .map_err(|err| ClientError::new(err.to_string()))?;
+            queries.push(self.encode_qname(dns_id, &qname)?);
+        }
+        Ok(queries)
+    }
+
+    fn encode_qname(&mut self, dns_id: &mut u16, qname: &str) -> Result<Vec<u8>, ClientError> {
+        let params = QueryParams {
+            id: *dns_id,
+            qname,
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+        };
+        *dns_id = dns_id.wrapping_add(1);
+        self.sequence = self.sequence.wrapping_add(1);
+        encode_query(&params).map_err(|err| ClientError::new(err.to_string()))
+    }
+}
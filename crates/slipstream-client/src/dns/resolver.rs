@@ -1,13 +1,18 @@
 use crate::error::ClientError;
 use crate::net::SockaddrStorage;
 use crate::pacing::{PacingBudgetSnapshot, PacingPollBudget};
+use crate::shaping::{PathShaping, ShapingConfig};
 use slipstream_core::resolve_host_port;
+use slipstream_dns::PROBE_CNX_ID_LEN;
 use slipstream_ffi::{socket_addr_to_storage, ResolverMode, ResolverSpec};
 use std::collections::HashMap;
 use std::net::SocketAddr;
+#[cfg(not(windows))]
+use std::net::SocketAddrV6;
 use tracing::warn;
 
-use super::debug::DebugMetrics;
+use super::debug::{DebugMetrics, ResponseErrorCounters};
+use super::mtu::PathMtuProbe;
 
 pub(crate) struct ResolverState {
     pub(crate) addr: SocketAddr,
@@ -23,6 +28,14 @@ pub(crate) struct ResolverState {
     pub(crate) inflight_poll_ids: HashMap<u16, u64>,
     pub(crate) pacing_budget: Option<PacingPollBudget>,
     pub(crate) last_pacing_snapshot: Option<PacingBudgetSnapshot>,
+    pub(crate) response_errors: ResponseErrorCounters,
+    pub(crate) mtu_probe: Option<PathMtuProbe>,
+    /// Send MTU for the path once MTU discovery found a shorter QNAME limit.
+    pub(crate) path_mtu: Option<u32>,
+    /// Server connection ID of the last short-header packet sent on the path;
+    /// downstream limit reports name it.
+    pub(crate) server_cnx_id: Option<[u8; PROBE_CNX_ID_LEN]>,
+    pub(crate) shaping: PathShaping,
     pub(crate) debug: DebugMetrics,
 }
 
@@ -33,11 +46,22 @@ impl ResolverState {
             self.path_id, self.unique_path_id, self.addr, self.mode
         )
     }
+
+    /// Remembers the destination connection ID of a QUIC packet sent on the path.
+    pub(crate) fn note_sent_packet(&mut self, packet: &[u8]) {
+        // Short header: fixed bit set, long header bit clear, DCID right after.
+        if packet.first().is_some_and(|first| first & 0xc0 == 0x40) {
+            if let Some(cnx_id) = packet.get(1..1 + PROBE_CNX_ID_LEN) {
+                self.server_cnx_id = cnx_id.try_into().ok();
+            }
+        }
+    }
 }
 
 pub(crate) fn resolve_resolvers(
     resolvers: &[ResolverSpec],
     mtu: u32,
+    shaping: &ShapingConfig,
     debug_poll: bool,
 ) -> Result<Vec<ResolverState>, ClientError> {
     let mut resolved = Vec::with_capacity(resolvers.len());
@@ -71,6 +95,11 @@ pub(crate) fn resolve_resolvers(
                 ResolverMode::Recursive => None,
             },
             last_pacing_snapshot: None,
+            response_errors: ResponseErrorCounters::default(),
+            mtu_probe: None,
+            path_mtu: None,
+            server_cnx_id: None,
+            shaping: PathShaping::new(shaping),
             debug: DebugMetrics::new(debug_poll),
         });
     }
@@ -79,13 +108,14 @@ pub(crate) fn resolve_resolvers(
 
 pub(crate) fn reset_resolver_path(resolver: &mut ResolverState) {
     warn!(
-        "Path for resolver {} became unavailable; resetting state",
-        resolver.addr
+        resolver = %resolver.addr,
+        "Path became unavailable; resetting state"
     );
     resolver.added = false;
     resolver.path_id = -1;
     resolver.unique_path_id = None;
     resolver.local_addr_storage = None;
+    resolver.server_cnx_id = None;
     resolver.pending_polls = 0;
     resolver.inflight_poll_ids.clear();
     resolver.last_pacing_snapshot = None;
@@ -117,6 +147,7 @@ pub(crate) fn sockaddr_storage_to_socket_addr(
 #[cfg(test)]
 mod tests {
     use super::resolve_resolvers;
+    use crate::shaping::ShapingConfig;
     use slipstream_core::{AddressFamily, HostPort};
     use slipstream_ffi::{ResolverMode, ResolverSpec};
 
@@ -141,7 +172,7 @@ mod tests {
             },
         ];
 
-        match resolve_resolvers(&resolvers, 900, false) {
+        match resolve_resolvers(&resolvers, 900, &ShapingConfig::default(), false) {
             Ok(_) => panic!("expected duplicate resolver error"),
             Err(err) => assert!(err.to_string().contains("Duplicate resolver address")),
         }
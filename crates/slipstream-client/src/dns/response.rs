@@ -1,8 +1,8 @@
 use crate::error::ClientError;
 use crate::net::{Sockaddr, SockaddrStorage};
-use slipstream_dns::decode_response;
+use slipstream_dns::{decode_response_with_mode, ResponseMode};
 use slipstream_ffi::picoquic::{
-    picoquic_cnx_t, picoquic_current_time, picoquic_incoming_packet_ex, picoquic_quic_t,
+    picoquic_cnx_t, picoquic_get_quic_time, picoquic_incoming_packet_ex, picoquic_quic_t,
     PICOQUIC_PACKET_LOOP_RECV_MAX,
 };
 use slipstream_ffi::{socket_addr_to_storage, ResolverMode};
@@ -16,6 +16,7 @@ pub(crate) struct DnsResponseContext<'a> {
     pub(crate) quic: *mut picoquic_quic_t,
     pub(crate) local_addr_storage: &'a SockaddrStorage,
     pub(crate) resolvers: &'a mut [ResolverState],
+    pub(crate) response_mode: ResponseMode,
 }
 
 pub(crate) fn handle_dns_response(
@@ -25,69 +26,87 @@ pub(crate) fn handle_dns_response(
 ) -> Result<(), ClientError> {
     let peer = normalize_dual_stack_addr(peer);
     let response_id = dns_response_id(buf);
-    if let Some(payload) = decode_response(buf) {
-        let resolver_index = ctx
-            .resolvers
-            .iter()
-            .position(|resolver| resolver.addr == peer);
-        let mut peer_storage = socket_addr_to_storage(peer);
-        let mut local_storage = if let Some(index) = resolver_index {
-            ctx.resolvers[index]
-                .local_addr_storage
-                .as_ref()
-                .map(|storage| unsafe { std::ptr::read(storage) })
-                .unwrap_or_else(|| unsafe { std::ptr::read(ctx.local_addr_storage) })
-        } else {
-            unsafe { std::ptr::read(ctx.local_addr_storage) }
-        };
-        let mut first_cnx: *mut picoquic_cnx_t = std::ptr::null_mut();
-        let mut first_path: libc::c_int = -1;
-        let current_time = unsafe { picoquic_current_time() };
-        let ret = unsafe {
-            picoquic_incoming_packet_ex(
-                ctx.quic,
-                payload.as_ptr() as *mut u8,
-                payload.len(),
-                &mut peer_storage as *mut _ as *mut Sockaddr,
-                &mut local_storage as *mut _ as *mut Sockaddr,
-                0,
-                0,
-                &mut first_cnx,
-                &mut first_path,
-                current_time,
-            )
-        };
-        if ret < 0 {
-            return Err(ClientError::new("Failed processing inbound QUIC packet"));
+    if let Some(response_id) = response_id {
+        if let Some(probe) = find_resolver_by_addr(ctx.resolvers, peer)
+            .and_then(|resolver| resolver.mtu_probe.as_mut())
+            .filter(|probe| probe.is_probe_response(response_id))
+        {
+            probe.on_response(decode_response_with_mode(buf, ctx.response_mode), buf.len());
+            return Ok(());
         }
-        let resolver = if let Some(resolver) = find_resolver_by_path_id(ctx.resolvers, first_path) {
-            Some(resolver)
-        } else {
-            find_resolver_by_addr(ctx.resolvers, peer)
-        };
-        if let Some(resolver) = resolver {
-            if first_path >= 0 && resolver.path_id != first_path {
-                resolver.path_id = first_path;
-                resolver.added = true;
-            }
-            resolver.debug.dns_responses = resolver.debug.dns_responses.saturating_add(1);
-            if let Some(response_id) = response_id {
-                if resolver.mode == ResolverMode::Authoritative {
-                    resolver.inflight_poll_ids.remove(&response_id);
+    }
+    let payload = match decode_response_with_mode(buf, ctx.response_mode) {
+        Ok(payload) => payload,
+        Err(err) => {
+            if let Some(resolver) = find_resolver_by_addr(ctx.resolvers, peer) {
+                resolver.response_errors.record(&err);
+                if let Some(response_id) = response_id {
+                    resolver.debug.dns_responses = resolver.debug.dns_responses.saturating_add(1);
+                    if resolver.mode == ResolverMode::Authoritative {
+                        resolver.inflight_poll_ids.remove(&response_id);
+                    }
                 }
             }
-            if resolver.mode == ResolverMode::Recursive {
-                resolver.pending_polls =
-                    resolver.pending_polls.saturating_add(1).min(MAX_POLL_BURST);
-            }
+            return Ok(());
         }
-    } else if let Some(response_id) = response_id {
-        if let Some(resolver) = find_resolver_by_addr(ctx.resolvers, peer) {
-            resolver.debug.dns_responses = resolver.debug.dns_responses.saturating_add(1);
+    };
+    let resolver_index = ctx
+        .resolvers
+        .iter()
+        .position(|resolver| resolver.addr == peer);
+    let mut peer_storage = socket_addr_to_storage(peer);
+    let mut local_storage = if let Some(index) = resolver_index {
+        ctx.resolvers[index]
+            .local_addr_storage
+            .as_ref()
+            .map(|storage| unsafe { std::ptr::read(storage) })
+            .unwrap_or_else(|| unsafe { std::ptr::read(ctx.local_addr_storage) })
+    } else {
+        unsafe { std::ptr::read(ctx.local_addr_storage) }
+    };
+    let mut first_cnx: *mut picoquic_cnx_t = std::ptr::null_mut();
+    let mut first_path: libc::c_int = -1;
+    let current_time = unsafe { picoquic_get_quic_time(ctx.quic) };
+    let ret = unsafe {
+        picoquic_incoming_packet_ex(
+            ctx.quic,
+            payload.as_ptr() as *mut u8,
+            payload.len(),
+            &mut peer_storage as *mut _ as *mut Sockaddr,
+            &mut local_storage as *mut _ as *mut Sockaddr,
+            0,
+            0,
+            &mut first_cnx,
+            &mut first_path,
+            current_time,
+        )
+    };
+    if ret < 0 {
+        return Err(ClientError::new("Failed processing inbound QUIC packet"));
+    }
+    let resolver = if let Some(resolver) = find_resolver_by_path_id(ctx.resolvers, first_path) {
+        Some(resolver)
+    } else {
+        find_resolver_by_addr(ctx.resolvers, peer)
+    };
+    if let Some(resolver) = resolver {
+        if first_path >= 0 && resolver.path_id != first_path {
+            resolver.path_id = first_path;
+            resolver.added = true;
+        }
+        resolver.debug.dns_responses = resolver.debug.dns_responses.saturating_add(1);
+        resolver.debug.recv_bytes = resolver
+            .debug
+            .recv_bytes
+            .saturating_add(payload.len() as u64);
+        if let Some(response_id) = response_id {
             if resolver.mode == ResolverMode::Authoritative {
                 resolver.inflight_poll_ids.remove(&response_id);
             }
         }
+        if resolver.mode == ResolverMode::Recursive {
+            resolver.pending_polls = resolver.pending_polls.saturating_add(1).min(MAX_POLL_BURST);
+        }
     }
     Ok(())
 }
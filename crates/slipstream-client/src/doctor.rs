@@ -0,0 +1,480 @@
+mod report;
+
+use crate::dns::normalize_dual_stack_addr;
+use crate::error::ClientError;
+use crate::runtime::{bind_udp_socket, map_io};
+use slipstream_core::{resolve_host_port, HostPort};
+use slipstream_dns::{
+    build_probe_qname, decode_response_with_mode, encode_query, max_response_payload_len,
+    ProbeKind, ProbeReply, ProbeRequest, QueryParams, ResponseError, ResponseMode, CLASS_IN, RR_A,
+    RR_TXT,
+};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::time::timeout_at;
+
+pub use report::ResolverReport;
+pub(crate) use report::{CaseHandling, QtypeResult, SizeResult};
+
+// Probe plan; see docs/usage.md for details.
+const LATENCY_PROBES: usize = 5;
+// DNS message sizes to request, smallest first.
+const RESPONSE_SIZES: [usize; 6] = [512, 900, 1232, 1452, 2048, 4096];
+const QTYPE_PROBES: [(&str, u16); 4] = [("A", RR_A), ("AAAA", 28), ("NULL", 10), ("MX", 15)];
+const MAX_QNAME_LEN: usize = 253;
+const RECV_BUF_LEN: usize = 65535;
+
+pub struct DoctorConfig<'a> {
+    pub domain: &'a str,
+    pub resolvers: &'a [HostPort],
+    pub timeout: Duration,
+    pub attempts: u32,
+}
+
+/// Probes each resolver in turn and returns one report per resolver.
+pub async fn run_doctor(config: &DoctorConfig<'_>) -> Result<Vec<ResolverReport>, ClientError> {
+    let mut reports = Vec::with_capacity(config.resolvers.len());
+    for resolver in config.resolvers {
+        let addr = resolve_host_port(resolver).map_err(|err| ClientError::new(err.to_string()))?;
+        let mut prober = Prober::new(normalize_dual_stack_addr(addr), config).await?;
+        reports.push(prober.run().await?);
+    }
+    Ok(reports)
+}
+
+enum ProbeOutcome {
+    Reply {
+        reply: ProbeReply,
+        rtt: Duration,
+        size: usize,
+    },
+    Failed(ResponseError),
+    Timeout,
+}
+
+struct Prober<'a> {
+    socket: UdpSocket,
+    resolver: SocketAddr,
+    domain: &'a str,
+    timeout: Duration,
+    attempts: u32,
+    next_id: u16,
+    next_nonce: u32,
+    recv_buf: Vec<u8>,
+    tolerant_needed: bool,
+}
+
+impl<'a> Prober<'a> {
+    async fn new(resolver: SocketAddr, config: &DoctorConfig<'a>) -> Result<Self, ClientError> {
+        let seed = RandomState::new().build_hasher().finish();
+        Ok(Self {
+            socket: bind_udp_socket().await?,
+            resolver,
+            domain: config.domain,
+            timeout: config.timeout,
+            attempts: config.attempts.max(1),
+            next_id: seed as u16,
+            next_nonce: (seed >> 32) as u32,
+            recv_buf: vec![0u8; RECV_BUF_LEN],
+            tolerant_needed: false,
+        })
+    }
+
+    async fn run(&mut self) -> Result<ResolverReport, ClientError> {
+        let mut report = ResolverReport::new(self.resolver);
+
+        let min_qname_len = self.shortest_qname()?.len() - 1;
+        let mut rtts = Vec::with_capacity(LATENCY_PROBES);
+        let mut names = Vec::with_capacity(LATENCY_PROBES);
+        for _ in 0..LATENCY_PROBES {
+            let request = self.fresh_request(0);
+            let qname = self.qname(request, 0)?;
+            if let ProbeOutcome::Reply { reply, rtt, .. } =
+                self.probe(&qname, request, RR_TXT).await?
+            {
+                rtts.push(rtt);
+                report.id_preserved &= reply.id == self.next_id.wrapping_sub(1);
+                names.push((qname.trim_end_matches('.').to_string(), reply.qname));
+            }
+        }
+        if rtts.is_empty() {
+            return Ok(report);
+        }
+        report.reachable = true;
+        rtts.sort();
+        report.latency = Some((rtts[0], rtts[rtts.len() / 2], rtts[rtts.len() - 1]));
+        report.case = Some(CaseHandling::classify(&names));
+
+        report.max_qname_len = Some(self.max_qname_len(min_qname_len).await?);
+        report.response_sizes = self.response_sizes().await?;
+        report.caches = self.caches().await?;
+        for (name, qtype) in QTYPE_PROBES {
+            let request = self.fresh_request(0);
+            let qname = self.qname(request, 0)?;
+            let result = match self.exchange(&qname, qtype).await? {
+                None => QtypeResult::Timeout,
+                Some((packet, _)) => match decode_response_with_mode(&packet, ResponseMode::Strict)
+                {
+                    Err(ResponseError::Rcode(rcode)) => QtypeResult::Rcode(rcode),
+                    _ => QtypeResult::Forwarded,
+                },
+            };
+            report.qtypes.push((name, result));
+        }
+        report.tolerant_needed = self.tolerant_needed;
+        report.recommend(self.domain);
+        Ok(report)
+    }
+
+    /// Binary search for the longest QNAME that still gets an answer.
+    async fn max_qname_len(&mut self, min_len: usize) -> Result<usize, ClientError> {
+        if self.probe_len_ok(MAX_QNAME_LEN).await? {
+            return Ok(MAX_QNAME_LEN);
+        }
+        let (mut good, mut bad) = (min_len, MAX_QNAME_LEN);
+        while bad - good > 1 {
+            let mid = good + (bad - good) / 2;
+            if self.probe_len_ok(mid).await? {
+                good = mid;
+            } else {
+                bad = mid;
+            }
+        }
+        Ok(good)
+    }
+
+    async fn probe_len_ok(&mut self, qname_len: usize) -> Result<bool, ClientError> {
+        let request = self.fresh_request(0);
+        let qname = self.qname(request, qname_len)?;
+        Ok(matches!(
+            self.probe(&qname, request, RR_TXT).await?,
+            ProbeOutcome::Reply { .. }
+        ))
+    }
+
+    /// Requests growing responses and stops at the first size that does not come back.
+    async fn response_sizes(&mut self) -> Result<Vec<(usize, SizeResult)>, ClientError> {
+        let mut results = Vec::new();
+        for size in RESPONSE_SIZES {
+            let payload_len = max_response_payload_len(size, &self.shortest_qname()?);
+            let request = self.fresh_request(payload_len as u16);
+            let qname = self.qname(request, 0)?;
+            let result = match self.probe(&qname, request, RR_TXT).await? {
+                ProbeOutcome::Reply { size, .. } => SizeResult::Ok(size),
+                ProbeOutcome::Failed(ResponseError::Truncated) => SizeResult::Truncated,
+                ProbeOutcome::Failed(err) => SizeResult::Failed(err.kind()),
+                ProbeOutcome::Timeout => SizeResult::Timeout,
+            };
+            let done = !matches!(result, SizeResult::Ok(_));
+            results.push((size, result));
+            if done {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    /// Asks the same question twice; an identical server stamp means a cached answer.
+    async fn caches(&mut self) -> Result<Option<bool>, ClientError> {
+        let request = self.fresh_request(0);
+        let qname = self.qname(request, 0)?;
+        let first = self.probe(&qname, request, RR_TXT).await?;
+        let second = self.probe(&qname, request, RR_TXT).await?;
+        Ok(match (first, second) {
+            (
+                ProbeOutcome::Reply { reply: first, .. },
+                ProbeOutcome::Reply { reply: second, .. },
+            ) => Some(first.stamp == second.stamp),
+            _ => None,
+        })
+    }
+
+    fn fresh_request(&mut self, response_len: u16) -> ProbeRequest {
+        self.next_nonce = self.next_nonce.wrapping_add(1);
+        ProbeRequest {
+            kind: ProbeKind::Echo,
+            nonce: self.next_nonce,
+            response_len,
+            cnx_id: None,
+        }
+    }
+
+    /// An unpadded probe QNAME; every probe label has the same length.
+    fn shortest_qname(&self) -> Result<String, ClientError> {
+        self.qname(
+            ProbeRequest {
+                kind: ProbeKind::Echo,
+                nonce: 0,
+                response_len: 0,
+                cnx_id: None,
+            },
+            0,
+        )
+    }
+
+    fn qname(&self, request: ProbeRequest, qname_len: usize) -> Result<String, ClientError> {
+        build_probe_qname(&request, self.domain, qname_len)
+            .map_err(|err| ClientError::new(err.to_string()))
+    }
+
+    async fn probe(
+        &mut self,
+        qname: &str,
+        request: ProbeRequest,
+        qtype: u16,
+    ) -> Result<ProbeOutcome, ClientError> {
+        let Some((packet, rtt)) = self.exchange(qname, qtype).await? else {
+            return Ok(ProbeOutcome::Timeout);
+        };
+        let (payload, tolerant_only) =
+            match decode_response_with_mode(&packet, ResponseMode::Strict) {
+                Ok(payload) => (payload, false),
+                Err(err) => match decode_response_with_mode(&packet, ResponseMode::Tolerant) {
+                    Ok(payload) => (payload, true),
+                    Err(_) => return Ok(ProbeOutcome::Failed(err)),
+                },
+            };
+        match ProbeReply::decode(&payload) {
+            Some(reply) if reply.nonce == request.nonce => {
+                self.tolerant_needed |= tolerant_only;
+                Ok(ProbeOutcome::Reply {
+                    reply,
+                    rtt,
+                    size: packet.len(),
+                })
+            }
+            _ => Ok(ProbeOutcome::Failed(ResponseError::Malformed(
+                "probe reply",
+            ))),
+        }
+    }
+
+    /// Sends one query, retrying on timeout, and returns the matching response.
+    async fn exchange(
+        &mut self,
+        qname: &str,
+        qtype: u16,
+    ) -> Result<Option<(Vec<u8>, Duration)>, ClientError> {
+        for _ in 0..self.attempts {
+            let id = self.next_id;
+            self.next_id = self.next_id.wrapping_add(1);
+            let query = encode_query(&QueryParams {
+                id,
+                qname,
+                qtype,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+            })
+            .map_err(|err| ClientError::new(err.to_string()))?;
+            let sent_at = Instant::now();
+            self.socket
+                .send_to(&query, self.resolver)
+                .await
+                .map_err(map_io)?;
+            let deadline = tokio::time::Instant::from_std(sent_at + self.timeout);
+            loop {
+                let recv = timeout_at(deadline, self.socket.recv_from(&mut self.recv_buf)).await;
+                let Ok(recv) = recv else {
+                    break;
+                };
+                let (size, peer) = recv.map_err(map_io)?;
+                let packet = &self.recv_buf[..size];
+                if normalize_dual_stack_addr(peer) != self.resolver
+                    || size < 2
+                    || u16::from_be_bytes([packet[0], packet[1]]) != id
+                {
+                    continue;
+                }
+                return Ok(Some((packet.to_vec(), sent_at.elapsed())));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_doctor, CaseHandling, DoctorConfig, SizeResult};
+    use slipstream_core::{parse_host_port, AddressKind};
+    use slipstream_dns::{
+        decode_query, encode_response, max_response_payload_len, DecodeQueryError, ProbeReply,
+        ResponseParams,
+    };
+    use slipstream_ffi::ResolverMode;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+
+    const DOMAIN: &str = "tunnel.example.com";
+
+    /// Stand-in for a recursive resolver in front of slipstream-server.
+    #[derive(Clone, Copy)]
+    struct StandIn {
+        max_qname_len: usize,
+        max_response_len: usize,
+        lowercase: bool,
+        rewrite_ids: bool,
+        cache: bool,
+    }
+
+    async fn spawn_stand_in(behavior: StandIn) -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.expect("bind");
+        let addr = socket.local_addr().expect("addr");
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            let mut cache: HashMap<String, u64> = HashMap::new();
+            let mut stamp = 0u64;
+            loop {
+                let Ok((size, peer)) = socket.recv_from(&mut buf).await else {
+                    return;
+                };
+                let mut query = buf[..size].to_vec();
+                let qname_len = query.len().saturating_sub(12 + 4 + 11 + 2);
+                if qname_len > behavior.max_qname_len {
+                    continue;
+                }
+                let client_id = u16::from_be_bytes([query[0], query[1]]);
+                if behavior.rewrite_ids {
+                    query[0] ^= 0x5a;
+                }
+                if behavior.lowercase {
+                    query[12..].make_ascii_lowercase();
+                }
+                let response = match decode_query(&query, DOMAIN) {
+                    Ok(decoded) => {
+                        let probe = decoded.probe.expect("probe query");
+                        stamp += 1;
+                        let stamp = if behavior.cache {
+                            *cache.entry(decoded.question.name.clone()).or_insert(stamp)
+                        } else {
+                            stamp
+                        };
+                        let reply = ProbeReply {
+                            id: decoded.id,
+                            nonce: probe.nonce,
+                            stamp,
+                            qname: decoded.question.name.clone(),
+                        };
+                        encode_response(&ResponseParams {
+                            id: client_id,
+                            rd: decoded.rd,
+                            cd: decoded.cd,
+                            question: &decoded.question,
+                            payload: Some(&reply.encode(probe.response_len as usize)),
+                            rcode: None,
+                        })
+                    }
+                    Err(DecodeQueryError::Reply {
+                        question: Some(question),
+                        rcode,
+                        ..
+                    }) => encode_response(&ResponseParams {
+                        id: client_id,
+                        rd: true,
+                        cd: false,
+                        question: &question,
+                        payload: None,
+                        rcode: Some(rcode),
+                    }),
+                    Err(_) => continue,
+                };
+                let mut response = response.expect("encode response");
+                if response.len() > behavior.max_response_len {
+                    // Truncate like a resolver would: header and question only, TC set.
+                    response[2] |= 0x02;
+                    response[6] = 0;
+                    response[7] = 0;
+                    let question_end = 12 + (size - 12 - 11);
+                    response.truncate(question_end);
+                    response[10] = 0;
+                    response[11] = 0;
+                }
+                let _ = socket.send_to(&response, peer).await;
+            }
+        });
+        addr.to_string()
+    }
+
+    async fn doctor(behavior: StandIn) -> super::ResolverReport {
+        let resolver = spawn_stand_in(behavior).await;
+        let resolvers =
+            vec![parse_host_port(&resolver, 53, AddressKind::Resolver).expect("resolver")];
+        let config = DoctorConfig {
+            domain: DOMAIN,
+            resolvers: &resolvers,
+            timeout: Duration::from_millis(200),
+            attempts: 1,
+        };
+        let mut reports = run_doctor(&config).await.expect("doctor");
+        reports.remove(0)
+    }
+
+    #[tokio::test]
+    async fn measures_direct_server_path() {
+        let report = doctor(StandIn {
+            max_qname_len: usize::MAX,
+            max_response_len: usize::MAX,
+            lowercase: false,
+            rewrite_ids: false,
+            cache: false,
+        })
+        .await;
+        assert!(report.reachable);
+        assert!(report.id_preserved);
+        assert_eq!(report.max_qname_len, Some(253));
+        assert_eq!(report.case, Some(CaseHandling::Preserved));
+        assert_eq!(report.caches, Some(false));
+        assert!(report
+            .response_sizes
+            .iter()
+            .all(|(_, result)| matches!(result, SizeResult::Ok(_))));
+        let recommendation = report.recommendation.expect("recommendation");
+        assert_eq!(recommendation.mode, ResolverMode::Authoritative);
+        assert_eq!(recommendation.mtu, None);
+    }
+
+    #[tokio::test]
+    async fn measures_restrictive_resolver() {
+        let report = doctor(StandIn {
+            max_qname_len: 150,
+            max_response_len: 1232,
+            lowercase: true,
+            rewrite_ids: true,
+            cache: true,
+        })
+        .await;
+        assert!(report.reachable);
+        assert!(!report.id_preserved);
+        let max_qname_len = report.max_qname_len.expect("qname len");
+        assert!((140..=150).contains(&max_qname_len), "{}", max_qname_len);
+        assert_eq!(report.case, Some(CaseHandling::Lowercased));
+        assert_eq!(report.caches, Some(true));
+        assert_eq!(
+            report
+                .response_sizes
+                .last()
+                .map(|(size, result)| (*size, *result)),
+            Some((1452, SizeResult::Truncated))
+        );
+        let recommendation = report.recommendation.expect("recommendation");
+        assert_eq!(recommendation.mode, ResolverMode::Recursive);
+        assert!(recommendation.mtu.is_some());
+    }
+
+    #[test]
+    fn payload_len_matches_message_size() {
+        let qname = "1AEBAGBAFAYDQQ.tunnel.example.com.";
+        for size in [512, 900, 1232, 4096] {
+            let payload_len = max_response_payload_len(size, qname);
+            let rdata_len = payload_len + payload_len.div_ceil(255);
+            let message_len = 12 + (qname.len() + 1) + 4 + 12 + 11 + rdata_len;
+            assert!(message_len <= size && message_len + 2 >= size, "{}", size);
+        }
+    }
+}
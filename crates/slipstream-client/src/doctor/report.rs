@@ -0,0 +1,293 @@
+use slipstream_dns::{max_payload_len_for_name_len, LabelLayout};
+use slipstream_ffi::ResolverMode;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+// Server QUIC MTU; see docs/config.md for details.
+const SERVER_QUIC_MTU: usize = 900;
+const MAX_QNAME_LEN: usize = 253;
+
+/// How the resolver treats the case of QNAMEs it forwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CaseHandling {
+    Preserved,
+    Lowercased,
+    /// Mixed changes, typically DNS 0x20 randomization.
+    Randomized,
+}
+
+impl CaseHandling {
+    /// Classifies `(sent, echoed)` QNAME pairs.
+    pub(crate) fn classify(names: &[(String, String)]) -> Self {
+        if names.iter().all(|(sent, echoed)| sent == echoed) {
+            CaseHandling::Preserved
+        } else if names
+            .iter()
+            .all(|(sent, echoed)| sent.to_ascii_lowercase() == *echoed)
+        {
+            CaseHandling::Lowercased
+        } else {
+            CaseHandling::Randomized
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SizeResult {
+    /// Answer arrived; carries the received message size.
+    Ok(usize),
+    /// TC bit set without answers.
+    Truncated,
+    Failed(&'static str),
+    Timeout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum QtypeResult {
+    /// The server's NODATA answer came back (or some other non-error answer).
+    Forwarded,
+    Rcode(u8),
+    Timeout,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Recommendation {
+    pub(crate) mode: ResolverMode,
+    /// `--mtu` value when the single-query default would exceed the QNAME limit.
+    pub(crate) mtu: Option<usize>,
+    pub(crate) tolerant_responses: bool,
+    pub(crate) warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolverReport {
+    pub(crate) resolver: SocketAddr,
+    pub reachable: bool,
+    /// Minimum, median, and maximum round-trip time of small probes.
+    pub(crate) latency: Option<(Duration, Duration, Duration)>,
+    /// The server saw our DNS ids, i.e. nothing re-originated the queries.
+    pub(crate) id_preserved: bool,
+    pub(crate) case: Option<CaseHandling>,
+    pub(crate) max_qname_len: Option<usize>,
+    /// Requested message sizes in increasing order, up to the first failure.
+    pub(crate) response_sizes: Vec<(usize, SizeResult)>,
+    pub(crate) caches: Option<bool>,
+    pub(crate) qtypes: Vec<(&'static str, QtypeResult)>,
+    pub(crate) tolerant_needed: bool,
+    pub(crate) recommendation: Option<Recommendation>,
+}
+
+impl ResolverReport {
+    pub(crate) fn new(resolver: SocketAddr) -> Self {
+        Self {
+            resolver,
+            reachable: false,
+            latency: None,
+            id_preserved: true,
+            case: None,
+            max_qname_len: None,
+            response_sizes: Vec::new(),
+            caches: None,
+            qtypes: Vec::new(),
+            tolerant_needed: false,
+            recommendation: None,
+        }
+    }
+
+    fn largest_response(&self) -> Option<usize> {
+        self.response_sizes
+            .iter()
+            .filter_map(|(_, result)| match result {
+                SizeResult::Ok(size) => Some(*size),
+                _ => None,
+            })
+            .max()
+    }
+
+    pub(crate) fn recommend(&mut self, domain: &str) {
+        let mut warnings = Vec::new();
+        let mode = if self.id_preserved && self.caches == Some(false) {
+            ResolverMode::Authoritative
+        } else {
+            ResolverMode::Recursive
+        };
+
+        let max_qname_len = self.max_qname_len.unwrap_or(MAX_QNAME_LEN);
+        let mtu = if max_qname_len < MAX_QNAME_LEN {
+            let payload_len = payload_len_for_qname_len(domain, max_qname_len);
+            if payload_len == 0 {
+                warnings.push(format!(
+                    "QNAMEs are limited to {} characters, leaving no room for data",
+                    max_qname_len
+                ));
+            }
+            Some(payload_len)
+        } else {
+            None
+        };
+
+        let needed = message_len_for_payload(SERVER_QUIC_MTU, max_qname_len);
+        match self.largest_response() {
+            Some(largest) if largest < needed => warnings.push(format!(
+                "largest answer received was {} bytes; full server packets need about {}",
+                largest, needed
+            )),
+            None => warnings.push("no TXT answer of 512 bytes or more came back".to_string()),
+            _ => {}
+        }
+        if self.qtypes.is_empty() {
+            warnings.push("qtype probes did not run".to_string());
+        }
+
+        self.recommendation = Some(Recommendation {
+            mode,
+            mtu,
+            tolerant_responses: self.tolerant_needed,
+            warnings,
+        });
+    }
+}
+
+/// Largest payload whose QNAME for `domain` stays within `max_qname_len` characters.
+fn payload_len_for_qname_len(domain: &str, max_qname_len: usize) -> usize {
+    max_payload_len_for_name_len(domain, &LabelLayout::default(), max_qname_len).unwrap_or(0)
+}
+
+/// Size of a response carrying `payload_len` TXT bytes for a QNAME of `qname_len` characters.
+fn message_len_for_payload(payload_len: usize, qname_len: usize) -> usize {
+    12 + (qname_len + 2) + 4 + 12 + 11 + payload_len + payload_len.div_ceil(255)
+}
+
+fn format_ms(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1_000.0)
+}
+
+impl fmt::Display for ResolverReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Resolver {}", self.resolver)?;
+        if !self.reachable {
+            return writeln!(f, "  reachable:        no (no probe answers)");
+        }
+        writeln!(f, "  reachable:        yes")?;
+        if let Some((min, median, max)) = self.latency {
+            writeln!(
+                f,
+                "  latency:          min {}, median {}, max {}",
+                format_ms(min),
+                format_ms(median),
+                format_ms(max)
+            )?;
+        }
+        writeln!(
+            f,
+            "  query ids:        {}",
+            if self.id_preserved {
+                "preserved (direct path to the server)"
+            } else {
+                "rewritten (recursive resolver)"
+            }
+        )?;
+        if let Some(len) = self.max_qname_len {
+            writeln!(f, "  max QNAME length: {}", len)?;
+        }
+        let sizes: Vec<String> = self
+            .response_sizes
+            .iter()
+            .map(|(size, result)| match result {
+                SizeResult::Ok(received) => format!("{} ok ({} B)", size, received),
+                SizeResult::Truncated => format!("{} truncated (TC)", size),
+                SizeResult::Failed(kind) => format!("{} failed ({})", size, kind),
+                SizeResult::Timeout => format!("{} dropped", size),
+            })
+            .collect();
+        writeln!(f, "  response sizes:   {}", sizes.join(", "))?;
+        writeln!(
+            f,
+            "  caching:          {}",
+            match self.caches {
+                Some(true) => "yes",
+                Some(false) => "no",
+                None => "unknown",
+            }
+        )?;
+        if let Some(case) = self.case {
+            writeln!(
+                f,
+                "  QNAME case:       {}",
+                match case {
+                    CaseHandling::Preserved => "preserved",
+                    CaseHandling::Lowercased => "lowercased",
+                    CaseHandling::Randomized => "randomized (0x20)",
+                }
+            )?;
+        }
+        let qtypes: Vec<String> = self
+            .qtypes
+            .iter()
+            .map(|(name, result)| match result {
+                QtypeResult::Forwarded => format!("{} forwarded", name),
+                QtypeResult::Rcode(rcode) => format!("{} rcode {}", name, rcode),
+                QtypeResult::Timeout => format!("{} dropped", name),
+            })
+            .collect();
+        writeln!(f, "  other qtypes:     {}", qtypes.join(", "))?;
+        writeln!(
+            f,
+            "  answer format:    {}",
+            if self.tolerant_needed {
+                "rewritten (CNAME or extra records)"
+            } else {
+                "unchanged"
+            }
+        )?;
+        if let Some(recommendation) = &self.recommendation {
+            let mut flags = vec![match recommendation.mode {
+                ResolverMode::Authoritative => format!("--authoritative {}", self.resolver),
+                ResolverMode::Recursive => format!("--resolver {}", self.resolver),
+            }];
+            if let Some(mtu) = recommendation.mtu {
+                flags.push(format!("--mtu {}", mtu));
+            }
+            if recommendation.tolerant_responses {
+                flags.push("--tolerant-responses".to_string());
+            }
+            writeln!(f, "  recommendation:   {}", flags.join(" "))?;
+            for warning in &recommendation.warnings {
+                writeln!(f, "  warning:          {}", warning)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{payload_len_for_qname_len, CaseHandling};
+
+    #[test]
+    fn classifies_case_handling() {
+        let pair = |sent: &str, echoed: &str| (sent.to_string(), echoed.to_string());
+        assert_eq!(
+            CaseHandling::classify(&[pair("AbC.d", "AbC.d")]),
+            CaseHandling::Preserved
+        );
+        assert_eq!(
+            CaseHandling::classify(&[pair("AbC.d", "abc.d")]),
+            CaseHandling::Lowercased
+        );
+        assert_eq!(
+            CaseHandling::classify(&[pair("AbC.d", "aBc.D")]),
+            CaseHandling::Randomized
+        );
+    }
+
+    #[test]
+    fn payload_len_shrinks_with_qname_limit() {
+        let full = payload_len_for_qname_len("t.example.com", 253);
+        assert_eq!(full, 146);
+        let limited = payload_len_for_qname_len("t.example.com", 150);
+        assert!(limited > 0 && limited < full);
+        assert_eq!(payload_len_for_qname_len("t.example.com", 10), 0);
+    }
+}
@@ -6,7 +6,7 @@ pub struct ClientError {
 }
 
 impl ClientError {
-    pub(crate) fn new(message: impl Into<String>) -> Self {
+    pub fn new(message: impl Into<String>) -> Self {
         Self {
             message: message.into(),
         }
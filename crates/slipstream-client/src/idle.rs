@@ -0,0 +1,146 @@
+use slipstream_ffi::ClientConfig;
+
+/// Idle polling knobs; an `idle_after_us` of 0 keeps the active cadence forever.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct IdlePolicy {
+    /// Time without stream data before polling backs off.
+    pub(crate) idle_after_us: u64,
+    /// First idle poll interval; doubles after every idle poll.
+    pub(crate) min_interval_us: u64,
+    /// Floor rate: the interval stops growing here.
+    pub(crate) max_interval_us: u64,
+}
+
+impl IdlePolicy {
+    pub(crate) fn from_client_config(config: &ClientConfig<'_>) -> Self {
+        let (min_ms, max_ms) = config.idle_poll_interval_ms;
+        Self {
+            idle_after_us: config.idle_after_secs.saturating_mul(1_000_000),
+            min_interval_us: min_ms.saturating_mul(1_000),
+            max_interval_us: max_ms.saturating_mul(1_000),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IdleTransition {
+    Entered,
+    Exited,
+}
+
+/// Backs polling off exponentially once the tunnel carries no stream data.
+///
+/// While idle, the regular poll machinery is suspended and every path gets one
+/// poll per interval instead. Stream activity returns to the active cadence.
+pub(crate) struct IdlePoller {
+    policy: IdlePolicy,
+    idle: bool,
+    interval_us: u64,
+    next_poll_at: u64,
+}
+
+impl IdlePoller {
+    pub(crate) fn new(policy: IdlePolicy) -> Self {
+        Self {
+            policy,
+            idle: false,
+            interval_us: policy.min_interval_us,
+            next_poll_at: 0,
+        }
+    }
+
+    pub(crate) fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Current idle poll interval, while idle.
+    pub(crate) fn interval_us(&self) -> Option<u64> {
+        self.idle.then_some(self.interval_us)
+    }
+
+    /// Moves between the active and idle states given the last stream activity.
+    pub(crate) fn update(&mut self, last_activity_at: u64, now: u64) -> Option<IdleTransition> {
+        if self.policy.idle_after_us == 0 {
+            return None;
+        }
+        let quiet_for = now.saturating_sub(last_activity_at);
+        if self.idle && quiet_for < self.policy.idle_after_us {
+            self.idle = false;
+            return Some(IdleTransition::Exited);
+        }
+        if !self.idle && quiet_for >= self.policy.idle_after_us {
+            self.idle = true;
+            self.interval_us = self.policy.min_interval_us;
+            self.next_poll_at = now.saturating_add(self.interval_us);
+            return Some(IdleTransition::Entered);
+        }
+        None
+    }
+
+    /// Whether the next idle poll is due; backs the interval off when it is.
+    pub(crate) fn poll_due(&mut self, now: u64) -> bool {
+        if !self.idle || now < self.next_poll_at {
+            return false;
+        }
+        self.interval_us = self
+            .interval_us
+            .saturating_mul(2)
+            .min(self.policy.max_interval_us);
+        self.next_poll_at = now.saturating_add(self.interval_us);
+        true
+    }
+
+    /// Time the loop must wake up for the next idle poll.
+    pub(crate) fn next_wakeup(&self) -> Option<u64> {
+        self.idle.then_some(self.next_poll_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdlePolicy, IdlePoller, IdleTransition};
+
+    const POLICY: IdlePolicy = IdlePolicy {
+        idle_after_us: 10_000_000,
+        min_interval_us: 1_000_000,
+        max_interval_us: 4_000_000,
+    };
+
+    #[test]
+    fn backs_off_to_floor_and_snaps_back() {
+        let mut poller = IdlePoller::new(POLICY);
+        assert_eq!(poller.update(0, 9_999_999), None);
+        assert_eq!(poller.update(0, 10_000_000), Some(IdleTransition::Entered));
+        assert!(poller.is_idle());
+
+        let mut now = 10_000_000;
+        let mut gaps = Vec::new();
+        for _ in 0..4 {
+            let next = poller.next_wakeup().expect("idle wakeup");
+            assert!(!poller.poll_due(next - 1));
+            assert!(poller.poll_due(next));
+            gaps.push(next - now);
+            now = next;
+        }
+        assert_eq!(gaps, [1_000_000, 2_000_000, 4_000_000, 4_000_000]);
+        assert_eq!(poller.interval_us(), Some(4_000_000));
+
+        assert_eq!(poller.update(now, now), Some(IdleTransition::Exited));
+        assert!(!poller.poll_due(now + 10_000_000));
+        assert_eq!(poller.next_wakeup(), None);
+        // A fresh idle period starts over at the shortest interval.
+        let later = now + 10_000_000;
+        assert_eq!(poller.update(now, later), Some(IdleTransition::Entered));
+        assert_eq!(poller.next_wakeup(), Some(later + 1_000_000));
+    }
+
+    #[test]
+    fn zero_idle_after_disables_backoff() {
+        let mut poller = IdlePoller::new(IdlePolicy {
+            idle_after_us: 0,
+            ..POLICY
+        });
+        assert_eq!(poller.update(0, u64::MAX), None);
+        assert!(!poller.is_idle());
+    }
+}
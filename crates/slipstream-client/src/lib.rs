@@ -0,0 +1,21 @@
+mod control;
+mod dns;
+mod doctor;
+mod error;
+mod idle;
+mod metrics;
+mod net;
+mod pacing;
+mod pinning;
+mod runtime;
+mod shaping;
+mod speedtest;
+mod streams;
+mod transport;
+
+pub use doctor::{run_doctor, DoctorConfig, ResolverReport};
+pub use error::ClientError;
+pub use runtime::{run_client, run_client_with};
+pub use speedtest::{
+    cpu_time, run_speedtest, ResolverUsage, SpeedtestConfig, SpeedtestReport, Transfer,
+};
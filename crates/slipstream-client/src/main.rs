@@ -1,33 +1,36 @@
-mod dns;
-mod error;
-mod net;
-mod pacing;
-mod pinning;
-mod runtime;
-mod streams;
-
-use clap::{ArgGroup, CommandFactory, FromArgMatches, Parser};
+use clap::{ArgGroup, CommandFactory, FromArgMatches, Parser, Subcommand};
+use slipstream_core::control::run_control_cli;
+use slipstream_core::logging::{init_logging, LogFormat};
 use slipstream_core::{normalize_domain, parse_host_port, AddressKind, HostPort};
-use slipstream_ffi::{ClientConfig, ResolverMode, ResolverSpec};
+use slipstream_ffi::{ClientConfig, DnsTransportKind, DomainSpec, ResolverMode, ResolverSpec};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 use tokio::runtime::Builder;
-use tracing_subscriber::EnvFilter;
 
-use runtime::run_client;
+use slipstream_client::{run_client, run_doctor, run_speedtest, DoctorConfig, SpeedtestConfig};
 
 #[derive(Parser, Debug)]
 #[command(
     name = "slipstream-client",
-    about = "slipstream-client - A high-performance covert channel over DNS (client)",
-    group(
-        ArgGroup::new("resolvers")
-            .required(true)
-            .multiple(true)
-            .args(["resolver", "authoritative"])
-    )
+    about = "slipstream-client - A high-performance covert channel over DNS (client)"
 )]
 struct Args {
     #[arg(long = "tcp-listen-port", short = 'l', default_value_t = 5201)]
     tcp_listen_port: u16,
+    #[command(flatten)]
+    tunnel: TunnelArgs,
+}
+
+/// Tunnel options shared by the client and `speedtest`.
+#[derive(clap::Args, Debug)]
+#[command(group(
+    ArgGroup::new("resolvers")
+        .required(true)
+        .multiple(true)
+        .args(["resolver", "authoritative"])
+))]
+struct TunnelArgs {
     #[arg(long = "resolver", short = 'r', value_parser = parse_resolver)]
     resolver: Vec<HostPort>,
     #[arg(
@@ -38,6 +41,16 @@ struct Args {
     congestion_control: Option<String>,
     #[arg(long = "authoritative", value_parser = parse_resolver)]
     authoritative: Vec<HostPort>,
+    #[arg(
+        long = "dns-transport",
+        value_name = "TRANSPORT",
+        default_value = "udp"
+    )]
+    dns_transport: DnsTransportKind,
+    #[arg(long = "dns-tls-name", value_name = "NAME")]
+    dns_tls_name: Option<String>,
+    #[arg(long = "dns-tls-ca", value_name = "PATH")]
+    dns_tls_ca: Option<String>,
     #[arg(
         short = 'g',
         long = "gso",
@@ -46,66 +59,327 @@ struct Args {
         default_missing_value = "true"
     )]
     gso: bool,
-    #[arg(long = "domain", short = 'd', value_parser = parse_domain)]
-    domain: String,
+    #[arg(
+        long = "domain",
+        short = 'd',
+        value_name = "DOMAIN[,weight=N][,resolver=HOST:PORT]",
+        value_parser = parse_domain_spec,
+        required = true
+    )]
+    domains: Vec<DomainSpec>,
+    #[arg(
+        long = "label-len",
+        default_value_t = 57,
+        value_parser = clap::value_parser!(u8).range(1..=63)
+    )]
+    label_len: u8,
+    #[arg(long = "sequence-label")]
+    sequence_label: bool,
+    #[arg(long = "mtu", value_parser = clap::value_parser!(u32).range(1..))]
+    mtu: Option<u32>,
+    #[arg(long = "tolerant-responses")]
+    tolerant_responses: bool,
+    #[arg(long = "path-mtu-discovery")]
+    path_mtu_discovery: bool,
+    #[arg(long = "poll-jitter-ms", default_value_t = 0)]
+    poll_jitter_ms: u64,
+    #[arg(
+        long = "pad-bucket",
+        value_name = "BYTES",
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u32).range(0..=1500)
+    )]
+    pad_bucket: u32,
+    #[arg(long = "cover-interval-ms", default_value_t = 0)]
+    cover_interval_ms: u64,
+    #[arg(long = "max-qps", default_value_t = 0)]
+    max_qps: u32,
+    #[arg(long = "idle-after-secs", default_value_t = 10)]
+    idle_after_secs: u64,
+    #[arg(
+        long = "idle-poll-interval",
+        value_name = "MIN_MS-MAX_MS",
+        default_value = "1000-30000",
+        value_parser = parse_idle_poll_interval
+    )]
+    idle_poll_interval: (u64, u64),
+    #[arg(long = "lazy")]
+    lazy: bool,
+    #[arg(
+        long = "lazy-idle-timeout-secs",
+        default_value_t = 300,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    lazy_idle_timeout_secs: u64,
+    #[arg(long = "metrics-listen", value_name = "ADDR")]
+    metrics_listen: Option<SocketAddr>,
+    #[arg(long = "control-socket", value_name = "PATH")]
+    control_socket: Option<String>,
+    #[arg(long = "qlog-dir", value_name = "DIR")]
+    qlog_dir: Option<String>,
+    #[arg(long = "qlog-max-mb", value_name = "MB", default_value_t = 256)]
+    qlog_max_mb: u64,
+    #[arg(long = "keylog-file", value_name = "FILE")]
+    keylog_file: Option<String>,
+    #[arg(long = "pcap", value_name = "FILE")]
+    pcap: Option<String>,
+    #[arg(long = "pcap-max-mb", value_name = "MB", default_value_t = 0)]
+    pcap_max_mb: u64,
+    #[arg(long = "pcap-files", value_name = "N", default_value_t = 0)]
+    pcap_files: u64,
     #[arg(long = "cert", value_name = "PATH")]
     cert: Option<String>,
     #[arg(long = "keep-alive-interval", short = 't', default_value_t = 400)]
     keep_alive_interval: u16,
+    #[arg(long = "log-format", value_name = "FORMAT", default_value = "text")]
+    log_format: LogFormat,
     #[arg(long = "debug-poll")]
     debug_poll: bool,
     #[arg(long = "debug-streams")]
     debug_streams: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Probe resolvers against slipstream-server and recommend client settings
+    Doctor(DoctorArgs),
+    /// Send a command to a running client's --control-socket
+    Ctl(CtlArgs),
+    /// Open a tunnel and measure it against a server run with --enable-diagnostics
+    Speedtest(Box<SpeedtestArgs>),
+}
+
+#[derive(clap::Args, Debug)]
+struct DoctorArgs {
+    #[arg(long = "domain", short = 'd', value_parser = parse_domain)]
+    domain: String,
+    #[arg(long = "resolver", short = 'r', value_parser = parse_resolver, required = true)]
+    resolver: Vec<HostPort>,
+    #[arg(long = "timeout-ms", default_value_t = 2000)]
+    timeout_ms: u64,
+    #[arg(long = "attempts", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
+    attempts: u32,
+}
+
+#[derive(clap::Args, Debug)]
+struct CtlArgs {
+    #[arg(long = "socket", value_name = "PATH")]
+    socket: PathBuf,
+    /// status, paths, streams, close-stream, add-resolver or remove-resolver
+    command: String,
+    #[arg(value_name = "KEY=VALUE")]
+    args: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct SpeedtestArgs {
+    #[arg(
+        long = "duration-secs",
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    duration_secs: u64,
+    #[arg(
+        long = "rtt-probes",
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    rtt_probes: u32,
+    #[command(flatten)]
+    tunnel: TunnelArgs,
+}
+
+fn cli() -> clap::Command {
+    Command::augment_subcommands(Args::command())
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+}
+
 fn main() {
-    init_logging();
-    let matches = Args::command().get_matches();
+    let matches = cli().get_matches();
+    if let Some((_, sub_matches)) = matches.subcommand() {
+        let command = Command::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+        init_logging(match &command {
+            Command::Speedtest(args) => args.tunnel.log_format,
+            _ => LogFormat::Text,
+        });
+        match command {
+            Command::Doctor(args) => run_doctor_command(args),
+            Command::Ctl(args) => {
+                std::process::exit(run_control_cli(&args.socket, &args.command, &args.args))
+            }
+            Command::Speedtest(args) => run_speedtest_command(*args, sub_matches),
+        }
+    }
     let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
-    let resolvers = build_resolvers(&matches).unwrap_or_else(|err| {
+    init_logging(args.tunnel.log_format);
+    let resolvers = exit_on_resolver_error(build_resolvers(&matches));
+    let config = client_config(args.tcp_listen_port, &args.tunnel, &resolvers);
+
+    let runtime = Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .expect("Failed to build Tokio runtime");
+    match runtime.block_on(run_client(&config)) {
+        Ok(code) => std::process::exit(code),
+        Err(err) => {
+            tracing::error!("Client error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn exit_on_resolver_error(resolvers: Result<Vec<ResolverSpec>, String>) -> Vec<ResolverSpec> {
+    resolvers.unwrap_or_else(|err| {
         tracing::error!("Resolver error: {}", err);
         std::process::exit(2);
-    });
+    })
+}
 
-    let config = ClientConfig {
-        tcp_listen_port: args.tcp_listen_port,
-        resolvers: &resolvers,
+fn client_config<'a>(
+    tcp_listen_port: u16,
+    args: &'a TunnelArgs,
+    resolvers: &'a [ResolverSpec],
+) -> ClientConfig<'a> {
+    ClientConfig {
+        tcp_listen_port,
+        resolvers,
         congestion_control: args.congestion_control.as_deref(),
         gso: args.gso,
-        domain: &args.domain,
+        domains: &args.domains,
+        label_len: args.label_len as usize,
+        sequence_label: args.sequence_label,
+        mtu: args.mtu,
+        tolerant_responses: args.tolerant_responses,
+        path_mtu_discovery: args.path_mtu_discovery,
+        poll_jitter_ms: args.poll_jitter_ms,
+        pad_bucket: args.pad_bucket,
+        cover_interval_ms: args.cover_interval_ms,
+        max_qps: args.max_qps,
+        idle_after_secs: args.idle_after_secs,
+        idle_poll_interval_ms: args.idle_poll_interval,
+        lazy: args.lazy,
+        lazy_idle_timeout_secs: args.lazy_idle_timeout_secs,
+        metrics_listen: args.metrics_listen,
+        control_socket: args.control_socket.as_deref(),
+        qlog_dir: args.qlog_dir.as_deref(),
+        qlog_max_mb: args.qlog_max_mb,
+        keylog_file: args.keylog_file.as_deref(),
+        pcap: args.pcap.as_deref(),
+        pcap_max_mb: args.pcap_max_mb,
+        pcap_files: args.pcap_files,
+        dns_transport: args.dns_transport,
+        dns_tls_name: args.dns_tls_name.as_deref(),
+        dns_tls_ca: args.dns_tls_ca.as_deref(),
         cert: args.cert.as_deref(),
         keep_alive_interval: args.keep_alive_interval as usize,
         debug_poll: args.debug_poll,
         debug_streams: args.debug_streams,
-    };
+    }
+}
 
+fn run_speedtest_command(args: SpeedtestArgs, matches: &clap::ArgMatches) -> ! {
+    let resolvers = exit_on_resolver_error(build_resolvers(matches));
+    // run_speedtest picks its own listen port.
+    let client = client_config(0, &args.tunnel, &resolvers);
+    let config = SpeedtestConfig {
+        client: &client,
+        duration: Duration::from_secs(args.duration_secs),
+        rtt_probes: args.rtt_probes,
+    };
     let runtime = Builder::new_current_thread()
         .enable_io()
         .enable_time()
         .build()
         .expect("Failed to build Tokio runtime");
-    match runtime.block_on(run_client(&config)) {
-        Ok(code) => std::process::exit(code),
+    match runtime.block_on(run_speedtest(&config)) {
+        Ok(report) => {
+            print!("{}", report);
+            std::process::exit(0);
+        }
         Err(err) => {
-            tracing::error!("Client error: {}", err);
+            tracing::error!("Speed test error: {}", err);
             std::process::exit(1);
         }
     }
 }
 
-fn init_logging() {
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .without_time()
-        .try_init();
+fn run_doctor_command(args: DoctorArgs) -> ! {
+    let config = DoctorConfig {
+        domain: &args.domain,
+        resolvers: &args.resolver,
+        timeout: Duration::from_millis(args.timeout_ms),
+        attempts: args.attempts,
+    };
+    let runtime = Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .expect("Failed to build Tokio runtime");
+    match runtime.block_on(run_doctor(&config)) {
+        Ok(reports) => {
+            for report in &reports {
+                println!("{}", report);
+            }
+            let all_reachable = reports.iter().all(|report| report.reachable);
+            std::process::exit(if all_reachable { 0 } else { 1 });
+        }
+        Err(err) => {
+            tracing::error!("Doctor error: {}", err);
+            std::process::exit(1);
+        }
+    }
 }
 
 fn parse_domain(input: &str) -> Result<String, String> {
     normalize_domain(input).map_err(|err| err.to_string())
 }
 
+fn parse_domain_spec(input: &str) -> Result<DomainSpec, String> {
+    let mut parts = input.split(',');
+    let domain = parse_domain(parts.next().unwrap_or_default())?;
+    let mut spec = DomainSpec {
+        domain,
+        weight: 1,
+        resolver: None,
+    };
+    for option in parts {
+        match option.split_once('=') {
+            Some(("weight", value)) => {
+                spec.weight = value
+                    .parse()
+                    .ok()
+                    .filter(|weight| *weight > 0)
+                    .ok_or_else(|| format!("Invalid domain weight: {}", value))?;
+            }
+            Some(("resolver", value)) => spec.resolver = Some(parse_resolver(value)?),
+            _ => return Err(format!("Unknown domain option: {}", option)),
+        }
+    }
+    Ok(spec)
+}
+
+// Idle polls double as keep-alives, so the slowest one must beat the QUIC idle timeout.
+const MAX_IDLE_POLL_INTERVAL_MS: u64 = 60_000;
+
+fn parse_idle_poll_interval(input: &str) -> Result<(u64, u64), String> {
+    let invalid = || format!("Invalid idle poll interval: {}", input);
+    let (min, max) = input.split_once('-').ok_or_else(invalid)?;
+    let min: u64 = min.trim().parse().map_err(|_| invalid())?;
+    let max: u64 = max.trim().parse().map_err(|_| invalid())?;
+    if min == 0 || min > max {
+        return Err(invalid());
+    }
+    if max > MAX_IDLE_POLL_INTERVAL_MS {
+        return Err(format!(
+            "Idle poll interval must not exceed {} ms",
+            MAX_IDLE_POLL_INTERVAL_MS
+        ));
+    }
+    Ok((min, max))
+}
+
 fn parse_resolver(input: &str) -> Result<HostPort, String> {
     parse_host_port(input, 53, AddressKind::Resolver).map_err(|err| err.to_string())
 }
@@ -178,6 +452,85 @@ mod tests {
         assert_eq!(resolvers[2].resolver.port, 5353);
     }
 
+    #[test]
+    fn parses_doctor_without_client_flags() {
+        let matches = cli()
+            .try_get_matches_from([
+                "slipstream-client",
+                "doctor",
+                "--domain",
+                "example.com",
+                "--resolver",
+                "1.1.1.1",
+                "--resolver",
+                "[2001:db8::1]:5353",
+            ])
+            .expect("doctor should parse");
+        let Command::Doctor(args) = Command::from_arg_matches(&matches).expect("command") else {
+            panic!("expected the doctor subcommand");
+        };
+        assert_eq!(args.domain, "example.com");
+        assert_eq!(args.resolver.len(), 2);
+        assert_eq!(args.resolver[1].port, 5353);
+        assert_eq!(args.timeout_ms, 2000);
+
+        assert!(cli()
+            .try_get_matches_from(["slipstream-client", "doctor", "--domain", "example.com"])
+            .is_err());
+    }
+
+    #[test]
+    fn parses_ctl_without_client_flags() {
+        let matches = cli()
+            .try_get_matches_from([
+                "slipstream-client",
+                "ctl",
+                "--socket",
+                "/run/slipstream.sock",
+                "close-stream",
+                "id=4",
+            ])
+            .expect("ctl should parse");
+        let Command::Ctl(args) = Command::from_arg_matches(&matches).expect("command") else {
+            panic!("expected the ctl subcommand");
+        };
+        assert_eq!(args.socket.to_str(), Some("/run/slipstream.sock"));
+        assert_eq!(args.command, "close-stream");
+        assert_eq!(args.args, ["id=4"]);
+    }
+
+    #[test]
+    fn parses_speedtest_with_tunnel_flags() {
+        let matches = cli()
+            .try_get_matches_from([
+                "slipstream-client",
+                "speedtest",
+                "--domain",
+                "example.com",
+                "--authoritative",
+                "8.8.8.8",
+                "--resolver",
+                "9.9.9.9",
+                "--duration-secs",
+                "5",
+            ])
+            .expect("speedtest should parse");
+        let (_, sub_matches) = matches.subcommand().expect("subcommand");
+        let Command::Speedtest(args) = Command::from_arg_matches(&matches).expect("command") else {
+            panic!("expected the speedtest subcommand");
+        };
+        assert_eq!(args.duration_secs, 5);
+        assert_eq!(args.rtt_probes, 10);
+        assert_eq!(args.tunnel.domains[0].domain, "example.com");
+        let resolvers = build_resolvers(sub_matches).expect("resolvers should parse");
+        assert_eq!(resolvers[0].mode, ResolverMode::Authoritative);
+        assert_eq!(resolvers[1].resolver.host, "9.9.9.9");
+
+        assert!(cli()
+            .try_get_matches_from(["slipstream-client", "speedtest", "--domain", "example.com"])
+            .is_err());
+    }
+
     #[test]
     fn maps_authoritative_first() {
         let matches = Args::command()
@@ -198,4 +551,51 @@ mod tests {
         assert_eq!(resolvers[1].resolver.host, "9.9.9.9");
         assert_eq!(resolvers[1].mode, ResolverMode::Recursive);
     }
+
+    #[test]
+    fn parses_domain_options() {
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "a.example.com.",
+                "--domain",
+                "b.example.net,weight=3,resolver=9.9.9.9",
+                "--resolver",
+                "9.9.9.9",
+            ])
+            .expect("matches should parse");
+        let args = Args::from_arg_matches(&matches).expect("args");
+        let domains = &args.tunnel.domains;
+        assert_eq!(domains.len(), 2);
+        assert_eq!(domains[0].domain, "a.example.com");
+        assert_eq!(domains[0].weight, 1);
+        assert!(domains[0].resolver.is_none());
+        assert_eq!(domains[1].weight, 3);
+        let pinned = domains[1].resolver.as_ref().expect("pinned resolver");
+        assert_eq!((pinned.host.as_str(), pinned.port), ("9.9.9.9", 53));
+
+        assert!(parse_domain_spec("example.com,weight=0").is_err());
+        assert!(parse_domain_spec("example.com,ttl=5").is_err());
+    }
+
+    #[test]
+    fn parses_idle_poll_interval() {
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "example.com",
+                "--resolver",
+                "1.1.1.1",
+            ])
+            .expect("matches should parse");
+        let args = Args::from_arg_matches(&matches).expect("args");
+        assert_eq!(args.tunnel.idle_poll_interval, (1_000, 30_000));
+        assert_eq!(parse_idle_poll_interval("500-500"), Ok((500, 500)));
+        assert!(parse_idle_poll_interval("0-1000").is_err());
+        assert!(parse_idle_poll_interval("2000-1000").is_err());
+        assert!(parse_idle_poll_interval("1000-120000").is_err());
+        assert!(parse_idle_poll_interval("1000").is_err());
+    }
 }
@@ -0,0 +1,170 @@
+use crate::dns::ResolverState;
+use slipstream_core::metrics::{MetricKind, MetricsEncoder};
+use slipstream_ffi::picoquic::picoquic_path_quality_t;
+use slipstream_ffi::ResolverMode;
+
+// Metric name, help text and how to read the value from a source.
+type CounterFamily<T> = (&'static str, &'static str, fn(&T) -> u64);
+type GaugeFamily<T> = (&'static str, &'static str, fn(&T) -> f64);
+
+/// One resolver path as seen by the metrics endpoint.
+pub(crate) struct PathSample<'a> {
+    pub(crate) resolver: &'a ResolverState,
+    /// Congestion state of the QUIC path, once the path is up.
+    pub(crate) quality: Option<picoquic_path_quality_t>,
+}
+
+/// Renders the client exposition for `--metrics-listen`.
+pub(crate) fn render_client_metrics(
+    paths: &[PathSample<'_>],
+    streams: usize,
+    connected: bool,
+) -> String {
+    let mut encoder = MetricsEncoder::new();
+    encoder.family(
+        "slipstream_client_connected",
+        MetricKind::Gauge,
+        "Whether the QUIC connection to the server is up.",
+    );
+    encoder.gauge(
+        "slipstream_client_connected",
+        &[],
+        if connected { 1.0 } else { 0.0 },
+    );
+    encoder.family(
+        "slipstream_client_streams",
+        MetricKind::Gauge,
+        "Open TCP streams carried by the tunnel.",
+    );
+    encoder.gauge("slipstream_client_streams", &[], streams as f64);
+
+    let labels: Vec<(String, &'static str)> = paths
+        .iter()
+        .map(|path| {
+            (
+                path.resolver.addr.to_string(),
+                mode_label(path.resolver.mode),
+            )
+        })
+        .collect();
+    let counters: [CounterFamily<ResolverState>; 5] = [
+        (
+            "slipstream_client_queries",
+            "DNS queries sent.",
+            |resolver| resolver.debug.queries_sent,
+        ),
+        (
+            "slipstream_client_responses",
+            "DNS responses received.",
+            |resolver| resolver.debug.dns_responses,
+        ),
+        (
+            "slipstream_client_polls",
+            "Poll queries sent.",
+            |resolver| resolver.debug.polls_sent,
+        ),
+        (
+            "slipstream_client_sent_bytes",
+            "QUIC bytes sent upstream.",
+            |resolver| resolver.debug.send_bytes,
+        ),
+        (
+            "slipstream_client_received_bytes",
+            "QUIC bytes received downstream.",
+            |resolver| resolver.debug.recv_bytes,
+        ),
+    ];
+    for (name, help, value) in counters {
+        encoder.family(name, MetricKind::Counter, help);
+        for (path, (resolver, mode)) in paths.iter().zip(&labels) {
+            encoder.counter(
+                name,
+                &[("resolver", resolver), ("mode", mode)],
+                value(path.resolver),
+            );
+        }
+    }
+
+    encoder.family(
+        "slipstream_client_response_errors",
+        MetricKind::Counter,
+        "DNS responses that carried no QUIC packet, by failure class.",
+    );
+    for (path, (resolver, mode)) in paths.iter().zip(&labels) {
+        for (kind, count) in path.resolver.response_errors.entries() {
+            encoder.counter(
+                "slipstream_client_response_errors",
+                &[("resolver", resolver), ("mode", mode), ("kind", kind)],
+                count,
+            );
+        }
+    }
+
+    let quality_counters: [CounterFamily<picoquic_path_quality_t>; 2] = [
+        (
+            "slipstream_client_packets_sent",
+            "QUIC packets sent on the path.",
+            |quality| quality.sent,
+        ),
+        (
+            "slipstream_client_packets_lost",
+            "QUIC packets declared lost on the path.",
+            |quality| quality.lost,
+        ),
+    ];
+    for (name, help, value) in quality_counters {
+        encoder.family(name, MetricKind::Counter, help);
+        for (path, (resolver, mode)) in paths.iter().zip(&labels) {
+            if let Some(quality) = path.quality.as_ref() {
+                encoder.counter(
+                    name,
+                    &[("resolver", resolver), ("mode", mode)],
+                    value(quality),
+                );
+            }
+        }
+    }
+
+    let quality_gauges: [GaugeFamily<picoquic_path_quality_t>; 4] = [
+        (
+            "slipstream_client_rtt_seconds",
+            "Smoothed round-trip time of the path.",
+            |quality| quality.rtt as f64 / 1_000_000.0,
+        ),
+        (
+            "slipstream_client_cwnd_bytes",
+            "Congestion window of the path.",
+            |quality| quality.cwin as f64,
+        ),
+        (
+            "slipstream_client_bytes_in_transit",
+            "Bytes sent on the path and not yet acknowledged.",
+            |quality| quality.bytes_in_transit as f64,
+        ),
+        (
+            "slipstream_client_pacing_rate_bytes",
+            "Pacing rate of the path in bytes per second.",
+            |quality| quality.pacing_rate as f64,
+        ),
+    ];
+    for (name, help, value) in quality_gauges {
+        encoder.family(name, MetricKind::Gauge, help);
+        for (path, (resolver, mode)) in paths.iter().zip(&labels) {
+            if let Some(quality) = path.quality.as_ref() {
+                encoder.gauge(
+                    name,
+                    &[("resolver", resolver), ("mode", mode)],
+                    value(quality),
+                );
+            }
+        }
+    }
+    encoder.finish()
+}
+
+pub(crate) fn mode_label(mode: ResolverMode) -> &'static str {
+    match mode {
+        ResolverMode::Recursive => "recursive",
+        ResolverMode::Authoritative => "authoritative",
+    }
+}
@@ -1,39 +1,58 @@
 mod path;
 mod setup;
 
+pub(crate) use self::path::fetch_path_quality;
 use self::path::{
-    apply_path_mode, drain_path_events, fetch_path_quality, find_resolver_by_addr_mut,
-    loop_burst_total, path_poll_burst_max,
+    apply_path_mode, drain_path_events, find_resolver_by_addr_mut, loop_burst_total,
+    path_poll_burst_max, update_path_mtu,
 };
-use self::setup::{bind_udp_socket, compute_mtu, map_io};
+use self::setup::compute_mtu;
+pub(crate) use self::setup::{bind_udp_socket, map_io, unspecified_addr};
+use crate::control::{handle_control, handle_control_disconnected, ControlContext};
 use crate::dns::{
     add_paths, expire_inflight_polls, handle_dns_response, maybe_report_debug,
-    normalize_dual_stack_addr, refresh_resolver_path, resolve_resolvers, resolver_mode_to_c,
-    send_poll_queries, sockaddr_storage_to_socket_addr, DnsResponseContext,
+    normalize_dual_stack_addr, refresh_resolver_path, resolve_domains, resolve_resolvers,
+    resolver_mode_to_c, send_mtu_probes, send_poll_queries, sockaddr_storage_to_socket_addr,
+    DnsResponseContext, DomainRotation, PathMtuProbe, QueryEncoder, ResolverState,
 };
 use crate::error::ClientError;
+use crate::idle::{IdlePolicy, IdlePoller, IdleTransition};
+use crate::metrics::{render_client_metrics, PathSample};
 use crate::net::{Sockaddr, SockaddrStorage};
 use crate::pacing::{cwnd_target_polls, inflight_packet_estimate};
 use crate::pinning::configure_pinned_certificate;
+use crate::shaping::{Jitter, ShapingConfig};
 use crate::streams::{
-    client_callback, drain_commands, drain_stream_data, handle_command, spawn_acceptor, ClientState,
+    client_callback, drain_commands, drain_stream_data, handle_command, spawn_acceptor,
+    ClientState, Command,
 };
-use slipstream_dns::{build_qname, encode_query, QueryParams, CLASS_IN, RR_TXT};
+use crate::transport::{ClientTransport, DnsTransport, TlsConfig};
+use slipstream_core::control::ControlHandle;
+use slipstream_core::metrics::{serve_metrics, MetricsSnapshot};
+use slipstream_core::pcap::{PcapConfig, PcapSink};
+use slipstream_core::qlog::spawn_qlog_pruner;
+use slipstream_core::udp_batch::{RecvBatch, SendBatch};
+use slipstream_dns::{LabelLayout, ResponseMode, MAX_UDP_RESPONSE_LEN};
+use slipstream_ffi::clock::{ClockSource, QuicClock};
 use slipstream_ffi::{
-    configure_quic_with_custom,
+    configure_quic_tracing, configure_quic_with_custom,
     picoquic::{
         picoquic_close, picoquic_cnx_t, picoquic_connection_id_t, picoquic_create,
-        picoquic_create_client_cnx, picoquic_current_time, picoquic_disable_keep_alive,
-        picoquic_enable_keep_alive, picoquic_enable_path_callbacks,
-        picoquic_enable_path_callbacks_default, picoquic_get_next_wake_delay,
-        picoquic_prepare_next_packet_ex, picoquic_set_callback, slipstream_has_ready_stream,
-        slipstream_is_flow_blocked, slipstream_mixed_cc_algorithm, slipstream_set_cc_override,
+        picoquic_create_client_cnx, picoquic_disable_keep_alive, picoquic_enable_keep_alive,
+        picoquic_enable_path_callbacks, picoquic_enable_path_callbacks_default,
+        picoquic_get_next_wake_delay, picoquic_prepare_next_packet_ex, picoquic_set_callback,
+        picoquic_set_padding_policy, slipstream_has_ready_stream, slipstream_is_flow_blocked,
+        slipstream_mixed_cc_algorithm, slipstream_set_cc_override,
         slipstream_set_default_path_mode, PICOQUIC_CONNECTION_ID_MAX_SIZE,
         PICOQUIC_MAX_PACKET_SIZE, PICOQUIC_PACKET_LOOP_RECV_MAX, PICOQUIC_PACKET_LOOP_SEND_MAX,
     },
-    socket_addr_to_storage, ClientConfig, QuicGuard, ResolverMode,
+    socket_addr_to_storage, ClientConfig, DnsTransportKind, QuicGuard, ResolverMode,
 };
+use std::collections::hash_map::RandomState;
 use std::ffi::CString;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::TcpListener as TokioTcpListener;
@@ -46,26 +65,274 @@ const SLIPSTREAM_ALPN: &str = "picoquic_sample";
 const SLIPSTREAM_SNI: &str = "test.example.com";
 const DNS_WAKE_DELAY_MAX_US: i64 = 10_000_000;
 const DNS_POLL_SLICE_US: u64 = 50_000;
+const METRICS_PUBLISH_INTERVAL_US: u64 = 1_000_000;
 
 pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
-    let domain_len = config.domain.len();
-    let mtu = compute_mtu(domain_len)?;
-    let mut resolvers = resolve_resolvers(config.resolvers, mtu, config.debug_poll)?;
-    if resolvers.is_empty() {
-        return Err(ClientError::new("At least one resolver is required"));
-    }
+    let pcap = match config.pcap {
+        Some(_) if config.dns_transport != DnsTransportKind::Udp => {
+            return Err(ClientError::new("--pcap requires --dns-transport udp"));
+        }
+        Some(path) => {
+            let sink = PcapSink::open(PcapConfig {
+                path: path.into(),
+                max_bytes: config.pcap_max_mb.saturating_mul(1024 * 1024),
+                max_files: config.pcap_files,
+            })
+            .map_err(|err| ClientError::new(format!("pcap {}: {}", path, err)))?;
+            info!("Capturing DNS traffic to {}", path);
+            Some(sink)
+        }
+        None => None,
+    };
+    let tls = match config.dns_transport {
+        DnsTransportKind::Tls => Some(TlsConfig::new(
+            config.dns_tls_name,
+            config.dns_tls_ca,
+            None,
+        )?),
+        DnsTransportKind::Https => Some(TlsConfig::new(
+            config.dns_tls_name,
+            config.dns_tls_ca,
+            Some(b"http/1.1"),
+        )?),
+        _ if config.dns_tls_name.is_some() || config.dns_tls_ca.is_some() => {
+            return Err(ClientError::new(
+                "--dns-tls-name and --dns-tls-ca require --dns-transport dot or doh",
+            ));
+        }
+        _ => None,
+    };
+    let (kind, gso) = (config.dns_transport, config.gso);
+    run_client_with(config, ClockSource::System, || {
+        ClientTransport::open(kind, tls.as_ref(), pcap.clone(), gso)
+    })
+    .await
+}
 
-    let udp = bind_udp_socket().await?;
-    let mut local_addr_storage = socket_addr_to_storage(udp.local_addr().map_err(map_io)?);
+/// Runs the client over transports from `open_transport`, one per QUIC connection.
+///
+/// `config.dns_transport` and the `--pcap` options are ignored; `open_transport`
+/// decides how queries travel.
+pub async fn run_client_with<T, F, Fut>(
+    config: &ClientConfig<'_>,
+    clock: ClockSource,
+    mut open_transport: F,
+) -> Result<i32, ClientError>
+where
+    T: DnsTransport,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ClientError>>,
+{
+    let layout = LabelLayout::new(config.label_len, config.sequence_label)
+        .map_err(|err| ClientError::new(err.to_string()))?;
+    let domains = DomainRotation::new(resolve_domains(config.domains)?)?;
+    let mut encoder = QueryEncoder::new(domains, layout)?;
+    let response_mode = if config.tolerant_responses {
+        ResponseMode::Tolerant
+    } else {
+        ResponseMode::Strict
+    };
+    let max_packet_len = match config.mtu {
+        Some(mtu) => encoder.enable_segmentation((mtu as usize).min(PICOQUIC_MAX_PACKET_SIZE))?,
+        None => encoder.max_payload_len(),
+    };
+    let mtu = compute_mtu(max_packet_len)?;
+    if max_packet_len > encoder.max_payload_len() {
+        info!(
+            "Segmenting QUIC packets up to {} bytes across DNS queries ({} bytes per query)",
+            mtu,
+            encoder.max_payload_len()
+        );
+    }
+    if config.pad_bucket > mtu {
+        return Err(ClientError::new(format!(
+            "Pad bucket {} exceeds the QUIC MTU of {} bytes",
+            config.pad_bucket, mtu
+        )));
+    }
+    let shaping = ShapingConfig::from_client_config(config);
+    // Resolve once up front so configuration errors surface before listening.
+    let mut resolvers = Some(prepare_resolvers(config, &encoder, mtu, &shaping)?);
+    if config.domains.len() > 1 {
+        info!("Rotating queries across {} domains", config.domains.len());
+    }
+    if shaping.is_enabled() || config.pad_bucket > 0 {
+        info!(
+            "Traffic shaping: poll_jitter_ms={} pad_bucket={} cover_interval_ms={} max_qps={}",
+            config.poll_jitter_ms, config.pad_bucket, config.cover_interval_ms, config.max_qps
+        );
+    }
 
     let (command_tx, mut command_rx) = mpsc::unbounded_channel();
     let data_notify = Arc::new(Notify::new());
-    let debug_streams = config.debug_streams;
     let listener = TokioTcpListener::bind(("0.0.0.0", config.tcp_listen_port))
         .await
         .map_err(map_io)?;
     spawn_acceptor(listener, command_tx.clone());
     info!("Listening on TCP port {}", config.tcp_listen_port);
+    let metrics = match config.metrics_listen {
+        Some(addr) => {
+            let listener = TokioTcpListener::bind(addr).await.map_err(map_io)?;
+            let snapshot = MetricsSnapshot::new();
+            tokio::spawn(serve_metrics(listener, snapshot.clone()));
+            info!("Serving metrics on http://{}/metrics", addr);
+            Some(snapshot)
+        }
+        None => None,
+    };
+    let control = match config.control_socket {
+        Some(path) => {
+            let control = ControlHandle::bind(Path::new(path))
+                .map_err(|err| ClientError::new(format!("Control socket {}: {}", path, err)))?;
+            info!("Control socket listening on {}", path);
+            control
+        }
+        None => ControlHandle::disabled(),
+    };
+    if let Some(dir) = config.qlog_dir {
+        spawn_qlog_pruner(dir.into(), config.qlog_max_mb.saturating_mul(1024 * 1024));
+        info!("Writing qlog traces to {}", dir);
+    }
+    let mut tunnel = Tunnel {
+        encoder,
+        response_mode,
+        mtu,
+        shaping,
+        jitter: Jitter::new(),
+        metrics,
+        control,
+        clock,
+    };
+    loop {
+        if let Some(metrics) = tunnel.metrics.as_ref() {
+            metrics.publish(render_client_metrics(&[], 0, false));
+        }
+        let first_stream = if config.lazy {
+            info!("Waiting for a TCP connection to open the tunnel");
+            match wait_for_stream(&mut command_rx, &mut tunnel.control).await {
+                Some(command) => Some(command),
+                None => return Ok(0),
+            }
+        } else {
+            None
+        };
+        let resolvers = match resolvers.take() {
+            Some(resolvers) => resolvers,
+            None => prepare_resolvers(config, &tunnel.encoder, mtu, &shaping)?,
+        };
+        let transport = open_transport().await?;
+        let end = run_connection(
+            config,
+            &mut tunnel,
+            resolvers,
+            &command_tx,
+            &mut command_rx,
+            &data_notify,
+            first_stream,
+            transport,
+        )
+        .await?;
+        if !config.lazy {
+            return Ok(0);
+        }
+        if end == ConnectionEnd::Closed {
+            warn!("Tunnel connection closed; reconnecting on the next TCP connection");
+        }
+    }
+}
+
+/// Query encoding and shaping state that outlives a single QUIC connection.
+struct Tunnel<'a> {
+    encoder: QueryEncoder<'a>,
+    response_mode: ResponseMode,
+    mtu: u32,
+    shaping: ShapingConfig,
+    jitter: Jitter,
+    metrics: Option<MetricsSnapshot>,
+    control: ControlHandle,
+    clock: ClockSource,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectionEnd {
+    /// The connection closed or failed.
+    Closed,
+    /// `--lazy` tore the connection down after it carried no streams for a while.
+    IdleTimeout,
+}
+
+fn prepare_resolvers(
+    config: &ClientConfig<'_>,
+    encoder: &QueryEncoder<'_>,
+    mtu: u32,
+    shaping: &ShapingConfig,
+) -> Result<Vec<ResolverState>, ClientError> {
+    let mut resolvers = resolve_resolvers(config.resolvers, mtu, shaping, config.debug_poll)?;
+    if resolvers.is_empty() {
+        return Err(ClientError::new("At least one resolver is required"));
+    }
+    let resolver_addrs: Vec<_> = resolvers.iter().map(|resolver| resolver.addr).collect();
+    encoder.domains().validate(&resolver_addrs)?;
+    if config.path_mtu_discovery {
+        let seed = RandomState::new().build_hasher().finish();
+        for (index, resolver) in resolvers.iter_mut().enumerate() {
+            let nonce = (seed as u32).wrapping_add((index as u32) << 16);
+            // Probe with the longest domain the resolver sees; validate() ensures one exists.
+            if let Some(domain) = encoder.domains().longest_for(resolver.addr) {
+                resolver.mtu_probe = Some(PathMtuProbe::new(domain, nonce));
+            }
+        }
+    }
+    Ok(resolvers)
+}
+
+/// Waits for the next accepted TCP connection, dropping leftovers of closed streams.
+///
+/// Control requests are answered in the meantime.
+async fn wait_for_stream(
+    command_rx: &mut mpsc::UnboundedReceiver<Command>,
+    control: &mut ControlHandle,
+) -> Option<Command> {
+    loop {
+        tokio::select! {
+            command = command_rx.recv() => match command {
+                Some(command @ Command::NewStream(_)) => return Some(command),
+                Some(_) => {}
+                None => return None,
+            },
+            call = control.next() => {
+                let result = handle_control_disconnected(&call.request);
+                call.respond(result);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_connection<T: DnsTransport>(
+    config: &ClientConfig<'_>,
+    tunnel: &mut Tunnel<'_>,
+    mut resolvers: Vec<ResolverState>,
+    command_tx: &mpsc::UnboundedSender<Command>,
+    command_rx: &mut mpsc::UnboundedReceiver<Command>,
+    data_notify: &Arc<Notify>,
+    first_stream: Option<Command>,
+    transport: T,
+) -> Result<ConnectionEnd, ClientError> {
+    let Tunnel {
+        encoder,
+        response_mode,
+        mtu,
+        shaping,
+        jitter,
+        metrics,
+        control,
+        clock,
+    } = tunnel;
+    let clock = QuicClock::new(*clock);
+    let (response_mode, mtu, shaping) = (*response_mode, *mtu, *shaping);
+    let mut local_addr_storage = socket_addr_to_storage(transport.local_addr().map_err(map_io)?);
+    let debug_streams = config.debug_streams;
 
     let alpn = CString::new(SLIPSTREAM_ALPN)
         .map_err(|_| ClientError::new("ALPN contains an unexpected null byte"))?;
@@ -78,15 +345,16 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
         None => None,
     };
 
+    let current_time = clock.now();
     let mut state = Box::new(ClientState::new(
-        command_tx,
+        command_tx.clone(),
         data_notify.clone(),
         debug_streams,
+        current_time,
     ));
     let state_ptr: *mut ClientState = &mut *state;
     let _state = state;
 
-    let current_time = unsafe { picoquic_current_time() };
     let quic = unsafe {
         picoquic_create(
             8,
@@ -100,7 +368,7 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
             std::ptr::null_mut(),
             std::ptr::null(),
             current_time,
-            std::ptr::null_mut(),
+            clock.simulated_time_ptr(),
             std::ptr::null(),
             std::ptr::null(),
             0,
@@ -122,6 +390,12 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
             .map(|value| value.as_ptr())
             .unwrap_or(std::ptr::null());
         slipstream_set_cc_override(override_ptr);
+        if config.pad_bucket > 0 {
+            // Pad every packet up to the next multiple of the bucket size.
+            picoquic_set_padding_policy(quic, config.pad_bucket, config.pad_bucket);
+        }
+        configure_quic_tracing(quic, config.qlog_dir, config.keylog_file)
+            .map_err(ClientError::new)?;
     }
     unsafe {
         slipstream_set_default_path_mode(resolver_mode_to_c(resolvers[0].mode));
@@ -152,44 +426,72 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
     unsafe {
         picoquic_set_callback(cnx, Some(client_callback), state_ptr as *mut _);
         picoquic_enable_path_callbacks(cnx, 1);
-        if config.keep_alive_interval > 0 {
-            picoquic_enable_keep_alive(cnx, config.keep_alive_interval as u64 * 1000);
-        } else {
-            picoquic_disable_keep_alive(cnx);
-        }
     }
-
-    if config.gso {
-        warn!("GSO is not implemented in the Rust client loop yet.");
+    set_keep_alive(cnx, config.keep_alive_interval);
+    if let Some(command) = first_stream {
+        handle_command(cnx, state_ptr, command);
     }
 
     let mut dns_id = 1u16;
-    let mut recv_buf = vec![0u8; 65535];
     let mut send_buf = vec![0u8; PICOQUIC_MAX_PACKET_SIZE];
     let packet_loop_send_max = loop_burst_total(&resolvers, PICOQUIC_PACKET_LOOP_SEND_MAX);
     let packet_loop_recv_max = loop_burst_total(&resolvers, PICOQUIC_PACKET_LOOP_RECV_MAX);
+    // Queries never advertise more EDNS(0) payload than this, so no valid
+    // answer is longer; a longer datagram is truncated and fails to decode.
+    let mut recv_batch = RecvBatch::new(packet_loop_recv_max, MAX_UDP_RESPONSE_LEN);
+    // Queries of one loop iteration, sent together once polls are added.
+    let mut send_batch = SendBatch::new();
     let mut zero_send_loops = 0u64;
     let mut zero_send_with_streams = 0u64;
+    let mut idle = IdlePoller::new(IdlePolicy::from_client_config(config));
+    let lazy_idle_timeout_us = config.lazy_idle_timeout_secs.saturating_mul(1_000_000);
+    let mut no_streams_since: Option<u64> = None;
+    let mut end = ConnectionEnd::Closed;
+    let mut last_metrics_at = 0u64;
 
     loop {
-        let current_time = unsafe { picoquic_current_time() };
-        drain_commands(cnx, state_ptr, &mut command_rx);
-        drain_stream_data(cnx, state_ptr);
+        let current_time = clock.now();
+        // Streams accepted once the connection is going away stay queued for
+        // the next one instead of being handed to this one.
         let closing = unsafe { (*state_ptr).is_closing() };
-        if closing {
+        if closing || end == ConnectionEnd::IdleTimeout {
             break;
         }
+        drain_commands(cnx, state_ptr, command_rx);
+        drain_stream_data(cnx, state_ptr);
 
         let ready = unsafe { (*state_ptr).is_ready() };
         if ready {
             add_paths(cnx, &mut resolvers)?;
             for resolver in resolvers.iter_mut() {
                 if resolver.added {
+                    update_path_mtu(resolver, encoder, mtu);
                     apply_path_mode(cnx, resolver)?;
                 }
             }
         }
         drain_path_events(cnx, &mut resolvers, state_ptr);
+        if ready {
+            send_mtu_probes(&transport, &mut dns_id, &mut resolvers, current_time).await?;
+            let last_activity_at = unsafe { (*state_ptr).last_activity_at() };
+            match idle.update(last_activity_at, current_time) {
+                Some(IdleTransition::Entered) => {
+                    debug!("No stream data for a while; backing off polls");
+                    // Idle polls keep the connection alive instead.
+                    set_keep_alive(cnx, 0);
+                }
+                Some(IdleTransition::Exited) => {
+                    debug!("Stream activity; resuming the active poll cadence");
+                    set_keep_alive(cnx, config.keep_alive_interval);
+                }
+                None => {}
+            }
+        }
+        if idle.is_idle() {
+            for resolver in resolvers.iter_mut() {
+                resolver.pending_polls = 0;
+            }
+        }
 
         for resolver in resolvers.iter_mut() {
             if resolver.mode == ResolverMode::Authoritative {
@@ -222,7 +524,15 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                 }
                 ResolverMode::Recursive => resolver.pending_polls,
             };
-            if pending_for_sleep > 0 {
+            if pending_for_sleep > 0 && !idle.is_idle() {
+                has_work = true;
+            }
+            // Keep the short slice while MTU probes wait for answers or timeouts.
+            if resolver
+                .mtu_probe
+                .as_ref()
+                .is_some_and(|probe| !probe.is_done())
+            {
                 has_work = true;
             }
             if resolver.mode == ResolverMode::Authoritative
@@ -230,13 +540,29 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
             {
                 has_work = true;
             }
+            if resolver.shaping.has_deferred() {
+                has_work = true;
+            }
         }
         // Avoid a tight poll loop when idle, but keep the short slice during active transfers.
-        let timeout_us = if has_work {
+        let mut timeout_us = if has_work {
             delay_us.clamp(1, DNS_POLL_SLICE_US)
         } else {
             delay_us.max(1)
         };
+        if let Some(since) = no_streams_since {
+            let deadline = since.saturating_add(lazy_idle_timeout_us);
+            timeout_us = timeout_us.min(deadline.saturating_sub(current_time).max(1));
+        }
+        if let Some(wakeup) = idle.next_wakeup() {
+            timeout_us = timeout_us.min(wakeup.saturating_sub(current_time).max(1));
+        }
+        // Wake up for held-back poll rounds and cover polls.
+        for resolver in resolvers.iter().filter(|resolver| resolver.added) {
+            if let Some(wakeup) = resolver.shaping.next_wakeup(current_time) {
+                timeout_us = timeout_us.min(wakeup - current_time);
+            }
+        }
         let timeout = Duration::from_micros(timeout_us);
 
         tokio::select! {
@@ -246,24 +572,31 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                 }
             }
             _ = data_notify.notified() => {}
-            recv = udp.recv_from(&mut recv_buf) => {
+            call = control.next() => {
+                let mut ctx = ControlContext {
+                    cnx,
+                    state: unsafe { &mut *state_ptr },
+                    resolvers: &mut resolvers,
+                    encoder,
+                    mtu,
+                    shaping: &shaping,
+                    debug_poll: config.debug_poll,
+                    idle: idle.is_idle(),
+                };
+                let result = handle_control(&call.request, &mut ctx);
+                call.respond(result);
+            }
+            recv = transport.recv_batch(&mut recv_batch) => {
                 match recv {
-                    Ok((size, peer)) => {
+                    Ok(()) => {
                         let mut response_ctx = DnsResponseContext {
                             quic,
                             local_addr_storage: &local_addr_storage,
                             resolvers: &mut resolvers,
+                            response_mode,
                         };
-                        handle_dns_response(&recv_buf[..size], peer, &mut response_ctx)?;
-                        for _ in 1..packet_loop_recv_max {
-                            match udp.try_recv_from(&mut recv_buf) {
-                                Ok((size, peer)) => {
-                                    handle_dns_response(&recv_buf[..size], peer, &mut response_ctx)?;
-                                }
-                                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
-                                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
-                                Err(err) => return Err(map_io(err)),
-                            }
+                        for (response, peer) in recv_batch.iter() {
+                            handle_dns_response(response, peer, &mut response_ctx)?;
                         }
                     }
                     Err(err) => {
@@ -279,12 +612,41 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
             _ = sleep(timeout) => {}
         }
 
-        drain_commands(cnx, state_ptr, &mut command_rx);
+        drain_commands(cnx, state_ptr, command_rx);
         drain_stream_data(cnx, state_ptr);
         drain_path_events(cnx, &mut resolvers, state_ptr);
 
+        if config.lazy {
+            let now = clock.now();
+            if unsafe { (*state_ptr).streams_len() } > 0 {
+                no_streams_since = None;
+            } else {
+                let since = *no_streams_since.get_or_insert(now);
+                if now.saturating_sub(since) >= lazy_idle_timeout_us {
+                    info!(
+                        "No TCP connections for {} s; closing the tunnel",
+                        config.lazy_idle_timeout_secs
+                    );
+                    // The send loop below flushes the close; the next iteration exits.
+                    unsafe {
+                        picoquic_close(cnx, 0);
+                    }
+                    no_streams_since = None;
+                    end = ConnectionEnd::IdleTimeout;
+                }
+            }
+        }
+
+        let flush_time = clock.now();
+        for resolver in resolvers.iter_mut() {
+            while let Some(query) = resolver.shaping.next_deferred(flush_time) {
+                send_batch.push(&query, resolver.addr);
+                resolver.debug.queries_sent = resolver.debug.queries_sent.saturating_add(1);
+            }
+        }
+
         for _ in 0..packet_loop_send_max {
-            let current_time = unsafe { picoquic_current_time() };
+            let current_time = clock.now();
             let mut send_length: libc::size_t = 0;
             let mut addr_to: SockaddrStorage = unsafe { std::mem::zeroed() };
             let mut addr_from: SockaddrStorage = unsafe { std::mem::zeroed() };
@@ -333,57 +695,77 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
             if addr_to.ss_family == 0 {
                 break;
             }
-            if let Ok(dest) = sockaddr_storage_to_socket_addr(&addr_to) {
-                let dest = normalize_dual_stack_addr(dest);
-                if let Some(resolver) = find_resolver_by_addr_mut(&mut resolvers, dest) {
-                    resolver.local_addr_storage = Some(unsafe { std::ptr::read(&addr_from) });
-                    resolver.debug.send_packets = resolver.debug.send_packets.saturating_add(1);
-                    resolver.debug.send_bytes =
-                        resolver.debug.send_bytes.saturating_add(send_length as u64);
-                }
-            }
-
-            let qname = build_qname(&send_buf[..send_length], config.domain)
-                .map_err(|err| ClientError::new(err.to_string()))?;
-            let params = QueryParams {
-                id: dns_id,
-                qname: &qname,
-                qtype: RR_TXT,
-                qclass: CLASS_IN,
-                rd: true,
-                cd: false,
-                qdcount: 1,
-                is_query: true,
-            };
-            dns_id = dns_id.wrapping_add(1);
-            let packet = encode_query(&params).map_err(|err| ClientError::new(err.to_string()))?;
-
             let dest = sockaddr_storage_to_socket_addr(&addr_to)?;
             let dest = normalize_dual_stack_addr(dest);
+            let mut resolver = find_resolver_by_addr_mut(&mut resolvers, dest);
+            if let Some(resolver) = resolver.as_deref_mut() {
+                resolver.local_addr_storage = Some(unsafe { std::ptr::read(&addr_from) });
+                resolver.debug.send_packets = resolver.debug.send_packets.saturating_add(1);
+                resolver.debug.send_bytes =
+                    resolver.debug.send_bytes.saturating_add(send_length as u64);
+                resolver.note_sent_packet(&send_buf[..send_length]);
+            }
+
+            let queries = encoder.encode(&mut dns_id, dest, &send_buf[..send_length])?;
             local_addr_storage = addr_from;
-            udp.send_to(&packet, dest).await.map_err(map_io)?;
+            for packet in queries {
+                if let Some(resolver) = resolver.as_deref_mut() {
+                    // Keep queries in order behind any already waiting for QPS budget.
+                    if resolver.shaping.has_deferred() || !resolver.shaping.admit(current_time) {
+                        resolver.shaping.defer(packet);
+                        continue;
+                    }
+                }
+                send_batch.push(&packet, dest);
+                if let Some(resolver) = resolver.as_deref_mut() {
+                    resolver.debug.queries_sent = resolver.debug.queries_sent.saturating_add(1);
+                }
+            }
         }
 
         let has_ready_stream = unsafe { slipstream_has_ready_stream(cnx) != 0 };
         let flow_blocked = unsafe { slipstream_is_flow_blocked(cnx) != 0 };
+        let idle_streams = unsafe { (*state_ptr).streams_len() } == 0;
+        let poll_time = clock.now();
+        let idle_poll_due = idle.poll_due(poll_time);
         for resolver in resolvers.iter_mut() {
             if !refresh_resolver_path(cnx, resolver) {
                 continue;
             }
-            match resolver.mode {
-                ResolverMode::Authoritative => {
-                    let quality = fetch_path_quality(cnx, resolver);
-                    let snapshot = resolver.last_pacing_snapshot;
-                    let pacing_target = snapshot
-                        .map(|snapshot| snapshot.target_inflight)
-                        .unwrap_or_else(|| cwnd_target_polls(quality.cwin, mtu));
-                    let inflight_packets = inflight_packet_estimate(quality.bytes_in_transit, mtu);
-                    let mut poll_deficit = pacing_target.saturating_sub(inflight_packets);
-                    if has_ready_stream && !flow_blocked {
-                        poll_deficit = 0;
+            if idle.is_idle() {
+                resolver.pending_polls = 0;
+                if idle_poll_due {
+                    let mut to_send = 1;
+                    send_poll_queries(
+                        cnx,
+                        &mut send_batch,
+                        encoder,
+                        &mut local_addr_storage,
+                        &mut dns_id,
+                        resolver,
+                        &mut to_send,
+                        &mut send_buf,
+                    )?;
+                    if to_send == 0 {
+                        resolver.debug.idle_polls = resolver.debug.idle_polls.saturating_add(1);
                     }
-                    if poll_deficit > 0 && resolver.debug.enabled {
-                        debug!(
+                }
+            } else {
+                match resolver.mode {
+                    ResolverMode::Authoritative => {
+                        let quality = fetch_path_quality(cnx, resolver);
+                        let snapshot = resolver.last_pacing_snapshot;
+                        let pacing_target = snapshot
+                            .map(|snapshot| snapshot.target_inflight)
+                            .unwrap_or_else(|| cwnd_target_polls(quality.cwin, mtu));
+                        let inflight_packets =
+                            inflight_packet_estimate(quality.bytes_in_transit, mtu);
+                        let mut poll_deficit = pacing_target.saturating_sub(inflight_packets);
+                        if has_ready_stream && !flow_blocked {
+                            poll_deficit = 0;
+                        }
+                        if poll_deficit > 0 && resolver.debug.enabled {
+                            debug!(
                             "cc_state: {} cwnd={} in_transit={} rtt_us={} flow_blocked={} deficit={}",
                             resolver.label(),
                             quality.cwin,
@@ -392,65 +774,87 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                             flow_blocked,
                             poll_deficit
                         );
-                    }
-                    if poll_deficit > 0 {
-                        let burst_max = path_poll_burst_max(resolver);
-                        let mut to_send = poll_deficit.min(burst_max);
-                        send_poll_queries(
-                            cnx,
-                            &udp,
-                            config,
-                            &mut local_addr_storage,
-                            &mut dns_id,
-                            resolver,
-                            &mut to_send,
-                            &mut send_buf,
-                        )
-                        .await?;
-                    }
-                }
-                ResolverMode::Recursive => {
-                    resolver.last_pacing_snapshot = None;
-                    if resolver.pending_polls > 0 {
-                        let burst_max = path_poll_burst_max(resolver);
-                        if resolver.pending_polls > burst_max {
-                            let mut to_send = burst_max;
+                        }
+                        if poll_deficit > 0 && resolver.shaping.poll_due(poll_time) {
+                            let burst_max = path_poll_burst_max(resolver);
+                            let mut to_send = poll_deficit.min(burst_max);
                             send_poll_queries(
                                 cnx,
-                                &udp,
-                                config,
+                                &mut send_batch,
+                                encoder,
                                 &mut local_addr_storage,
                                 &mut dns_id,
                                 resolver,
                                 &mut to_send,
                                 &mut send_buf,
-                            )
-                            .await?;
-                            resolver.pending_polls = resolver
-                                .pending_polls
-                                .saturating_sub(burst_max)
-                                .saturating_add(to_send);
-                        } else {
-                            let mut pending = resolver.pending_polls;
-                            send_poll_queries(
-                                cnx,
-                                &udp,
-                                config,
-                                &mut local_addr_storage,
-                                &mut dns_id,
-                                resolver,
-                                &mut pending,
-                                &mut send_buf,
-                            )
-                            .await?;
-                            resolver.pending_polls = pending;
+                            )?;
+                            resolver.shaping.polled(&shaping, jitter, poll_time);
+                        }
+                    }
+                    ResolverMode::Recursive => {
+                        resolver.last_pacing_snapshot = None;
+                        if resolver.pending_polls > 0 && resolver.shaping.poll_due(poll_time) {
+                            let burst_max = path_poll_burst_max(resolver);
+                            if resolver.pending_polls > burst_max {
+                                let mut to_send = burst_max;
+                                send_poll_queries(
+                                    cnx,
+                                    &mut send_batch,
+                                    encoder,
+                                    &mut local_addr_storage,
+                                    &mut dns_id,
+                                    resolver,
+                                    &mut to_send,
+                                    &mut send_buf,
+                                )?;
+                                resolver.pending_polls = resolver
+                                    .pending_polls
+                                    .saturating_sub(burst_max)
+                                    .saturating_add(to_send);
+                            } else {
+                                let mut pending = resolver.pending_polls;
+                                send_poll_queries(
+                                    cnx,
+                                    &mut send_batch,
+                                    encoder,
+                                    &mut local_addr_storage,
+                                    &mut dns_id,
+                                    resolver,
+                                    &mut pending,
+                                    &mut send_buf,
+                                )?;
+                                resolver.pending_polls = pending;
+                            }
+                            resolver.shaping.polled(&shaping, jitter, poll_time);
                         }
                     }
                 }
             }
+            if !idle_streams {
+                resolver.shaping.reset_cover();
+            } else if resolver.shaping.cover_due(&shaping, jitter, poll_time) {
+                let mut to_send = 1;
+                send_poll_queries(
+                    cnx,
+                    &mut send_batch,
+                    encoder,
+                    &mut local_addr_storage,
+                    &mut dns_id,
+                    resolver,
+                    &mut to_send,
+                    &mut send_buf,
+                )?;
+                if to_send == 0 {
+                    resolver.shaping.counters.cover_polls =
+                        resolver.shaping.counters.cover_polls.saturating_add(1);
+                }
+            }
         }
 
-        let report_time = unsafe { picoquic_current_time() };
+        transport.send_batch(&send_batch).await.map_err(map_io)?;
+        send_batch.clear();
+
+        let report_time = clock.now();
         let streams_len = unsafe { (*state_ptr).streams_len() };
         let (enqueued_bytes, last_enqueue_at) = unsafe { (*state_ptr).debug_snapshot() };
         for resolver in resolvers.iter_mut() {
@@ -480,13 +884,45 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                 pending_for_debug,
                 inflight_polls,
                 resolver.last_pacing_snapshot,
+                idle.interval_us(),
             );
         }
+        if let Some(metrics) = metrics.as_ref() {
+            if report_time.saturating_sub(last_metrics_at) >= METRICS_PUBLISH_INTERVAL_US {
+                last_metrics_at = report_time;
+                let paths: Vec<_> = resolvers
+                    .iter()
+                    .map(|resolver| PathSample {
+                        resolver,
+                        quality: resolver.added.then(|| fetch_path_quality(cnx, resolver)),
+                    })
+                    .collect();
+                let connected = unsafe { (*state_ptr).is_ready() };
+                metrics.publish(render_client_metrics(&paths, streams_len, connected));
+            }
+        }
     }
 
     unsafe {
         picoquic_close(cnx, 0);
     }
 
-    Ok(0)
+    for resolver in &resolvers {
+        let summary = resolver.response_errors.summary();
+        if !summary.is_empty() {
+            info!(resolver = %resolver.addr, errors = %summary, "Resolver response errors");
+        }
+    }
+
+    Ok(end)
+}
+
+fn set_keep_alive(cnx: *mut picoquic_cnx_t, interval: usize) {
+    unsafe {
+        if interval > 0 {
+            picoquic_enable_keep_alive(cnx, interval as u64 * 1000);
+        } else {
+            picoquic_disable_keep_alive(cnx);
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use crate::dns::{
     normalize_dual_stack_addr, refresh_resolver_path, reset_resolver_path, resolver_mode_to_c,
-    sockaddr_storage_to_socket_addr, ResolverState,
+    sockaddr_storage_to_socket_addr, QueryEncoder, ResolverState,
 };
 use crate::error::ClientError;
 use crate::net::SockaddrStorage;
@@ -8,12 +8,14 @@ use crate::streams::{ClientState, PathEvent};
 use slipstream_ffi::picoquic::{
     picoquic_cnx_t, picoquic_get_default_path_quality, picoquic_get_path_addr,
     picoquic_get_path_quality, slipstream_get_path_id_from_unique, slipstream_set_path_ack_delay,
-    slipstream_set_path_mode, PICOQUIC_PACKET_LOOP_SEND_MAX,
+    slipstream_set_path_mode, slipstream_set_path_mtu, PICOQUIC_PACKET_LOOP_SEND_MAX,
 };
 use slipstream_ffi::ResolverMode;
 use std::net::SocketAddr;
+use tracing::info;
 
 const AUTHORITATIVE_LOOP_MULTIPLIER: usize = 4;
+const MAX_QNAME_LEN: usize = 253;
 
 pub(crate) fn apply_path_mode(
     cnx: *mut picoquic_cnx_t,
@@ -26,10 +28,39 @@ pub(crate) fn apply_path_mode(
         slipstream_set_path_mode(cnx, resolver.path_id, resolver_mode_to_c(resolver.mode));
         let disable_ack_delay = matches!(resolver.mode, ResolverMode::Authoritative) as libc::c_int;
         slipstream_set_path_ack_delay(cnx, resolver.path_id, disable_ack_delay);
+        if let Some(path_mtu) = resolver.path_mtu {
+            slipstream_set_path_mtu(cnx, resolver.path_id, path_mtu);
+        }
     }
     Ok(())
 }
 
+/// Caps the path MTU once MTU discovery finds that the resolver drops long QNAMEs.
+///
+/// Full-length QNAMEs (and with them segmentation) keep the global MTU.
+pub(crate) fn update_path_mtu(resolver: &mut ResolverState, encoder: &QueryEncoder<'_>, mtu: u32) {
+    let Some(probe) = resolver.mtu_probe.as_mut() else {
+        return;
+    };
+    if resolver.path_mtu.is_none() {
+        if let Some(max_qname_len) = probe.max_qname_len() {
+            let path_mtu = encoder.max_payload_len_for_name_len(max_qname_len) as u32;
+            if max_qname_len < MAX_QNAME_LEN && path_mtu > 0 && path_mtu < mtu {
+                resolver.path_mtu = Some(path_mtu);
+            }
+        }
+    }
+    if probe.take_finished() {
+        info!(
+            resolver = %resolver.addr,
+            max_qname = probe.max_qname_len(),
+            max_response = probe.max_response_len(),
+            path_mtu = resolver.path_mtu.unwrap_or(mtu),
+            "MTU discovery finished"
+        );
+    }
+}
+
 pub(crate) fn fetch_path_quality(
     cnx: *mut picoquic_cnx_t,
     resolver: &ResolverState,
@@ -1,42 +1,39 @@
 use crate::error::ClientError;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::net::SocketAddr;
 
+#[cfg(windows)]
+use std::net::{Ipv4Addr, SocketAddrV4};
 #[cfg(not(windows))]
 use std::net::{Ipv6Addr, SocketAddrV6};
 use tokio::net::UdpSocket as TokioUdpSocket;
 
-pub(crate) fn compute_mtu(domain_len: usize) -> Result<u32, ClientError> {
-    if domain_len >= 240 {
+/// Derives the QUIC MTU from the payload capacity of a single query.
+pub(crate) fn compute_mtu(max_payload_len: usize) -> Result<u32, ClientError> {
+    if max_payload_len == 0 {
         return Err(ClientError::new(
             "Domain name is too long for DNS transport",
         ));
     }
-    let mut mtu = ((240.0 - domain_len as f64) / 1.6) as u32;
+    let mtu = u32::try_from(max_payload_len).unwrap_or(u32::MAX);
+    // Windows UDP send can fail with WSAEMSGSIZE; keep a conservative cap.
     #[cfg(windows)]
-    {
-        // Windows UDP send can fail with WSAEMSGSIZE; keep a conservative cap.
-        mtu = mtu.min(512);
-    }
-    if mtu == 0 {
-        return Err(ClientError::new(
-            "MTU computed to zero; check domain length",
-        ));
-    }
+    let mtu = mtu.min(512);
     Ok(mtu)
 }
 
 pub(crate) async fn bind_udp_socket() -> Result<TokioUdpSocket, ClientError> {
-    #[cfg(windows)]
-    {
-        let bind_v4 = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
-        return TokioUdpSocket::bind(bind_v4).await.map_err(map_io);
-    }
+    TokioUdpSocket::bind(unspecified_addr())
+        .await
+        .map_err(map_io)
+}
 
+/// Wildcard address the client binds to; dual-stack except on Windows.
+pub(crate) fn unspecified_addr() -> SocketAddr {
+    #[cfg(windows)]
+    let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
     #[cfg(not(windows))]
-    {
-        let bind_v6 = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0));
-        return TokioUdpSocket::bind(bind_v6).await.map_err(map_io);
-    }
+    let addr = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0));
+    addr
 }
 
 pub(crate) fn map_io(err: std::io::Error) -> ClientError {
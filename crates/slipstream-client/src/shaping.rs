@@ -0,0 +1,313 @@
+use slipstream_ffi::ClientConfig;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+
+// Deferred query bound; see docs/config.md for details.
+const MAX_DEFERRED_QUERIES: usize = 256;
+
+/// Opt-in traffic shaping knobs; zero disables each one.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ShapingConfig {
+    /// Upper bound of the random delay between poll rounds of a path.
+    pub(crate) poll_jitter_us: u64,
+    /// Mean spacing of cover polls on idle paths.
+    pub(crate) cover_interval_us: u64,
+    /// Per-resolver query rate cap.
+    pub(crate) max_qps: u32,
+}
+
+impl ShapingConfig {
+    pub(crate) fn from_client_config(config: &ClientConfig<'_>) -> Self {
+        Self {
+            poll_jitter_us: config.poll_jitter_ms.saturating_mul(1_000),
+            cover_interval_us: config.cover_interval_ms.saturating_mul(1_000),
+            max_qps: config.max_qps,
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.poll_jitter_us > 0 || self.cover_interval_us > 0 || self.max_qps > 0
+    }
+}
+
+/// Shaping activity on one path, reported with `--debug-poll`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ShapingCounters {
+    pub(crate) cover_polls: u64,
+    pub(crate) jitter_waits: u64,
+    pub(crate) rate_limited: u64,
+    pub(crate) deferred: u64,
+    pub(crate) dropped: u64,
+}
+
+impl ShapingCounters {
+    /// Non-zero counters as `name=count` pairs, or an empty string.
+    pub(crate) fn summary(&self) -> String {
+        [
+            ("cover_polls", self.cover_polls),
+            ("jitter_waits", self.jitter_waits),
+            ("rate_limited", self.rate_limited),
+            ("deferred", self.deferred),
+            ("dropped", self.dropped),
+        ]
+        .iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(name, count)| format!("{}={}", name, count))
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+}
+
+/// Per-resolver shaping state: poll timing, cover schedule and the QPS budget.
+pub(crate) struct PathShaping {
+    limiter: Option<QpsLimiter>,
+    deferred: VecDeque<Vec<u8>>,
+    next_poll_at: u64,
+    next_cover_at: u64,
+    pub(crate) counters: ShapingCounters,
+}
+
+impl PathShaping {
+    pub(crate) fn new(config: &ShapingConfig) -> Self {
+        Self {
+            limiter: (config.max_qps > 0).then(|| QpsLimiter::new(config.max_qps)),
+            deferred: VecDeque::new(),
+            next_poll_at: 0,
+            next_cover_at: 0,
+            counters: ShapingCounters::default(),
+        }
+    }
+
+    /// Whether a poll round may start; counts rounds held back by jitter.
+    pub(crate) fn poll_due(&mut self, now: u64) -> bool {
+        if now >= self.next_poll_at {
+            return true;
+        }
+        self.counters.jitter_waits = self.counters.jitter_waits.saturating_add(1);
+        false
+    }
+
+    /// Schedules the next poll round after a random delay.
+    pub(crate) fn polled(&mut self, config: &ShapingConfig, jitter: &mut Jitter, now: u64) {
+        if config.poll_jitter_us > 0 {
+            self.next_poll_at = now.saturating_add(jitter.below(config.poll_jitter_us));
+        }
+    }
+
+    /// Whether an idle path is due for a cover poll; schedules the one after it.
+    pub(crate) fn cover_due(
+        &mut self,
+        config: &ShapingConfig,
+        jitter: &mut Jitter,
+        now: u64,
+    ) -> bool {
+        if config.cover_interval_us == 0 {
+            return false;
+        }
+        let due = self.next_cover_at != 0 && now >= self.next_cover_at;
+        if due || self.next_cover_at == 0 {
+            self.next_cover_at = now.saturating_add(jitter.around(config.cover_interval_us));
+        }
+        due
+    }
+
+    /// Pushes the cover schedule back while the path carries real traffic.
+    pub(crate) fn reset_cover(&mut self) {
+        self.next_cover_at = 0;
+    }
+
+    /// Earliest future time a held-back poll round or cover poll needs the loop.
+    pub(crate) fn next_wakeup(&self, now: u64) -> Option<u64> {
+        [self.next_poll_at, self.next_cover_at]
+            .into_iter()
+            .filter(|at| *at > now)
+            .min()
+    }
+
+    pub(crate) fn has_deferred(&self) -> bool {
+        !self.deferred.is_empty()
+    }
+
+    /// Takes one query from the QPS budget; counts queries held back.
+    pub(crate) fn admit(&mut self, now: u64) -> bool {
+        let Some(limiter) = self.limiter.as_mut() else {
+            return true;
+        };
+        if limiter.try_take(now) {
+            return true;
+        }
+        self.counters.rate_limited = self.counters.rate_limited.saturating_add(1);
+        false
+    }
+
+    /// Queues a query the budget could not cover; drops the oldest when full.
+    ///
+    /// Dropped queries carry QUIC packets, which QUIC recovers like any other loss.
+    pub(crate) fn defer(&mut self, query: Vec<u8>) {
+        if self.deferred.len() >= MAX_DEFERRED_QUERIES {
+            self.deferred.pop_front();
+            self.counters.dropped = self.counters.dropped.saturating_add(1);
+        }
+        self.deferred.push_back(query);
+        self.counters.deferred = self.counters.deferred.saturating_add(1);
+    }
+
+    /// Next deferred query the budget now covers.
+    pub(crate) fn next_deferred(&mut self, now: u64) -> Option<Vec<u8>> {
+        if self.deferred.is_empty() {
+            return None;
+        }
+        let limiter = self.limiter.as_mut()?;
+        if !limiter.try_take(now) {
+            return None;
+        }
+        self.deferred.pop_front()
+    }
+}
+
+/// Small xorshift generator for timing jitter; not for anything cryptographic.
+pub(crate) struct Jitter {
+    state: u64,
+}
+
+impl Jitter {
+    pub(crate) fn new() -> Self {
+        Self::with_seed(RandomState::new().build_hasher().finish())
+    }
+
+    fn with_seed(seed: u64) -> Self {
+        Self { state: seed | 1 }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    /// Uniform value in `0..bound`, or 0 when `bound` is 0.
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return 0;
+        }
+        self.next_u64() % bound
+    }
+
+    /// Uniform value in `[mean / 2, mean * 3 / 2)`.
+    pub(crate) fn around(&mut self, mean: u64) -> u64 {
+        mean / 2 + self.below(mean)
+    }
+}
+
+/// Token bucket holding up to a quarter second of queries.
+pub(crate) struct QpsLimiter {
+    rate_per_us: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: u64,
+}
+
+impl QpsLimiter {
+    pub(crate) fn new(max_qps: u32) -> Self {
+        let burst = (max_qps as f64 / 4.0).max(1.0);
+        Self {
+            rate_per_us: max_qps as f64 / 1_000_000.0,
+            burst,
+            tokens: burst,
+            last_refill: 0,
+        }
+    }
+
+    fn refill(&mut self, now: u64) {
+        if self.last_refill != 0 {
+            let elapsed = now.saturating_sub(self.last_refill) as f64;
+            self.tokens = (self.tokens + elapsed * self.rate_per_us).min(self.burst);
+        }
+        self.last_refill = now;
+    }
+
+    /// Takes one query's worth of budget if available.
+    pub(crate) fn try_take(&mut self, now: u64) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Jitter, PathShaping, QpsLimiter, ShapingConfig, MAX_DEFERRED_QUERIES};
+
+    #[test]
+    fn limiter_caps_rate_after_burst() {
+        let mut limiter = QpsLimiter::new(40);
+        let mut now = 1;
+        let burst = (0..100).filter(|_| limiter.try_take(now)).count();
+        assert_eq!(burst, 10);
+        let mut sent = 0;
+        for _ in 0..1_000 {
+            now += 1_000;
+            if limiter.try_take(now) {
+                sent += 1;
+            }
+        }
+        assert!((39..=41).contains(&sent), "{}", sent);
+    }
+
+    #[test]
+    fn jitter_stays_in_range() {
+        let mut jitter = Jitter::with_seed(42);
+        assert_eq!(jitter.below(0), 0);
+        for _ in 0..1_000 {
+            assert!(jitter.below(20_000) < 20_000);
+            let value = jitter.around(1_000);
+            assert!((500..1_500).contains(&value));
+        }
+    }
+
+    #[test]
+    fn defers_and_drops_over_budget() {
+        let config = ShapingConfig {
+            max_qps: 4,
+            ..ShapingConfig::default()
+        };
+        let mut shaping = PathShaping::new(&config);
+        let now = 1;
+        assert!(shaping.admit(now));
+        assert!(!shaping.admit(now));
+        for index in 0..=MAX_DEFERRED_QUERIES {
+            shaping.defer(vec![index as u8]);
+        }
+        assert_eq!(shaping.counters.rate_limited, 1);
+        assert_eq!(shaping.counters.dropped, 1);
+        assert_eq!(shaping.next_deferred(now), None);
+        // A quarter second buys one more query at 4 QPS; the oldest was dropped.
+        assert_eq!(shaping.next_deferred(now + 250_000), Some(vec![1]));
+        assert!(shaping.has_deferred());
+    }
+
+    #[test]
+    fn schedules_cover_polls_around_interval() {
+        let config = ShapingConfig {
+            cover_interval_us: 1_000_000,
+            ..ShapingConfig::default()
+        };
+        let mut jitter = Jitter::with_seed(7);
+        let mut shaping = PathShaping::new(&config);
+        assert!(!shaping.cover_due(&config, &mut jitter, 1));
+        let next = shaping.next_wakeup(1).expect("cover scheduled");
+        assert!((500_001..1_500_001).contains(&next));
+        assert!(!shaping.cover_due(&config, &mut jitter, next - 1));
+        assert!(shaping.cover_due(&config, &mut jitter, next));
+        shaping.reset_cover();
+        assert_eq!(shaping.next_wakeup(next), None);
+    }
+}
@@ -0,0 +1,382 @@
+use crate::error::ClientError;
+use crate::runtime::{map_io, run_client};
+use serde_json::{json, Value};
+use slipstream_core::control::control_request;
+use slipstream_core::diagnostics::DiagnosticRequest;
+use slipstream_ffi::ClientConfig;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, timeout_at, Instant};
+
+// The first echo also waits for the QUIC handshake.
+const FIRST_ECHO_TIMEOUT: Duration = Duration::from_secs(30);
+const ECHO_TIMEOUT: Duration = Duration::from_secs(10);
+// How long the server gets to drain an upload after the client stops sending.
+const UPLOAD_DRAIN_TIMEOUT: Duration = Duration::from_secs(60);
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(10);
+const PROBE_LEN: usize = 32;
+const CHUNK_LEN: usize = 16 * 1024;
+// The server's source service needs a size; this is far more than a DNS tunnel
+// moves in one test, so the duration ends the download.
+const DOWNLOAD_LIMIT_BYTES: u64 = 1 << 30;
+
+pub struct SpeedtestConfig<'a> {
+    /// Tunnel settings; the TCP port and control socket are chosen by the test.
+    pub client: &'a ClientConfig<'a>,
+    pub duration: Duration,
+    pub rtt_probes: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Transfer {
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Transfer {
+    pub fn bits_per_sec(&self) -> f64 {
+        self.bytes as f64 * 8.0 / self.elapsed.as_secs_f64().max(1e-3)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolverUsage {
+    pub resolver: String,
+    pub queries: u64,
+    pub responses: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpeedtestReport {
+    /// Minimum, median, and maximum round-trip time of echoed probes.
+    pub rtt: (Duration, Duration, Duration),
+    pub rtt_probes: usize,
+    pub upload: Transfer,
+    pub download: Transfer,
+    /// Queries sent while measuring, per resolver.
+    pub resolvers: Vec<ResolverUsage>,
+    /// User and system CPU time of this process while measuring.
+    pub cpu: Option<(Duration, Duration)>,
+    pub elapsed: Duration,
+}
+
+/// Starts a tunnel in-process and measures it against the server's
+/// `--enable-diagnostics` endpoint: echo for RTT, discard for upload and
+/// source for download.
+pub async fn run_speedtest(config: &SpeedtestConfig<'_>) -> Result<SpeedtestReport, ClientError> {
+    let port = free_tcp_port()?;
+    let temp_socket =
+        std::env::temp_dir().join(format!("slipstream-speedtest-{}.sock", std::process::id()));
+    let socket: Option<PathBuf> = match config.client.control_socket {
+        Some(path) => Some(path.into()),
+        None if cfg!(unix) => Some(temp_socket.clone()),
+        None => None,
+    };
+    let socket_str = socket
+        .as_ref()
+        .map(|path| path.to_string_lossy().into_owned());
+    let client = ClientConfig {
+        tcp_listen_port: port,
+        control_socket: socket_str.as_deref(),
+        lazy: false,
+        ..*config.client
+    };
+
+    let result = tokio::select! {
+        result = run_client(&client) => match result {
+            Ok(_) => Err(ClientError::new("The tunnel closed before the speed test finished")),
+            Err(err) => Err(err),
+        },
+        report = measure(port, socket.as_deref(), config) => report,
+    };
+    if config.client.control_socket.is_none() {
+        let _ = std::fs::remove_file(&temp_socket);
+    }
+    result
+}
+
+async fn measure(
+    port: u16,
+    socket: Option<&Path>,
+    config: &SpeedtestConfig<'_>,
+) -> Result<SpeedtestReport, ClientError> {
+    let mut echo = open_stream(port, DiagnosticRequest::Echo).await?;
+    // Warm up: the first probe waits for the handshake and is not counted.
+    echo_probe(&mut echo, FIRST_ECHO_TIMEOUT).await?;
+
+    let before = resolver_usage(socket).await;
+    let cpu_before = cpu_time();
+    let started = Instant::now();
+
+    let mut rtts = Vec::with_capacity(config.rtt_probes as usize);
+    for _ in 0..config.rtt_probes.max(1) {
+        rtts.push(echo_probe(&mut echo, ECHO_TIMEOUT).await?);
+    }
+    drop(echo);
+    rtts.sort();
+
+    let upload = upload(port, config.duration).await?;
+    let download = download(port, config.duration).await?;
+
+    let elapsed = started.elapsed();
+    let cpu = cpu_before
+        .zip(cpu_time())
+        .map(|((user0, sys0), (user1, sys1))| (user1 - user0, sys1 - sys0));
+    let after = resolver_usage(socket).await;
+    Ok(SpeedtestReport {
+        rtt: (rtts[0], rtts[rtts.len() / 2], rtts[rtts.len() - 1]),
+        rtt_probes: rtts.len(),
+        upload,
+        download,
+        resolvers: usage_delta(&before, &after),
+        cpu,
+        elapsed,
+    })
+}
+
+async fn open_stream(port: u16, request: DiagnosticRequest) -> Result<TcpStream, ClientError> {
+    let deadline = Instant::now() + LISTEN_TIMEOUT;
+    // The client binds its listener once the resolvers are set up.
+    let mut stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(stream) => break stream,
+            Err(err) if Instant::now() >= deadline => return Err(map_io(err)),
+            Err(_) => sleep(Duration::from_millis(50)).await,
+        }
+    };
+    let _ = stream.set_nodelay(true);
+    stream.write_all(&request.preface()).await.map_err(map_io)?;
+    Ok(stream)
+}
+
+async fn echo_probe(stream: &mut TcpStream, limit: Duration) -> Result<Duration, ClientError> {
+    let probe: Vec<u8> = (0..PROBE_LEN as u8).collect();
+    let mut reply = [0u8; PROBE_LEN];
+    let started = Instant::now();
+    let exchange = async {
+        stream.write_all(&probe).await?;
+        stream.read_exact(&mut reply).await
+    };
+    match timeout(limit, exchange).await {
+        Ok(Ok(_)) if reply[..] == probe[..] => Ok(started.elapsed()),
+        _ => Err(ClientError::new(
+            "No echo from the server; is slipstream-server running with --enable-diagnostics?",
+        )),
+    }
+}
+
+async fn upload(port: u16, duration: Duration) -> Result<Transfer, ClientError> {
+    let stream = open_stream(port, DiagnosticRequest::Discard).await?;
+    let (read_half, mut write_half) = stream.into_split();
+    let chunk = vec![0x5au8; CHUNK_LEN];
+    let started = Instant::now();
+    let deadline = started + duration;
+    // Stopping mid-write is fine: the server reports what it received.
+    let _ = timeout_at(deadline, async {
+        while write_half.write_all(&chunk).await.is_ok() {}
+    })
+    .await;
+    write_half.shutdown().await.map_err(map_io)?;
+    let mut line = String::new();
+    timeout(
+        UPLOAD_DRAIN_TIMEOUT,
+        BufReader::new(read_half).read_line(&mut line),
+    )
+    .await
+    .map_err(|_| ClientError::new("Timed out waiting for the server to drain the upload"))?
+    .map_err(map_io)?;
+    let bytes = line
+        .trim()
+        .parse()
+        .map_err(|_| ClientError::new(format!("Unexpected discard reply {:?}", line)))?;
+    Ok(Transfer {
+        bytes,
+        elapsed: started.elapsed(),
+    })
+}
+
+async fn download(port: u16, duration: Duration) -> Result<Transfer, ClientError> {
+    let request = DiagnosticRequest::Source {
+        bytes: DOWNLOAD_LIMIT_BYTES,
+    };
+    let mut stream = open_stream(port, request).await?;
+    let mut buf = vec![0u8; CHUNK_LEN];
+    let mut bytes = 0u64;
+    let started = Instant::now();
+    let deadline = started + duration;
+    loop {
+        match timeout_at(deadline, stream.read(&mut buf)).await {
+            Err(_) => break,
+            Ok(Ok(0)) if bytes >= DOWNLOAD_LIMIT_BYTES => break,
+            Ok(Ok(0)) => return Err(ClientError::new("The server closed the download early")),
+            Ok(Ok(n)) => bytes += n as u64,
+            Ok(Err(err)) => return Err(map_io(err)),
+        }
+    }
+    Ok(Transfer {
+        bytes,
+        elapsed: started.elapsed(),
+    })
+}
+
+fn free_tcp_port() -> Result<u16, ClientError> {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).map_err(map_io)?;
+    Ok(listener.local_addr().map_err(map_io)?.port())
+}
+
+async fn resolver_usage(socket: Option<&Path>) -> Vec<ResolverUsage> {
+    let Some(socket) = socket else {
+        return Vec::new();
+    };
+    match control_request(socket, &json!({ "command": "paths" })).await {
+        Ok(response) => parse_paths(&response),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn parse_paths(response: &Value) -> Vec<ResolverUsage> {
+    let Some(paths) = response.get("result").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    paths
+        .iter()
+        .filter_map(|path| {
+            Some(ResolverUsage {
+                resolver: path.get("resolver")?.as_str()?.to_string(),
+                queries: path.get("queries")?.as_u64()?,
+                responses: path.get("responses")?.as_u64()?,
+            })
+        })
+        .collect()
+}
+
+fn usage_delta(before: &[ResolverUsage], after: &[ResolverUsage]) -> Vec<ResolverUsage> {
+    after
+        .iter()
+        .map(|usage| {
+            let base = before.iter().find(|old| old.resolver == usage.resolver);
+            ResolverUsage {
+                resolver: usage.resolver.clone(),
+                queries: usage.queries - base.map_or(0, |old| old.queries.min(usage.queries)),
+                responses: usage.responses
+                    - base.map_or(0, |old| old.responses.min(usage.responses)),
+            }
+        })
+        .collect()
+}
+
+/// User and system CPU time this process has used so far; None where
+/// `getrusage` is unavailable.
+#[cfg(unix)]
+pub fn cpu_time() -> Option<(Duration, Duration)> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+    let to_duration = |tv: libc::timeval| {
+        Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+    };
+    Some((to_duration(usage.ru_utime), to_duration(usage.ru_stime)))
+}
+
+#[cfg(not(unix))]
+pub fn cpu_time() -> Option<(Duration, Duration)> {
+    None
+}
+
+fn format_ms(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1_000.0)
+}
+
+fn format_rate(bits_per_sec: f64) -> String {
+    if bits_per_sec >= 1_000_000.0 {
+        format!("{:.2} Mbit/s", bits_per_sec / 1_000_000.0)
+    } else {
+        format!("{:.1} kbit/s", bits_per_sec / 1_000.0)
+    }
+}
+
+fn format_transfer(transfer: &Transfer) -> String {
+    format!(
+        "{} ({} bytes in {:.1} s)",
+        format_rate(transfer.bits_per_sec()),
+        transfer.bytes,
+        transfer.elapsed.as_secs_f64()
+    )
+}
+
+impl fmt::Display for SpeedtestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (min, median, max) = self.rtt;
+        writeln!(f, "Speed test ({:.1} s)", self.elapsed.as_secs_f64())?;
+        writeln!(
+            f,
+            "  rtt:      min {}, median {}, max {} ({} probes)",
+            format_ms(min),
+            format_ms(median),
+            format_ms(max),
+            self.rtt_probes
+        )?;
+        writeln!(f, "  upload:   {}", format_transfer(&self.upload))?;
+        writeln!(f, "  download: {}", format_transfer(&self.download))?;
+        if let Some((user, system)) = self.cpu {
+            let share = (user + system).as_secs_f64() / self.elapsed.as_secs_f64().max(1e-3);
+            writeln!(
+                f,
+                "  cpu:      {:.2} s user, {:.2} s system ({:.0}% of one core)",
+                user.as_secs_f64(),
+                system.as_secs_f64(),
+                share * 100.0
+            )?;
+        }
+        for usage in &self.resolvers {
+            writeln!(
+                f,
+                "  resolver {}: {} queries, {} responses",
+                usage.resolver, usage.queries, usage.responses
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_usage_from_paths_response() {
+        let before = parse_paths(&json!({
+            "ok": true,
+            "result": [
+                { "resolver": "1.1.1.1:53", "queries": 10, "responses": 9 },
+            ],
+        }));
+        let after = parse_paths(&json!({
+            "ok": true,
+            "result": [
+                { "resolver": "1.1.1.1:53", "queries": 110, "responses": 100 },
+                { "resolver": "9.9.9.9:53", "queries": 5, "responses": 5 },
+            ],
+        }));
+        let delta = usage_delta(&before, &after);
+        assert_eq!(delta.len(), 2);
+        assert_eq!((delta[0].queries, delta[0].responses), (100, 91));
+        assert_eq!((delta[1].queries, delta[1].responses), (5, 5));
+        assert!(parse_paths(&json!({ "ok": false, "error": "nope" })).is_empty());
+    }
+
+    #[test]
+    fn formats_rates() {
+        assert_eq!(format_rate(512_000.0), "512.0 kbit/s");
+        assert_eq!(format_rate(2_500_000.0), "2.50 Mbit/s");
+        let transfer = Transfer {
+            bytes: 125_000,
+            elapsed: Duration::from_secs(1),
+        };
+        assert_eq!(transfer.bits_per_sec(), 1_000_000.0);
+    }
+}
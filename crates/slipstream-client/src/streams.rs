@@ -1,10 +1,10 @@
 use slipstream_core::tcp::{stream_read_limit_chunks, tcp_send_buffer_bytes};
 use slipstream_ffi::picoquic::{
-    picoquic_add_to_stream, picoquic_call_back_event_t, picoquic_cnx_t, picoquic_current_time,
+    picoquic_add_to_stream, picoquic_call_back_event_t, picoquic_cnx_t,
     picoquic_get_next_local_stream_id, picoquic_mark_active_stream,
     picoquic_provide_stream_data_buffer, picoquic_reset_stream, picoquic_stream_data_consumed,
 };
-use slipstream_ffi::{SLIPSTREAM_FILE_CANCEL_ERROR, SLIPSTREAM_INTERNAL_ERROR};
+use slipstream_ffi::{cnx_time, SLIPSTREAM_FILE_CANCEL_ERROR, SLIPSTREAM_INTERNAL_ERROR};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -26,6 +26,7 @@ pub(crate) struct ClientState {
     debug_streams: bool,
     debug_enqueued_bytes: u64,
     debug_last_enqueue_at: u64,
+    last_activity_at: u64,
 }
 
 impl ClientState {
@@ -33,6 +34,7 @@ impl ClientState {
         command_tx: mpsc::UnboundedSender<Command>,
         data_notify: Arc<Notify>,
         debug_streams: bool,
+        now: u64,
     ) -> Self {
         Self {
             ready: false,
@@ -44,6 +46,7 @@ impl ClientState {
             debug_streams,
             debug_enqueued_bytes: 0,
             debug_last_enqueue_at: 0,
+            last_activity_at: now,
         }
     }
 
@@ -63,9 +66,55 @@ impl ClientState {
         (self.debug_enqueued_bytes, self.debug_last_enqueue_at)
     }
 
+    /// Last time a stream was accepted or carried data in either direction.
+    pub(crate) fn last_activity_at(&self) -> u64 {
+        self.last_activity_at
+    }
+
     pub(crate) fn take_path_events(&mut self) -> Vec<PathEvent> {
         std::mem::take(&mut self.path_events)
     }
+
+    /// Snapshot of the open streams for the control socket, by stream id.
+    pub(crate) fn stream_infos(&self) -> Vec<StreamInfo> {
+        let mut infos: Vec<_> = self
+            .streams
+            .iter()
+            .map(|(stream_id, stream)| StreamInfo {
+                stream_id: *stream_id,
+                rx_bytes: stream.rx_bytes,
+                tx_bytes: stream.tx_bytes,
+                queued_bytes: stream.queued_bytes,
+                opened_at: stream.opened_at,
+                state: match (stream.fin_sent, stream.fin_offset.is_some()) {
+                    (false, false) => "open",
+                    (true, false) => "half_closed_local",
+                    (false, true) => "half_closed_remote",
+                    (true, true) => "closing",
+                },
+            })
+            .collect();
+        infos.sort_by_key(|info| info.stream_id);
+        infos
+    }
+
+    /// Resets a stream and drops its TCP connection; false if it is not open.
+    pub(crate) fn close_stream(&mut self, cnx: *mut picoquic_cnx_t, stream_id: u64) -> bool {
+        if self.streams.remove(&stream_id).is_none() {
+            return false;
+        }
+        let _ = unsafe { picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_FILE_CANCEL_ERROR) };
+        true
+    }
+}
+
+pub(crate) struct StreamInfo {
+    pub(crate) stream_id: u64,
+    pub(crate) rx_bytes: u64,
+    pub(crate) tx_bytes: u64,
+    pub(crate) queued_bytes: usize,
+    pub(crate) opened_at: u64,
+    pub(crate) state: &'static str,
 }
 
 struct ClientStream {
@@ -77,6 +126,8 @@ struct ClientStream {
     consumed_offset: u64,
     fin_offset: Option<u64>,
     fin_enqueued: bool,
+    fin_sent: bool,
+    opened_at: u64,
 }
 
 enum StreamWrite {
@@ -139,21 +190,18 @@ pub(crate) unsafe extern "C" fn client_callback(
             };
             if let Some(stream) = state.streams.remove(&stream_id) {
                 warn!(
-                    "stream {}: reset event={} rx_bytes={} tx_bytes={} queued={} consumed_offset={} fin_offset={:?} fin_enqueued={}",
                     stream_id,
-                    reason,
-                    stream.rx_bytes,
-                    stream.tx_bytes,
-                    stream.queued_bytes,
-                    stream.consumed_offset,
-                    stream.fin_offset,
-                    stream.fin_enqueued
+                    event = reason,
+                    rx_bytes = stream.rx_bytes,
+                    tx_bytes = stream.tx_bytes,
+                    queued = stream.queued_bytes,
+                    consumed_offset = stream.consumed_offset,
+                    fin_offset = ?stream.fin_offset,
+                    fin_enqueued = stream.fin_enqueued,
+                    "stream reset"
                 );
             } else {
-                warn!(
-                    "stream {}: reset event={} (unknown stream)",
-                    stream_id, reason
-                );
+                warn!(stream_id, event = reason, "stream reset (unknown stream)");
             }
             let _ = picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_FILE_CANCEL_ERROR);
         }
@@ -163,10 +211,8 @@ pub(crate) unsafe extern "C" fn client_callback(
             state.closing = true;
             info!("Connection closed");
         }
-        picoquic_call_back_event_t::picoquic_callback_prepare_to_send => {
-            if !bytes.is_null() {
-                let _ = picoquic_provide_stream_data_buffer(bytes as *mut _, 0, 0, 0);
-            }
+        picoquic_call_back_event_t::picoquic_callback_prepare_to_send if !bytes.is_null() => {
+            let _ = picoquic_provide_stream_data_buffer(bytes as *mut _, 0, 0, 0);
         }
         picoquic_call_back_event_t::picoquic_callback_path_available => {
             state.path_events.push(PathEvent::Available(stream_id));
@@ -188,17 +234,15 @@ fn handle_stream_data(
     data: &[u8],
 ) {
     let debug_streams = state.debug_streams;
+    if !data.is_empty() {
+        state.last_activity_at = unsafe { cnx_time(cnx) };
+    }
     let mut reset_stream = false;
     let mut remove_stream = false;
 
     {
         let Some(stream) = state.streams.get_mut(&stream_id) else {
-            warn!(
-                "stream {}: data for unknown stream len={} fin={}",
-                stream_id,
-                data.len(),
-                fin
-            );
+            warn!(stream_id, len = data.len(), fin, "data for unknown stream");
             unsafe {
                 let _ = picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_FILE_CANCEL_ERROR);
             }
@@ -214,8 +258,11 @@ fn handle_stream_data(
                 .is_err()
             {
                 warn!(
-                    "stream {}: tcp write channel closed queued={} rx_bytes={} tx_bytes={}",
-                    stream_id, stream.queued_bytes, stream.rx_bytes, stream.tx_bytes
+                    stream_id,
+                    queued = stream.queued_bytes,
+                    rx_bytes = stream.rx_bytes,
+                    tx_bytes = stream.tx_bytes,
+                    "tcp write channel closed"
                 );
                 reset_stream = true;
             } else {
@@ -231,11 +278,11 @@ fn handle_stream_data(
             if !stream.fin_enqueued {
                 if stream.write_tx.send(StreamWrite::Fin).is_err() {
                     warn!(
-                        "stream {}: tcp write channel closed on fin queued={} rx_bytes={} tx_bytes={}",
                         stream_id,
-                        stream.queued_bytes,
-                        stream.rx_bytes,
-                        stream.tx_bytes
+                        queued = stream.queued_bytes,
+                        rx_bytes = stream.rx_bytes,
+                        tx_bytes = stream.tx_bytes,
+                        "tcp write channel closed on fin"
                     );
                     reset_stream = true;
                 } else {
@@ -251,7 +298,7 @@ fn handle_stream_data(
 
     if reset_stream {
         if debug_streams {
-            debug!("stream {}: resetting", stream_id);
+            debug!(stream_id, "stream resetting");
         }
         unsafe {
             let _ = picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_FILE_CANCEL_ERROR);
@@ -259,7 +306,7 @@ fn handle_stream_data(
         state.streams.remove(&stream_id);
     } else if remove_stream {
         if debug_streams {
-            debug!("stream {}: finished", stream_id);
+            debug!(stream_id, "stream finished");
         }
         state.streams.remove(&stream_id);
     }
@@ -317,6 +364,8 @@ pub(crate) fn handle_command(
     let state = unsafe { &mut *state_ptr };
     match command {
         Command::NewStream(stream) => {
+            let now = unsafe { cnx_time(cnx) };
+            state.last_activity_at = now;
             let _ = stream.set_nodelay(true);
             let read_limit = stream_read_limit_chunks(
                 &stream,
@@ -357,13 +406,15 @@ pub(crate) fn handle_command(
                     consumed_offset: 0,
                     fin_offset: None,
                     fin_enqueued: false,
+                    fin_sent: false,
+                    opened_at: now,
                 },
             );
             let _ = unsafe { picoquic_mark_active_stream(cnx, stream_id, 1, std::ptr::null_mut()) };
             if state.debug_streams {
-                debug!("stream {}: accepted", stream_id);
+                debug!(stream_id, "stream accepted");
             } else {
-                info!("Accepted TCP stream {}", stream_id);
+                info!(stream_id, "Accepted TCP stream");
             }
         }
         Command::StreamData { stream_id, data } => {
@@ -371,59 +422,59 @@ pub(crate) fn handle_command(
                 unsafe { picoquic_add_to_stream(cnx, stream_id, data.as_ptr(), data.len(), 0) };
             if ret < 0 {
                 warn!(
-                    "stream {}: add_to_stream failed ret={} chunk_len={}",
                     stream_id,
                     ret,
-                    data.len()
+                    chunk_len = data.len(),
+                    "add_to_stream failed"
                 );
                 let _ = unsafe { picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR) };
                 state.streams.remove(&stream_id);
             } else if let Some(stream) = state.streams.get_mut(&stream_id) {
                 stream.tx_bytes = stream.tx_bytes.saturating_add(data.len() as u64);
-                let now = unsafe { picoquic_current_time() };
+                let now = unsafe { cnx_time(cnx) };
                 state.debug_enqueued_bytes =
                     state.debug_enqueued_bytes.saturating_add(data.len() as u64);
                 state.debug_last_enqueue_at = now;
+                state.last_activity_at = now;
             }
         }
         Command::StreamClosed { stream_id } => {
             let ret = unsafe { picoquic_add_to_stream(cnx, stream_id, std::ptr::null(), 0, 1) };
             if ret < 0 {
-                warn!(
-                    "stream {}: add_to_stream(fin) failed ret={}",
-                    stream_id, ret
-                );
+                warn!(stream_id, ret, "add_to_stream(fin) failed");
+            } else if let Some(stream) = state.streams.get_mut(&stream_id) {
+                stream.fin_sent = true;
             }
         }
         Command::StreamReadError { stream_id } => {
             if let Some(stream) = state.streams.remove(&stream_id) {
                 warn!(
-                    "stream {}: tcp read error rx_bytes={} tx_bytes={} queued={} consumed_offset={} fin_offset={:?}",
                     stream_id,
-                    stream.rx_bytes,
-                    stream.tx_bytes,
-                    stream.queued_bytes,
-                    stream.consumed_offset,
-                    stream.fin_offset
+                    rx_bytes = stream.rx_bytes,
+                    tx_bytes = stream.tx_bytes,
+                    queued = stream.queued_bytes,
+                    consumed_offset = stream.consumed_offset,
+                    fin_offset = ?stream.fin_offset,
+                    "tcp read error"
                 );
             } else {
-                warn!("stream {}: tcp read error (unknown stream)", stream_id);
+                warn!(stream_id, "tcp read error (unknown stream)");
             }
             let _ = unsafe { picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR) };
         }
         Command::StreamWriteError { stream_id } => {
             if let Some(stream) = state.streams.remove(&stream_id) {
                 warn!(
-                    "stream {}: tcp write error rx_bytes={} tx_bytes={} queued={} consumed_offset={} fin_offset={:?}",
                     stream_id,
-                    stream.rx_bytes,
-                    stream.tx_bytes,
-                    stream.queued_bytes,
-                    stream.consumed_offset,
-                    stream.fin_offset
+                    rx_bytes = stream.rx_bytes,
+                    tx_bytes = stream.tx_bytes,
+                    queued = stream.queued_bytes,
+                    consumed_offset = stream.consumed_offset,
+                    fin_offset = ?stream.fin_offset,
+                    "tcp write error"
                 );
             } else {
-                warn!("stream {}: tcp write error (unknown stream)", stream_id);
+                warn!(stream_id, "tcp write error (unknown stream)");
             }
             let _ = unsafe { picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR) };
         }
@@ -443,8 +494,10 @@ pub(crate) fn handle_command(
                 };
                 if ret < 0 {
                     warn!(
-                        "stream {}: stream_data_consumed failed ret={} consumed_offset={}",
-                        stream_id, ret, stream.consumed_offset
+                        stream_id,
+                        ret,
+                        consumed_offset = stream.consumed_offset,
+                        "stream_data_consumed failed"
                     );
                     reset_stream = true;
                 } else if stream.fin_enqueued && stream.queued_bytes == 0 {
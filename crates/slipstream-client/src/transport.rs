@@ -0,0 +1,185 @@
+mod https;
+#[cfg(test)]
+mod memory;
+mod tcp;
+mod tls;
+
+pub(crate) use https::HttpsTransport;
+#[cfg(test)]
+pub(crate) use memory::memory_transport;
+pub(crate) use tcp::TcpTransport;
+pub(crate) use tls::TlsConfig;
+
+pub(crate) use slipstream_core::transport::DnsTransport;
+
+use crate::error::ClientError;
+use crate::runtime::{bind_udp_socket, map_io, unspecified_addr};
+use slipstream_core::pcap::{CapturedUdpSocket, PcapSink};
+use slipstream_core::udp_batch::{RecvBatch, SendBatch};
+use slipstream_ffi::DnsTransportKind;
+use std::future::poll_fn;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+type Response = (Vec<u8>, SocketAddr);
+
+/// The transport picked by `--dns-transport`.
+pub(crate) enum ClientTransport {
+    Udp(CapturedUdpSocket),
+    /// DNS over TCP, or over TLS.
+    Tcp(TcpTransport),
+    Https(HttpsTransport),
+}
+
+impl ClientTransport {
+    /// Opens a transport for one QUIC connection; only UDP is captured to `pcap`
+    /// and sends bursts with `gso`. DoT and DoH connect with `tls`.
+    pub(crate) async fn open(
+        kind: DnsTransportKind,
+        tls: Option<&TlsConfig>,
+        pcap: Option<PcapSink>,
+        gso: bool,
+    ) -> Result<Self, ClientError> {
+        if kind != DnsTransportKind::Udp && gso {
+            warn!("GSO only applies to --dns-transport udp");
+        }
+        let tls = || {
+            tls.cloned()
+                .ok_or_else(|| ClientError::new("DNS over TLS or HTTPS needs TLS settings"))
+        };
+        match kind {
+            DnsTransportKind::Udp => {
+                let socket = bind_udp_socket().await?;
+                let socket = CapturedUdpSocket::new(socket, pcap).map_err(map_io)?;
+                if gso && !socket.enable_gso() {
+                    warn!("UDP GSO is not available here; sending one datagram per query");
+                }
+                Ok(Self::Udp(socket))
+            }
+            DnsTransportKind::Tcp => Ok(Self::Tcp(TcpTransport::new(unspecified_addr(), None))),
+            DnsTransportKind::Tls => Ok(Self::Tcp(TcpTransport::new(
+                unspecified_addr(),
+                Some(tls()?),
+            ))),
+            DnsTransportKind::Https => {
+                Ok(Self::Https(HttpsTransport::new(unspecified_addr(), tls()?)))
+            }
+        }
+    }
+}
+
+impl DnsTransport for ClientTransport {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Self::Udp(udp) => DnsTransport::local_addr(udp),
+            Self::Tcp(tcp) => tcp.local_addr(),
+            Self::Https(https) => https.local_addr(),
+        }
+    }
+
+    async fn send_to(&self, packet: &[u8], dest: SocketAddr) -> io::Result<()> {
+        match self {
+            Self::Udp(udp) => DnsTransport::send_to(udp, packet, dest).await,
+            Self::Tcp(tcp) => tcp.send_to(packet, dest).await,
+            Self::Https(https) => https.send_to(packet, dest).await,
+        }
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self {
+            Self::Udp(udp) => DnsTransport::recv_from(udp, buf).await,
+            Self::Tcp(tcp) => tcp.recv_from(buf).await,
+            Self::Https(https) => https.recv_from(buf).await,
+        }
+    }
+
+    fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self {
+            Self::Udp(udp) => DnsTransport::try_recv_from(udp, buf),
+            Self::Tcp(tcp) => tcp.try_recv_from(buf),
+            Self::Https(https) => https.try_recv_from(buf),
+        }
+    }
+
+    async fn recv_batch(&self, batch: &mut RecvBatch) -> io::Result<()> {
+        match self {
+            Self::Udp(udp) => DnsTransport::recv_batch(udp, batch).await,
+            Self::Tcp(tcp) => tcp.recv_batch(batch).await,
+            Self::Https(https) => https.recv_batch(batch).await,
+        }
+    }
+
+    async fn send_batch(&self, batch: &SendBatch) -> io::Result<()> {
+        match self {
+            Self::Udp(udp) => DnsTransport::send_batch(udp, batch).await,
+            Self::Tcp(tcp) => tcp.send_batch(batch).await,
+            Self::Https(https) => https.send_batch(batch).await,
+        }
+    }
+}
+
+/// Responses that arrived on a transport's connections, waiting for the
+/// event loop.
+///
+/// A response longer than the caller's buffer is dropped rather than cut
+/// short: over TCP a resolver may answer with more than the EDNS(0) size the
+/// query advertised, and a truncated message would only fail to decode.
+struct ResponseQueue {
+    rx: Mutex<mpsc::UnboundedReceiver<Response>>,
+}
+
+impl ResponseQueue {
+    fn new() -> (mpsc::UnboundedSender<Response>, Self) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (tx, Self { rx: Mutex::new(rx) })
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        loop {
+            // The receiver is polled in place, so a cancelled call loses nothing.
+            let response =
+                poll_fn(|cx| self.rx.lock().expect("responses lock").poll_recv(cx)).await;
+            let (response, peer) = response.ok_or(io::ErrorKind::BrokenPipe)?;
+            if let Some(len) = copy_response(&response, peer, buf) {
+                return Ok((len, peer));
+            }
+        }
+    }
+
+    fn try_recv(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut rx = self.rx.lock().expect("responses lock");
+        loop {
+            match rx.try_recv() {
+                Ok((response, peer)) => {
+                    if let Some(len) = copy_response(&response, peer, buf) {
+                        return Ok((len, peer));
+                    }
+                }
+                Err(mpsc::error::TryRecvError::Empty) => {
+                    return Err(io::ErrorKind::WouldBlock.into())
+                }
+                Err(mpsc::error::TryRecvError::Disconnected) => {
+                    return Err(io::ErrorKind::BrokenPipe.into())
+                }
+            }
+        }
+    }
+}
+
+/// Copies a response into the caller's buffer, or drops it if it does not fit.
+fn copy_response(response: &[u8], peer: SocketAddr, buf: &mut [u8]) -> Option<usize> {
+    if response.len() > buf.len() {
+        warn!(
+            resolver = %peer,
+            len = response.len(),
+            max = buf.len(),
+            "Dropping a DNS response longer than the receive buffer"
+        );
+        return None;
+    }
+    buf[..response.len()].copy_from_slice(response);
+    Some(response.len())
+}
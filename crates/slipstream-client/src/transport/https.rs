@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::BufReader;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_openssl::SslStream;
+use tracing::{debug, warn};
+
+use super::{DnsTransport, Response, ResponseQueue, TlsConfig};
+
+/// Path queries are posted to, as RFC 8484 suggests.
+const DOH_PATH: &str = "/dns-query";
+/// Connections kept open to each resolver, so one slow answer does not hold up the rest.
+const CONNECTIONS_PER_RESOLVER: usize = 4;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HEADER_LEN: usize = 16 * 1024;
+const MAX_BODY_LEN: usize = u16::MAX as usize;
+
+type Connection = BufReader<SslStream<TcpStream>>;
+type Queries = Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<Vec<u8>>>>;
+
+/// DNS over HTTPS (RFC 8484): each query is one HTTP/1.1 POST to
+/// [`DOH_PATH`] on a small pool of keep-alive connections per resolver.
+///
+/// A query whose request fails, times out or gets a status other than 200 is
+/// dropped; QUIC retransmits its contents.
+pub(crate) struct HttpsTransport {
+    local_addr: SocketAddr,
+    tls: TlsConfig,
+    /// Queries waiting for a free connection, by resolver.
+    resolvers: Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<Vec<u8>>>>,
+    responses_tx: mpsc::UnboundedSender<Response>,
+    responses: ResponseQueue,
+}
+
+impl HttpsTransport {
+    /// `local_addr` is only reported to picoquic; connections bind an ephemeral port.
+    pub(crate) fn new(local_addr: SocketAddr, tls: TlsConfig) -> Self {
+        let (responses_tx, responses) = ResponseQueue::new();
+        Self {
+            local_addr,
+            tls,
+            resolvers: Mutex::new(HashMap::new()),
+            responses_tx,
+            responses,
+        }
+    }
+}
+
+impl DnsTransport for HttpsTransport {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    async fn send_to(&self, packet: &[u8], dest: SocketAddr) -> io::Result<()> {
+        let mut resolvers = self.resolvers.lock().expect("https resolvers lock");
+        let queue = resolvers.entry(dest).or_insert_with(|| {
+            let (queue_tx, queue_rx) = mpsc::unbounded_channel();
+            let queries: Queries = Arc::new(tokio::sync::Mutex::new(queue_rx));
+            for _ in 0..CONNECTIONS_PER_RESOLVER {
+                tokio::spawn(run_connection(
+                    dest,
+                    self.tls.clone(),
+                    queries.clone(),
+                    self.responses_tx.clone(),
+                ));
+            }
+            queue_tx
+        });
+        // The connection tasks only exit once the queue's sender is dropped.
+        let _ = queue.send(packet.to_vec());
+        Ok(())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        // `responses_tx` lives as long as `self`, so the queue never closes.
+        self.responses.recv(buf).await
+    }
+
+    fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.responses.try_recv(buf)
+    }
+}
+
+/// Posts queries from `queries` to `dest` over one connection, reopening it
+/// when the resolver closes it or a request fails.
+async fn run_connection(
+    dest: SocketAddr,
+    tls: TlsConfig,
+    queries: Queries,
+    responses: mpsc::UnboundedSender<Response>,
+) {
+    let host = tls.host(dest);
+    let mut connection = None;
+    loop {
+        let Some(query) = queries.lock().await.recv().await else {
+            return;
+        };
+        // A kept-alive connection may have been closed by the resolver since
+        // its last answer, so a failure on one is retried on a fresh one.
+        let reply = loop {
+            let reused = connection.is_some();
+            let attempt = tokio::time::timeout(
+                REQUEST_TIMEOUT,
+                post(&mut connection, &tls, dest, &host, &query),
+            )
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()));
+            match attempt {
+                Ok(reply) => break Some(reply),
+                Err(err) => {
+                    connection = None;
+                    debug!(resolver = %dest, error = %err, "DNS over HTTPS request failed");
+                    if !reused {
+                        break None;
+                    }
+                }
+            }
+        };
+        let Some(reply) = reply else {
+            continue;
+        };
+        if !reply.keep_alive {
+            connection = None;
+        }
+        if reply.status != 200 {
+            debug!(resolver = %dest, status = reply.status, "DNS over HTTPS query rejected");
+            continue;
+        }
+        if responses.send((reply.body, dest)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends one query on `connection`, connecting first if it is closed.
+async fn post(
+    connection: &mut Option<Connection>,
+    tls: &TlsConfig,
+    dest: SocketAddr,
+    host: &str,
+    query: &[u8],
+) -> io::Result<Reply> {
+    let stream = match connection {
+        Some(stream) => stream,
+        None => {
+            let tcp = TcpStream::connect(dest).await?;
+            let _ = tcp.set_nodelay(true);
+            let stream = tls.handshake(dest, tcp).await.inspect_err(|err| {
+                warn!(resolver = %dest, error = %err, "DNS over HTTPS handshake failed");
+            })?;
+            connection.insert(BufReader::new(stream))
+        }
+    };
+    exchange(stream, host, query).await
+}
+
+/// An HTTP response to a posted query.
+#[derive(Debug)]
+struct Reply {
+    status: u16,
+    body: Vec<u8>,
+    /// Whether the connection can carry the next request.
+    keep_alive: bool,
+}
+
+/// Writes a POST carrying `query` and reads the response to it.
+async fn exchange<S>(stream: &mut BufReader<S>, host: &str, query: &[u8]) -> io::Result<Reply>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Content-Type: application/dns-message\r\n\
+         Accept: application/dns-message\r\n\
+         Content-Length: {}\r\n\
+         \r\n",
+        DOH_PATH,
+        host,
+        query.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(query);
+    stream.write_all(&request).await?;
+    stream.flush().await?;
+    read_reply(stream).await
+}
+
+async fn read_reply<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Reply> {
+    let mut header_budget = MAX_HEADER_LEN;
+    let status_line = read_line(reader, &mut header_budget).await?;
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    let status = parts
+        .next()
+        .and_then(|code| code.parse::<u16>().ok())
+        .filter(|_| version.starts_with("HTTP/1."))
+        .ok_or_else(|| invalid("malformed HTTP status line"))?;
+    let mut keep_alive = version != "HTTP/1.0";
+    let mut content_len = None;
+    let mut chunked = false;
+    loop {
+        let line = read_line(reader, &mut header_budget).await?;
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(invalid("malformed HTTP header"));
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            let len = value
+                .parse::<usize>()
+                .map_err(|_| invalid("malformed Content-Length"))?;
+            content_len = Some(len);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value
+                .rsplit(',')
+                .next()
+                .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+        } else if name.eq_ignore_ascii_case("connection") {
+            for option in value.split(',').map(str::trim) {
+                if option.eq_ignore_ascii_case("close") {
+                    keep_alive = false;
+                } else if option.eq_ignore_ascii_case("keep-alive") {
+                    keep_alive = true;
+                }
+            }
+        }
+    }
+
+    let body = if chunked {
+        read_chunked(reader, &mut header_budget).await?
+    } else if let Some(len) = content_len {
+        if len > MAX_BODY_LEN {
+            return Err(invalid("HTTP body too long"));
+        }
+        let mut body = vec![0u8; len];
+        reader.read_exact(&mut body).await?;
+        body
+    } else {
+        // Without a length the body runs to the end of the connection.
+        keep_alive = false;
+        let mut body = Vec::new();
+        reader
+            .take(MAX_BODY_LEN as u64 + 1)
+            .read_to_end(&mut body)
+            .await?;
+        if body.len() > MAX_BODY_LEN {
+            return Err(invalid("HTTP body too long"));
+        }
+        body
+    };
+    Ok(Reply {
+        status,
+        body,
+        keep_alive,
+    })
+}
+
+async fn read_chunked<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    header_budget: &mut usize,
+) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader, header_budget).await?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid("malformed chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        if body.len() + size > MAX_BODY_LEN {
+            return Err(invalid("HTTP body too long"));
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).await?;
+        if !read_line(reader, header_budget).await?.is_empty() {
+            return Err(invalid("malformed chunk"));
+        }
+    }
+    // Trailers, which carry nothing needed here.
+    while !read_line(reader, header_budget).await?.is_empty() {}
+    Ok(body)
+}
+
+/// Reads one CRLF-terminated line, charging it to `budget`.
+async fn read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    budget: &mut usize,
+) -> io::Result<String> {
+    let mut line = Vec::new();
+    let read = (&mut *reader)
+        .take(*budget as u64)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.last() != Some(&b'\n') {
+        return Err(if read == 0 {
+            io::ErrorKind::UnexpectedEof.into()
+        } else {
+            invalid("HTTP header too long")
+        });
+    }
+    *budget -= read;
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| invalid("HTTP header is not UTF-8"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exchange, read_reply, HttpsTransport, MAX_BODY_LEN};
+    use crate::transport::tls::tests::TestResolverIdentity;
+    use crate::transport::{DnsTransport, TlsConfig};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    const SERVER_NAME: &str = "test.example.com";
+
+    async fn reply(raw: &[u8]) -> std::io::Result<super::Reply> {
+        read_reply(&mut BufReader::new(raw)).await
+    }
+
+    #[tokio::test]
+    async fn reads_replies_with_a_length_chunks_or_to_the_end() {
+        let sized = reply(b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabcNEXT")
+            .await
+            .expect("sized");
+        assert_eq!((sized.status, sized.body.as_slice()), (200, &b"abc"[..]));
+        assert!(sized.keep_alive);
+
+        let chunked = reply(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+              2;ext=1\r\nab\r\n1\r\nc\r\n0\r\nTrailer: x\r\n\r\n",
+        )
+        .await
+        .expect("chunked");
+        assert_eq!(chunked.body, b"abc");
+        assert!(chunked.keep_alive);
+
+        let to_eof = reply(b"HTTP/1.1 200 OK\r\n\r\nabc").await.expect("to eof");
+        assert_eq!(to_eof.body, b"abc");
+        assert!(!to_eof.keep_alive);
+
+        let closed =
+            reply(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .expect("closed");
+        assert_eq!(closed.status, 400);
+        assert!(!closed.keep_alive);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_and_malformed_replies() {
+        let too_long = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_LEN + 1
+        );
+        assert!(reply(too_long.as_bytes()).await.is_err());
+        let long_header = format!("HTTP/1.1 200 OK\r\nX: {}\r\n\r\n", "a".repeat(20_000));
+        assert!(reply(long_header.as_bytes()).await.is_err());
+        assert!(reply(b"SSH-2.0-OpenSSH\r\n\r\n").await.is_err());
+        assert!(reply(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nabc")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn posts_the_query_as_a_dns_message() {
+        let (client, server) = tokio::io::duplex(4096);
+        let resolver = tokio::spawn(async move {
+            let mut server = BufReader::new(server);
+            let mut head = String::new();
+            loop {
+                let mut line = String::new();
+                server.read_line(&mut line).await.expect("request line");
+                head.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut query = [0u8; 4];
+            server.read_exact(&mut query).await.expect("query");
+            server
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .await
+                .expect("reply");
+            (head, query)
+        });
+
+        let mut client = BufReader::new(client);
+        let reply = exchange(&mut client, "resolver.example", b"\x12\x34\x01\x00")
+            .await
+            .expect("exchange");
+        assert_eq!(reply.body, b"ok");
+        let (head, query) = resolver.await.expect("resolver");
+        assert!(head.starts_with("POST /dns-query HTTP/1.1\r\n"), "{}", head);
+        assert!(head.contains("Host: resolver.example\r\n"), "{}", head);
+        assert!(head.contains("Content-Type: application/dns-message\r\n"));
+        assert!(head.contains("Content-Length: 4\r\n"));
+        assert_eq!(&query, b"\x12\x34\x01\x00");
+    }
+
+    #[tokio::test]
+    async fn round_trips_queries_over_tls() {
+        let identity = TestResolverIdentity::new(SERVER_NAME);
+        let ca = identity.ca.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let resolver = listener.local_addr().expect("addr");
+        tokio::spawn(async move {
+            loop {
+                let Ok((tcp, _)) = listener.accept().await else {
+                    return;
+                };
+                let accept = identity.accept(tcp);
+                tokio::spawn(async move {
+                    let Ok(stream) = accept.await else {
+                        return;
+                    };
+                    let mut stream = BufReader::new(stream);
+                    // Answers each query with itself, QR bit set.
+                    loop {
+                        let mut len = 0;
+                        loop {
+                            let mut line = String::new();
+                            if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                                return;
+                            }
+                            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                                len = value.trim().parse().expect("length");
+                            }
+                            if line == "\r\n" {
+                                break;
+                            }
+                        }
+                        let mut query = vec![0u8; len];
+                        stream.read_exact(&mut query).await.expect("query");
+                        query[2] |= 0x80;
+                        let mut response =
+                            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", len)
+                                .into_bytes();
+                        response.extend_from_slice(&query);
+                        stream.write_all(&response).await.expect("response");
+                    }
+                });
+            }
+        });
+
+        let tls = TlsConfig::new(Some(SERVER_NAME), ca.to_str(), Some(b"http/1.1")).expect("tls");
+        let transport = HttpsTransport::new("[::]:0".parse().expect("addr"), tls);
+        let mut buf = [0u8; 512];
+        for id in [1u16, 2] {
+            let mut query = vec![0u8; 12];
+            query[..2].copy_from_slice(&id.to_be_bytes());
+            transport.send_to(&query, resolver).await.expect("send");
+            let recv = tokio::time::timeout(Duration::from_secs(10), transport.recv_from(&mut buf));
+            let (len, peer) = recv.await.expect("answer in time").expect("recv");
+            assert_eq!((len, peer), (12, resolver));
+            assert_eq!(u16::from_be_bytes([buf[0], buf[1]]), id);
+            assert_ne!(buf[2] & 0x80, 0);
+        }
+
+        // A resolver without a certificate for the expected name gets no queries.
+        let tls = TlsConfig::new(Some("other.example.com"), ca.to_str(), None).expect("tls");
+        let transport = HttpsTransport::new("[::]:0".parse().expect("addr"), tls);
+        transport.send_to(&[0u8; 12], resolver).await.expect("send");
+        let recv = tokio::time::timeout(Duration::from_millis(500), transport.recv_from(&mut buf));
+        assert!(recv.await.is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use std::io;
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+
+use super::{DnsTransport, ResponseQueue};
+
+type Message = (Vec<u8>, SocketAddr);
+
+/// In-process transport for tests; the paired [`MemoryNetwork`] plays the resolvers.
+pub(crate) struct MemoryTransport {
+    local_addr: SocketAddr,
+    queries: mpsc::UnboundedSender<Message>,
+    responses: ResponseQueue,
+}
+
+/// The resolver side of a [`MemoryTransport`].
+pub(crate) struct MemoryNetwork {
+    /// Queries sent by the transport, with their destination.
+    pub(crate) queries: mpsc::UnboundedReceiver<Message>,
+    responses: mpsc::UnboundedSender<Message>,
+}
+
+impl MemoryNetwork {
+    /// Delivers `response` to the transport as if `from` had sent it.
+    pub(crate) fn respond(&self, response: &[u8], from: SocketAddr) {
+        let _ = self.responses.send((response.to_vec(), from));
+    }
+}
+
+pub(crate) fn memory_transport(local_addr: SocketAddr) -> (MemoryTransport, MemoryNetwork) {
+    let (queries_tx, queries_rx) = mpsc::unbounded_channel();
+    let (responses_tx, responses) = ResponseQueue::new();
+    (
+        MemoryTransport {
+            local_addr,
+            queries: queries_tx,
+            responses,
+        },
+        MemoryNetwork {
+            queries: queries_rx,
+            responses: responses_tx,
+        },
+    )
+}
+
+impl DnsTransport for MemoryTransport {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    async fn send_to(&self, packet: &[u8], dest: SocketAddr) -> io::Result<()> {
+        self.queries
+            .send((packet.to_vec(), dest))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.responses.recv(buf).await
+    }
+
+    fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.responses.try_recv(buf)
+    }
+}
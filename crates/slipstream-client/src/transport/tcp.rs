@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use super::{DnsTransport, Response, ResponseQueue, TlsConfig};
+
+/// DNS over TCP (RFC 7766), or over TLS (RFC 7858) with `tls`, with one
+/// pipelined connection per resolver.
+///
+/// A connection is opened by the first query to a resolver and reopened by the
+/// next query after the resolver closes it. Queries written to a connection
+/// that then fails are lost; QUIC retransmits their contents.
+pub(crate) struct TcpTransport {
+    local_addr: SocketAddr,
+    tls: Option<TlsConfig>,
+    /// Frames waiting to be written, by resolver.
+    connections: Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<Vec<u8>>>>,
+    responses_tx: mpsc::UnboundedSender<Response>,
+    responses: ResponseQueue,
+}
+
+impl TcpTransport {
+    /// `local_addr` is only reported to picoquic; connections bind an ephemeral port.
+    pub(crate) fn new(local_addr: SocketAddr, tls: Option<TlsConfig>) -> Self {
+        let (responses_tx, responses) = ResponseQueue::new();
+        Self {
+            local_addr,
+            tls,
+            connections: Mutex::new(HashMap::new()),
+            responses_tx,
+            responses,
+        }
+    }
+}
+
+impl DnsTransport for TcpTransport {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    async fn send_to(&self, packet: &[u8], dest: SocketAddr) -> io::Result<()> {
+        let len = u16::try_from(packet.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "DNS query too long"))?;
+        let mut frame = Vec::with_capacity(2 + packet.len());
+        frame.extend_from_slice(&len.to_be_bytes());
+        frame.extend_from_slice(packet);
+
+        let mut connections = self.connections.lock().expect("tcp connections lock");
+        if let Some(queue) = connections.get(&dest) {
+            match queue.send(frame) {
+                Ok(()) => return Ok(()),
+                // The connection task has exited; reconnect below.
+                Err(mpsc::error::SendError(returned)) => frame = returned,
+            }
+        }
+        let (queue_tx, queue_rx) = mpsc::unbounded_channel();
+        let _ = queue_tx.send(frame);
+        connections.insert(dest, queue_tx);
+        tokio::spawn(run_connection(
+            dest,
+            self.tls.clone(),
+            queue_rx,
+            self.responses_tx.clone(),
+        ));
+        Ok(())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        // `responses_tx` lives as long as `self`, so the queue never closes.
+        self.responses.recv(buf).await
+    }
+
+    fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.responses.try_recv(buf)
+    }
+}
+
+/// Connects to `dest`, then writes queued frames and forwards its responses
+/// until either side closes.
+async fn run_connection(
+    dest: SocketAddr,
+    tls: Option<TlsConfig>,
+    queue: mpsc::UnboundedReceiver<Vec<u8>>,
+    responses: mpsc::UnboundedSender<Response>,
+) {
+    let stream = match TcpStream::connect(dest).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            warn!(resolver = %dest, error = %err, "DNS over TCP connect failed");
+            return;
+        }
+    };
+    let _ = stream.set_nodelay(true);
+    match tls {
+        None => serve_connection(dest, stream, queue, responses).await,
+        Some(tls) => match tls.handshake(dest, stream).await {
+            Ok(stream) => serve_connection(dest, stream, queue, responses).await,
+            Err(err) => warn!(resolver = %dest, error = %err, "DNS over TLS handshake failed"),
+        },
+    }
+}
+
+async fn serve_connection<S>(
+    dest: SocketAddr,
+    stream: S,
+    mut queue: mpsc::UnboundedReceiver<Vec<u8>>,
+    responses: mpsc::UnboundedSender<Response>,
+) where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut read_task = tokio::spawn(async move {
+        while let Ok(response) = read_frame(&mut reader).await {
+            if responses.send((response, dest)).is_err() {
+                break;
+            }
+        }
+    });
+    loop {
+        tokio::select! {
+            frame = queue.recv() => {
+                let Some(frame) = frame else {
+                    break;
+                };
+                if let Err(err) = writer.write_all(&frame).await {
+                    debug!(resolver = %dest, error = %err, "DNS over TCP write failed");
+                    break;
+                }
+            }
+            _ = &mut read_task => {
+                debug!(resolver = %dest, "DNS over TCP connection closed");
+                break;
+            }
+        }
+    }
+    read_task.abort();
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = reader.read_u16().await?;
+    let mut response = vec![0u8; usize::from(len)];
+    reader.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TcpTransport;
+    use crate::transport::tls::tests::TestResolverIdentity;
+    use crate::transport::{DnsTransport, TlsConfig};
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers each framed query by echoing it with the QR bit set, then closes
+    /// the connection after `per_connection` queries. Speaks TLS with `identity`.
+    async fn spawn_resolver(
+        per_connection: usize,
+        identity: Option<TestResolverIdentity>,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let identity = identity.map(Arc::new);
+        tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    return;
+                };
+                match &identity {
+                    None => echo(stream, per_connection).await,
+                    Some(identity) => {
+                        if let Ok(stream) = identity.accept(stream).await {
+                            echo(stream, per_connection).await;
+                        }
+                    }
+                }
+            }
+        });
+        addr
+    }
+
+    async fn echo<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, per_connection: usize) {
+        for _ in 0..per_connection {
+            let Ok(len) = stream.read_u16().await else {
+                break;
+            };
+            let mut query = vec![0u8; usize::from(len)];
+            if stream.read_exact(&mut query).await.is_err() {
+                break;
+            }
+            query[2] |= 0x80;
+            let mut frame = len.to_be_bytes().to_vec();
+            frame.extend_from_slice(&query);
+            if stream.write_all(&frame).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    fn query(id: u16) -> Vec<u8> {
+        let mut query = vec![0u8; 12];
+        query[..2].copy_from_slice(&id.to_be_bytes());
+        query
+    }
+
+    #[tokio::test]
+    async fn pipelines_queries_and_reconnects() {
+        let resolver = spawn_resolver(2, None).await;
+        let transport = TcpTransport::new("[::]:0".parse().expect("addr"), None);
+        let mut buf = [0u8; 512];
+
+        for id in [1u16, 2] {
+            transport.send_to(&query(id), resolver).await.expect("send");
+        }
+        for id in [1u16, 2] {
+            let (len, peer) = transport.recv_from(&mut buf).await.expect("recv");
+            assert_eq!(peer, resolver);
+            assert_eq!(len, 12);
+            assert_eq!(u16::from_be_bytes([buf[0], buf[1]]), id);
+            assert_ne!(buf[2] & 0x80, 0);
+        }
+        assert_eq!(
+            transport.try_recv_from(&mut buf).map_err(|err| err.kind()),
+            Err(std::io::ErrorKind::WouldBlock)
+        );
+
+        // The resolver closed the first connection; the next query opens another.
+        loop {
+            transport.send_to(&query(3), resolver).await.expect("send");
+            let recv = tokio::time::timeout(
+                std::time::Duration::from_millis(200),
+                transport.recv_from(&mut buf),
+            )
+            .await;
+            if let Ok(recv) = recv {
+                recv.expect("recv");
+                break;
+            }
+        }
+        assert_eq!(u16::from_be_bytes([buf[0], buf[1]]), 3);
+    }
+
+    #[tokio::test]
+    async fn drops_answers_longer_than_the_receive_buffer() {
+        let resolver = spawn_resolver(2, None).await;
+        let transport = TcpTransport::new("[::]:0".parse().expect("addr"), None);
+        let mut long = query(1);
+        long.resize(600, 0);
+        transport.send_to(&long, resolver).await.expect("send");
+        transport.send_to(&query(2), resolver).await.expect("send");
+
+        let mut buf = [0u8; 512];
+        let (len, _) = transport.recv_from(&mut buf).await.expect("recv");
+        assert_eq!(len, 12);
+        assert_eq!(u16::from_be_bytes([buf[0], buf[1]]), 2);
+    }
+
+    #[tokio::test]
+    async fn carries_queries_over_tls() {
+        let identity = TestResolverIdentity::new("resolver.example.com");
+        let tls =
+            TlsConfig::new(Some("resolver.example.com"), identity.ca.to_str(), None).expect("tls");
+        let resolver = spawn_resolver(2, Some(identity)).await;
+        let transport = TcpTransport::new("[::]:0".parse().expect("addr"), Some(tls));
+        let mut buf = [0u8; 512];
+
+        for id in [1u16, 2] {
+            transport.send_to(&query(id), resolver).await.expect("send");
+        }
+        for id in [1u16, 2] {
+            let recv = tokio::time::timeout(Duration::from_secs(10), transport.recv_from(&mut buf));
+            let (len, peer) = recv.await.expect("answer in time").expect("recv");
+            assert_eq!((len, peer), (12, resolver));
+            assert_eq!(u16::from_be_bytes([buf[0], buf[1]]), id);
+            assert_ne!(buf[2] & 0x80, 0);
+        }
+    }
+}
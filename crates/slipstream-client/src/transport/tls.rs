@@ -0,0 +1,182 @@
+use openssl::ssl::{SslConnector, SslMethod, SslVersion};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tokio::net::TcpStream;
+use tokio_openssl::SslStream;
+
+use crate::error::ClientError;
+
+/// TLS client settings for the DoT and DoH transports.
+///
+/// Resolvers are verified against the system roots plus any `ca_file`, and
+/// must present a certificate for `server_name`, or for their own address
+/// when it is unset.
+#[derive(Clone)]
+pub(crate) struct TlsConfig {
+    connector: SslConnector,
+    server_name: Option<String>,
+}
+
+impl TlsConfig {
+    /// `alpn` is the application protocol to offer, if any.
+    pub(crate) fn new(
+        server_name: Option<&str>,
+        ca_file: Option<&str>,
+        alpn: Option<&[u8]>,
+    ) -> Result<Self, ClientError> {
+        let tls_error = |err| ClientError::new(format!("DNS TLS setup: {}", err));
+        let mut builder = SslConnector::builder(SslMethod::tls_client()).map_err(tls_error)?;
+        builder
+            .set_min_proto_version(Some(SslVersion::TLS1_2))
+            .map_err(tls_error)?;
+        if let Some(path) = ca_file {
+            builder
+                .set_ca_file(path)
+                .map_err(|err| ClientError::new(format!("DNS TLS CA {}: {}", path, err)))?;
+        }
+        if let Some(alpn) = alpn {
+            let mut protos = Vec::with_capacity(1 + alpn.len());
+            protos.push(alpn.len() as u8);
+            protos.extend_from_slice(alpn);
+            builder.set_alpn_protos(&protos).map_err(tls_error)?;
+        }
+        Ok(Self {
+            connector: builder.build(),
+            server_name: server_name.map(str::to_string),
+        })
+    }
+
+    /// Name sent as SNI and in the HTTP `Host` header; the address when unset.
+    pub(crate) fn host(&self, dest: SocketAddr) -> String {
+        match (&self.server_name, dest) {
+            (Some(name), _) => name.clone(),
+            (None, SocketAddr::V4(addr)) => addr.ip().to_string(),
+            (None, SocketAddr::V6(addr)) => format!("[{}]", addr.ip()),
+        }
+    }
+
+    /// Runs the TLS handshake with the resolver at `dest` over `tcp`.
+    pub(crate) async fn handshake(
+        &self,
+        dest: SocketAddr,
+        tcp: TcpStream,
+    ) -> io::Result<SslStream<TcpStream>> {
+        let name = match &self.server_name {
+            Some(name) => name.clone(),
+            None => dest.ip().to_string(),
+        };
+        // Verifies the certificate against `name`, as a host or an address.
+        let ssl = self
+            .connector
+            .configure()
+            .and_then(|config| config.into_ssl(&name))
+            .map_err(io::Error::other)?;
+        let mut stream = SslStream::new(ssl, tcp).map_err(io::Error::other)?;
+        Pin::new(&mut stream)
+            .connect()
+            .await
+            .map_err(io::Error::other)?;
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::ssl::{Ssl, SslAcceptor, SslMethod};
+    use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+    use openssl::x509::{X509NameBuilder, X509};
+    use std::future::Future;
+    use std::io;
+    use std::path::PathBuf;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpStream;
+    use tokio_openssl::SslStream;
+
+    /// A resolver's TLS identity: a fresh self-signed certificate, written to
+    /// `ca` for clients to trust.
+    pub(crate) struct TestResolverIdentity {
+        acceptor: SslAcceptor,
+        pub(crate) ca: PathBuf,
+    }
+
+    impl TestResolverIdentity {
+        pub(crate) fn new(name: &str) -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let (cert, key) = self_signed(name);
+            let ca = std::env::temp_dir().join(format!(
+                "slipstream-dns-tls-test-{}-{}.pem",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::write(&ca, cert.to_pem().expect("pem")).expect("write ca");
+            let mut acceptor =
+                SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).expect("acceptor");
+            acceptor.set_certificate(&cert).expect("cert");
+            acceptor.set_private_key(&key).expect("key");
+            Self {
+                acceptor: acceptor.build(),
+                ca,
+            }
+        }
+
+        /// Runs the server side of the handshake on an accepted connection.
+        pub(crate) fn accept(
+            &self,
+            tcp: TcpStream,
+        ) -> impl Future<Output = io::Result<SslStream<TcpStream>>> + Send + 'static {
+            let ssl = Ssl::new(self.acceptor.context()).expect("ssl");
+            async move {
+                let mut stream = SslStream::new(ssl, tcp).map_err(io::Error::other)?;
+                Pin::new(&mut stream)
+                    .accept()
+                    .await
+                    .map_err(io::Error::other)?;
+                Ok(stream)
+            }
+        }
+    }
+
+    impl Drop for TestResolverIdentity {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.ca);
+        }
+    }
+
+    /// A self-signed certificate for `name`, trusted as its own CA.
+    fn self_signed(name: &str) -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).expect("group");
+        let key = PKey::from_ec_key(EcKey::generate(&group).expect("ec key")).expect("key");
+        let mut subject = X509NameBuilder::new().expect("name");
+        subject.append_entry_by_text("CN", name).expect("cn");
+        let subject = subject.build();
+        let mut cert = X509::builder().expect("builder");
+        cert.set_version(2).expect("version");
+        let serial = BigNum::from_u32(1).and_then(|serial| serial.to_asn1_integer());
+        cert.set_serial_number(&serial.expect("serial"))
+            .expect("serial");
+        cert.set_subject_name(&subject).expect("subject");
+        cert.set_issuer_name(&subject).expect("issuer");
+        cert.set_pubkey(&key).expect("pubkey");
+        cert.set_not_before(&Asn1Time::days_from_now(0).expect("now"))
+            .expect("not before");
+        cert.set_not_after(&Asn1Time::days_from_now(1).expect("later"))
+            .expect("not after");
+        let constraints = BasicConstraints::new().critical().ca().build();
+        cert.append_extension(constraints.expect("constraints"))
+            .expect("constraints");
+        let san = SubjectAlternativeName::new()
+            .dns(name)
+            .build(&cert.x509v3_context(None, None));
+        cert.append_extension(san.expect("san")).expect("san");
+        cert.sign(&key, MessageDigest::sha256()).expect("sign");
+        (cert.build(), key)
+    }
+}
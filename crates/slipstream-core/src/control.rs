@@ -0,0 +1,374 @@
+use serde_json::{json, Map, Value};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::sync::{mpsc, oneshot};
+
+/// Request bytes one control connection may send before it is cut off.
+const MAX_REQUEST_BYTES: usize = 64 * 1024;
+
+/// One JSON command: `{"command": "...", ...arguments}`.
+#[derive(Debug, Clone)]
+pub struct ControlRequest {
+    pub command: String,
+    args: Map<String, Value>,
+}
+
+impl ControlRequest {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let value: Value =
+            serde_json::from_str(line).map_err(|err| format!("Invalid JSON: {}", err))?;
+        let Value::Object(mut args) = value else {
+            return Err("Request must be a JSON object".to_string());
+        };
+        let command = match args.remove("command") {
+            Some(Value::String(command)) => command,
+            _ => return Err("Request needs a string \"command\" field".to_string()),
+        };
+        Ok(Self { command, args })
+    }
+
+    pub fn str_arg(&self, name: &str) -> Result<&str, String> {
+        match self.args.get(name) {
+            Some(Value::String(value)) => Ok(value),
+            Some(_) => Err(format!("Argument {} must be a string", name)),
+            None => Err(format!("Missing argument {}", name)),
+        }
+    }
+
+    pub fn u64_arg(&self, name: &str) -> Result<u64, String> {
+        match self.args.get(name) {
+            Some(value) => value
+                .as_u64()
+                .ok_or_else(|| format!("Argument {} must be a non-negative integer", name)),
+            None => Err(format!("Missing argument {}", name)),
+        }
+    }
+
+    pub fn opt_str_arg(&self, name: &str) -> Result<Option<&str>, String> {
+        if self.args.contains_key(name) {
+            self.str_arg(name).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// A request waiting for the event loop; the socket task sends the reply back.
+pub struct ControlCall {
+    pub request: ControlRequest,
+    reply: oneshot::Sender<Value>,
+}
+
+impl ControlCall {
+    pub fn respond(self, result: Result<Value, String>) {
+        let _ = self.reply.send(response_value(result));
+    }
+}
+
+fn response_value(result: Result<Value, String>) -> Value {
+    match result {
+        Ok(result) => json!({ "ok": true, "result": result }),
+        Err(error) => json!({ "ok": false, "error": error }),
+    }
+}
+
+/// Event loop side of `--control-socket`; never yields a call when disabled.
+pub struct ControlHandle {
+    rx: Option<mpsc::UnboundedReceiver<ControlCall>>,
+    path: Option<PathBuf>,
+}
+
+impl ControlHandle {
+    pub fn disabled() -> Self {
+        Self {
+            rx: None,
+            path: None,
+        }
+    }
+
+    /// Binds the socket (mode 0600) and starts answering connections on it.
+    ///
+    /// A stale socket file is replaced; one that still accepts connections is not.
+    #[cfg(unix)]
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ));
+            }
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use by another process", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = bind_private(path)?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(serve_control(listener, tx));
+        Ok(Self {
+            rx: Some(rx),
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(_path: &Path) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "control sockets require Unix domain sockets",
+        ))
+    }
+
+    /// Next request from the socket; pending forever when disabled.
+    pub async fn next(&mut self) -> ControlCall {
+        if let Some(rx) = self.rx.as_mut() {
+            if let Some(call) = rx.recv().await {
+                return call;
+            }
+            self.rx = None;
+        }
+        std::future::pending().await
+    }
+}
+
+impl Drop for ControlHandle {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Binds a listener at `path` that only this user could ever connect to.
+///
+/// The socket is created in a fresh 0700 directory next to `path`, made 0600
+/// and then renamed into place, so it is never reachable with looser permissions.
+#[cfg(unix)]
+fn bind_private(path: &Path) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = parent.join(format!(
+        ".slipstream-ctl-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let staged = dir.join("s");
+    let result = tokio::net::UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&dir);
+    result
+}
+
+#[cfg(unix)]
+async fn serve_control(listener: tokio::net::UnixListener, tx: mpsc::UnboundedSender<ControlCall>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(_) => {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let _ = serve_connection(stream, tx).await;
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn serve_connection(
+    stream: tokio::net::UnixStream,
+    tx: mpsc::UnboundedSender<ControlCall>,
+) -> io::Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    let (read_half, mut write_half) = stream.into_split();
+    let mut lines = BufReader::new(read_half.take(MAX_REQUEST_BYTES as u64)).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match ControlRequest::parse(&line) {
+            Ok(request) => {
+                let (reply, reply_rx) = oneshot::channel();
+                if tx.send(ControlCall { request, reply }).is_err() {
+                    return Ok(());
+                }
+                match reply_rx.await {
+                    Ok(response) => response,
+                    Err(_) => return Ok(()),
+                }
+            }
+            Err(err) => response_value(Err(err)),
+        };
+        let mut out = response.to_string();
+        out.push('\n');
+        write_half.write_all(out.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Builds a request from CLI words: a command followed by `key=value` arguments.
+///
+/// Values that parse as unsigned integers are sent as numbers.
+pub fn build_control_request(command: &str, args: &[String]) -> Result<Value, String> {
+    let mut request = Map::new();
+    request.insert("command".to_string(), Value::String(command.to_string()));
+    for arg in args {
+        let (key, value) = arg
+            .split_once('=')
+            .ok_or_else(|| format!("Expected key=value, got {}", arg))?;
+        let value = match value.parse::<u64>() {
+            Ok(number) => Value::from(number),
+            Err(_) => Value::String(value.to_string()),
+        };
+        request.insert(key.to_string(), value);
+    }
+    Ok(Value::Object(request))
+}
+
+/// Sends one request to a control socket and returns the response object.
+#[cfg(unix)]
+pub async fn control_request(path: &Path, request: &Value) -> io::Result<Value> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let stream = tokio::net::UnixStream::connect(path).await?;
+    let (read_half, mut write_half) = stream.into_split();
+    let mut line = request.to_string();
+    line.push('\n');
+    write_half.write_all(line.as_bytes()).await?;
+    let mut response = String::new();
+    BufReader::new(read_half).read_line(&mut response).await?;
+    serde_json::from_str(&response).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(not(unix))]
+pub async fn control_request(_path: &Path, _request: &Value) -> io::Result<Value> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "control sockets require Unix domain sockets",
+    ))
+}
+
+/// Runs a `ctl` subcommand: prints the response and returns the exit code.
+pub fn run_control_cli(socket: &Path, command: &str, args: &[String]) -> i32 {
+    let request = match build_control_request(command, args) {
+        Ok(request) => request,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 2;
+        }
+    };
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("Failed to build Tokio runtime: {}", err);
+            return 1;
+        }
+    };
+    match runtime.block_on(control_request(socket, &request)) {
+        Ok(response) => {
+            let pretty =
+                serde_json::to_string_pretty(&response).unwrap_or_else(|_| response.to_string());
+            println!("{}", pretty);
+            if response.get("ok") == Some(&Value::Bool(true)) {
+                0
+            } else {
+                1
+            }
+        }
+        Err(err) => {
+            eprintln!("{}: {}", socket.display(), err);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_control_request, ControlRequest};
+    use serde_json::json;
+
+    #[cfg(unix)]
+    #[test]
+    fn round_trips_over_socket() {
+        use super::{control_request, ControlHandle};
+        use std::os::unix::fs::PermissionsExt;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .expect("runtime");
+        runtime.block_on(async {
+            let path = std::env::temp_dir().join(format!(
+                "slipstream-control-test-{}.sock",
+                std::process::id()
+            ));
+            let mut handle = ControlHandle::bind(&path).expect("bind");
+            let mode = std::fs::metadata(&path)
+                .expect("metadata")
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+            let client = tokio::spawn({
+                let path = path.clone();
+                async move { control_request(&path, &json!({"command": "status"})).await }
+            });
+            let call = handle.next().await;
+            assert_eq!(call.request.command, "status");
+            call.respond(Ok(json!({"streams": 2})));
+            let response = client.await.expect("join").expect("response");
+            assert_eq!(response, json!({"ok": true, "result": {"streams": 2}}));
+            drop(handle);
+            assert!(!path.exists());
+        });
+    }
+
+    #[test]
+    fn parses_command_and_arguments() {
+        let request =
+            ControlRequest::parse(r#"{"command":"close-stream","id":4,"resolver":"1.1.1.1:53"}"#)
+                .expect("request");
+        assert_eq!(request.command, "close-stream");
+        assert_eq!(request.u64_arg("id"), Ok(4));
+        assert_eq!(request.str_arg("resolver"), Ok("1.1.1.1:53"));
+        assert!(request.u64_arg("resolver").is_err());
+        assert!(request.str_arg("missing").is_err());
+        assert_eq!(request.opt_str_arg("mode"), Ok(None));
+        assert!(ControlRequest::parse(r#"{"id":4}"#).is_err());
+        assert!(ControlRequest::parse("[]").is_err());
+    }
+
+    #[test]
+    fn builds_request_from_cli_words() {
+        let args = vec!["id=7".to_string(), "mode=recursive".to_string()];
+        assert_eq!(
+            build_control_request("close-stream", &args),
+            Ok(json!({"command": "close-stream", "id": 7, "mode": "recursive"}))
+        );
+        assert!(build_control_request("status", &["oops".to_string()]).is_err());
+    }
+}
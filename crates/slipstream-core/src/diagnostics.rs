@@ -0,0 +1,211 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Starts a stream that asks `slipstream-server --enable-diagnostics` for a
+/// built-in service instead of the target. The magic is followed by a command
+/// and a newline; everything after the newline belongs to the service. The
+/// leading NUL keeps it clear of text protocols a target might speak.
+pub const DIAGNOSTIC_MAGIC: &[u8] = b"\0SLIPSTREAM-DIAG/1 ";
+/// Longest preface the server buffers before giving up on a newline.
+pub const MAX_PREFACE_LEN: usize = 64;
+
+/// A built-in server service, opened by a stream preface or named as the
+/// server's `--target-address`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticRequest {
+    /// Read and drop everything; reply with the byte count once the client
+    /// finishes sending.
+    Discard,
+    /// Send `bytes` pseudo-random bytes, then FIN.
+    Source { bytes: u64 },
+    /// Send back whatever arrives.
+    Echo,
+    /// Send `bytes` of the RFC 864 character pattern, then FIN.
+    Chargen { bytes: u64 },
+    /// Answer each line at once with the line and the server's receive time
+    /// in microseconds since the Unix epoch.
+    Latency,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Preface {
+    /// The stream does not start with the magic.
+    Absent,
+    /// Could still become a diagnostic preface; wait for more bytes.
+    Incomplete,
+    /// A complete preface of `len` bytes.
+    Request {
+        request: DiagnosticRequest,
+        len: usize,
+    },
+    /// The magic matched but the command did not parse.
+    Invalid(String),
+}
+
+impl DiagnosticRequest {
+    /// Bytes the client writes first to open this service.
+    pub fn preface(self) -> Vec<u8> {
+        let mut preface = DIAGNOSTIC_MAGIC.to_vec();
+        preface.extend_from_slice(self.to_string().as_bytes());
+        preface.push(b'\n');
+        preface
+    }
+}
+
+impl fmt::Display for DiagnosticRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiagnosticRequest::Discard => f.write_str("discard"),
+            DiagnosticRequest::Source { bytes } => write!(f, "source:{}", bytes),
+            DiagnosticRequest::Echo => f.write_str("echo"),
+            DiagnosticRequest::Chargen { bytes } => write!(f, "chargen:{}", bytes),
+            DiagnosticRequest::Latency => f.write_str("latency"),
+        }
+    }
+}
+
+/// Parses `discard`, `source:SIZE`, `echo`, `chargen:SIZE` or `latency`; SIZE
+/// takes an optional k, m or g suffix (powers of 1024). The services that
+/// generate data need a size so no stream runs forever.
+impl FromStr for DiagnosticRequest {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.split_once(':') {
+            None => match input {
+                "discard" => Ok(DiagnosticRequest::Discard),
+                "echo" => Ok(DiagnosticRequest::Echo),
+                "latency" => Ok(DiagnosticRequest::Latency),
+                "source" | "chargen" => Err(format!(
+                    "Diagnostic service {:?} needs a size, e.g. {}:10m",
+                    input, input
+                )),
+                other => Err(format!("Unknown diagnostic service {:?}", other)),
+            },
+            Some(("source", size)) => Ok(DiagnosticRequest::Source {
+                bytes: parse_size(size)?,
+            }),
+            Some(("chargen", size)) => Ok(DiagnosticRequest::Chargen {
+                bytes: parse_size(size)?,
+            }),
+            Some(_) => Err(format!("Unknown diagnostic service {:?}", input)),
+        }
+    }
+}
+
+/// Parses a byte count with an optional k, m or g suffix (powers of 1024).
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let (digits, scale) = match input.as_bytes().last() {
+        Some(b'k' | b'K') => (&input[..input.len() - 1], 1 << 10),
+        Some(b'm' | b'M') => (&input[..input.len() - 1], 1 << 20),
+        Some(b'g' | b'G') => (&input[..input.len() - 1], 1 << 30),
+        _ => (input, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(scale))
+        .ok_or_else(|| format!("Invalid size {:?}", input))
+}
+
+/// Classifies the first bytes of a stream.
+pub fn parse_preface(buf: &[u8]) -> Preface {
+    let magic_len = DIAGNOSTIC_MAGIC.len().min(buf.len());
+    if buf[..magic_len] != DIAGNOSTIC_MAGIC[..magic_len] {
+        return Preface::Absent;
+    }
+    if buf.len() < DIAGNOSTIC_MAGIC.len() {
+        return Preface::Incomplete;
+    }
+    let Some(newline) = buf.iter().position(|byte| *byte == b'\n') else {
+        if buf.len() >= MAX_PREFACE_LEN {
+            return Preface::Invalid("Diagnostic preface is too long".to_string());
+        }
+        return Preface::Incomplete;
+    };
+    let len = newline + 1;
+    if len > MAX_PREFACE_LEN {
+        return Preface::Invalid("Diagnostic preface is too long".to_string());
+    }
+    let command = String::from_utf8_lossy(&buf[DIAGNOSTIC_MAGIC.len()..newline]);
+    match command.trim().parse() {
+        Ok(request) => Preface::Request { request, len },
+        Err(err) => Preface::Invalid(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_requests() {
+        for request in [
+            DiagnosticRequest::Discard,
+            DiagnosticRequest::Source { bytes: 1 << 20 },
+            DiagnosticRequest::Echo,
+            DiagnosticRequest::Chargen { bytes: 4096 },
+            DiagnosticRequest::Latency,
+        ] {
+            let mut stream = request.preface();
+            let len = stream.len();
+            stream.extend_from_slice(b"payload");
+            assert_eq!(parse_preface(&stream), Preface::Request { request, len });
+        }
+    }
+
+    #[test]
+    fn waits_for_partial_prefaces() {
+        let preface = DiagnosticRequest::Echo.preface();
+        assert_eq!(parse_preface(&[]), Preface::Incomplete);
+        assert_eq!(parse_preface(&preface[..3]), Preface::Incomplete);
+        assert_eq!(
+            parse_preface(&preface[..preface.len() - 1]),
+            Preface::Incomplete
+        );
+    }
+
+    #[test]
+    fn passes_other_streams_through() {
+        assert_eq!(parse_preface(b"GET / HTTP/1.1\r\n"), Preface::Absent);
+        assert_eq!(parse_preface(b"\0SLIP"), Preface::Incomplete);
+        assert_eq!(parse_preface(b"\0SLIQ"), Preface::Absent);
+        assert_eq!(parse_preface(b"\x16\x03\x01"), Preface::Absent);
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        let mut unknown = DIAGNOSTIC_MAGIC.to_vec();
+        unknown.extend_from_slice(b"flood\n");
+        assert!(matches!(parse_preface(&unknown), Preface::Invalid(_)));
+
+        let mut endless = DIAGNOSTIC_MAGIC.to_vec();
+        endless.resize(MAX_PREFACE_LEN, b'x');
+        assert!(matches!(parse_preface(&endless), Preface::Invalid(_)));
+    }
+
+    #[test]
+    fn parses_service_names() {
+        assert_eq!("echo".parse(), Ok(DiagnosticRequest::Echo));
+        assert!("chargen".parse::<DiagnosticRequest>().is_err());
+        assert!("source".parse::<DiagnosticRequest>().is_err());
+        assert_eq!(
+            "chargen:10M".parse(),
+            Ok(DiagnosticRequest::Chargen { bytes: 10 << 20 })
+        );
+        assert_eq!(
+            "source:2k".parse(),
+            Ok(DiagnosticRequest::Source { bytes: 2048 })
+        );
+        assert_eq!(
+            "chargen:1500"
+                .parse::<DiagnosticRequest>()
+                .map(|r| r.to_string()),
+            Ok("chargen:1500".to_string())
+        );
+        assert!("chargen:".parse::<DiagnosticRequest>().is_err());
+        assert!("chargen:12x".parse::<DiagnosticRequest>().is_err());
+        assert!("echo:7".parse::<DiagnosticRequest>().is_err());
+        assert!("localhost".parse::<DiagnosticRequest>().is_err());
+    }
+}
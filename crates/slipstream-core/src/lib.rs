@@ -1,8 +1,17 @@
 use std::fmt;
 
+pub mod control;
+pub mod diagnostics;
+pub mod logging;
 mod macros;
+pub mod metrics;
+pub mod pcap;
+pub mod qlog;
+pub mod rng;
 pub mod stream;
 pub mod tcp;
+pub mod transport;
+pub mod udp_batch;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -0,0 +1,319 @@
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+const MAX_REQUEST_BYTES: usize = 4096;
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+        }
+    }
+}
+
+/// Builds an OpenMetrics text exposition one metric family at a time.
+pub struct MetricsEncoder {
+    out: String,
+}
+
+impl MetricsEncoder {
+    pub fn new() -> Self {
+        Self { out: String::new() }
+    }
+
+    /// Starts a metric family; its samples must follow before the next family.
+    pub fn family(&mut self, name: &str, kind: MetricKind, help: &str) {
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind.as_str());
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+    }
+
+    /// Writes a counter sample; `name` is the family name without `_total`.
+    pub fn counter(&mut self, name: &str, labels: &[(&str, &str)], value: u64) {
+        self.sample(&format!("{}_total", name), labels, &value.to_string());
+    }
+
+    pub fn gauge(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.sample(name, labels, &format_float(value));
+    }
+
+    pub fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+        let mut cumulative = 0u64;
+        for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+            cumulative += count;
+            let le = format_float(*bound);
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", &le));
+            self.sample(
+                &format!("{}_bucket", name),
+                &bucket_labels,
+                &cumulative.to_string(),
+            );
+        }
+        let mut bucket_labels = labels.to_vec();
+        bucket_labels.push(("le", "+Inf"));
+        self.sample(
+            &format!("{}_bucket", name),
+            &bucket_labels,
+            &histogram.count.to_string(),
+        );
+        self.sample(
+            &format!("{}_sum", name),
+            labels,
+            &format_float(histogram.sum),
+        );
+        self.sample(
+            &format!("{}_count", name),
+            labels,
+            &histogram.count.to_string(),
+        );
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: &str) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            self.out.push('{');
+            for (index, (key, label)) in labels.iter().enumerate() {
+                if index > 0 {
+                    self.out.push(',');
+                }
+                let _ = write!(self.out, "{}=\"{}\"", key, escape_label(label));
+            }
+            self.out.push('}');
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+
+    pub fn finish(mut self) -> String {
+        self.out.push_str("# EOF\n");
+        self.out
+    }
+}
+
+impl Default for MetricsEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fixed-bucket histogram; `bounds` are upper bounds in ascending order.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        if let Some(index) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.counts[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Adds `other`'s observations; both must share the same bounds.
+    pub fn merge(&mut self, other: &Histogram) {
+        debug_assert_eq!(self.bounds, other.bounds);
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_float(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{:.1}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Latest rendered exposition, shared between the event loop and the HTTP endpoint.
+#[derive(Clone, Default)]
+pub struct MetricsSnapshot {
+    text: Arc<Mutex<String>>,
+}
+
+impl MetricsSnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn publish(&self, text: String) {
+        if let Ok(mut current) = self.text.lock() {
+            *current = text;
+        }
+    }
+
+    fn render(&self) -> String {
+        let text = self
+            .text
+            .lock()
+            .map(|text| text.clone())
+            .unwrap_or_default();
+        if text.is_empty() {
+            MetricsEncoder::new().finish()
+        } else {
+            text
+        }
+    }
+}
+
+/// Serves the snapshot at `GET /metrics`; other paths get 404.
+pub async fn serve_metrics(listener: TcpListener, snapshot: MetricsSnapshot) {
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(_) => {
+                // Back off on errors such as running out of file descriptors.
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let snapshot = snapshot.clone();
+        tokio::spawn(async move {
+            let mut request = Vec::with_capacity(512);
+            let mut buf = [0u8; 512];
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(size) => request.extend_from_slice(&buf[..size]),
+                }
+                if request.len() > MAX_REQUEST_BYTES {
+                    return;
+                }
+            }
+            let response = http_response(&request, &snapshot.render());
+            let _ = stream.write_all(&response).await;
+            let _ = stream.shutdown().await;
+        });
+    }
+}
+
+fn http_response(request: &[u8], body: &str) -> Vec<u8> {
+    let request_line = request
+        .split(|byte| *byte == b'\n')
+        .next()
+        .map(|line| String::from_utf8_lossy(line).trim().to_string())
+        .unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", CONTENT_TYPE, body),
+        (Some("GET"), Some(_)) => ("404 Not Found", "text/plain", "not found\n"),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n",
+        ),
+    };
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+    .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{http_response, Histogram, MetricKind, MetricsEncoder};
+
+    #[test]
+    fn encodes_families_and_samples() {
+        let mut encoder = MetricsEncoder::new();
+        encoder.family("demo_queries", MetricKind::Counter, "Queries sent.");
+        encoder.counter("demo_queries", &[("resolver", "1.1.1.1:53")], 7);
+        encoder.family("demo_rtt_seconds", MetricKind::Gauge, "Smoothed RTT.");
+        encoder.gauge("demo_rtt_seconds", &[("note", "a\"b")], 0.25);
+        let text = encoder.finish();
+        assert_eq!(
+            text,
+            "# TYPE demo_queries counter\n\
+             # HELP demo_queries Queries sent.\n\
+             demo_queries_total{resolver=\"1.1.1.1:53\"} 7\n\
+             # TYPE demo_rtt_seconds gauge\n\
+             # HELP demo_rtt_seconds Smoothed RTT.\n\
+             demo_rtt_seconds{note=\"a\\\"b\"} 0.25\n\
+             # EOF\n"
+        );
+    }
+
+    #[test]
+    fn encodes_cumulative_histogram() {
+        static BOUNDS: [f64; 2] = [0.001, 0.01];
+        let mut histogram = Histogram::new(&BOUNDS);
+        histogram.observe(0.0005);
+        histogram.observe(0.005);
+        histogram.observe(1.0);
+        let mut encoder = MetricsEncoder::new();
+        encoder.histogram("demo_latency_seconds", &[], &histogram);
+        let text = encoder.finish();
+        assert!(text.contains("demo_latency_seconds_bucket{le=\"0.001\"} 1\n"));
+        assert!(text.contains("demo_latency_seconds_bucket{le=\"0.01\"} 2\n"));
+        assert!(text.contains("demo_latency_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("demo_latency_seconds_count 3\n"));
+    }
+
+    #[test]
+    fn merges_histograms() {
+        static BOUNDS: [f64; 2] = [0.001, 0.01];
+        let mut left = Histogram::new(&BOUNDS);
+        left.observe(0.0005);
+        let mut right = Histogram::new(&BOUNDS);
+        right.observe(0.005);
+        right.observe(1.0);
+        left.merge(&right);
+        let mut encoder = MetricsEncoder::new();
+        encoder.histogram("demo_latency_seconds", &[], &left);
+        let text = encoder.finish();
+        assert!(text.contains("demo_latency_seconds_bucket{le=\"0.01\"} 2\n"));
+        assert!(text.contains("demo_latency_seconds_count 3\n"));
+    }
+
+    #[test]
+    fn routes_only_metrics_path() {
+        let ok = String::from_utf8(http_response(b"GET /metrics HTTP/1.1\r\n\r\n", "x 1\n"))
+            .expect("utf8");
+        assert!(ok.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(ok.ends_with("\r\n\r\nx 1\n"));
+        let missing =
+            String::from_utf8(http_response(b"GET / HTTP/1.1\r\n\r\n", "")).expect("utf8");
+        assert!(missing.starts_with("HTTP/1.1 404"));
+    }
+}
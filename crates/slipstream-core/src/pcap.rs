@@ -0,0 +1,494 @@
+use crate::transport::{recv_one_by_one, send_one_by_one};
+#[cfg(target_os = "linux")]
+use crate::udp_batch;
+use crate::udp_batch::{batching_enabled, RecvBatch, SendBatch};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(target_os = "linux")]
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tracing::warn;
+
+// Raw IP packets; the IP version comes from each packet's first nibble.
+const LINKTYPE_RAW: u32 = 101;
+const SNAPLEN: u32 = 65_535;
+const FILE_HEADER_LEN: u64 = 24;
+const RECORD_HEADER_LEN: u64 = 16;
+// Datagrams waiting for the writer thread before new ones are dropped.
+const QUEUE_DEPTH: usize = 4096;
+
+/// Where `--pcap` writes and when it rotates.
+#[derive(Debug, Clone)]
+pub struct PcapConfig {
+    pub path: PathBuf,
+    /// Rotate once the current file would grow past this size; 0 never rotates.
+    pub max_bytes: u64,
+    /// Keep at most this many files, the current one included; 0 keeps all.
+    pub max_files: u64,
+}
+
+struct Datagram {
+    time: SystemTime,
+    src: SocketAddr,
+    dst: SocketAddr,
+    payload: Vec<u8>,
+}
+
+/// Queue in front of the pcap writer thread; cloning shares the same capture.
+#[derive(Clone)]
+pub struct PcapSink {
+    tx: mpsc::SyncSender<Datagram>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl PcapSink {
+    /// Creates the first capture file and starts the writer thread.
+    pub fn open(config: PcapConfig) -> io::Result<Self> {
+        let mut writer = RotatingWriter::create(config)?;
+        let (tx, rx) = mpsc::sync_channel::<Datagram>(QUEUE_DEPTH);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let thread_dropped = dropped.clone();
+        std::thread::Builder::new()
+            .name("slipstream-pcap".to_string())
+            .spawn(move || {
+                while let Ok(first) = rx.recv() {
+                    let mut result = writer.write(&first);
+                    while result.is_ok() {
+                        match rx.try_recv() {
+                            Ok(datagram) => result = writer.write(&datagram),
+                            Err(_) => break,
+                        }
+                    }
+                    if let Err(err) = result.and_then(|()| writer.flush()) {
+                        warn!(error = %err, "Stopping pcap capture after a write error");
+                        return;
+                    }
+                    let dropped = thread_dropped.swap(0, Ordering::Relaxed);
+                    if dropped > 0 {
+                        warn!(
+                            dropped,
+                            "pcap writer fell behind; datagrams were not captured"
+                        );
+                    }
+                }
+                let _ = writer.flush();
+            })?;
+        Ok(Self { tx, dropped })
+    }
+
+    /// Queues one datagram; drops it instead of blocking when the writer is behind.
+    pub fn record(&self, src: SocketAddr, dst: SocketAddr, payload: &[u8]) {
+        let datagram = Datagram {
+            time: SystemTime::now(),
+            src,
+            dst,
+            payload: payload.to_vec(),
+        };
+        if let Err(mpsc::TrySendError::Full(_)) = self.tx.try_send(datagram) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// UDP socket that copies every datagram it sends or receives to an optional capture.
+pub struct CapturedUdpSocket {
+    socket: UdpSocket,
+    local_addr: SocketAddr,
+    pcap: Option<PcapSink>,
+    batched: bool,
+    gso: AtomicBool,
+}
+
+impl CapturedUdpSocket {
+    pub fn new(socket: UdpSocket, pcap: Option<PcapSink>) -> io::Result<Self> {
+        let local_addr = socket.local_addr()?;
+        Ok(Self {
+            socket,
+            local_addr,
+            pcap,
+            batched: batching_enabled(),
+            gso: AtomicBool::new(false),
+        })
+    }
+
+    /// Sends runs of equal-sized datagrams to one peer as a single UDP GSO
+    /// message from now on. Returns false, changing nothing, where the kernel
+    /// or platform cannot.
+    pub fn enable_gso(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        if self.batched && udp_batch::linux::gso_supported(self.socket.as_raw_fd()) {
+            self.gso.store(true, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let sent = self.socket.send_to(buf, target).await?;
+        if let Some(pcap) = self.pcap.as_ref() {
+            pcap.record(self.local_addr, target, &buf[..sent]);
+        }
+        Ok(sent)
+    }
+
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (size, peer) = self.socket.recv_from(buf).await?;
+        self.capture_received(&buf[..size], peer);
+        Ok((size, peer))
+    }
+
+    pub fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (size, peer) = self.socket.try_recv_from(buf)?;
+        self.capture_received(&buf[..size], peer);
+        Ok((size, peer))
+    }
+
+    /// Waits for at least one datagram, then takes whatever else is queued,
+    /// up to the capacity of `batch`; one recvmmsg call where batching is on.
+    pub async fn recv_batch(&self, batch: &mut RecvBatch) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if self.batched {
+            batch.clear();
+            let fd = self.socket.as_raw_fd();
+            self.socket
+                .async_io(Interest::READABLE, || {
+                    udp_batch::linux::recv_mmsg(fd, batch)
+                })
+                .await?;
+            for (payload, peer) in batch.iter() {
+                self.capture_received(payload, peer);
+            }
+            return Ok(());
+        }
+        recv_one_by_one(self, batch).await
+    }
+
+    /// Sends every datagram in `batch`, in order; one sendmmsg call where
+    /// batching is on. Datagrams the kernel rejects are skipped and the first
+    /// error is returned at the end, like `DnsTransport::send_batch`.
+    pub async fn send_batch(&self, batch: &SendBatch) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if self.batched {
+            let fd = self.socket.as_raw_fd();
+            let mut sent = 0;
+            let mut skipped = Vec::new();
+            let mut first_error = None;
+            while sent < batch.len() {
+                let gso = self.gso.load(Ordering::Relaxed);
+                let result = self
+                    .socket
+                    .async_io(Interest::WRITABLE, || {
+                        udp_batch::linux::send_mmsg(fd, self.local_addr, batch, &mut sent, gso)
+                    })
+                    .await;
+                match result {
+                    Ok(()) => {}
+                    Err(err) if gso && udp_batch::linux::is_gso_error(&err) => {
+                        self.gso.store(false, Ordering::Relaxed);
+                        warn!(error = %err, "UDP GSO failed; sending datagrams one by one");
+                    }
+                    Err(err) => {
+                        // sendmmsg only fails on its first message: skip that one.
+                        let failed = if gso {
+                            batch.gso_runs(sent)[0].clone()
+                        } else {
+                            sent..sent + 1
+                        };
+                        sent = failed.end;
+                        skipped.push(failed);
+                        first_error.get_or_insert(err);
+                    }
+                }
+            }
+            if let Some(pcap) = self.pcap.as_ref() {
+                for (index, (payload, dest)) in batch.iter().enumerate() {
+                    if !skipped.iter().any(|range| range.contains(&index)) {
+                        pcap.record(self.local_addr, dest, payload);
+                    }
+                }
+            }
+            return first_error.map_or(Ok(()), Err);
+        }
+        send_one_by_one(self, batch).await
+    }
+
+    fn capture_received(&self, payload: &[u8], peer: SocketAddr) {
+        if let Some(pcap) = self.pcap.as_ref() {
+            pcap.record(peer, self.local_addr, payload);
+        }
+    }
+}
+
+struct RotatingWriter {
+    config: PcapConfig,
+    file: BufWriter<File>,
+    written: u64,
+    rotations: u64,
+}
+
+impl RotatingWriter {
+    fn create(config: PcapConfig) -> io::Result<Self> {
+        let file = create_capture_file(&config.path)?;
+        Ok(Self {
+            config,
+            file,
+            written: FILE_HEADER_LEN,
+            rotations: 0,
+        })
+    }
+
+    fn write(&mut self, datagram: &Datagram) -> io::Result<()> {
+        let packet = encode_packet(datagram.src, datagram.dst, &datagram.payload);
+        let record_len = RECORD_HEADER_LEN + packet.len() as u64;
+        if self.config.max_bytes > 0
+            && self.written > FILE_HEADER_LEN
+            && self.written + record_len > self.config.max_bytes
+        {
+            self.rotate()?;
+        }
+        let since_epoch = datagram.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut header = [0u8; RECORD_HEADER_LEN as usize];
+        header[0..4].copy_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+        header[4..8].copy_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+        header[8..12].copy_from_slice(&(packet.len() as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&(packet.len() as u32).to_le_bytes());
+        self.file.write_all(&header)?;
+        self.file.write_all(&packet)?;
+        self.written += record_len;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    /// Moves the current file to `<path>.<n>` and starts a new one at `<path>`.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.rotations += 1;
+        std::fs::rename(
+            &self.config.path,
+            rotated_path(&self.config.path, self.rotations),
+        )?;
+        if self.config.max_files > 0 && self.rotations >= self.config.max_files {
+            let expired = self.rotations + 1 - self.config.max_files;
+            let _ = std::fs::remove_file(rotated_path(&self.config.path, expired));
+        }
+        self.file = create_capture_file(&self.config.path)?;
+        self.written = FILE_HEADER_LEN;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: u64) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+fn create_capture_file(path: &Path) -> io::Result<BufWriter<File>> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut header = Vec::with_capacity(FILE_HEADER_LEN as usize);
+    header.extend_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes());
+    header.extend_from_slice(&0i32.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&SNAPLEN.to_le_bytes());
+    header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    file.write_all(&header)?;
+    Ok(file)
+}
+
+/// Wraps a UDP payload in synthetic IPv4 or IPv6 and UDP headers.
+///
+/// IPv4-mapped addresses are written as IPv4 so dual-stack sockets capture
+/// like plain ones.
+fn encode_packet(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (8 + payload.len()).min(u16::MAX as usize) as u16;
+    let payload = &payload[..udp_len as usize - 8];
+    let mut udp = Vec::with_capacity(udp_len as usize);
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&udp_len.to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+
+    match unify_families(src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            let mut pseudo = Vec::with_capacity(12);
+            pseudo.extend_from_slice(&src_ip.octets());
+            pseudo.extend_from_slice(&dst_ip.octets());
+            pseudo.extend_from_slice(&[0, 17]);
+            pseudo.extend_from_slice(&udp_len.to_be_bytes());
+            set_udp_checksum(&mut udp, &pseudo);
+
+            let total_len = (20 + udp.len()).min(u16::MAX as usize) as u16;
+            let mut packet = Vec::with_capacity(20 + udp.len());
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&total_len.to_be_bytes());
+            // Identification 0, don't fragment, TTL 64, UDP.
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+            packet.extend_from_slice(&src_ip.octets());
+            packet.extend_from_slice(&dst_ip.octets());
+            let checksum = internet_checksum(&[&packet]);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+            packet.extend_from_slice(&udp);
+            packet
+        }
+        (src_ip, dst_ip) => {
+            let src_ip = to_ipv6(src_ip);
+            let dst_ip = to_ipv6(dst_ip);
+            let mut pseudo = Vec::with_capacity(40);
+            pseudo.extend_from_slice(&src_ip.octets());
+            pseudo.extend_from_slice(&dst_ip.octets());
+            pseudo.extend_from_slice(&(udp_len as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, 17]);
+            set_udp_checksum(&mut udp, &pseudo);
+
+            let mut packet = Vec::with_capacity(40 + udp.len());
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&udp_len.to_be_bytes());
+            // Next header UDP, hop limit 64.
+            packet.extend_from_slice(&[17, 64]);
+            packet.extend_from_slice(&src_ip.octets());
+            packet.extend_from_slice(&dst_ip.octets());
+            packet.extend_from_slice(&udp);
+            packet
+        }
+    }
+}
+
+fn unify_families(src: IpAddr, dst: IpAddr) -> (IpAddr, IpAddr) {
+    let (src, dst) = (unmap(src), unmap(dst));
+    match (src, dst) {
+        (IpAddr::V6(v6), IpAddr::V4(_)) if v6.is_unspecified() => {
+            (IpAddr::V4(Ipv4Addr::UNSPECIFIED), dst)
+        }
+        (IpAddr::V4(_), IpAddr::V6(v6)) if v6.is_unspecified() => {
+            (src, IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+        }
+        _ => (src, dst),
+    }
+}
+
+fn unmap(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+fn set_udp_checksum(udp: &mut [u8], pseudo_header: &[u8]) {
+    let checksum = match internet_checksum(&[pseudo_header, udp]) {
+        // An all-zero UDP checksum means "none", so zero is sent as all ones.
+        0 => 0xffff,
+        checksum => checksum,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+}
+
+fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for part in parts {
+        let mut chunks = part.chunks_exact(2);
+        for chunk in &mut chunks {
+            sum += u32::from(u16::from_be_bytes([chunk[0], chunk[1]]));
+        }
+        if let [last] = chunks.remainder() {
+            sum += u32::from(*last) << 8;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        encode_packet, internet_checksum, rotated_path, Datagram, PcapConfig, RotatingWriter,
+    };
+    use std::net::SocketAddr;
+    use std::time::SystemTime;
+
+    #[test]
+    fn encodes_ipv4_for_mapped_peers() {
+        let src: SocketAddr = "[::ffff:192.0.2.1]:5353".parse().unwrap();
+        let dst: SocketAddr = "[::]:53".parse().unwrap();
+        let packet = encode_packet(src, dst, b"abc");
+        assert_eq!(packet.len(), 20 + 8 + 3);
+        assert_eq!(packet[0], 0x45);
+        assert_eq!(&packet[12..16], &[192, 0, 2, 1]);
+        assert_eq!(&packet[16..20], &[0, 0, 0, 0]);
+        assert_eq!(internet_checksum(&[&packet[..20]]), 0);
+        assert_eq!(u16::from_be_bytes([packet[20], packet[21]]), 5353);
+        assert_eq!(u16::from_be_bytes([packet[24], packet[25]]), 11);
+    }
+
+    #[test]
+    fn encodes_ipv6_with_udp_checksum() {
+        let src: SocketAddr = "[2001:db8::1]:40000".parse().unwrap();
+        let dst: SocketAddr = "[2001:db8::2]:53".parse().unwrap();
+        let packet = encode_packet(src, dst, b"hello");
+        assert_eq!(packet.len(), 40 + 8 + 5);
+        assert_eq!(packet[0] >> 4, 6);
+        let udp = &packet[40..];
+        let mut pseudo = Vec::new();
+        pseudo.extend_from_slice(&packet[8..40]);
+        pseudo.extend_from_slice(&(udp.len() as u32).to_be_bytes());
+        pseudo.extend_from_slice(&[0, 0, 0, 17]);
+        assert_eq!(internet_checksum(&[&pseudo, udp]), 0);
+    }
+
+    #[test]
+    fn ring_keeps_newest_files() {
+        let dir = std::env::temp_dir().join(format!("slipstream-pcap-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let path = dir.join("dns.pcap");
+        let mut writer = RotatingWriter::create(PcapConfig {
+            path: path.clone(),
+            max_bytes: 200,
+            max_files: 3,
+        })
+        .expect("writer");
+        let datagram = Datagram {
+            time: SystemTime::now(),
+            src: "192.0.2.1:53".parse().unwrap(),
+            dst: "192.0.2.2:1000".parse().unwrap(),
+            payload: vec![0; 100],
+        };
+        // Each record is 144 bytes, so every write after the first rotates.
+        for _ in 0..5 {
+            writer.write(&datagram).expect("write");
+        }
+        writer.flush().expect("flush");
+        assert_eq!(std::fs::metadata(&path).expect("current").len(), 24 + 144);
+        assert!(!rotated_path(&path, 2).exists());
+        assert!(rotated_path(&path, 3).exists());
+        assert!(rotated_path(&path, 4).exists());
+        std::fs::remove_dir_all(&dir).expect("cleanup");
+    }
+}
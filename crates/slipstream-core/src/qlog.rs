@@ -0,0 +1,98 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, warn};
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Deletes the oldest `.qlog` files in `dir` until the rest fit in `max_bytes`.
+///
+/// Returns how many files were removed. A trace that is still being written is
+/// only removed once every older trace is gone.
+pub fn prune_qlog_dir(dir: &Path, max_bytes: u64) -> io::Result<usize> {
+    let mut traces = Vec::new();
+    let mut total = 0u64;
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // Nothing to prune until the first connection creates the directory.
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("qlog") {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        total = total.saturating_add(metadata.len());
+        traces.push((modified, path, metadata.len()));
+    }
+    traces.sort();
+    let mut removed = 0;
+    for (_, path, len) in traces {
+        if total <= max_bytes {
+            break;
+        }
+        std::fs::remove_file(&path)?;
+        debug!(path = %path.display(), bytes = len, "Removed qlog trace");
+        total = total.saturating_sub(len);
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// Keeps `dir` under `max_bytes` by pruning it every few seconds; 0 disables the cap.
+pub fn spawn_qlog_pruner(dir: PathBuf, max_bytes: u64) {
+    if max_bytes == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(PRUNE_INTERVAL).await;
+            let dir = dir.clone();
+            let result = tokio::task::spawn_blocking(move || prune_qlog_dir(&dir, max_bytes)).await;
+            if let Ok(Err(err)) = result {
+                warn!(error = %err, "Failed pruning qlog dir");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prune_qlog_dir;
+    use std::fs::File;
+    use std::io::Write;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn prunes_oldest_traces_first() {
+        let dir = std::env::temp_dir().join(format!("slipstream-qlog-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let now = SystemTime::now();
+        for (name, age_secs) in [
+            ("a.server.qlog", 30),
+            ("b.server.qlog", 20),
+            ("c.server.qlog", 10),
+        ] {
+            let mut file = File::create(dir.join(name)).expect("create");
+            file.write_all(&[0u8; 100]).expect("write");
+            file.set_modified(now - Duration::from_secs(age_secs))
+                .expect("mtime");
+        }
+        std::fs::write(dir.join("notes.txt"), [0u8; 500]).expect("write");
+
+        assert_eq!(prune_qlog_dir(&dir, 250).expect("prune"), 1);
+        assert!(!dir.join("a.server.qlog").exists());
+        assert!(dir.join("b.server.qlog").exists());
+        assert_eq!(prune_qlog_dir(&dir, 250).expect("prune"), 0);
+        assert_eq!(prune_qlog_dir(&dir, 0).expect("prune"), 2);
+        assert!(dir.join("notes.txt").exists());
+        std::fs::remove_dir_all(&dir).expect("cleanup");
+    }
+}
@@ -0,0 +1,96 @@
+use crate::pcap::CapturedUdpSocket;
+use crate::udp_batch::{RecvBatch, SendBatch};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+
+/// Carries DNS messages between an event loop and its peers: queries to
+/// resolvers on the client, responses to resolvers on the server.
+///
+/// The loops race `recv_batch` against timers and commands, so it must be
+/// cancel safe: dropping it before it completes must not lose a message.
+pub trait DnsTransport {
+    /// Address reported to picoquic as the local end of every path.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Sends one DNS message to `dest`.
+    fn send_to(&self, packet: &[u8], dest: SocketAddr) -> impl Future<Output = io::Result<()>>;
+
+    /// Waits for the next DNS message and the peer it came from.
+    fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<(usize, SocketAddr)>>;
+
+    /// Like `recv_from`, but fails with `WouldBlock` instead of waiting.
+    fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// Waits for at least one DNS message, then takes whatever else is
+    /// queued, up to the capacity of `batch`.
+    fn recv_batch(&self, batch: &mut RecvBatch) -> impl Future<Output = io::Result<()>> {
+        recv_one_by_one(self, batch)
+    }
+
+    /// Sends every DNS message in `batch`, in order. A message that fails to
+    /// send is skipped; the first such error is returned once the rest of the
+    /// batch went out.
+    fn send_batch(&self, batch: &SendBatch) -> impl Future<Output = io::Result<()>> {
+        send_one_by_one(self, batch)
+    }
+}
+
+pub(crate) async fn recv_one_by_one<T: DnsTransport + ?Sized>(
+    transport: &T,
+    batch: &mut RecvBatch,
+) -> io::Result<()> {
+    batch.clear();
+    let (size, peer) = transport.recv_from(batch.next_slot()).await?;
+    batch.push(size, peer);
+    while !batch.is_full() {
+        match transport.try_recv_from(batch.next_slot()) {
+            Ok((size, peer)) => batch.push(size, peer),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn send_one_by_one<T: DnsTransport + ?Sized>(
+    transport: &T,
+    batch: &SendBatch,
+) -> io::Result<()> {
+    let mut first_error = None;
+    for (packet, dest) in batch.iter() {
+        if let Err(err) = transport.send_to(packet, dest).await {
+            first_error.get_or_insert(err);
+        }
+    }
+    first_error.map_or(Ok(()), Err)
+}
+
+impl DnsTransport for CapturedUdpSocket {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        CapturedUdpSocket::local_addr(self)
+    }
+
+    async fn send_to(&self, packet: &[u8], dest: SocketAddr) -> io::Result<()> {
+        CapturedUdpSocket::send_to(self, packet, dest)
+            .await
+            .map(|_| ())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        CapturedUdpSocket::recv_from(self, buf).await
+    }
+
+    fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        CapturedUdpSocket::try_recv_from(self, buf)
+    }
+
+    async fn recv_batch(&self, batch: &mut RecvBatch) -> io::Result<()> {
+        CapturedUdpSocket::recv_batch(self, batch).await
+    }
+
+    async fn send_batch(&self, batch: &SendBatch) -> io::Result<()> {
+        CapturedUdpSocket::send_batch(self, batch).await
+    }
+}
@@ -0,0 +1,561 @@
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::OnceLock;
+use tracing::warn;
+
+const BATCH_ENV: &str = "SLIPSTREAM_UDP_BATCH";
+static BATCHING: OnceLock<bool> = OnceLock::new();
+
+// UDP_MAX_SEGMENTS in the kernel.
+const GSO_MAX_SEGMENTS: usize = 64;
+// Largest UDP payload over IPv4; one GSO send must fit in a single datagram.
+const GSO_MAX_BYTES: usize = 65_507;
+
+/// Whether UDP sockets move datagrams with recvmmsg/sendmmsg. On by default
+/// on Linux; `SLIPSTREAM_UDP_BATCH=off` falls back to one syscall per
+/// datagram, which is what every other platform does.
+pub fn batching_enabled() -> bool {
+    *BATCHING.get_or_init(|| {
+        let supported = cfg!(target_os = "linux");
+        let Ok(requested) = std::env::var(BATCH_ENV) else {
+            return supported;
+        };
+        match requested.trim().to_ascii_lowercase().as_str() {
+            "" | "auto" => supported,
+            "off" => false,
+            other => {
+                warn!("{}={} is not recognized; using auto", BATCH_ENV, other);
+                supported
+            }
+        }
+    })
+}
+
+/// Datagrams taken from a transport in one go, each in a fixed-size slot.
+pub struct RecvBatch {
+    buf: Vec<u8>,
+    slot_len: usize,
+    received: Vec<(usize, SocketAddr)>,
+}
+
+impl RecvBatch {
+    /// Room for `capacity` datagrams of up to `slot_len` bytes each; longer
+    /// datagrams are truncated, as with `recv_from`.
+    pub fn new(capacity: usize, slot_len: usize) -> Self {
+        let capacity = capacity.max(1);
+        let slot_len = slot_len.max(1);
+        Self {
+            buf: vec![0; capacity * slot_len],
+            slot_len,
+            received: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len() / self.slot_len
+    }
+
+    pub fn len(&self) -> usize {
+        self.received.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.received.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    pub fn clear(&mut self) {
+        self.received.clear();
+    }
+
+    /// The slot the next datagram goes into; panics when the batch is full.
+    pub fn next_slot(&mut self) -> &mut [u8] {
+        let start = self.len() * self.slot_len;
+        &mut self.buf[start..start + self.slot_len]
+    }
+
+    /// Records that the next slot now holds `len` bytes from `peer`.
+    pub fn push(&mut self, len: usize, peer: SocketAddr) {
+        assert!(!self.is_full(), "receive batch is full");
+        self.received.push((len.min(self.slot_len), peer));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> + '_ {
+        self.received
+            .iter()
+            .enumerate()
+            .map(|(index, (len, peer))| {
+                let start = index * self.slot_len;
+                (&self.buf[start..start + len], *peer)
+            })
+    }
+}
+
+/// Datagrams to send in one go, in order.
+#[derive(Debug, Default)]
+pub struct SendBatch {
+    data: Vec<u8>,
+    packets: Vec<(Range<usize>, SocketAddr)>,
+}
+
+impl SendBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, packet: &[u8], dest: SocketAddr) {
+        let start = self.data.len();
+        self.data.extend_from_slice(packet);
+        self.packets.push((start..self.data.len(), dest));
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.packets.clear();
+    }
+
+    pub fn get(&self, index: usize) -> (&[u8], SocketAddr) {
+        let (range, dest) = &self.packets[index];
+        (&self.data[range.clone()], *dest)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> + '_ {
+        (0..self.len()).map(|index| self.get(index))
+    }
+
+    /// Splits the datagrams from `from` on into runs one GSO send can carry:
+    /// consecutive datagrams to the same destination, all as long as the
+    /// first except possibly the last.
+    pub(crate) fn gso_runs(&self, from: usize) -> Vec<Range<usize>> {
+        let mut runs = Vec::new();
+        let mut start = from;
+        while start < self.len() {
+            let (first, dest) = &self.packets[start];
+            let segment = first.len();
+            let mut bytes = segment;
+            let mut end = start + 1;
+            while segment > 0 && end < self.len() && end - start < GSO_MAX_SEGMENTS {
+                let (packet, next_dest) = &self.packets[end];
+                if next_dest != dest
+                    || packet.len() > segment
+                    || bytes + packet.len() > GSO_MAX_BYTES
+                {
+                    break;
+                }
+                bytes += packet.len();
+                end += 1;
+                if packet.len() < segment {
+                    break;
+                }
+            }
+            runs.push(start..end);
+            start = end;
+        }
+        runs
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) mod linux {
+    use super::{RecvBatch, SendBatch};
+    use std::io;
+    use std::mem;
+    use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
+    use std::ops::Range;
+    use std::os::fd::RawFd;
+
+    // Not exported by libc for every Linux target.
+    const UDP_SEGMENT: libc::c_int = 103;
+    // UIO_MAXIOV; sendmmsg and recvmmsg take at most this many messages.
+    const MAX_MESSAGES: usize = 1024;
+    // Room for one cmsghdr carrying a u16, 8-byte aligned.
+    type SegmentControl = [u64; 4];
+
+    /// Moves queued datagrams into the free slots of `batch` without waiting.
+    pub(crate) fn recv_mmsg(fd: RawFd, batch: &mut RecvBatch) -> io::Result<()> {
+        let first = batch.len();
+        let free = (batch.capacity() - first).min(MAX_MESSAGES);
+        if free == 0 {
+            return Ok(());
+        }
+        let slot_len = batch.slot_len;
+        let base = batch.buf.as_mut_ptr();
+        let mut addrs: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; free];
+        let mut iovs: Vec<libc::iovec> = (0..free)
+            .map(|index| libc::iovec {
+                iov_base: unsafe { base.add((first + index) * slot_len) }.cast(),
+                iov_len: slot_len,
+            })
+            .collect();
+        let mut msgs: Vec<libc::mmsghdr> = addrs
+            .iter_mut()
+            .zip(iovs.iter_mut())
+            .map(|(addr, iov)| {
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                msg.msg_hdr.msg_name = (addr as *mut libc::sockaddr_storage).cast();
+                msg.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+                msg.msg_hdr.msg_iov = iov;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg
+            })
+            .collect();
+        let received = unsafe {
+            libc::recvmmsg(
+                fd,
+                msgs.as_mut_ptr(),
+                free as libc::c_uint,
+                libc::MSG_DONTWAIT,
+                std::ptr::null_mut(),
+            )
+        };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for (msg, addr) in msgs.iter().zip(&addrs).take(received as usize) {
+            let peer = from_sockaddr(addr).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "unexpected address family")
+            })?;
+            batch.push(msg.msg_len as usize, peer);
+        }
+        Ok(())
+    }
+
+    /// Sends the datagrams of `batch` from `*sent` on, advancing `*sent` past
+    /// each one the kernel takes. With `gso`, runs of equal-sized datagrams
+    /// to one destination go out as a single UDP_SEGMENT message.
+    pub(crate) fn send_mmsg(
+        fd: RawFd,
+        local: SocketAddr,
+        batch: &SendBatch,
+        sent: &mut usize,
+        gso: bool,
+    ) -> io::Result<()> {
+        while *sent < batch.len() {
+            let mut runs: Vec<Range<usize>> = if gso {
+                batch.gso_runs(*sent)
+            } else {
+                (*sent..batch.len()).map(|index| index..index + 1).collect()
+            };
+            runs.truncate(MAX_MESSAGES);
+            let packets = runs[0].start..runs[runs.len() - 1].end;
+
+            let mut iovs: Vec<libc::iovec> = packets
+                .clone()
+                .map(|index| {
+                    let (packet, _) = batch.get(index);
+                    libc::iovec {
+                        iov_base: packet.as_ptr() as *mut libc::c_void,
+                        iov_len: packet.len(),
+                    }
+                })
+                .collect();
+            let mut addrs: Vec<(libc::sockaddr_storage, libc::socklen_t)> = runs
+                .iter()
+                .map(|run| to_sockaddr(batch.get(run.start).1, local))
+                .collect();
+            let mut controls: Vec<SegmentControl> = vec![[0; 4]; runs.len()];
+            let mut msgs: Vec<libc::mmsghdr> = Vec::with_capacity(runs.len());
+            for ((run, addr), control) in runs.iter().zip(addrs.iter_mut()).zip(controls.iter_mut())
+            {
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                msg.msg_hdr.msg_name = (&mut addr.0 as *mut libc::sockaddr_storage).cast();
+                msg.msg_hdr.msg_namelen = addr.1;
+                msg.msg_hdr.msg_iov = unsafe { iovs.as_mut_ptr().add(run.start - packets.start) };
+                msg.msg_hdr.msg_iovlen = run.len() as _;
+                if run.len() > 1 {
+                    let segment = batch.get(run.start).0.len() as u16;
+                    unsafe { set_segment_size(&mut msg.msg_hdr, control, segment) };
+                }
+                msgs.push(msg);
+            }
+
+            let sent_msgs =
+                unsafe { libc::sendmmsg(fd, msgs.as_mut_ptr(), msgs.len() as libc::c_uint, 0) };
+            if sent_msgs < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(last) = runs.get((sent_msgs as usize).wrapping_sub(1)) {
+                *sent = last.end;
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn set_segment_size(msg: &mut libc::msghdr, control: &mut SegmentControl, segment: u16) {
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = libc::CMSG_SPACE(mem::size_of::<u16>() as u32) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(msg);
+        (*cmsg).cmsg_level = libc::IPPROTO_UDP;
+        (*cmsg).cmsg_type = UDP_SEGMENT;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as u32) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<u16>(), segment);
+    }
+
+    /// Whether the kernel knows UDP_SEGMENT (Linux 4.18 and later).
+    pub(crate) fn gso_supported(fd: RawFd) -> bool {
+        let mut value: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::IPPROTO_UDP,
+                UDP_SEGMENT,
+                (&mut value as *mut libc::c_int).cast(),
+                &mut len,
+            )
+        };
+        ret == 0
+    }
+
+    /// Errors a device without segmentation offload reports for GSO sends.
+    pub(crate) fn is_gso_error(err: &io::Error) -> bool {
+        matches!(err.raw_os_error(), Some(libc::EIO) | Some(libc::EINVAL))
+    }
+
+    fn to_sockaddr(
+        dest: SocketAddr,
+        local: SocketAddr,
+    ) -> (libc::sockaddr_storage, libc::socklen_t) {
+        // A dual-stack IPv6 socket reaches IPv4 peers through mapped addresses.
+        let dest = match (dest, local) {
+            (SocketAddr::V4(v4), SocketAddr::V6(_)) => {
+                SocketAddr::V6(SocketAddrV6::new(v4.ip().to_ipv6_mapped(), v4.port(), 0, 0))
+            }
+            _ => dest,
+        };
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let len = match dest {
+            SocketAddr::V4(v4) => {
+                let addr = libc::sockaddr_in {
+                    sin_family: libc::AF_INET as libc::sa_family_t,
+                    sin_port: v4.port().to_be(),
+                    sin_addr: libc::in_addr {
+                        s_addr: u32::from_ne_bytes(v4.ip().octets()),
+                    },
+                    sin_zero: [0; 8],
+                };
+                unsafe {
+                    std::ptr::write((&mut storage as *mut libc::sockaddr_storage).cast(), addr)
+                };
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(v6) => {
+                let addr = libc::sockaddr_in6 {
+                    sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                    sin6_port: v6.port().to_be(),
+                    sin6_flowinfo: v6.flowinfo(),
+                    sin6_addr: libc::in6_addr {
+                        s6_addr: v6.ip().octets(),
+                    },
+                    sin6_scope_id: v6.scope_id(),
+                };
+                unsafe {
+                    std::ptr::write((&mut storage as *mut libc::sockaddr_storage).cast(), addr)
+                };
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+        (storage, len as libc::socklen_t)
+    }
+
+    fn from_sockaddr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let addr = unsafe {
+                    &*(storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in>()
+                };
+                Some(SocketAddr::V4(SocketAddrV4::new(
+                    addr.sin_addr.s_addr.to_ne_bytes().into(),
+                    u16::from_be(addr.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                let addr = unsafe {
+                    &*(storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in6>()
+                };
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    addr.sin6_addr.s6_addr.into(),
+                    u16::from_be(addr.sin6_port),
+                    addr.sin6_flowinfo,
+                    addr.sin6_scope_id,
+                )))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn recv_batch_hands_out_slots_in_order() {
+        let mut batch = RecvBatch::new(2, 4);
+        assert_eq!(batch.capacity(), 2);
+        batch.next_slot().copy_from_slice(b"abcd");
+        batch.push(9, addr(1));
+        batch.next_slot()[..2].copy_from_slice(b"ef");
+        batch.push(2, addr(2));
+        assert!(batch.is_full());
+        let received: Vec<_> = batch.iter().collect();
+        assert_eq!(
+            received,
+            vec![(&b"abcd"[..], addr(1)), (&b"ef"[..], addr(2))]
+        );
+        batch.clear();
+        assert!(batch.is_empty());
+    }
+
+    #[test]
+    fn gso_runs_split_on_destination_and_size() {
+        let mut batch = SendBatch::new();
+        for len in [100, 100, 100, 40, 100] {
+            batch.push(&vec![0; len], addr(1));
+        }
+        batch.push(&[0; 100], addr(2));
+        batch.push(&[0; 120], addr(2));
+        assert_eq!(batch.gso_runs(0), vec![0..4, 4..5, 5..6, 6..7]);
+        assert_eq!(batch.gso_runs(2), vec![2..4, 4..5, 5..6, 6..7]);
+    }
+
+    #[test]
+    fn gso_runs_respect_kernel_limits() {
+        let mut batch = SendBatch::new();
+        for _ in 0..70 {
+            batch.push(&[0; 10], addr(1));
+        }
+        assert_eq!(batch.gso_runs(0), vec![0..64, 64..70]);
+
+        let mut batch = SendBatch::new();
+        for _ in 0..3 {
+            batch.push(&[0; 30_000], addr(1));
+        }
+        assert_eq!(batch.gso_runs(0), vec![0..2, 2..3]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn batches_round_trip_over_loopback() {
+        use std::os::fd::AsRawFd;
+
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dest = receiver.local_addr().unwrap();
+        let mut batch = SendBatch::new();
+        for index in 0..5u8 {
+            batch.push(&[index; 8], dest);
+        }
+        batch.push(&[9; 3], dest);
+        let gso = linux::gso_supported(sender.as_raw_fd());
+        let mut sent = 0;
+        linux::send_mmsg(
+            sender.as_raw_fd(),
+            sender.local_addr().unwrap(),
+            &batch,
+            &mut sent,
+            gso,
+        )
+        .unwrap();
+        assert_eq!(sent, 6);
+
+        receiver
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut received = RecvBatch::new(16, 64);
+        while received.len() < 6 {
+            // Block for the first datagram, then take whatever is queued.
+            let mut peek = [0u8; 1];
+            receiver.peek_from(&mut peek).unwrap();
+            linux::recv_mmsg(receiver.as_raw_fd(), &mut received).unwrap();
+        }
+        let from = sender.local_addr().unwrap();
+        let expected: Vec<_> = batch.iter().map(|(packet, _)| (packet, from)).collect();
+        assert_eq!(received.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn captured_sockets_exchange_batches() {
+        use crate::pcap::CapturedUdpSocket;
+        use crate::transport::DnsTransport;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let bind = || async {
+                let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                CapturedUdpSocket::new(socket, None).unwrap()
+            };
+            let (client, server) = (bind().await, bind().await);
+            client.enable_gso();
+            let dest = DnsTransport::local_addr(&server).unwrap();
+            let mut batch = SendBatch::new();
+            for len in [40, 40, 40, 12] {
+                batch.push(&vec![len as u8; len], dest);
+            }
+            DnsTransport::send_batch(&client, &batch).await.unwrap();
+
+            let mut received = RecvBatch::new(8, 512);
+            let mut lens = Vec::new();
+            while lens.len() < 4 {
+                DnsTransport::recv_batch(&server, &mut received)
+                    .await
+                    .unwrap();
+                lens.extend(received.iter().map(|(packet, _)| packet.len()));
+            }
+            assert_eq!(lens, vec![40, 40, 40, 12]);
+        });
+    }
+
+    #[test]
+    fn send_batch_skips_rejected_datagrams() {
+        use crate::pcap::CapturedUdpSocket;
+        use crate::transport::DnsTransport;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let bind = || async {
+                let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                CapturedUdpSocket::new(socket, None).unwrap()
+            };
+            let (client, server) = (bind().await, bind().await);
+            let dest = DnsTransport::local_addr(&server).unwrap();
+            let mut batch = SendBatch::new();
+            // Too long for one UDP datagram, so the kernel rejects it.
+            for len in [10, 70_000, 12] {
+                batch.push(&vec![1; len], dest);
+            }
+            assert!(DnsTransport::send_batch(&client, &batch).await.is_err());
+
+            let mut received = RecvBatch::new(8, 512);
+            let mut lens = Vec::new();
+            while lens.len() < 2 {
+                DnsTransport::recv_batch(&server, &mut received)
+                    .await
+                    .unwrap();
+                lens.extend(received.iter().map(|(packet, _)| packet.len()));
+            }
+            assert_eq!(lens, vec![10, 12]);
+        });
+    }
+}
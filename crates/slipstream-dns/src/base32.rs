@@ -1,6 +1,22 @@
 use std::fmt;
+use std::sync::OnceLock;
+
+#[cfg(target_arch = "aarch64")]
+mod neon;
+#[cfg(target_arch = "x86_64")]
+mod x86;
 
 const ENCODE_TABLE: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const INVALID_VALUE: u8 = 0xff;
+static DECODE_TABLE: [u8; 256] = build_decode_table();
+
+// Inputs are processed in 5-byte / 8-character blocks; SIMD kernels handle a prefix of
+// whole blocks and the scalar block codec finishes the remainder.
+const BLOCK_BYTES: usize = 5;
+const BLOCK_CHARS: usize = 8;
+
+const BACKEND_ENV: &str = "SLIPSTREAM_BASE32_BACKEND";
+static BACKEND: OnceLock<Backend> = OnceLock::new();
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Base32Error {
@@ -22,43 +38,136 @@ impl fmt::Display for Base32Error {
 
 impl std::error::Error for Base32Error {}
 
-pub fn encode(input: &[u8]) -> String {
-    if input.is_empty() {
-        return String::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Sse2,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+    #[cfg(target_arch = "aarch64")]
+    Neon,
+}
+
+impl Backend {
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Scalar => "scalar",
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse2 => "sse2",
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => "avx2",
+            #[cfg(target_arch = "aarch64")]
+            Backend::Neon => "neon",
+        }
     }
+}
 
-    let mut out = String::with_capacity((input.len() * 8).div_ceil(5));
-    let mut buffer: u32 = 0;
-    let mut bits: u8 = 0;
+/// Returns the name of the base32 kernel selected for this process.
+pub fn backend_name() -> &'static str {
+    backend().name()
+}
 
-    for &byte in input {
-        buffer = (buffer << 8) | byte as u32;
-        bits += 8;
+fn backend() -> Backend {
+    *BACKEND.get_or_init(select_backend)
+}
 
-        while bits >= 5 {
-            let shift = bits - 5;
-            let index = ((buffer >> shift) & 0x1f) as usize;
-            out.push(ENCODE_TABLE[index] as char);
-            bits -= 5;
+fn select_backend() -> Backend {
+    let available = available_backends();
+    let best = available[available.len() - 1];
+    let Ok(requested) = std::env::var(BACKEND_ENV) else {
+        return best;
+    };
+    let requested = requested.trim().to_ascii_lowercase();
+    if requested.is_empty() || requested == "auto" {
+        return best;
+    }
+    match available.iter().find(|backend| backend.name() == requested) {
+        Some(backend) => *backend,
+        None => {
+            tracing::warn!(
+                "{}={} is not supported on this CPU; using {}",
+                BACKEND_ENV,
+                requested,
+                best.name()
+            );
+            best
         }
     }
+}
 
-    if bits > 0 {
-        let index = ((buffer << (5 - bits)) & 0x1f) as usize;
-        out.push(ENCODE_TABLE[index] as char);
+/// Backends usable on this CPU, ordered from slowest to fastest.
+fn available_backends() -> Vec<Backend> {
+    #[allow(unused_mut)]
+    let mut backends = vec![Backend::Scalar];
+    #[cfg(target_arch = "x86_64")]
+    {
+        // SSE2 is part of the x86_64 baseline.
+        backends.push(Backend::Sse2);
+        if std::arch::is_x86_feature_detected!("avx2") {
+            backends.push(Backend::Avx2);
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            backends.push(Backend::Neon);
+        }
     }
+    backends
+}
 
-    out
+/// Returns the alphabet character for the low 5 bits of `index`.
+pub(crate) fn encode_symbol(index: u8) -> u8 {
+    ENCODE_TABLE[(index & 0x1f) as usize]
 }
 
-pub fn decode(input: &str) -> Result<Vec<u8>, Base32Error> {
+pub fn encode(input: &[u8]) -> String {
+    encode_with(backend(), input)
+}
+
+fn encode_with(backend: Backend, input: &[u8]) -> String {
     if input.is_empty() {
-        return Ok(Vec::new());
+        return String::new();
     }
 
+    let mut out = vec![0u8; (input.len() * 8).div_ceil(5)];
+    // SAFETY: each kernel is only selected when the CPU supports it, and `out` has room for
+    // the full encoding of `input`.
+    let consumed = match backend {
+        Backend::Scalar => 0,
+        #[cfg(target_arch = "x86_64")]
+        Backend::Sse2 => unsafe { x86::encode_sse2(input, &mut out) },
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx2 => unsafe { x86::encode_avx2(input, &mut out) },
+        #[cfg(target_arch = "aarch64")]
+        Backend::Neon => unsafe { neon::encode_neon(input, &mut out) },
+    };
+    let written = consumed / BLOCK_BYTES * BLOCK_CHARS;
+    encode_scalar(&input[consumed..], &mut out[written..]);
+
+    // SAFETY: every byte of `out` was written from ENCODE_TABLE, which is ASCII.
+    unsafe { String::from_utf8_unchecked(out) }
+}
+
+pub fn decode(input: &str) -> Result<Vec<u8>, Base32Error> {
+    decode_with(backend(), input)
+}
+
+fn decode_with(backend: Backend, input: &str) -> Result<Vec<u8>, Base32Error> {
+    let bytes = input.as_bytes();
+    if bytes.iter().any(|&b| b == b'.' || b == b'=') {
+        let cleaned = strip_dots_and_padding(bytes)?;
+        return decode_data(backend, &cleaned);
+    }
+    decode_data(backend, bytes)
+}
+
+/// Removes inline dots and validates trailing `=` padding, returning the data characters.
+fn strip_dots_and_padding(input: &[u8]) -> Result<Vec<u8>, Base32Error> {
     let mut cleaned = Vec::with_capacity(input.len());
     let mut saw_pad = false;
-    for b in input.bytes() {
+    for &b in input {
         if b == b'.' {
             continue;
         }
@@ -73,10 +182,6 @@ pub fn decode(input: &str) -> Result<Vec<u8>, Base32Error> {
         cleaned.push(b);
     }
 
-    if cleaned.is_empty() {
-        return Ok(Vec::new());
-    }
-
     let mut len = cleaned.len();
     let mut pad = 0usize;
     while len > 0 && cleaned[len - 1] == b'=' {
@@ -93,72 +198,217 @@ pub fn decode(input: &str) -> Result<Vec<u8>, Base32Error> {
         }
     }
 
-    let data = &cleaned[..len];
-    let rem = data.len() % 8;
+    cleaned.truncate(len);
+    Ok(cleaned)
+}
+
+fn decode_data(backend: Backend, data: &[u8]) -> Result<Vec<u8>, Base32Error> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rem = data.len() % BLOCK_CHARS;
     if rem != 0 && rem != 2 && rem != 4 && rem != 5 && rem != 7 {
         return Err(Base32Error::InvalidLength);
     }
 
-    let mut out = Vec::with_capacity(data.len() * 5 / 8 + 4);
-    let mut index = 0usize;
+    let mut out = vec![0u8; data.len() * 5 / 8];
+    // SAFETY: each kernel is only selected when the CPU supports it, and `out` has room for
+    // the full decoding of `data`.
+    let consumed = match backend {
+        Backend::Scalar => 0,
+        #[cfg(target_arch = "x86_64")]
+        Backend::Sse2 => unsafe { x86::decode_sse2(data, &mut out)? },
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx2 => unsafe { x86::decode_avx2(data, &mut out)? },
+        #[cfg(target_arch = "aarch64")]
+        Backend::Neon => unsafe { neon::decode_neon(data, &mut out)? },
+    };
+    let written = consumed / BLOCK_CHARS * BLOCK_BYTES;
+    decode_scalar(&data[consumed..], &mut out[written..])?;
+    Ok(out)
+}
 
-    while index + 8 <= data.len() {
-        let v1 = decode_value(data[index])?;
-        let v2 = decode_value(data[index + 1])?;
-        let v3 = decode_value(data[index + 2])?;
-        let v4 = decode_value(data[index + 3])?;
-        let v5 = decode_value(data[index + 4])?;
-        let v6 = decode_value(data[index + 5])?;
-        let v7 = decode_value(data[index + 6])?;
-        let v8 = decode_value(data[index + 7])?;
+fn encode_scalar(input: &[u8], out: &mut [u8]) {
+    let blocks = input.chunks_exact(BLOCK_BYTES);
+    let tail = blocks.remainder();
+    let mut out_blocks = out.chunks_exact_mut(BLOCK_CHARS);
+    for (block, chars) in blocks.zip(&mut out_blocks) {
+        encode_block(load_block(block), chars);
+    }
+    if !tail.is_empty() {
+        let mut padded = [0u8; BLOCK_BYTES];
+        padded[..tail.len()].copy_from_slice(tail);
+        let mut chars = [0u8; BLOCK_CHARS];
+        encode_block(load_block(&padded), &mut chars);
+        let out_tail = out_blocks.into_remainder();
+        let tail_chars = out_tail.len();
+        out_tail.copy_from_slice(&chars[..tail_chars]);
+    }
+}
+
+fn decode_scalar(data: &[u8], out: &mut [u8]) -> Result<(), Base32Error> {
+    let blocks = data.chunks_exact(BLOCK_CHARS);
+    let tail = blocks.remainder();
+    let mut out_blocks = out.chunks_exact_mut(BLOCK_BYTES);
+    for (chars, block) in blocks.zip(&mut out_blocks) {
+        store_block(decode_block(chars)?, block);
+    }
+    if !tail.is_empty() {
+        let mut padded = [ENCODE_TABLE[0]; BLOCK_CHARS];
+        padded[..tail.len()].copy_from_slice(tail);
+        let mut bytes = [0u8; BLOCK_BYTES];
+        store_block(decode_block(&padded)?, &mut bytes);
+        let out_tail = out_blocks.into_remainder();
+        let tail_bytes = out_tail.len();
+        out_tail.copy_from_slice(&bytes[..tail_bytes]);
+    }
+    Ok(())
+}
+
+/// Loads a 5-byte block as a big-endian 40-bit integer.
+#[inline(always)]
+fn load_block(block: &[u8]) -> u64 {
+    u64::from_be_bytes([0, 0, 0, block[0], block[1], block[2], block[3], block[4]])
+}
 
-        out.push((v1 << 3) | (v2 >> 2));
-        out.push((v2 << 6) | (v3 << 1) | (v4 >> 4));
-        out.push((v4 << 4) | (v5 >> 1));
-        out.push((v5 << 7) | (v6 << 2) | (v7 >> 3));
-        out.push((v7 << 5) | v8);
+/// Stores the low 40 bits of `value` as a big-endian 5-byte block.
+#[inline(always)]
+fn store_block(value: u64, block: &mut [u8]) {
+    block.copy_from_slice(&value.to_be_bytes()[3..]);
+}
 
-        index += 8;
+#[inline(always)]
+fn encode_block(value: u64, chars: &mut [u8]) {
+    for (i, out) in chars.iter_mut().enumerate() {
+        let index = (value >> (35 - 5 * i)) & 0x1f;
+        *out = ENCODE_TABLE[index as usize];
     }
+}
 
-    let remaining = data.len() - index;
-    if remaining > 0 {
-        let v1 = decode_value(data[index])?;
-        let v2 = decode_value(data[index + 1])?;
-        out.push((v1 << 3) | (v2 >> 2));
+#[inline(always)]
+fn decode_block(chars: &[u8]) -> Result<u64, Base32Error> {
+    let mut value = 0u64;
+    let mut invalid = 0u8;
+    for &c in chars {
+        let v = DECODE_TABLE[c as usize];
+        invalid |= v;
+        value = (value << 5) | (v & 0x1f) as u64;
+    }
+    if invalid & 0x80 != 0 {
+        return Err(Base32Error::InvalidChar);
+    }
+    Ok(value)
+}
 
-        if remaining == 2 {
-            return Ok(out);
-        }
+const fn build_decode_table() -> [u8; 256] {
+    let mut table = [INVALID_VALUE; 256];
+    let mut i = 0;
+    while i < 32 {
+        let c = ENCODE_TABLE[i];
+        table[c as usize] = i as u8;
+        table[c.to_ascii_lowercase() as usize] = i as u8;
+        i += 1;
+    }
+    table
+}
 
-        let v3 = decode_value(data[index + 2])?;
-        let v4 = decode_value(data[index + 3])?;
-        out.push((v2 << 6) | (v3 << 1) | (v4 >> 4));
+#[cfg(test)]
+mod tests {
+    use super::{available_backends, decode_with, encode_with, Base32Error};
 
-        if remaining == 4 {
-            return Ok(out);
+    // Byte-at-a-time codec the block kernels replaced; kept as a reference.
+    fn reference_encode(input: &[u8]) -> String {
+        const TABLE: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+        let mut out = String::new();
+        let mut buffer: u32 = 0;
+        let mut bits: u8 = 0;
+        for &byte in input {
+            buffer = (buffer << 8) | byte as u32;
+            bits += 8;
+            while bits >= 5 {
+                let index = ((buffer >> (bits - 5)) & 0x1f) as usize;
+                out.push(TABLE[index] as char);
+                bits -= 5;
+            }
+        }
+        if bits > 0 {
+            let index = ((buffer << (5 - bits)) & 0x1f) as usize;
+            out.push(TABLE[index] as char);
         }
+        out
+    }
 
-        let v5 = decode_value(data[index + 4])?;
-        out.push((v4 << 4) | (v5 >> 1));
+    fn test_bytes(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(2_654_435_761).wrapping_add(1);
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
 
-        if remaining == 5 {
-            return Ok(out);
+    #[test]
+    fn backends_match_reference_encoding() {
+        for backend in available_backends() {
+            for len in 0..300 {
+                let input = test_bytes(len, len as u32);
+                let encoded = encode_with(backend, &input);
+                assert_eq!(
+                    encoded,
+                    reference_encode(&input),
+                    "{:?} len={}",
+                    backend,
+                    len
+                );
+                let decoded = decode_with(backend, &encoded).expect("decode");
+                assert_eq!(decoded, input, "{:?} len={}", backend, len);
+                let lower = encoded.to_ascii_lowercase();
+                let decoded = decode_with(backend, &lower).expect("decode lowercase");
+                assert_eq!(decoded, input, "{:?} len={}", backend, len);
+            }
         }
-
-        let v6 = decode_value(data[index + 5])?;
-        let v7 = decode_value(data[index + 6])?;
-        out.push((v5 << 7) | (v6 << 2) | (v7 >> 3));
     }
 
-    Ok(out)
-}
+    #[test]
+    fn backends_reject_invalid_chars_anywhere() {
+        let encoded = encode_with(available_backends()[0], &test_bytes(100, 7));
+        for backend in available_backends() {
+            for pos in 0..encoded.len() {
+                for bad in [b'0', b'1', b'8', b'@', b'[', b'`', b'{', b'-', 0x80, 0xff] {
+                    let mut bytes = encoded.clone().into_bytes();
+                    bytes[pos] = bad;
+                    let input = String::from_utf8_lossy(&bytes).into_owned();
+                    assert_eq!(
+                        decode_with(backend, &input),
+                        Err(Base32Error::InvalidChar),
+                        "{:?} pos={} byte={:#x}",
+                        backend,
+                        pos,
+                        bad
+                    );
+                }
+            }
+        }
+    }
 
-fn decode_value(b: u8) -> Result<u8, Base32Error> {
-    match b {
-        b'A'..=b'Z' => Ok(b - b'A'),
-        b'a'..=b'z' => Ok(b - b'a'),
-        b'2'..=b'7' => Ok(b - b'2' + 26),
-        _ => Err(Base32Error::InvalidChar),
+    #[test]
+    fn decode_skips_dots_and_checks_padding() {
+        for backend in available_backends() {
+            assert_eq!(decode_with(backend, "NBSW.Y3DP"), Ok(b"hello".to_vec()));
+            assert_eq!(decode_with(backend, "ME======"), Ok(b"a".to_vec()));
+            assert_eq!(decode_with(backend, "..."), Ok(Vec::new()));
+            assert_eq!(
+                decode_with(backend, "ME=A"),
+                Err(Base32Error::InvalidPadding)
+            );
+            assert_eq!(
+                decode_with(backend, "M======="),
+                Err(Base32Error::InvalidPadding)
+            );
+            assert_eq!(decode_with(backend, "MEB"), Err(Base32Error::InvalidLength));
+        }
     }
 }
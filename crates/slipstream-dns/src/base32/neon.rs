@@ -0,0 +1,106 @@
+//! NEON block kernels.
+//!
+//! Same lane-local spread/gather scheme as the x86 kernels, two blocks per 128-bit vector.
+
+use super::{load_block, store_block, Base32Error, BLOCK_BYTES, BLOCK_CHARS};
+use std::arch::aarch64::*;
+
+/// Encodes whole pairs of blocks and returns the number of input bytes consumed.
+///
+/// # Safety
+/// The CPU must support NEON and `out` must hold at least `input.len() / 5 * 8` bytes.
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn encode_neon(input: &[u8], out: &mut [u8]) -> usize {
+    const IN_STEP: usize = 2 * BLOCK_BYTES;
+    const OUT_STEP: usize = 2 * BLOCK_CHARS;
+    let mut consumed = 0;
+    let mut written = 0;
+    while consumed + IN_STEP <= input.len() {
+        let blocks = [
+            load_block(&input[consumed..]),
+            load_block(&input[consumed + BLOCK_BYTES..]),
+        ];
+        let indices = spread(vld1q_u64(blocks.as_ptr()));
+        let chars = indices_to_ascii(indices);
+        vst1q_u8(out[written..written + OUT_STEP].as_mut_ptr(), chars);
+        consumed += IN_STEP;
+        written += OUT_STEP;
+    }
+    consumed
+}
+
+/// Decodes whole pairs of blocks and returns the number of characters consumed.
+///
+/// # Safety
+/// The CPU must support NEON and `out` must hold at least `data.len() / 8 * 5` bytes.
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn decode_neon(data: &[u8], out: &mut [u8]) -> Result<usize, Base32Error> {
+    const IN_STEP: usize = 2 * BLOCK_CHARS;
+    const OUT_STEP: usize = 2 * BLOCK_BYTES;
+    let mut consumed = 0;
+    let mut written = 0;
+    while consumed + IN_STEP <= data.len() {
+        let chars = vld1q_u8(data[consumed..consumed + IN_STEP].as_ptr());
+        let (values, valid) = ascii_to_indices(chars);
+        if vminvq_u8(valid) != 0xff {
+            return Err(Base32Error::InvalidChar);
+        }
+        let mut blocks = [0u64; 2];
+        vst1q_u64(blocks.as_mut_ptr(), gather(values));
+        let out = &mut out[written..written + OUT_STEP];
+        store_block(blocks[0], &mut out[..BLOCK_BYTES]);
+        store_block(blocks[1], &mut out[BLOCK_BYTES..]);
+        consumed += IN_STEP;
+        written += OUT_STEP;
+    }
+    Ok(consumed)
+}
+
+#[inline(always)]
+unsafe fn spread(q: uint64x2_t) -> uint8x16_t {
+    let hi = vshrq_n_u64::<20>(q);
+    let lo = vandq_u64(q, vdupq_n_u64(0xf_ffff));
+    let x = vreinterpretq_u32_u64(vorrq_u64(hi, vshlq_n_u64::<32>(lo)));
+    let hi = vshrq_n_u32::<10>(x);
+    let lo = vandq_u32(x, vdupq_n_u32(0x3ff));
+    let x = vreinterpretq_u16_u32(vorrq_u32(hi, vshlq_n_u32::<16>(lo)));
+    let hi = vshrq_n_u16::<5>(x);
+    let lo = vandq_u16(x, vdupq_n_u16(0x1f));
+    vreinterpretq_u8_u16(vorrq_u16(hi, vshlq_n_u16::<8>(lo)))
+}
+
+#[inline(always)]
+unsafe fn gather(v: uint8x16_t) -> uint64x2_t {
+    let v = vreinterpretq_u16_u8(v);
+    let hi = vandq_u16(v, vdupq_n_u16(0xff));
+    let x = vreinterpretq_u32_u16(vorrq_u16(vshlq_n_u16::<5>(hi), vshrq_n_u16::<8>(v)));
+    let hi = vandq_u32(x, vdupq_n_u32(0xffff));
+    let x = vreinterpretq_u64_u32(vorrq_u32(vshlq_n_u32::<10>(hi), vshrq_n_u32::<16>(x)));
+    let hi = vandq_u64(x, vdupq_n_u64(0xffff_ffff));
+    vorrq_u64(vshlq_n_u64::<20>(hi), vshrq_n_u64::<32>(x))
+}
+
+#[inline(always)]
+unsafe fn indices_to_ascii(indices: uint8x16_t) -> uint8x16_t {
+    let digits = vcgtq_u8(indices, vdupq_n_u8(25));
+    let ascii = vaddq_u8(indices, vdupq_n_u8(b'A'));
+    vsubq_u8(ascii, vandq_u8(digits, vdupq_n_u8(b'A' + 26 - b'2')))
+}
+
+/// Returns the 5-bit values and a mask of lanes that held a valid character.
+#[inline(always)]
+unsafe fn ascii_to_indices(chars: uint8x16_t) -> (uint8x16_t, uint8x16_t) {
+    let upper = vsubq_u8(chars, vdupq_n_u8(b'A'));
+    let lower = vsubq_u8(chars, vdupq_n_u8(b'a'));
+    let digit_offset = vsubq_u8(chars, vdupq_n_u8(b'2'));
+    let is_upper = vcltq_u8(upper, vdupq_n_u8(26));
+    let is_lower = vcltq_u8(lower, vdupq_n_u8(26));
+    let is_digit = vcltq_u8(digit_offset, vdupq_n_u8(6));
+    let digit = vaddq_u8(digit_offset, vdupq_n_u8(26));
+    let values = vorrq_u8(
+        vorrq_u8(vandq_u8(is_upper, upper), vandq_u8(is_lower, lower)),
+        vandq_u8(is_digit, digit),
+    );
+    let valid = vorrq_u8(vorrq_u8(is_upper, is_lower), is_digit);
+    (values, valid)
+}
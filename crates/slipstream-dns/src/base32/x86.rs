@@ -0,0 +1,227 @@
+//! SSE2 and AVX2 block kernels.
+//!
+//! Blocks are loaded as 40-bit big-endian integers (one per 64-bit lane) and spread into
+//! 5-bit indices with lane-local shifts: 40 bits -> 2x20 bits (32-bit lanes) -> 4x10 bits
+//! (16-bit lanes) -> 8x5 bits (bytes). Decoding runs the same steps in reverse. The
+//! index <-> ASCII translation is done with compares instead of table lookups.
+
+use super::{load_block, store_block, Base32Error, BLOCK_BYTES, BLOCK_CHARS};
+use std::arch::x86_64::*;
+
+/// Encodes whole pairs of blocks and returns the number of input bytes consumed.
+///
+/// # Safety
+/// `out` must hold at least `input.len() / 5 * 8` bytes.
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn encode_sse2(input: &[u8], out: &mut [u8]) -> usize {
+    const IN_STEP: usize = 2 * BLOCK_BYTES;
+    const OUT_STEP: usize = 2 * BLOCK_CHARS;
+    let mut consumed = 0;
+    let mut written = 0;
+    while consumed + IN_STEP <= input.len() {
+        let q0 = load_block(&input[consumed..]) as i64;
+        let q1 = load_block(&input[consumed + BLOCK_BYTES..]) as i64;
+        let indices = spread_sse2(_mm_set_epi64x(q1, q0));
+        let chars = indices_to_ascii_sse2(indices);
+        _mm_storeu_si128(out[written..written + OUT_STEP].as_mut_ptr().cast(), chars);
+        consumed += IN_STEP;
+        written += OUT_STEP;
+    }
+    consumed
+}
+
+/// Decodes whole pairs of blocks and returns the number of characters consumed.
+///
+/// # Safety
+/// `out` must hold at least `data.len() / 8 * 5` bytes.
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn decode_sse2(data: &[u8], out: &mut [u8]) -> Result<usize, Base32Error> {
+    const IN_STEP: usize = 2 * BLOCK_CHARS;
+    const OUT_STEP: usize = 2 * BLOCK_BYTES;
+    let mut consumed = 0;
+    let mut written = 0;
+    while consumed + IN_STEP <= data.len() {
+        let chars = _mm_loadu_si128(data[consumed..consumed + IN_STEP].as_ptr().cast());
+        let (values, valid) = ascii_to_indices_sse2(chars);
+        if _mm_movemask_epi8(valid) != 0xffff {
+            return Err(Base32Error::InvalidChar);
+        }
+        let mut blocks = [0u64; 2];
+        _mm_storeu_si128(blocks.as_mut_ptr().cast(), gather_sse2(values));
+        let out = &mut out[written..written + OUT_STEP];
+        store_block(blocks[0], &mut out[..BLOCK_BYTES]);
+        store_block(blocks[1], &mut out[BLOCK_BYTES..]);
+        consumed += IN_STEP;
+        written += OUT_STEP;
+    }
+    Ok(consumed)
+}
+
+/// Encodes whole groups of four blocks and returns the number of input bytes consumed.
+///
+/// # Safety
+/// The CPU must support AVX2 and `out` must hold at least `input.len() / 5 * 8` bytes.
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn encode_avx2(input: &[u8], out: &mut [u8]) -> usize {
+    const IN_STEP: usize = 4 * BLOCK_BYTES;
+    const OUT_STEP: usize = 4 * BLOCK_CHARS;
+    let mut consumed = 0;
+    let mut written = 0;
+    while consumed + IN_STEP <= input.len() {
+        let block = &input[consumed..consumed + IN_STEP];
+        let q0 = load_block(block) as i64;
+        let q1 = load_block(&block[BLOCK_BYTES..]) as i64;
+        let q2 = load_block(&block[2 * BLOCK_BYTES..]) as i64;
+        let q3 = load_block(&block[3 * BLOCK_BYTES..]) as i64;
+        let indices = spread_avx2(_mm256_set_epi64x(q3, q2, q1, q0));
+        let chars = indices_to_ascii_avx2(indices);
+        _mm256_storeu_si256(out[written..written + OUT_STEP].as_mut_ptr().cast(), chars);
+        consumed += IN_STEP;
+        written += OUT_STEP;
+    }
+    // Finish an odd pair with the 128-bit kernel.
+    consumed + encode_sse2(&input[consumed..], &mut out[written..])
+}
+
+/// Decodes whole groups of four blocks and returns the number of characters consumed.
+///
+/// # Safety
+/// The CPU must support AVX2 and `out` must hold at least `data.len() / 8 * 5` bytes.
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn decode_avx2(data: &[u8], out: &mut [u8]) -> Result<usize, Base32Error> {
+    const IN_STEP: usize = 4 * BLOCK_CHARS;
+    const OUT_STEP: usize = 4 * BLOCK_BYTES;
+    let mut consumed = 0;
+    let mut written = 0;
+    while consumed + IN_STEP <= data.len() {
+        let chars = _mm256_loadu_si256(data[consumed..consumed + IN_STEP].as_ptr().cast());
+        let (values, valid) = ascii_to_indices_avx2(chars);
+        if _mm256_movemask_epi8(valid) != -1 {
+            return Err(Base32Error::InvalidChar);
+        }
+        let mut blocks = [0u64; 4];
+        _mm256_storeu_si256(blocks.as_mut_ptr().cast(), gather_avx2(values));
+        let out = &mut out[written..written + OUT_STEP];
+        for (block, chunk) in blocks.iter().zip(out.chunks_exact_mut(BLOCK_BYTES)) {
+            store_block(*block, chunk);
+        }
+        consumed += IN_STEP;
+        written += OUT_STEP;
+    }
+    Ok(consumed + decode_sse2(&data[consumed..], &mut out[written..])?)
+}
+
+#[inline(always)]
+unsafe fn spread_sse2(q: __m128i) -> __m128i {
+    // 40 -> 20 bits: the high half goes to the low (earlier) 32-bit lane.
+    let hi = _mm_srli_epi64::<20>(q);
+    let lo = _mm_and_si128(q, _mm_set1_epi64x(0xf_ffff));
+    let x = _mm_or_si128(hi, _mm_slli_epi64::<32>(lo));
+    // 20 -> 10 bits.
+    let hi = _mm_srli_epi32::<10>(x);
+    let lo = _mm_and_si128(x, _mm_set1_epi32(0x3ff));
+    let x = _mm_or_si128(hi, _mm_slli_epi32::<16>(lo));
+    // 10 -> 5 bits.
+    let hi = _mm_srli_epi16::<5>(x);
+    let lo = _mm_and_si128(x, _mm_set1_epi16(0x1f));
+    _mm_or_si128(hi, _mm_slli_epi16::<8>(lo))
+}
+
+#[inline(always)]
+unsafe fn gather_sse2(v: __m128i) -> __m128i {
+    let hi = _mm_and_si128(v, _mm_set1_epi16(0xff));
+    let x = _mm_or_si128(_mm_slli_epi16::<5>(hi), _mm_srli_epi16::<8>(v));
+    let hi = _mm_and_si128(x, _mm_set1_epi32(0xffff));
+    let x = _mm_or_si128(_mm_slli_epi32::<10>(hi), _mm_srli_epi32::<16>(x));
+    let hi = _mm_and_si128(x, _mm_set1_epi64x(0xffff_ffff));
+    _mm_or_si128(_mm_slli_epi64::<20>(hi), _mm_srli_epi64::<32>(x))
+}
+
+#[inline(always)]
+unsafe fn indices_to_ascii_sse2(indices: __m128i) -> __m128i {
+    // 'A' + i for i < 26, '2' + (i - 26) otherwise.
+    let digits = _mm_cmpgt_epi8(indices, _mm_set1_epi8(25));
+    let ascii = _mm_add_epi8(indices, _mm_set1_epi8(b'A' as i8));
+    _mm_sub_epi8(
+        ascii,
+        _mm_and_si128(digits, _mm_set1_epi8(b'A' as i8 + 26 - b'2' as i8)),
+    )
+}
+
+/// Returns the 5-bit values and a mask of lanes that held a valid character.
+#[inline(always)]
+unsafe fn ascii_to_indices_sse2(chars: __m128i) -> (__m128i, __m128i) {
+    let upper = _mm_sub_epi8(chars, _mm_set1_epi8(b'A' as i8));
+    let lower = _mm_sub_epi8(chars, _mm_set1_epi8(b'a' as i8));
+    let digit = _mm_sub_epi8(chars, _mm_set1_epi8(b'2' as i8 - 26));
+    // Unsigned range checks: x < n  <=>  min(x, n - 1) == x.
+    let is_upper = _mm_cmpeq_epi8(_mm_min_epu8(upper, _mm_set1_epi8(25)), upper);
+    let is_lower = _mm_cmpeq_epi8(_mm_min_epu8(lower, _mm_set1_epi8(25)), lower);
+    let digit_offset = _mm_sub_epi8(digit, _mm_set1_epi8(26));
+    let is_digit = _mm_cmpeq_epi8(_mm_min_epu8(digit_offset, _mm_set1_epi8(5)), digit_offset);
+    let values = _mm_or_si128(
+        _mm_or_si128(
+            _mm_and_si128(is_upper, upper),
+            _mm_and_si128(is_lower, lower),
+        ),
+        _mm_and_si128(is_digit, digit),
+    );
+    let valid = _mm_or_si128(_mm_or_si128(is_upper, is_lower), is_digit);
+    (values, valid)
+}
+
+#[inline(always)]
+unsafe fn spread_avx2(q: __m256i) -> __m256i {
+    let hi = _mm256_srli_epi64::<20>(q);
+    let lo = _mm256_and_si256(q, _mm256_set1_epi64x(0xf_ffff));
+    let x = _mm256_or_si256(hi, _mm256_slli_epi64::<32>(lo));
+    let hi = _mm256_srli_epi32::<10>(x);
+    let lo = _mm256_and_si256(x, _mm256_set1_epi32(0x3ff));
+    let x = _mm256_or_si256(hi, _mm256_slli_epi32::<16>(lo));
+    let hi = _mm256_srli_epi16::<5>(x);
+    let lo = _mm256_and_si256(x, _mm256_set1_epi16(0x1f));
+    _mm256_or_si256(hi, _mm256_slli_epi16::<8>(lo))
+}
+
+#[inline(always)]
+unsafe fn gather_avx2(v: __m256i) -> __m256i {
+    let hi = _mm256_and_si256(v, _mm256_set1_epi16(0xff));
+    let x = _mm256_or_si256(_mm256_slli_epi16::<5>(hi), _mm256_srli_epi16::<8>(v));
+    let hi = _mm256_and_si256(x, _mm256_set1_epi32(0xffff));
+    let x = _mm256_or_si256(_mm256_slli_epi32::<10>(hi), _mm256_srli_epi32::<16>(x));
+    let hi = _mm256_and_si256(x, _mm256_set1_epi64x(0xffff_ffff));
+    _mm256_or_si256(_mm256_slli_epi64::<20>(hi), _mm256_srli_epi64::<32>(x))
+}
+
+#[inline(always)]
+unsafe fn indices_to_ascii_avx2(indices: __m256i) -> __m256i {
+    let digits = _mm256_cmpgt_epi8(indices, _mm256_set1_epi8(25));
+    let ascii = _mm256_add_epi8(indices, _mm256_set1_epi8(b'A' as i8));
+    _mm256_sub_epi8(
+        ascii,
+        _mm256_and_si256(digits, _mm256_set1_epi8(b'A' as i8 + 26 - b'2' as i8)),
+    )
+}
+
+#[inline(always)]
+unsafe fn ascii_to_indices_avx2(chars: __m256i) -> (__m256i, __m256i) {
+    let upper = _mm256_sub_epi8(chars, _mm256_set1_epi8(b'A' as i8));
+    let lower = _mm256_sub_epi8(chars, _mm256_set1_epi8(b'a' as i8));
+    let digit = _mm256_sub_epi8(chars, _mm256_set1_epi8(b'2' as i8 - 26));
+    let is_upper = _mm256_cmpeq_epi8(_mm256_min_epu8(upper, _mm256_set1_epi8(25)), upper);
+    let is_lower = _mm256_cmpeq_epi8(_mm256_min_epu8(lower, _mm256_set1_epi8(25)), lower);
+    let digit_offset = _mm256_sub_epi8(digit, _mm256_set1_epi8(26));
+    let is_digit = _mm256_cmpeq_epi8(
+        _mm256_min_epu8(digit_offset, _mm256_set1_epi8(5)),
+        digit_offset,
+    );
+    let values = _mm256_or_si256(
+        _mm256_or_si256(
+            _mm256_and_si256(is_upper, upper),
+            _mm256_and_si256(is_lower, lower),
+        ),
+        _mm256_and_si256(is_digit, digit),
+    );
+    let valid = _mm256_or_si256(_mm256_or_si256(is_upper, is_lower), is_digit);
+    (values, valid)
+}
@@ -1,6 +1,7 @@
 use slipstream_dns::{
-    build_qname, decode_query, decode_response, encode_query, encode_response,
-    max_payload_len_for_domain, QueryParams, Question, ResponseParams, CLASS_IN, RR_TXT,
+    base32_backend, base32_decode, base32_encode, build_qname, decode_query, decode_response,
+    encode_query, encode_response, max_payload_len_for_domain, QueryParams, Question,
+    ResponseParams, CLASS_IN, RR_TXT,
 };
 use std::env;
 use std::time::Instant;
@@ -80,6 +81,14 @@ fn main() {
     };
     let response = encode_response(&response_params).expect("encode response");
 
+    println!("base32 backend: {}", base32_backend());
+    let encoded = base32_encode(&payload);
+    bench("base32_encode", iterations, payload_len, || {
+        let _ = base32_encode(&payload);
+    });
+    bench("base32_decode", iterations, encoded.len(), || {
+        let _ = base32_decode(&encoded).expect("base32 decode");
+    });
     bench("build_qname", iterations, payload_len, || {
         let _ = build_qname(&payload, &domain).expect("build qname");
     });
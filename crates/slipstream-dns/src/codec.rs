@@ -1,20 +1,27 @@
 use crate::base32;
 use crate::dots;
+use crate::layout::{SEQUENCE_LABEL_LEN, SEQUENCE_MARKER};
+use crate::probe::parse_probe_subdomain;
+use crate::segment::{SegmentHeader, SEGMENT_MARKER};
 
 use crate::name::{encode_name, extract_subdomain_multi, parse_name};
 use crate::types::{
-    DecodeQueryError, DecodedQuery, DnsError, QueryParams, Rcode, ResponseParams, EDNS_UDP_PAYLOAD,
-    RR_OPT, RR_TXT,
+    DecodeQueryError, DecodedQuery, DnsError, QueryParams, Rcode, ResponseError, ResponseMode,
+    ResponseParams, EDNS_UDP_PAYLOAD, RR_CNAME, RR_OPT, RR_TXT,
 };
 use crate::wire::{
-    parse_header, parse_question, parse_question_for_reply, read_u16, read_u32, write_u16,
-    write_u32,
+    max_udp_response_len, parse_header, parse_question, parse_question_for_reply, read_u16,
+    read_u32, write_u16, write_u32,
 };
 
 pub fn decode_query(packet: &[u8], domain: &str) -> Result<DecodedQuery, DecodeQueryError> {
     decode_query_with_domains(packet, &[domain])
 }
 
+/// Decodes a query under any of `domains`, whatever [`LabelLayout`] the client
+/// encoded it with.
+///
+/// [`LabelLayout`]: crate::LabelLayout
 pub fn decode_query_with_domains(
     packet: &[u8],
     domains: &[&str],
@@ -49,18 +56,27 @@ pub fn decode_query_with_domains(
         });
     }
 
-    let question = match parse_question(packet, header.offset) {
-        Ok((question, _)) => question,
+    let (question, max_response_len) = match parse_question(packet, header.offset) {
+        Ok((question, offset)) => (question, max_udp_response_len(packet, &header, offset)),
         Err(_) => return Err(DecodeQueryError::Drop),
     };
 
     if question.qtype != RR_TXT {
+        // Probes of other types check resolver qtype filtering; answer them with NODATA.
+        let is_probe = extract_subdomain_multi(&question.name, domains)
+            .ok()
+            .and_then(|subdomain| parse_probe_subdomain(&subdomain))
+            .is_some();
         return Err(DecodeQueryError::Reply {
             id: header.id,
             rd,
             cd,
             question: Some(question),
-            rcode: Rcode::NameError,
+            rcode: if is_probe {
+                Rcode::Ok
+            } else {
+                Rcode::NameError
+            },
         });
     }
 
@@ -77,7 +93,55 @@ pub fn decode_query_with_domains(
         }
     };
 
-    let undotted = dots::undotify(&subdomain_raw);
+    if let Some(probe) = parse_probe_subdomain(&subdomain_raw) {
+        return Ok(DecodedQuery {
+            id: header.id,
+            rd,
+            cd,
+            question,
+            payload: Vec::new(),
+            segment: None,
+            max_response_len,
+            probe: Some(probe),
+        });
+    }
+
+    // The sequence label only varies the QNAME; it carries no payload.
+    let (first_label, rest) = subdomain_raw
+        .split_once('.')
+        .unwrap_or((subdomain_raw.as_str(), ""));
+    let data_labels = if first_label.as_bytes().first() == Some(&SEQUENCE_MARKER) {
+        if first_label.len() != SEQUENCE_LABEL_LEN {
+            return Err(DecodeQueryError::Reply {
+                id: header.id,
+                rd,
+                cd,
+                question: Some(question),
+                rcode: Rcode::ServerFailure,
+            });
+        }
+        rest
+    } else {
+        subdomain_raw.as_str()
+    };
+    let (segment, data_labels) = match data_labels.split_once('.') {
+        Some((label, rest)) if label.as_bytes().first() == Some(&SEGMENT_MARKER) => {
+            match SegmentHeader::parse_label(label) {
+                Some(header) => (Some(header), rest),
+                None => {
+                    return Err(DecodeQueryError::Reply {
+                        id: header.id,
+                        rd,
+                        cd,
+                        question: Some(question),
+                        rcode: Rcode::ServerFailure,
+                    })
+                }
+            }
+        }
+        _ => (None, data_labels),
+    };
+    let undotted = dots::undotify(data_labels);
     if undotted.is_empty() {
         return Err(DecodeQueryError::Reply {
             id: header.id,
@@ -107,6 +171,9 @@ pub fn decode_query_with_domains(
         cd,
         question,
         payload,
+        segment,
+        max_response_len,
+        probe: None,
     })
 }
 
@@ -141,6 +208,19 @@ pub fn encode_query(params: &QueryParams<'_>) -> Result<Vec<u8>, DnsError> {
     Ok(out)
 }
 
+/// Largest TXT payload whose [`encode_response`] output for `qname` fits in `message_len` bytes.
+pub fn max_response_payload_len(message_len: usize, qname: &str) -> usize {
+    // Header, question, compressed answer name + fixed RR fields, and the OPT record.
+    let qname_wire_len = qname.trim_end_matches('.').len() + 2;
+    let overhead = 12 + qname_wire_len + 4 + 12 + 11;
+    let rdata_len = message_len.saturating_sub(overhead);
+    let mut payload_len = rdata_len - rdata_len.div_ceil(256);
+    while payload_len + payload_len.div_ceil(255) > rdata_len {
+        payload_len -= 1;
+    }
+    payload_len.min(u16::MAX as usize)
+}
+
 pub fn encode_response(params: &ResponseParams<'_>) -> Result<Vec<u8>, DnsError> {
     let payload_len = params.payload.map(|payload| payload.len()).unwrap_or(0);
 
@@ -207,67 +287,199 @@ pub fn encode_response(params: &ResponseParams<'_>) -> Result<Vec<u8>, DnsError>
     Ok(out)
 }
 
-pub fn decode_response(packet: &[u8]) -> Option<Vec<u8>> {
-    let header = parse_header(packet)?;
+// EDNS(0) Padding option (RFC 7830).
+const EDNS_OPTION_PADDING: u16 = 12;
+const EDNS_OPTION_HEADER_LEN: usize = 4;
+
+/// Encodes a response padded to a multiple of `block_len` bytes with EDNS(0) padding.
+///
+/// Padding never grows the message past `max_len`; a message that cannot reach the
+/// next multiple within `max_len` is padded up to `max_len` instead. A `block_len` of
+/// 0 disables padding.
+pub fn encode_response_padded(
+    params: &ResponseParams<'_>,
+    block_len: usize,
+    max_len: usize,
+) -> Result<Vec<u8>, DnsError> {
+    let mut out = encode_response(params)?;
+    let unpadded = out.len() + EDNS_OPTION_HEADER_LEN;
+    if block_len == 0 || unpadded > max_len {
+        return Ok(out);
+    }
+    let pad_len = ((block_len - unpadded % block_len) % block_len).min(max_len - unpadded);
+    // The OPT record is last and its RDATA is still empty.
+    let rdlen_at = out.len() - 2;
+    let rdlen = (EDNS_OPTION_HEADER_LEN + pad_len) as u16;
+    out[rdlen_at..].copy_from_slice(&rdlen.to_be_bytes());
+    write_u16(&mut out, EDNS_OPTION_PADDING);
+    write_u16(&mut out, pad_len as u16);
+    out.resize(out.len() + pad_len, 0);
+    Ok(out)
+}
+
+pub fn decode_response(packet: &[u8]) -> Result<Vec<u8>, ResponseError> {
+    decode_response_with_mode(packet, ResponseMode::Strict)
+}
+
+pub fn decode_response_with_mode(
+    packet: &[u8],
+    mode: ResponseMode,
+) -> Result<Vec<u8>, ResponseError> {
+    let header = parse_header(packet).ok_or(ResponseError::Malformed("header"))?;
     if !header.is_response {
-        return None;
+        return Err(ResponseError::NotResponse);
     }
-    let rcode = header.rcode?;
-    if rcode != Rcode::Ok {
-        return None;
+    if header.rcode != Some(Rcode::Ok) {
+        return Err(ResponseError::Rcode(header.rcode_value));
+    }
+    if header.ancount == 0 {
+        return Err(if header.truncated {
+            ResponseError::Truncated
+        } else {
+            ResponseError::NoData
+        });
     }
-    if header.ancount != 1 {
-        return None;
+    if mode == ResponseMode::Strict && header.ancount != 1 {
+        return Err(ResponseError::AnswerCount(header.ancount));
     }
 
     let mut offset = header.offset;
+    let mut qname = None;
     for _ in 0..header.qdcount {
-        let (_, new_offset) = parse_name(packet, offset).ok()?;
+        let (name, new_offset) =
+            parse_name(packet, offset).map_err(|_| ResponseError::Malformed("question"))?;
         offset = new_offset;
         if offset + 4 > packet.len() {
-            return None;
+            return Err(ResponseError::Malformed("question"));
         }
         offset += 4;
+        qname.get_or_insert(name);
     }
 
-    let (_, new_offset) = parse_name(packet, offset).ok()?;
-    offset = new_offset;
+    let mut answers = Vec::with_capacity(header.ancount as usize);
+    for _ in 0..header.ancount {
+        let (answer, new_offset) = parse_answer(packet, offset)?;
+        offset = new_offset;
+        answers.push(answer);
+    }
+
+    match mode {
+        ResponseMode::Strict => {
+            let answer = &answers[0];
+            if answer.rtype != RR_TXT {
+                return Err(ResponseError::AnswerType(answer.rtype));
+            }
+            decode_txt(packet, answer)
+        }
+        ResponseMode::Tolerant => decode_tolerant(packet, &answers, qname.as_deref()),
+    }
+}
+
+// Longest CNAME chain followed in tolerant mode.
+const MAX_CNAME_CHAIN: usize = 8;
+
+struct Answer {
+    owner: String,
+    rtype: u16,
+    rdata: usize,
+    rdlen: usize,
+}
+
+fn parse_answer(packet: &[u8], offset: usize) -> Result<(Answer, usize), ResponseError> {
+    let malformed = ResponseError::Malformed("answer");
+    let (owner, mut offset) = parse_name(packet, offset).map_err(|_| malformed.clone())?;
     if offset + 10 > packet.len() {
-        return None;
+        return Err(malformed);
     }
-    let qtype = read_u16(packet, offset)?;
+    let rtype = read_u16(packet, offset).ok_or(malformed.clone())?;
     offset += 2;
-    let _qclass = read_u16(packet, offset)?;
+    let _class = read_u16(packet, offset).ok_or(malformed.clone())?;
     offset += 2;
-    let _ttl = read_u32(packet, offset)?;
+    let _ttl = read_u32(packet, offset).ok_or(malformed.clone())?;
     offset += 4;
-    let rdlen = read_u16(packet, offset)? as usize;
+    let rdlen = read_u16(packet, offset).ok_or(malformed.clone())? as usize;
     offset += 2;
-    if offset + rdlen > packet.len() || rdlen < 1 {
-        return None;
+    if offset + rdlen > packet.len() {
+        return Err(malformed);
     }
-    if qtype != RR_TXT {
-        return None;
+    Ok((
+        Answer {
+            owner,
+            rtype,
+            rdata: offset,
+            rdlen,
+        },
+        offset + rdlen,
+    ))
+}
+
+fn decode_tolerant(
+    packet: &[u8],
+    answers: &[Answer],
+    qname: Option<&str>,
+) -> Result<Vec<u8>, ResponseError> {
+    let Some(qname) = qname else {
+        return match answers.iter().find(|answer| answer.rtype == RR_TXT) {
+            Some(answer) => decode_txt(packet, answer),
+            None => Err(ResponseError::AnswerType(answers[0].rtype)),
+        };
+    };
+
+    let mut owner = qname.to_string();
+    for _ in 0..=MAX_CNAME_CHAIN {
+        let owned_by = |answer: &&Answer| answer.owner.eq_ignore_ascii_case(&owner);
+        if let Some(answer) = answers
+            .iter()
+            .filter(owned_by)
+            .find(|answer| answer.rtype == RR_TXT)
+        {
+            return decode_txt(packet, answer);
+        }
+        let Some(cname) = answers
+            .iter()
+            .filter(owned_by)
+            .find(|answer| answer.rtype == RR_CNAME)
+        else {
+            break;
+        };
+        let (target, _) = parse_name(packet, cname.rdata)
+            .map_err(|_| ResponseError::Malformed("cname target"))?;
+        owner = target;
     }
 
-    let mut remaining = rdlen;
-    let mut cursor = offset;
-    let mut out = Vec::with_capacity(rdlen);
+    if let Some(answer) = answers.iter().find(|answer| answer.rtype == RR_TXT) {
+        return Err(ResponseError::OwnerMismatch {
+            expected: owner,
+            found: answer.owner.clone(),
+        });
+    }
+    let rtype = answers
+        .iter()
+        .map(|answer| answer.rtype)
+        .find(|rtype| *rtype != RR_CNAME)
+        .unwrap_or(RR_CNAME);
+    Err(ResponseError::AnswerType(rtype))
+}
+
+fn decode_txt(packet: &[u8], answer: &Answer) -> Result<Vec<u8>, ResponseError> {
+    let mut remaining = answer.rdlen;
+    let mut cursor = answer.rdata;
+    let mut out = Vec::with_capacity(answer.rdlen);
     while remaining > 0 {
         let txt_len = packet[cursor] as usize;
         cursor += 1;
         remaining -= 1;
         if txt_len > remaining {
-            return None;
+            return Err(ResponseError::Malformed("txt"));
         }
         out.extend_from_slice(&packet[cursor..cursor + txt_len]);
         cursor += txt_len;
         remaining -= txt_len;
     }
     if out.is_empty() {
-        return None;
+        return Err(ResponseError::EmptyTxt);
     }
-    Some(out)
+    Ok(out)
 }
 
 pub fn is_response(packet: &[u8]) -> bool {
@@ -287,7 +499,7 @@ fn encode_opt_record(out: &mut Vec<u8>) -> Result<(), DnsError> {
 
 #[cfg(test)]
 mod tests {
-    use super::encode_response;
+    use super::{decode_response, encode_response, encode_response_padded};
     use crate::types::{Question, ResponseParams, CLASS_IN, RR_TXT};
 
     #[test]
@@ -308,4 +520,32 @@ mod tests {
         };
         assert!(encode_response(&params).is_err());
     }
+
+    #[test]
+    fn pads_response_to_block_len() {
+        let question = Question {
+            name: "a.test.com.".to_string(),
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+        };
+        let payload = vec![0xAB; 100];
+        let params = ResponseParams {
+            id: 0x1234,
+            rd: true,
+            cd: false,
+            question: &question,
+            payload: Some(&payload),
+            rcode: None,
+        };
+        let plain = encode_response(&params).expect("plain");
+        let padded = encode_response_padded(&params, 128, 1232).expect("padded");
+        assert_eq!(padded.len(), 256);
+        assert_eq!(decode_response(&padded).expect("decode"), payload);
+        assert_eq!(
+            encode_response_padded(&params, 0, 1232).expect("off"),
+            plain
+        );
+        let capped = encode_response_padded(&params, 1024, 200).expect("capped");
+        assert_eq!(capped.len(), 200);
+    }
 }
@@ -1,3 +1,9 @@
+use crate::layout::DEFAULT_LABEL_LEN;
+
+/// Inserts dots the way the C implementation does (57-character labels).
+///
+/// Matches the C output byte-for-byte, including its placement quirk: once a name
+/// needs three or more dots, the leftmost label grows past 57 characters.
 pub fn dotify(input: &str) -> String {
     if input.is_empty() {
         return String::new();
@@ -5,7 +11,7 @@ pub fn dotify(input: &str) -> String {
 
     let bytes = input.as_bytes();
     let len = bytes.len();
-    let dots = (len - 1) / 57;
+    let dots = (len - 1) / DEFAULT_LABEL_LEN;
     let new_len = len + dots;
 
     let mut buf = Vec::with_capacity(new_len);
@@ -14,9 +20,9 @@ pub fn dotify(input: &str) -> String {
 
     let mut src = len as isize - 1;
     let mut dst = new_len as isize - 1;
-    let mut next_dot = len - (len % 57);
-    if len.is_multiple_of(57) {
-        next_dot = len - 57;
+    let mut next_dot = len - (len % DEFAULT_LABEL_LEN);
+    if len.is_multiple_of(DEFAULT_LABEL_LEN) {
+        next_dot = len - DEFAULT_LABEL_LEN;
     }
     let mut current_pos = len;
 
@@ -24,7 +30,7 @@ pub fn dotify(input: &str) -> String {
         if current_pos == next_dot {
             buf[dst as usize] = b'.';
             dst -= 1;
-            next_dot = next_dot.saturating_sub(57);
+            next_dot = next_dot.saturating_sub(DEFAULT_LABEL_LEN);
             current_pos -= 1;
             continue;
         }
@@ -38,6 +44,27 @@ pub fn dotify(input: &str) -> String {
     String::from_utf8(buf).unwrap_or_default()
 }
 
+/// Splits `input` into labels of `label_len` characters with a shorter final label.
+///
+/// `DEFAULT_LABEL_LEN` keeps the C-compatible placement of [`dotify`].
+pub fn dotify_with_label_len(input: &str, label_len: usize) -> String {
+    if label_len == DEFAULT_LABEL_LEN {
+        return dotify(input);
+    }
+    if input.is_empty() || label_len == 0 {
+        return input.to_string();
+    }
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len() + (bytes.len() - 1) / label_len);
+    for (idx, label) in bytes.chunks(label_len).enumerate() {
+        if idx > 0 {
+            out.push(b'.');
+        }
+        out.extend_from_slice(label);
+    }
+    String::from_utf8(out).unwrap_or_default()
+}
+
 pub fn undotify(input: &str) -> String {
     let mut out = Vec::with_capacity(input.len());
     for &b in input.as_bytes() {
@@ -50,7 +77,7 @@ pub fn undotify(input: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::dotify;
+    use super::{dotify, dotify_with_label_len, undotify};
 
     #[test]
     fn dotify_skips_trailing_dot_for_exact_segments() {
@@ -68,4 +95,19 @@ mod tests {
         assert_eq!(dotted, expected);
         assert!(!dotted.ends_with('.'));
     }
+
+    #[test]
+    fn dotify_with_label_len_fills_full_labels() {
+        let input = "A".repeat(130);
+        let dotted = dotify_with_label_len(&input, 63);
+        let expected = format!("{}.{}.{}", "A".repeat(63), "A".repeat(63), "A".repeat(4));
+        assert_eq!(dotted, expected);
+        assert_eq!(undotify(&dotted), input);
+    }
+
+    #[test]
+    fn dotify_with_default_label_len_matches_dotify() {
+        let input = "A".repeat(240);
+        assert_eq!(dotify_with_label_len(&input, 57), dotify(&input));
+    }
 }
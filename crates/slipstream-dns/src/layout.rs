@@ -0,0 +1,74 @@
+use crate::base32;
+use crate::types::DnsError;
+
+/// Label length used by the C implementation.
+pub const DEFAULT_LABEL_LEN: usize = 57;
+/// Longest label allowed by RFC 1035.
+pub const MAX_LABEL_LEN: usize = 63;
+/// First character of a sequence label. Like the segment and probe markers it is
+/// outside the base32 alphabet, so decoders recognise the label on their own.
+pub const SEQUENCE_MARKER: u8 = b'8';
+/// Characters in the optional sequence label: the marker plus 20 bits of base32.
+pub const SEQUENCE_LABEL_LEN: usize = 5;
+
+/// How a payload is laid out in QNAME labels.
+///
+/// Only the encoder needs a layout: decoding ignores dots and recognises the
+/// sequence label by its marker, so a server accepts every layout at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LabelLayout {
+    label_len: usize,
+    sequence_label: bool,
+}
+
+impl LabelLayout {
+    pub fn new(label_len: usize, sequence_label: bool) -> Result<Self, DnsError> {
+        if label_len == 0 || label_len > MAX_LABEL_LEN {
+            return Err(DnsError::new(format!(
+                "label length must be between 1 and {}",
+                MAX_LABEL_LEN
+            )));
+        }
+        Ok(Self {
+            label_len,
+            sequence_label,
+        })
+    }
+
+    pub fn label_len(&self) -> usize {
+        self.label_len
+    }
+
+    pub fn sequence_label(&self) -> bool {
+        self.sequence_label
+    }
+
+    /// Characters the layout adds in front of the data labels, including the dot.
+    pub(crate) fn prefix_len(&self) -> usize {
+        if self.sequence_label {
+            SEQUENCE_LABEL_LEN + 1
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for LabelLayout {
+    fn default() -> Self {
+        Self {
+            label_len: DEFAULT_LABEL_LEN,
+            sequence_label: false,
+        }
+    }
+}
+
+/// Encodes the low 20 bits of `sequence` as a marked, fixed-width base32 label.
+pub(crate) fn sequence_label(sequence: u32) -> String {
+    std::iter::once(SEQUENCE_MARKER as char)
+        .chain(
+            (0..SEQUENCE_LABEL_LEN - 1)
+                .rev()
+                .map(|i| base32::encode_symbol((sequence >> (5 * i)) as u8) as char),
+        )
+        .collect()
+}
@@ -1,36 +1,131 @@
 mod base32;
 mod codec;
 mod dots;
+mod layout;
 mod name;
+mod probe;
+mod relay;
+mod segment;
 mod types;
 mod wire;
 
-pub use base32::{decode as base32_decode, encode as base32_encode, Base32Error};
+pub use base32::{
+    backend_name as base32_backend, decode as base32_decode, encode as base32_encode, Base32Error,
+};
 pub use codec::{
-    decode_query, decode_query_with_domains, decode_response, encode_query, encode_response,
-    is_response,
+    decode_query, decode_query_with_domains, decode_response, decode_response_with_mode,
+    encode_query, encode_response, encode_response_padded, is_response, max_response_payload_len,
+};
+pub use dots::{dotify, dotify_with_label_len, undotify};
+pub use layout::{
+    LabelLayout, DEFAULT_LABEL_LEN, MAX_LABEL_LEN, SEQUENCE_LABEL_LEN, SEQUENCE_MARKER,
+};
+pub use probe::{
+    build_probe_qname, ProbeKind, ProbeReply, ProbeRequest, PROBE_CNX_ID_LEN, PROBE_LABEL_LEN,
+    PROBE_MARKER, PROBE_REPLY_HEADER_LEN,
+};
+pub use relay::{question_end, question_key, randomize_case, refused_response, truncate_response};
+pub use segment::{
+    SegmentHeader, SegmentReassembler, MAX_SEGMENTS, SEGMENT_HEADER_LABEL_LEN, SEGMENT_MARKER,
+    SEGMENT_PACKET_ID_MASK,
 };
-pub use dots::{dotify, undotify};
 pub use types::{
-    DecodeQueryError, DecodedQuery, DnsError, QueryParams, Question, Rcode, ResponseParams,
-    CLASS_IN, EDNS_UDP_PAYLOAD, RR_A, RR_OPT, RR_TXT,
+    DecodeQueryError, DecodedQuery, DnsError, QueryParams, Question, Rcode, ResponseError,
+    ResponseMode, ResponseParams, CLASSIC_UDP_RESPONSE_LEN, CLASS_IN, EDNS_UDP_PAYLOAD,
+    MAX_UDP_RESPONSE_LEN, RR_A, RR_CNAME, RR_OPT, RR_TXT,
 };
 
+const MAX_NAME_LEN: usize = name::MAX_DNS_NAME_LEN;
+
 pub fn build_qname(payload: &[u8], domain: &str) -> Result<String, DnsError> {
+    build_qname_with_layout(payload, domain, &LabelLayout::default(), 0)
+}
+
+/// Builds a QNAME using `layout`; `sequence` fills the sequence label when enabled.
+pub fn build_qname_with_layout(
+    payload: &[u8],
+    domain: &str,
+    layout: &LabelLayout,
+    sequence: u32,
+) -> Result<String, DnsError> {
+    build_qname_inner(payload, domain, layout, sequence, None)
+}
+
+/// Builds the QNAME for one segment of a packet split across several queries.
+pub fn build_segment_qname(
+    segment: &[u8],
+    header: &SegmentHeader,
+    domain: &str,
+    layout: &LabelLayout,
+    sequence: u32,
+) -> Result<String, DnsError> {
+    build_qname_inner(segment, domain, layout, sequence, Some(header))
+}
+
+fn build_qname_inner(
+    payload: &[u8],
+    domain: &str,
+    layout: &LabelLayout,
+    sequence: u32,
+    header: Option<&SegmentHeader>,
+) -> Result<String, DnsError> {
     let domain = domain.trim_end_matches('.');
     if domain.is_empty() {
         return Err(DnsError::new("domain must not be empty"));
     }
-    let max_payload = max_payload_len_for_domain(domain)?;
+    let header_len = header.map(|_| SEGMENT_HEADER_LABEL_LEN + 1).unwrap_or(0);
+    let max_payload = max_payload_len_with_prefix(domain, layout, header_len, MAX_NAME_LEN)?;
     if payload.len() > max_payload {
         return Err(DnsError::new("payload too large for domain"));
     }
     let base32 = base32_encode(payload);
-    let dotted = dotify(&base32);
-    Ok(format!("{}.{}.", dotted, domain))
+    let dotted = dotify_with_label_len(&base32, layout.label_len());
+    let mut qname = String::with_capacity(name::MAX_DNS_NAME_LEN + 1);
+    if layout.sequence_label() {
+        qname.push_str(&layout::sequence_label(sequence));
+        qname.push('.');
+    }
+    if let Some(header) = header {
+        qname.push_str(&header.to_label());
+        qname.push('.');
+    }
+    qname.push_str(&dotted);
+    qname.push('.');
+    qname.push_str(domain);
+    qname.push('.');
+    Ok(qname)
 }
 
 pub fn max_payload_len_for_domain(domain: &str) -> Result<usize, DnsError> {
+    max_payload_len_for_layout(domain, &LabelLayout::default())
+}
+
+/// Largest payload that fits in a QNAME for `domain` when encoded with `layout`.
+pub fn max_payload_len_for_layout(domain: &str, layout: &LabelLayout) -> Result<usize, DnsError> {
+    max_payload_len_with_prefix(domain, layout, 0, MAX_NAME_LEN)
+}
+
+/// Largest payload whose QNAME stays within `max_name_len` characters (without the
+/// trailing dot), for resolvers that drop names shorter than the DNS limit.
+pub fn max_payload_len_for_name_len(
+    domain: &str,
+    layout: &LabelLayout,
+    max_name_len: usize,
+) -> Result<usize, DnsError> {
+    max_payload_len_with_prefix(domain, layout, 0, max_name_len.min(MAX_NAME_LEN))
+}
+
+/// Largest segment that fits in a QNAME next to a segment header label.
+pub fn max_segment_payload_len(domain: &str, layout: &LabelLayout) -> Result<usize, DnsError> {
+    max_payload_len_with_prefix(domain, layout, SEGMENT_HEADER_LABEL_LEN + 1, MAX_NAME_LEN)
+}
+
+fn max_payload_len_with_prefix(
+    domain: &str,
+    layout: &LabelLayout,
+    extra_prefix_len: usize,
+    max_name_len: usize,
+) -> Result<usize, DnsError> {
     let domain = domain.trim_end_matches('.');
     if domain.is_empty() {
         return Err(DnsError::new("domain must not be empty"));
@@ -38,14 +133,15 @@ pub fn max_payload_len_for_domain(domain: &str) -> Result<usize, DnsError> {
     if domain.len() > name::MAX_DNS_NAME_LEN {
         return Err(DnsError::new("domain too long"));
     }
-    let max_name_len = name::MAX_DNS_NAME_LEN;
-    let max_dotted_len = max_name_len.saturating_sub(domain.len() + 1);
+    let max_dotted_len =
+        max_name_len.saturating_sub(domain.len() + 1 + layout.prefix_len() + extra_prefix_len);
     if max_dotted_len == 0 {
         return Ok(0);
     }
+    let label_len = layout.label_len();
     let mut max_base32_len = 0usize;
     for len in 1..=max_dotted_len {
-        let dots = (len - 1) / 57;
+        let dots = (len - 1) / label_len;
         if len + dots > max_dotted_len {
             break;
         }
@@ -68,7 +164,11 @@ fn base32_len(payload_len: usize) -> usize {
 
 #[cfg(test)]
 mod tests {
-    use super::{build_qname, max_payload_len_for_domain};
+    use super::{
+        build_qname, build_qname_with_layout, build_segment_qname, max_payload_len_for_domain,
+        max_payload_len_for_layout, max_payload_len_for_name_len, max_response_payload_len,
+        max_segment_payload_len, LabelLayout, SegmentHeader,
+    };
 
     #[test]
     fn build_qname_rejects_payload_overflow() {
@@ -84,4 +184,119 @@ mod tests {
         let payload = vec![0u8; 1];
         assert!(build_qname(&payload, &domain).is_err());
     }
+
+    #[test]
+    fn max_payload_len_accounts_for_layout() {
+        let domain = "t.example.com";
+        let default = max_payload_len_for_domain(domain).expect("default");
+        let wide = LabelLayout::new(63, false).expect("layout");
+        let sequenced = LabelLayout::new(57, true).expect("layout");
+        assert_eq!(default, 146);
+        assert_eq!(
+            max_payload_len_for_layout(domain, &wide).expect("wide"),
+            147
+        );
+        assert_eq!(
+            max_payload_len_for_layout(domain, &sequenced).expect("sequenced"),
+            143
+        );
+    }
+
+    #[test]
+    fn build_qname_with_layout_fits_max_payload() {
+        let domain = "t.example.com";
+        for layout in [
+            LabelLayout::new(63, false).expect("layout"),
+            LabelLayout::new(63, true).expect("layout"),
+            LabelLayout::new(20, true).expect("layout"),
+        ] {
+            let max_payload = max_payload_len_for_layout(domain, &layout).expect("max payload");
+            let payload = vec![0xa5u8; max_payload];
+            let qname =
+                build_qname_with_layout(&payload, domain, &layout, 0xabcde).expect("build qname");
+            assert!(qname.trim_end_matches('.').len() <= 253, "{}", qname);
+            assert!(qname
+                .split('.')
+                .all(|label| label.len() <= layout.label_len()));
+            let payload = vec![0u8; max_payload + 1];
+            assert!(build_qname_with_layout(&payload, domain, &layout, 0).is_err());
+        }
+    }
+
+    #[test]
+    fn label_layout_rejects_out_of_range_lengths() {
+        assert!(LabelLayout::new(0, false).is_err());
+        assert!(LabelLayout::new(64, false).is_err());
+        assert!(LabelLayout::new(63, true).is_ok());
+    }
+
+    #[test]
+    fn build_segment_qname_fits_max_segment() {
+        let domain = "t.example.com";
+        let layout = LabelLayout::new(63, true).expect("layout");
+        let max_segment = max_segment_payload_len(domain, &layout).expect("max segment");
+        assert!(max_segment < max_payload_len_for_layout(domain, &layout).expect("max payload"));
+        let header = SegmentHeader {
+            session: u32::MAX,
+            packet_id: 42,
+            index: 0,
+            count: 2,
+        };
+        let segment = vec![0x5au8; max_segment];
+        let qname = build_segment_qname(&segment, &header, domain, &layout, 1).expect("qname");
+        assert!(qname.trim_end_matches('.').len() <= 253, "{}", qname);
+        let segment = vec![0u8; max_segment + 1];
+        assert!(build_segment_qname(&segment, &header, domain, &layout, 1).is_err());
+    }
+
+    #[test]
+    fn max_payload_len_for_name_len_respects_limit() {
+        let domain = "t.example.com";
+        let layout = LabelLayout::new(57, true).expect("layout");
+        assert_eq!(
+            max_payload_len_for_name_len(domain, &layout, 253).expect("full"),
+            max_payload_len_for_layout(domain, &layout).expect("layout")
+        );
+        for limit in [60, 100, 150, 200] {
+            let max_payload =
+                max_payload_len_for_name_len(domain, &layout, limit).expect("max payload");
+            let qname = build_qname_with_layout(&vec![0u8; max_payload], domain, &layout, 7)
+                .expect("qname");
+            assert!(qname.trim_end_matches('.').len() <= limit, "{}", qname);
+            let longer = build_qname_with_layout(&vec![0u8; max_payload + 1], domain, &layout, 7)
+                .expect("qname");
+            assert!(longer.trim_end_matches('.').len() > limit, "{}", longer);
+        }
+        assert_eq!(
+            max_payload_len_for_name_len(domain, &layout, 10).expect("tiny"),
+            0
+        );
+    }
+
+    #[test]
+    fn max_response_payload_len_fills_message() {
+        use crate::{encode_response, Question, ResponseParams, CLASS_IN, RR_TXT};
+        let question = Question {
+            name: "AEBAGBA.t.example.com.".to_string(),
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+        };
+        for message_len in [512, 900, 1232, 1452] {
+            let payload_len = max_response_payload_len(message_len, &question.name);
+            let encode = |len: usize| {
+                encode_response(&ResponseParams {
+                    id: 1,
+                    rd: true,
+                    cd: false,
+                    question: &question,
+                    payload: Some(&vec![0u8; len]),
+                    rcode: None,
+                })
+                .expect("encode")
+                .len()
+            };
+            assert!(encode(payload_len) <= message_len);
+            assert!(encode(payload_len + 1) > message_len);
+        }
+    }
 }
@@ -0,0 +1,286 @@
+use crate::base32;
+use crate::name::MAX_DNS_NAME_LEN;
+use crate::types::DnsError;
+
+/// First character of a probe label. Like the segment marker it is outside the base32
+/// alphabet, and it is never a valid sequence label.
+pub const PROBE_MARKER: u8 = b'1';
+/// Characters in a probe label: the marker plus 7 request bytes in base32.
+pub const PROBE_LABEL_LEN: usize = 13;
+/// Bytes of the server's connection ID a probe can name: picoquic's default
+/// length, which the server uses for every connection.
+pub const PROBE_CNX_ID_LEN: usize = 8;
+/// Characters in a probe label that also carries a connection ID.
+const PROBE_CNX_ID_LABEL_LEN: usize = 25;
+/// Bytes in a probe reply before the echoed QNAME and padding.
+pub const PROBE_REPLY_HEADER_LEN: usize = 16;
+
+const PROBE_VERSION: u8 = 1;
+const PROBE_KIND_SHIFT: u8 = 4;
+const FILLER: &[u8] = b"AbCdEfGhIjKlMnOpQrStUvWxYz";
+
+/// What the server does with a probe besides answering it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProbeKind {
+    /// Answer with a reply padded to `response_len` bytes.
+    #[default]
+    Echo,
+    /// `response_len` is the largest DNS response (in bytes) that reached the client
+    /// through the resolver sending this probe; the reply is not padded.
+    ReportDownstream,
+}
+
+impl ProbeKind {
+    fn to_bits(self) -> u8 {
+        match self {
+            ProbeKind::Echo => 0,
+            ProbeKind::ReportDownstream => 1,
+        }
+    }
+
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0 => Some(ProbeKind::Echo),
+            1 => Some(ProbeKind::ReportDownstream),
+            _ => None,
+        }
+    }
+}
+
+/// A capability probe sent by `slipstream-client doctor` and by path MTU discovery.
+///
+/// Probes bypass QUIC: the server answers them directly with a [`ProbeReply`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeRequest {
+    pub kind: ProbeKind,
+    /// Makes each probe QNAME unique unless a cache hit is wanted.
+    pub nonce: u32,
+    /// Requested TXT payload length; replies are padded up to it.
+    pub response_len: u16,
+    /// Destination connection ID of the QUIC path a report is about. The server
+    /// applies the limit to that connection and path only; reports without one
+    /// are ignored.
+    pub cnx_id: Option<[u8; PROBE_CNX_ID_LEN]>,
+}
+
+impl ProbeRequest {
+    pub(crate) fn to_label(self) -> String {
+        let nonce = self.nonce.to_be_bytes();
+        let len = self.response_len.to_be_bytes();
+        let mut bytes = vec![
+            PROBE_VERSION | (self.kind.to_bits() << PROBE_KIND_SHIFT),
+            nonce[0],
+            nonce[1],
+            nonce[2],
+            nonce[3],
+            len[0],
+            len[1],
+        ];
+        if let Some(cnx_id) = self.cnx_id {
+            bytes.extend_from_slice(&cnx_id);
+        }
+        let mut label = String::with_capacity(PROBE_CNX_ID_LABEL_LEN);
+        label.push(PROBE_MARKER as char);
+        label.push_str(&base32::encode(&bytes));
+        label
+    }
+
+    pub(crate) fn parse_label(label: &str) -> Option<Self> {
+        if !matches!(label.len(), PROBE_LABEL_LEN | PROBE_CNX_ID_LABEL_LEN)
+            || label.as_bytes()[0] != PROBE_MARKER
+        {
+            return None;
+        }
+        let bytes = base32::decode(&label[1..]).ok()?;
+        if !matches!(bytes.len(), 7 | 15) || bytes[0] & 0x0f != PROBE_VERSION {
+            return None;
+        }
+        Some(Self {
+            kind: ProbeKind::from_bits(bytes[0] >> PROBE_KIND_SHIFT)?,
+            nonce: u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]),
+            response_len: u16::from_be_bytes([bytes[5], bytes[6]]),
+            cnx_id: bytes[7..].try_into().ok(),
+        })
+    }
+}
+
+/// What the server saw when it answered a probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeReply {
+    /// DNS id of the query as received by the server.
+    pub id: u16,
+    pub nonce: u32,
+    /// Server clock when the reply was built; identical stamps mean a cached answer.
+    pub stamp: u64,
+    /// QNAME as received by the server, without the trailing dot.
+    pub qname: String,
+}
+
+impl ProbeReply {
+    /// Encodes the reply as a TXT payload padded to `response_len` bytes.
+    ///
+    /// Servers answer [`ProbeKind::ReportDownstream`] probes with `response_len` 0.
+    pub fn encode(&self, response_len: usize) -> Vec<u8> {
+        let qname = self.qname.trim_end_matches('.').as_bytes();
+        let qname = &qname[..qname.len().min(u8::MAX as usize)];
+        let mut out = Vec::with_capacity(response_len.max(PROBE_REPLY_HEADER_LEN + qname.len()));
+        out.push(PROBE_VERSION);
+        out.extend_from_slice(&self.id.to_be_bytes());
+        out.extend_from_slice(&self.nonce.to_be_bytes());
+        out.extend_from_slice(&self.stamp.to_be_bytes());
+        out.push(qname.len() as u8);
+        out.extend_from_slice(qname);
+        let mut filler = FILLER.iter().cycle();
+        while out.len() < response_len {
+            out.push(*filler.next().unwrap_or(&b'A'));
+        }
+        out
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        if payload.len() < PROBE_REPLY_HEADER_LEN || payload[0] != PROBE_VERSION {
+            return None;
+        }
+        let id = u16::from_be_bytes([payload[1], payload[2]]);
+        let nonce = u32::from_be_bytes(payload[3..7].try_into().ok()?);
+        let stamp = u64::from_be_bytes(payload[7..15].try_into().ok()?);
+        let qname_len = payload[15] as usize;
+        let qname = payload.get(PROBE_REPLY_HEADER_LEN..PROBE_REPLY_HEADER_LEN + qname_len)?;
+        Some(Self {
+            id,
+            nonce,
+            stamp,
+            qname: String::from_utf8(qname.to_vec()).ok()?,
+        })
+    }
+}
+
+/// Builds a probe QNAME padded with filler labels to `qname_len` characters
+/// (excluding the trailing dot).
+///
+/// The filler alternates letter case so case rewriting by resolvers is visible in
+/// the echoed QNAME. Names shorter than the probe label plus domain are not padded,
+/// and one character above that minimum comes out one short (labels cannot be empty).
+pub fn build_probe_qname(
+    request: &ProbeRequest,
+    domain: &str,
+    qname_len: usize,
+) -> Result<String, DnsError> {
+    let domain = domain.trim_end_matches('.');
+    if domain.is_empty() {
+        return Err(DnsError::new("domain must not be empty"));
+    }
+    if qname_len > MAX_DNS_NAME_LEN {
+        return Err(DnsError::new("probe QNAME too long"));
+    }
+    let mut qname = request.to_label();
+    let min_len = qname.len() + 1 + domain.len();
+    if min_len > MAX_DNS_NAME_LEN {
+        return Err(DnsError::new("domain too long"));
+    }
+    // Each filler label costs its length plus a dot; never leave room for an empty label.
+    let mut remaining = qname_len.saturating_sub(min_len);
+    let mut filler = FILLER.iter().cycle();
+    while remaining >= 2 {
+        let label_len = (remaining - 1).min(63);
+        let label_len = if remaining - 1 - label_len == 1 {
+            label_len - 1
+        } else {
+            label_len
+        };
+        qname.push('.');
+        for _ in 0..label_len {
+            qname.push(*filler.next().unwrap_or(&b'A') as char);
+        }
+        remaining -= label_len + 1;
+    }
+    qname.push('.');
+    qname.push_str(domain);
+    qname.push('.');
+    Ok(qname)
+}
+
+/// Finds a probe label at the front of `subdomain`.
+pub(crate) fn parse_probe_subdomain(subdomain: &str) -> Option<ProbeRequest> {
+    let label = subdomain.split('.').next()?;
+    if label.as_bytes().first() != Some(&PROBE_MARKER) {
+        return None;
+    }
+    ProbeRequest::parse_label(label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        build_probe_qname, ProbeKind, ProbeReply, ProbeRequest, PROBE_CNX_ID_LABEL_LEN,
+        PROBE_LABEL_LEN,
+    };
+
+    #[test]
+    fn request_label_round_trips() {
+        for kind in [ProbeKind::Echo, ProbeKind::ReportDownstream] {
+            let request = ProbeRequest {
+                kind,
+                nonce: 0xdead_beef,
+                response_len: 1232,
+                cnx_id: None,
+            };
+            let label = request.to_label();
+            assert_eq!(label.len(), PROBE_LABEL_LEN);
+            assert_eq!(ProbeRequest::parse_label(&label), Some(request));
+            assert_eq!(
+                ProbeRequest::parse_label(&label.to_ascii_lowercase()),
+                Some(request)
+            );
+        }
+        assert_eq!(ProbeRequest::parse_label("0AAAAAAAAAAAA"), None);
+    }
+
+    #[test]
+    fn request_label_carries_connection_id() {
+        let request = ProbeRequest {
+            kind: ProbeKind::ReportDownstream,
+            nonce: 1,
+            response_len: 900,
+            cnx_id: Some([1, 2, 3, 4, 5, 6, 7, 8]),
+        };
+        let label = request.to_label();
+        assert_eq!(label.len(), PROBE_CNX_ID_LABEL_LEN);
+        assert_eq!(ProbeRequest::parse_label(&label), Some(request));
+        assert_eq!(ProbeRequest::parse_label(&label[..label.len() - 1]), None);
+    }
+
+    #[test]
+    fn probe_qname_hits_requested_length() {
+        let request = ProbeRequest {
+            kind: ProbeKind::Echo,
+            nonce: 7,
+            response_len: 0,
+            cnx_id: None,
+        };
+        for len in [0, 30, 64, 65, 66, 127, 128, 200, 253] {
+            let qname = build_probe_qname(&request, "t.example.com", len).expect("qname");
+            let trimmed = qname.trim_end_matches('.');
+            assert_eq!(trimmed.len(), len.max(PROBE_LABEL_LEN + 14), "{}", len);
+            assert!(trimmed
+                .split('.')
+                .all(|label| !label.is_empty() && label.len() <= 63));
+        }
+        assert!(build_probe_qname(&request, "t.example.com", 254).is_err());
+    }
+
+    #[test]
+    fn reply_round_trips_with_padding() {
+        let reply = ProbeReply {
+            id: 0x1234,
+            nonce: 42,
+            stamp: 123_456_789,
+            qname: "1ABC.AbCd.t.example.com".to_string(),
+        };
+        let encoded = reply.encode(600);
+        assert_eq!(encoded.len(), 600);
+        assert_eq!(ProbeReply::decode(&encoded), Some(reply.clone()));
+        assert_eq!(ProbeReply::decode(&reply.encode(0)), Some(reply));
+        assert_eq!(ProbeReply::decode(&[1, 2, 3]), None);
+    }
+}
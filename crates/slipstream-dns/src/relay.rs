@@ -0,0 +1,142 @@
+const HEADER_LEN: usize = 12;
+const FLAG_QR: u8 = 0x80;
+const FLAG_TC: u8 = 0x02;
+const FLAG_RA: u8 = 0x80;
+const RCODE_REFUSED: u8 = 5;
+
+/// End of the first question, or `None` if the message has none.
+pub fn question_end(message: &[u8]) -> Option<usize> {
+    if message.len() < HEADER_LEN || u16::from_be_bytes([message[4], message[5]]) == 0 {
+        return None;
+    }
+    let mut offset = HEADER_LEN;
+    loop {
+        let len = *message.get(offset)? as usize;
+        if len == 0 {
+            offset += 1;
+            break;
+        }
+        if len & 0xc0 != 0 {
+            offset += 2;
+            break;
+        }
+        offset += 1 + len;
+    }
+    let end = offset + 4;
+    (end <= message.len()).then_some(end)
+}
+
+/// The first question with its name lowercased, which is what caches key on.
+pub fn question_key(message: &[u8]) -> Option<Vec<u8>> {
+    let end = question_end(message)?;
+    let mut key = message[HEADER_LEN..end].to_vec();
+    for_each_label(&mut key, |label| label.make_ascii_lowercase());
+    Some(key)
+}
+
+/// Flips the case of letters in the first question name where `flip` says so,
+/// like resolvers using 0x20 encoding.
+pub fn randomize_case(message: &mut [u8], mut flip: impl FnMut() -> bool) {
+    let Some(end) = question_end(message) else {
+        return;
+    };
+    for_each_label(&mut message[HEADER_LEN..end], |label| {
+        for byte in label.iter_mut() {
+            if byte.is_ascii_alphabetic() && flip() {
+                *byte ^= 0x20;
+            }
+        }
+    });
+}
+
+/// Cuts a response down to its header and question and sets TC, as resolvers
+/// do when an answer does not fit.
+pub fn truncate_response(response: &mut Vec<u8>) {
+    let Some(end) = question_end(response) else {
+        return;
+    };
+    response.truncate(end);
+    response[2] |= FLAG_TC;
+    response[4..6].copy_from_slice(&1u16.to_be_bytes());
+    response[6..HEADER_LEN].fill(0);
+}
+
+/// A REFUSED answer to `query`, or `None` if it has no question to echo.
+pub fn refused_response(query: &[u8]) -> Option<Vec<u8>> {
+    let end = question_end(query)?;
+    let mut response = query[..end].to_vec();
+    // Keep the opcode and RD; everything else in the flags is ours.
+    response[2] = (response[2] & 0x79) | FLAG_QR;
+    response[3] = FLAG_RA | RCODE_REFUSED;
+    response[4..6].copy_from_slice(&1u16.to_be_bytes());
+    response[6..HEADER_LEN].fill(0);
+    Some(response)
+}
+
+/// Calls `f` on each uncompressed label of the name at the start of `question`.
+fn for_each_label(question: &mut [u8], mut f: impl FnMut(&mut [u8])) {
+    let mut offset = 0;
+    while let Some(&len) = question.get(offset) {
+        let len = len as usize;
+        if len == 0 || len & 0xc0 != 0 || offset + 1 + len > question.len() {
+            break;
+        }
+        f(&mut question[offset + 1..offset + 1 + len]);
+        offset += 1 + len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{question_end, question_key, randomize_case, refused_response, truncate_response};
+
+    fn query() -> Vec<u8> {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1];
+        query.extend_from_slice(b"\x03AbC\x07example\x00\x00\x10\x00\x01");
+        // An OPT record after the question.
+        query.extend_from_slice(&[0, 0, 41, 0x04, 0xd0, 0, 0, 0, 0, 0, 0]);
+        query
+    }
+
+    #[test]
+    fn finds_the_question() {
+        let query = query();
+        assert_eq!(question_end(&query), Some(12 + 13 + 4));
+        assert_eq!(question_end(&query[..20]), None);
+        let mut no_question = query.clone();
+        no_question[5] = 0;
+        assert_eq!(question_end(&no_question), None);
+        assert_eq!(
+            question_key(&query).expect("key"),
+            b"\x03abc\x07example\x00\x00\x10\x00\x01"
+        );
+    }
+
+    #[test]
+    fn randomizes_name_case_only() {
+        let mut query = query();
+        randomize_case(&mut query, || true);
+        assert_eq!(&query[12..29], b"\x03aBc\x07EXAMPLE\x00\x00\x10\x00\x01");
+        assert_eq!(question_key(&query), question_key(&self::query()));
+    }
+
+    #[test]
+    fn truncates_to_the_question() {
+        let mut response = query();
+        response[2] |= 0x80;
+        response[7] = 1;
+        response.extend_from_slice(&[0xc0, 0x0c, 0, 16, 0, 1, 0, 0, 0, 60, 0, 1, 0]);
+        truncate_response(&mut response);
+        assert_eq!(response.len(), 29);
+        assert_eq!(response[2], 0x83);
+        assert_eq!(&response[4..12], &[0, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn refuses_with_the_question() {
+        let refused = refused_response(&query()).expect("refused");
+        assert_eq!(&refused[..4], &[0x12, 0x34, 0x81, 0x85]);
+        assert_eq!(&refused[4..12], &[0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&refused[12..], &query()[12..29]);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::base32;
+use std::collections::HashMap;
+
+/// First character of a segment header label. It is outside the base32 alphabet, so the
+/// server can tell segmented queries apart without negotiation.
+pub const SEGMENT_MARKER: u8 = b'0';
+/// Characters in a segment header label: the marker plus 9 header bytes in base32.
+pub const SEGMENT_HEADER_LABEL_LEN: usize = 16;
+/// Most segments a single packet can be split into.
+pub const MAX_SEGMENTS: usize = u8::MAX as usize;
+/// Packet ids are 24 bits and wrap around.
+pub const SEGMENT_PACKET_ID_MASK: u32 = 0x00ff_ffff;
+
+const SEGMENT_HEADER_LEN: usize = 9;
+
+/// Identifies one segment of a QUIC packet carried across several queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentHeader {
+    /// Picked at random by each client, so packet ids of different clients never mix.
+    pub session: u32,
+    pub packet_id: u32,
+    pub index: u8,
+    pub count: u8,
+}
+
+impl SegmentHeader {
+    pub(crate) fn to_label(self) -> String {
+        let id = self.packet_id & SEGMENT_PACKET_ID_MASK;
+        let session = self.session.to_be_bytes();
+        let bytes = [
+            session[0],
+            session[1],
+            session[2],
+            session[3],
+            (id >> 16) as u8,
+            (id >> 8) as u8,
+            id as u8,
+            self.index,
+            self.count,
+        ];
+        let mut label = String::with_capacity(SEGMENT_HEADER_LABEL_LEN);
+        label.push(SEGMENT_MARKER as char);
+        label.push_str(&base32::encode(&bytes));
+        label
+    }
+
+    pub(crate) fn parse_label(label: &str) -> Option<Self> {
+        if label.len() != SEGMENT_HEADER_LABEL_LEN || label.as_bytes()[0] != SEGMENT_MARKER {
+            return None;
+        }
+        let bytes = base32::decode(&label[1..]).ok()?;
+        if bytes.len() != SEGMENT_HEADER_LEN {
+            return None;
+        }
+        let header = Self {
+            session: u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            packet_id: u32::from_be_bytes([0, bytes[4], bytes[5], bytes[6]]),
+            index: bytes[7],
+            count: bytes[8],
+        };
+        if header.count == 0 || header.index >= header.count {
+            return None;
+        }
+        Some(header)
+    }
+}
+
+struct PendingPacket {
+    segments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+    first_seen: u64,
+}
+
+/// Pending packets are keyed by session and packet id.
+type PacketKey = (u32, u32);
+
+/// Reassembles segmented packets, bounded by age, buffered bytes, and packet count.
+///
+/// When a cap is reached the session buffering the most bytes loses its oldest
+/// packet, so one client flooding segments mostly evicts its own packets.
+///
+/// Timestamps are in microseconds and only need to be monotonic.
+pub struct SegmentReassembler {
+    pending: HashMap<PacketKey, PendingPacket>,
+    timeout_us: u64,
+    max_bytes: usize,
+    max_packets: usize,
+    buffered_bytes: usize,
+    dropped_packets: u64,
+}
+
+impl SegmentReassembler {
+    pub fn new(timeout_us: u64, max_bytes: usize, max_packets: usize) -> Self {
+        Self {
+            pending: HashMap::new(),
+            timeout_us,
+            max_bytes,
+            max_packets: max_packets.max(1),
+            buffered_bytes: 0,
+            dropped_packets: 0,
+        }
+    }
+
+    /// Stores a segment and returns the packet once all of its segments have arrived.
+    ///
+    /// Duplicate segments (e.g. resolver retries) are ignored, and so is a segment
+    /// whose count disagrees with earlier segments of the same packet.
+    pub fn insert(&mut self, header: SegmentHeader, data: &[u8], now: u64) -> Option<Vec<u8>> {
+        if header.count == 0 || header.index >= header.count {
+            return None;
+        }
+        if header.count == 1 {
+            return Some(data.to_vec());
+        }
+        self.expire(now);
+
+        let count = header.count as usize;
+        let index = header.index as usize;
+        let key = (header.session, header.packet_id & SEGMENT_PACKET_ID_MASK);
+        if let Some(entry) = self.pending.get(&key) {
+            if entry.segments.len() != count || entry.segments[index].is_some() {
+                return None;
+            }
+        }
+        if data.len() > self.max_bytes {
+            return None;
+        }
+        while self.buffered_bytes + data.len() > self.max_bytes
+            || (!self.pending.contains_key(&key) && self.pending.len() >= self.max_packets)
+        {
+            let Some(victim) = self.eviction_candidate(key) else {
+                break;
+            };
+            self.drop_packet(victim);
+        }
+        if self.buffered_bytes + data.len() > self.max_bytes {
+            return None;
+        }
+
+        let entry = self.pending.entry(key).or_insert_with(|| PendingPacket {
+            segments: vec![None; count],
+            received: 0,
+            bytes: 0,
+            first_seen: now,
+        });
+        entry.segments[index] = Some(data.to_vec());
+        entry.received += 1;
+        entry.bytes += data.len();
+        self.buffered_bytes += data.len();
+        if entry.received < count {
+            return None;
+        }
+
+        let entry = self.pending.remove(&key)?;
+        self.buffered_bytes -= entry.bytes;
+        let mut packet = Vec::with_capacity(entry.bytes);
+        for segment in entry.segments.into_iter().flatten() {
+            packet.extend_from_slice(&segment);
+        }
+        Some(packet)
+    }
+
+    /// Drops packets whose first segment is older than the timeout.
+    pub fn expire(&mut self, now: u64) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let expire_before = now.saturating_sub(self.timeout_us);
+        let expired: Vec<PacketKey> = self
+            .pending
+            .iter()
+            .filter(|(_, entry)| entry.first_seen < expire_before)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired {
+            self.drop_packet(key);
+        }
+    }
+
+    pub fn pending_packets(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    /// Incomplete packets dropped so far because of timeouts, caps, or conflicts.
+    pub fn dropped_packets(&self) -> u64 {
+        self.dropped_packets
+    }
+
+    /// The oldest packet, other than `keep`, of the session buffering the most bytes.
+    fn eviction_candidate(&self, keep: PacketKey) -> Option<PacketKey> {
+        let mut session_bytes: HashMap<u32, usize> = HashMap::new();
+        for (key, entry) in self.pending.iter().filter(|(key, _)| **key != keep) {
+            *session_bytes.entry(key.0).or_default() += entry.bytes.max(1);
+        }
+        let (session, _) = session_bytes
+            .into_iter()
+            .max_by_key(|(session, bytes)| (*bytes, *session))?;
+        self.pending
+            .iter()
+            .filter(|(key, _)| key.0 == session && **key != keep)
+            .min_by_key(|(key, entry)| (entry.first_seen, key.1))
+            .map(|(key, _)| *key)
+    }
+
+    fn drop_packet(&mut self, key: PacketKey) {
+        if let Some(entry) = self.pending.remove(&key) {
+            self.buffered_bytes -= entry.bytes;
+            self.dropped_packets = self.dropped_packets.saturating_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SegmentHeader, SegmentReassembler};
+
+    fn header(packet_id: u32, index: u8, count: u8) -> SegmentHeader {
+        session_header(1, packet_id, index, count)
+    }
+
+    fn session_header(session: u32, packet_id: u32, index: u8, count: u8) -> SegmentHeader {
+        SegmentHeader {
+            session,
+            packet_id,
+            index,
+            count,
+        }
+    }
+
+    #[test]
+    fn header_label_round_trips() {
+        let original = session_header(0xfeedbeef, 0xabcdef, 3, 9);
+        let label = original.to_label();
+        assert_eq!(label.len(), super::SEGMENT_HEADER_LABEL_LEN);
+        assert!(label.starts_with('0'));
+        assert_eq!(SegmentHeader::parse_label(&label), Some(original));
+        assert_eq!(
+            SegmentHeader::parse_label(&label.to_ascii_lowercase()),
+            Some(original)
+        );
+        assert_eq!(
+            SegmentHeader::parse_label(&header(1, 2, 2).to_label()),
+            None
+        );
+    }
+
+    #[test]
+    fn reassembles_out_of_order_and_ignores_duplicates() {
+        let mut reassembler = SegmentReassembler::new(1_000_000, 1024, 16);
+        assert_eq!(reassembler.insert(header(7, 2, 3), b"ef", 0), None);
+        assert_eq!(reassembler.insert(header(7, 0, 3), b"ab", 1), None);
+        assert_eq!(reassembler.insert(header(7, 0, 3), b"ab", 2), None);
+        assert_eq!(
+            reassembler.insert(header(7, 1, 3), b"cd", 3),
+            Some(b"abcdef".to_vec())
+        );
+        assert_eq!(reassembler.pending_packets(), 0);
+        assert_eq!(reassembler.buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn expires_incomplete_packets() {
+        let mut reassembler = SegmentReassembler::new(100, 1024, 16);
+        assert_eq!(reassembler.insert(header(1, 0, 2), b"ab", 0), None);
+        reassembler.expire(50);
+        assert_eq!(reassembler.pending_packets(), 1);
+        reassembler.expire(101);
+        assert_eq!(reassembler.pending_packets(), 0);
+        assert_eq!(reassembler.dropped_packets(), 1);
+        assert_eq!(reassembler.insert(header(1, 1, 2), b"cd", 102), None);
+    }
+
+    #[test]
+    fn evicts_oldest_packets_over_caps() {
+        let mut reassembler = SegmentReassembler::new(1_000_000, 6, 2);
+        assert_eq!(reassembler.insert(header(1, 0, 2), b"aaa", 0), None);
+        assert_eq!(reassembler.insert(header(2, 0, 2), b"bbb", 1), None);
+        assert_eq!(reassembler.insert(header(3, 0, 2), b"ccc", 2), None);
+        assert_eq!(reassembler.pending_packets(), 2);
+        assert!(reassembler.buffered_bytes() <= 6);
+        assert_eq!(reassembler.insert(header(1, 1, 2), b"aaa", 3), None);
+        assert_eq!(
+            reassembler.insert(header(3, 1, 2), b"ccc", 4),
+            Some(b"cccccc".to_vec())
+        );
+        assert_eq!(reassembler.insert(header(9, 0, 2), &[0u8; 7], 5), None);
+    }
+
+    #[test]
+    fn count_mismatch_is_ignored() {
+        let mut reassembler = SegmentReassembler::new(1_000_000, 1024, 16);
+        assert_eq!(reassembler.insert(header(5, 0, 2), b"ab", 0), None);
+        assert_eq!(reassembler.insert(header(5, 1, 3), b"xx", 1), None);
+        assert_eq!(reassembler.dropped_packets(), 0);
+        assert_eq!(
+            reassembler.insert(header(5, 1, 2), b"cd", 2),
+            Some(b"abcd".to_vec())
+        );
+    }
+
+    #[test]
+    fn sessions_do_not_share_packet_ids() {
+        let mut reassembler = SegmentReassembler::new(1_000_000, 1024, 16);
+        assert_eq!(
+            reassembler.insert(session_header(1, 5, 0, 2), b"ab", 0),
+            None
+        );
+        assert_eq!(
+            reassembler.insert(session_header(2, 5, 0, 3), b"xx", 1),
+            None
+        );
+        assert_eq!(
+            reassembler.insert(session_header(2, 5, 1, 3), b"yy", 2),
+            None
+        );
+        assert_eq!(
+            reassembler.insert(session_header(1, 5, 1, 2), b"cd", 3),
+            Some(b"abcd".to_vec())
+        );
+        assert_eq!(reassembler.pending_packets(), 1);
+    }
+
+    #[test]
+    fn eviction_prefers_the_heaviest_session() {
+        let mut reassembler = SegmentReassembler::new(1_000_000, 1024, 4);
+        assert_eq!(
+            reassembler.insert(session_header(1, 1, 0, 2), b"ab", 0),
+            None
+        );
+        for id in 0..3 {
+            assert_eq!(
+                reassembler.insert(session_header(9, id, 0, 2), b"zz", 1 + id as u64),
+                None
+            );
+        }
+        assert_eq!(
+            reassembler.insert(session_header(9, 3, 0, 2), b"zz", 4),
+            None
+        );
+        assert_eq!(reassembler.dropped_packets(), 1);
+        assert_eq!(
+            reassembler.insert(session_header(1, 1, 1, 2), b"cd", 5),
+            Some(b"abcd".to_vec())
+        );
+    }
+}
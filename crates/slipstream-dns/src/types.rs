@@ -1,10 +1,17 @@
+use crate::probe::ProbeRequest;
+use crate::segment::SegmentHeader;
 use std::fmt;
 
 pub const RR_A: u16 = 1;
+pub const RR_CNAME: u16 = 5;
 pub const RR_TXT: u16 = 16;
 pub const RR_OPT: u16 = 41;
 pub const CLASS_IN: u16 = 1;
 pub const EDNS_UDP_PAYLOAD: u16 = 1232;
+/// Largest UDP response a requester without EDNS(0) accepts.
+pub const CLASSIC_UDP_RESPONSE_LEN: usize = 512;
+/// Cap on the EDNS(0) payload size honoured in queries, whatever they advertise.
+pub const MAX_UDP_RESPONSE_LEN: usize = 4096;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Rcode {
@@ -49,6 +56,12 @@ pub struct DecodedQuery {
     pub cd: bool,
     pub question: Question,
     pub payload: Vec<u8>,
+    pub segment: Option<SegmentHeader>,
+    /// Largest response the requester accepts, from its EDNS(0) payload size;
+    /// between 512 and [`MAX_UDP_RESPONSE_LEN`] bytes.
+    pub max_response_len: usize,
+    /// Set for doctor probes; the payload is empty and the query must not reach QUIC.
+    pub probe: Option<ProbeRequest>,
 }
 
 #[derive(Debug, Clone)]
@@ -105,3 +118,91 @@ impl fmt::Display for DnsError {
 }
 
 impl std::error::Error for DnsError {}
+
+/// How strictly `decode_response_with_mode` matches the answer section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResponseMode {
+    /// Exactly one TXT answer, as sent by the slipstream server.
+    #[default]
+    Strict,
+    /// Follows CNAME chains, skips unrelated records, and matches owner names
+    /// case-insensitively.
+    Tolerant,
+}
+
+/// Why a DNS response did not yield a payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponseError {
+    /// The packet could not be parsed; names the field that failed.
+    Malformed(&'static str),
+    /// QR bit not set.
+    NotResponse,
+    /// TC bit set and no answers.
+    Truncated,
+    /// Any RCODE other than NOERROR, including values outside `Rcode`.
+    Rcode(u8),
+    /// NOERROR with an empty answer section.
+    NoData,
+    /// Strict mode only: more than one answer record.
+    AnswerCount(u16),
+    /// No usable TXT answer; carries the type of the first other record.
+    AnswerType(u16),
+    /// Tolerant mode only: a TXT answer exists but not for the queried name.
+    OwnerMismatch { expected: String, found: String },
+    /// The TXT answer carried no data.
+    EmptyTxt,
+}
+
+impl ResponseError {
+    /// Every value [`ResponseError::kind`] returns, in a stable order.
+    pub const KINDS: [&'static str; 10] = [
+        "malformed",
+        "not_response",
+        "truncated",
+        "name_error",
+        "rcode",
+        "no_data",
+        "answer_count",
+        "answer_type",
+        "owner_mismatch",
+        "empty_txt",
+    ];
+
+    /// Short stable name of the failure class, suitable for counters and logs.
+    ///
+    /// NXDOMAIN gets its own class: it is how the server says it has nothing to send.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ResponseError::Malformed(_) => "malformed",
+            ResponseError::NotResponse => "not_response",
+            ResponseError::Truncated => "truncated",
+            ResponseError::Rcode(3) => "name_error",
+            ResponseError::Rcode(_) => "rcode",
+            ResponseError::NoData => "no_data",
+            ResponseError::AnswerCount(_) => "answer_count",
+            ResponseError::AnswerType(_) => "answer_type",
+            ResponseError::OwnerMismatch { .. } => "owner_mismatch",
+            ResponseError::EmptyTxt => "empty_txt",
+        }
+    }
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseError::Malformed(field) => write!(f, "malformed {}", field),
+            ResponseError::NotResponse => write!(f, "not a response"),
+            ResponseError::Truncated => write!(f, "truncated response"),
+            ResponseError::Rcode(rcode) => write!(f, "rcode {}", rcode),
+            ResponseError::NoData => write!(f, "no answer records"),
+            ResponseError::AnswerCount(count) => write!(f, "unexpected answer count {}", count),
+            ResponseError::AnswerType(rtype) => write!(f, "unexpected answer type {}", rtype),
+            ResponseError::OwnerMismatch { expected, found } => {
+                write!(f, "answer owner {} does not match {}", found, expected)
+            }
+            ResponseError::EmptyTxt => write!(f, "empty TXT answer"),
+        }
+    }
+}
+
+impl std::error::Error for ResponseError {}
@@ -1,5 +1,8 @@
 use crate::name::parse_name;
-use crate::types::{DecodeQueryError, DnsError, Question, Rcode};
+use crate::types::{
+    DecodeQueryError, DnsError, Question, Rcode, CLASSIC_UDP_RESPONSE_LEN, MAX_UDP_RESPONSE_LEN,
+    RR_OPT,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub(crate) struct Header {
@@ -9,7 +12,11 @@ pub(crate) struct Header {
     pub(crate) cd: bool,
     pub(crate) qdcount: u16,
     pub(crate) ancount: u16,
+    pub(crate) nscount: u16,
+    pub(crate) arcount: u16,
     pub(crate) rcode: Option<Rcode>,
+    pub(crate) rcode_value: u8,
+    pub(crate) truncated: bool,
     pub(crate) offset: usize,
 }
 
@@ -21,13 +28,15 @@ pub(crate) fn parse_header(packet: &[u8]) -> Option<Header> {
     let flags = read_u16(packet, 2)?;
     let qdcount = read_u16(packet, 4)?;
     let ancount = read_u16(packet, 6)?;
-    let _nscount = read_u16(packet, 8)?;
-    let _arcount = read_u16(packet, 10)?;
+    let nscount = read_u16(packet, 8)?;
+    let arcount = read_u16(packet, 10)?;
 
     let is_response = flags & 0x8000 != 0;
     let rd = flags & 0x0100 != 0;
     let cd = flags & 0x0010 != 0;
-    let rcode = Rcode::from_u8((flags & 0x000f) as u8);
+    let truncated = flags & 0x0200 != 0;
+    let rcode_value = (flags & 0x000f) as u8;
+    let rcode = Rcode::from_u8(rcode_value);
 
     Some(Header {
         id,
@@ -36,7 +45,11 @@ pub(crate) fn parse_header(packet: &[u8]) -> Option<Header> {
         cd,
         qdcount,
         ancount,
+        nscount,
+        arcount,
         rcode,
+        rcode_value,
+        truncated,
         offset: 12,
     })
 }
@@ -90,6 +103,31 @@ pub(crate) fn parse_question(packet: &[u8], offset: usize) -> Result<(Question,
     ))
 }
 
+/// Largest UDP response the sender of `packet` accepts: the payload size of its
+/// EDNS(0) OPT record, or 512 bytes without one, capped at [`MAX_UDP_RESPONSE_LEN`].
+///
+/// `offset` is where the records after the question section start.
+pub(crate) fn max_udp_response_len(packet: &[u8], header: &Header, mut offset: usize) -> usize {
+    let records = header.ancount as usize + header.nscount as usize + header.arcount as usize;
+    for _ in 0..records {
+        let Ok((_, name_end)) = parse_name(packet, offset) else {
+            break;
+        };
+        let (Some(rtype), Some(class), Some(rdlen)) = (
+            read_u16(packet, name_end),
+            read_u16(packet, name_end + 2),
+            read_u16(packet, name_end + 8),
+        ) else {
+            break;
+        };
+        if rtype == RR_OPT {
+            return (class as usize).clamp(CLASSIC_UDP_RESPONSE_LEN, MAX_UDP_RESPONSE_LEN);
+        }
+        offset = name_end + 10 + rdlen as usize;
+    }
+    CLASSIC_UDP_RESPONSE_LEN
+}
+
 pub(crate) fn read_u16(packet: &[u8], offset: usize) -> Option<u16> {
     if offset + 2 > packet.len() {
         return None;
@@ -0,0 +1,60 @@
+use slipstream_dns::{
+    build_qname_with_layout, decode_query_with_domains, encode_query, DecodeQueryError,
+    LabelLayout, QueryParams, Rcode, CLASS_IN, RR_TXT,
+};
+
+fn query_for(qname: &str) -> Vec<u8> {
+    encode_query(&QueryParams {
+        id: 11,
+        qname,
+        qtype: RR_TXT,
+        qclass: CLASS_IN,
+        rd: true,
+        cd: false,
+        qdcount: 1,
+        is_query: true,
+    })
+    .expect("encode query")
+}
+
+#[test]
+fn decode_query_with_domains_round_trips_wide_labels() {
+    let layout = LabelLayout::new(63, false).expect("layout");
+    let payload: Vec<u8> = (0..140u8).collect();
+    let qname = build_qname_with_layout(&payload, "example.com", &layout, 0).expect("qname");
+    assert!(qname.split('.').any(|label| label.len() == 63));
+
+    let decoded = decode_query_with_domains(&query_for(&qname), &["example.com"]).expect("decode");
+    assert_eq!(decoded.payload, payload);
+}
+
+#[test]
+fn decode_query_with_domains_strips_sequence_label() {
+    let layout = LabelLayout::new(57, true).expect("layout");
+    let payload = vec![4u8, 5, 6, 7];
+    let first = build_qname_with_layout(&payload, "example.com", &layout, 1).expect("qname");
+    let second = build_qname_with_layout(&payload, "example.com", &layout, 2).expect("qname");
+    assert_ne!(first, second);
+
+    for qname in [first, second] {
+        let decoded =
+            decode_query_with_domains(&query_for(&qname), &["example.com"]).expect("decode");
+        assert_eq!(decoded.payload, payload);
+    }
+}
+
+#[test]
+fn decode_query_with_domains_rejects_sequence_label_without_data() {
+    match decode_query_with_domains(&query_for("8AAAB.example.com."), &["example.com"]) {
+        Err(DecodeQueryError::Reply { rcode, .. }) => assert_eq!(rcode, Rcode::NameError),
+        other => panic!("expected name error, got {:?}", other),
+    }
+}
+
+#[test]
+fn decode_query_with_domains_rejects_malformed_sequence_label() {
+    match decode_query_with_domains(&query_for("8AAB.AEBAG.example.com."), &["example.com"]) {
+        Err(DecodeQueryError::Reply { rcode, .. }) => assert_eq!(rcode, Rcode::ServerFailure),
+        other => panic!("expected server failure, got {:?}", other),
+    }
+}
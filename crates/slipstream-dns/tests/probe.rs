@@ -0,0 +1,120 @@
+use slipstream_dns::{
+    build_probe_qname, decode_query_with_domains, decode_response, encode_query, encode_response,
+    DecodeQueryError, ProbeKind, ProbeReply, ProbeRequest, QueryParams, Rcode, ResponseParams,
+    CLASSIC_UDP_RESPONSE_LEN, CLASS_IN, MAX_UDP_RESPONSE_LEN, RR_A, RR_TXT,
+};
+
+const DOMAIN: &str = "tunnel.example.com";
+
+fn probe_query(qtype: u16, qname: &str) -> Vec<u8> {
+    encode_query(&QueryParams {
+        id: 0x4242,
+        qname,
+        qtype,
+        qclass: CLASS_IN,
+        rd: true,
+        cd: false,
+        qdcount: 1,
+        is_query: true,
+    })
+    .expect("encode query")
+}
+
+#[test]
+fn probe_round_trips_through_server_decode() {
+    let request = ProbeRequest {
+        kind: ProbeKind::Echo,
+        nonce: 99,
+        response_len: 700,
+        cnx_id: None,
+    };
+    let qname = build_probe_qname(&request, DOMAIN, 180).expect("qname");
+    let decoded =
+        decode_query_with_domains(&probe_query(RR_TXT, &qname), &[DOMAIN]).expect("decode probe");
+    assert_eq!(decoded.probe, Some(request));
+    assert!(decoded.payload.is_empty());
+
+    let reply = ProbeReply {
+        id: decoded.id,
+        nonce: request.nonce,
+        stamp: 1,
+        qname: decoded.question.name.clone(),
+    };
+    let response = encode_response(&ResponseParams {
+        id: decoded.id,
+        rd: decoded.rd,
+        cd: decoded.cd,
+        question: &decoded.question,
+        payload: Some(&reply.encode(request.response_len as usize)),
+        rcode: None,
+    })
+    .expect("encode response");
+    let payload = decode_response(&response).expect("decode response");
+    assert_eq!(payload.len(), 700);
+    let parsed = ProbeReply::decode(&payload).expect("probe reply");
+    assert_eq!(parsed.id, 0x4242);
+    assert_eq!(parsed.qname, qname.trim_end_matches('.'));
+}
+
+#[test]
+fn non_txt_probe_gets_nodata() {
+    let request = ProbeRequest {
+        kind: ProbeKind::Echo,
+        nonce: 1,
+        response_len: 0,
+        cnx_id: None,
+    };
+    let qname = build_probe_qname(&request, DOMAIN, 0).expect("qname");
+    match decode_query_with_domains(&probe_query(RR_A, &qname), &[DOMAIN]) {
+        Err(DecodeQueryError::Reply { rcode, .. }) => assert_eq!(rcode, Rcode::Ok),
+        other => panic!("expected NODATA reply, got {:?}", other),
+    }
+
+    let data_qname = format!("AEBAGBA.{}.", DOMAIN);
+    match decode_query_with_domains(&probe_query(RR_A, &data_qname), &[DOMAIN]) {
+        Err(DecodeQueryError::Reply { rcode, .. }) => assert_eq!(rcode, Rcode::NameError),
+        other => panic!("expected NAME_ERROR reply, got {:?}", other),
+    }
+}
+
+#[test]
+fn downstream_report_decodes_with_its_kind() {
+    let request = ProbeRequest {
+        kind: ProbeKind::ReportDownstream,
+        nonce: 5,
+        response_len: 1232,
+        cnx_id: None,
+    };
+    let qname = build_probe_qname(&request, DOMAIN, 0).expect("qname");
+    let decoded =
+        decode_query_with_domains(&probe_query(RR_TXT, &qname), &[DOMAIN]).expect("decode report");
+    assert_eq!(decoded.probe, Some(request));
+}
+
+#[test]
+fn max_response_len_follows_edns_payload_size() {
+    let request = ProbeRequest {
+        kind: ProbeKind::Echo,
+        nonce: 3,
+        response_len: 60_000,
+        cnx_id: None,
+    };
+    let qname = build_probe_qname(&request, DOMAIN, 0).expect("qname");
+    let query = probe_query(RR_TXT, &qname);
+    let decode = |packet: &[u8]| {
+        decode_query_with_domains(packet, &[DOMAIN])
+            .expect("decode probe")
+            .max_response_len
+    };
+    assert_eq!(decode(&query), 1232);
+
+    // The OPT record closes the query; its class is the payload size.
+    let class_at = query.len() - 8;
+    let mut large = query.clone();
+    large[class_at..class_at + 2].copy_from_slice(&60_000u16.to_be_bytes());
+    assert_eq!(decode(&large), MAX_UDP_RESPONSE_LEN);
+
+    let mut classic = query[..query.len() - 11].to_vec();
+    classic[10..12].copy_from_slice(&0u16.to_be_bytes());
+    assert_eq!(decode(&classic), CLASSIC_UDP_RESPONSE_LEN);
+}
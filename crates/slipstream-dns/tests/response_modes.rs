@@ -0,0 +1,185 @@
+use slipstream_dns::{
+    decode_response, decode_response_with_mode, ResponseError, ResponseMode, CLASS_IN, RR_A,
+    RR_CNAME, RR_TXT,
+};
+
+const RR_RRSIG: u16 = 46;
+const QNAME: &str = "AEBAGBA.tunnel.example.com";
+
+struct ResponseBuilder {
+    flags: u16,
+    answers: Vec<Vec<u8>>,
+}
+
+impl ResponseBuilder {
+    fn new() -> Self {
+        Self {
+            flags: 0x8180,
+            answers: Vec::new(),
+        }
+    }
+
+    fn flags(mut self, flags: u16) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    fn record(mut self, owner: &str, rtype: u16, rdata: &[u8]) -> Self {
+        let mut out = name(owner);
+        out.extend_from_slice(&rtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&60u32.to_be_bytes());
+        out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(rdata);
+        self.answers.push(out);
+        self
+    }
+
+    fn txt(self, owner: &str, payload: &[u8]) -> Self {
+        let mut rdata = vec![payload.len() as u8];
+        rdata.extend_from_slice(payload);
+        self.record(owner, RR_TXT, &rdata)
+    }
+
+    fn cname(self, owner: &str, target: &str) -> Self {
+        self.record(owner, RR_CNAME, &name(target))
+    }
+
+    fn build(self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&0x1234u16.to_be_bytes());
+        out.extend_from_slice(&self.flags.to_be_bytes());
+        out.extend_from_slice(&1u16.to_be_bytes());
+        out.extend_from_slice(&(self.answers.len() as u16).to_be_bytes());
+        out.extend_from_slice(&0u16.to_be_bytes());
+        out.extend_from_slice(&0u16.to_be_bytes());
+        out.extend_from_slice(&name(QNAME));
+        out.extend_from_slice(&RR_TXT.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        for answer in self.answers {
+            out.extend_from_slice(&answer);
+        }
+        out
+    }
+}
+
+fn name(name: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+    out
+}
+
+fn tolerant(packet: &[u8]) -> Result<Vec<u8>, ResponseError> {
+    decode_response_with_mode(packet, ResponseMode::Tolerant)
+}
+
+#[test]
+fn follows_cname_chain() {
+    let packet = ResponseBuilder::new()
+        .cname(QNAME, "hop1.cdn.example.net")
+        .cname("hop1.cdn.example.net", "hop2.cdn.example.net")
+        .txt("hop2.cdn.example.net", b"payload")
+        .build();
+    assert_eq!(tolerant(&packet), Ok(b"payload".to_vec()));
+    assert_eq!(decode_response(&packet), Err(ResponseError::AnswerCount(3)));
+}
+
+#[test]
+fn skips_rrsig_and_unrelated_txt() {
+    let packet = ResponseBuilder::new()
+        .record(QNAME, RR_RRSIG, &[0u8; 24])
+        .txt("other.example.com", b"noise")
+        .txt(QNAME, b"payload")
+        .build();
+    assert_eq!(tolerant(&packet), Ok(b"payload".to_vec()));
+}
+
+#[test]
+fn matches_owner_name_case_insensitively() {
+    let packet = ResponseBuilder::new()
+        .txt(&QNAME.to_ascii_lowercase(), b"payload")
+        .build();
+    assert_eq!(tolerant(&packet), Ok(b"payload".to_vec()));
+    assert_eq!(decode_response(&packet), Ok(b"payload".to_vec()));
+}
+
+#[test]
+fn reports_owner_mismatch_and_answer_type() {
+    let packet = ResponseBuilder::new()
+        .txt("other.example.com", b"noise")
+        .build();
+    assert!(matches!(
+        tolerant(&packet),
+        Err(ResponseError::OwnerMismatch { .. })
+    ));
+
+    let packet = ResponseBuilder::new()
+        .record(QNAME, RR_A, &[192, 0, 2, 1])
+        .build();
+    assert_eq!(tolerant(&packet), Err(ResponseError::AnswerType(RR_A)));
+    assert_eq!(
+        decode_response(&packet),
+        Err(ResponseError::AnswerType(RR_A))
+    );
+}
+
+#[test]
+fn cname_loop_is_bounded() {
+    let packet = ResponseBuilder::new()
+        .cname(QNAME, "loop.example.net")
+        .cname("loop.example.net", QNAME)
+        .build();
+    assert_eq!(tolerant(&packet), Err(ResponseError::AnswerType(RR_CNAME)));
+}
+
+#[test]
+fn classifies_header_failures() {
+    let refused = ResponseBuilder::new().flags(0x8185).build();
+    assert_eq!(decode_response(&refused), Err(ResponseError::Rcode(5)));
+
+    let truncated = ResponseBuilder::new().flags(0x8380).build();
+    assert_eq!(decode_response(&truncated), Err(ResponseError::Truncated));
+
+    let empty = ResponseBuilder::new().build();
+    assert_eq!(decode_response(&empty), Err(ResponseError::NoData));
+
+    let query = ResponseBuilder::new().flags(0x0100).build();
+    assert_eq!(decode_response(&query), Err(ResponseError::NotResponse));
+
+    assert_eq!(
+        decode_response(&[0u8; 4]),
+        Err(ResponseError::Malformed("header"))
+    );
+
+    let mut cut = ResponseBuilder::new().txt(QNAME, b"payload").build();
+    cut.truncate(cut.len() - 3);
+    assert_eq!(tolerant(&cut), Err(ResponseError::Malformed("answer")));
+
+    let empty_txt = ResponseBuilder::new().record(QNAME, RR_TXT, &[0]).build();
+    assert_eq!(decode_response(&empty_txt), Err(ResponseError::EmptyTxt));
+}
+
+#[test]
+fn kinds_cover_every_error() {
+    let errors = [
+        ResponseError::Malformed("header"),
+        ResponseError::NotResponse,
+        ResponseError::Truncated,
+        ResponseError::Rcode(3),
+        ResponseError::Rcode(2),
+        ResponseError::NoData,
+        ResponseError::AnswerCount(2),
+        ResponseError::AnswerType(RR_A),
+        ResponseError::OwnerMismatch {
+            expected: QNAME.to_string(),
+            found: "other.example.com".to_string(),
+        },
+        ResponseError::EmptyTxt,
+    ];
+    let kinds: Vec<&str> = errors.iter().map(ResponseError::kind).collect();
+    assert_eq!(kinds, ResponseError::KINDS);
+}
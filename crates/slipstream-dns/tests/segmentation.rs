@@ -0,0 +1,58 @@
+use slipstream_dns::{
+    build_qname_with_layout, build_segment_qname, decode_query_with_domains, encode_query,
+    max_segment_payload_len, LabelLayout, QueryParams, SegmentHeader, SegmentReassembler, CLASS_IN,
+    RR_TXT,
+};
+
+fn query_for(id: u16, qname: &str) -> Vec<u8> {
+    encode_query(&QueryParams {
+        id,
+        qname,
+        qtype: RR_TXT,
+        qclass: CLASS_IN,
+        rd: true,
+        cd: false,
+        qdcount: 1,
+        is_query: true,
+    })
+    .expect("encode query")
+}
+
+#[test]
+fn segmented_packet_round_trips_through_queries() {
+    let domain = "tunnel.example.com";
+    let layout = LabelLayout::new(63, true).expect("layout");
+    let segment_len = max_segment_payload_len(domain, &layout).expect("segment len");
+    let packet: Vec<u8> = (0..1200u32).map(|i| (i * 7) as u8).collect();
+    let segments: Vec<&[u8]> = packet.chunks(segment_len).collect();
+    let count = segments.len() as u8;
+
+    let mut reassembler = SegmentReassembler::new(1_000_000, 64 * 1024, 64);
+    let mut reassembled = None;
+    for (index, segment) in segments.iter().enumerate().rev() {
+        let header = SegmentHeader {
+            session: 0xdeadbeef,
+            packet_id: 0x123456,
+            index: index as u8,
+            count,
+        };
+        let qname =
+            build_segment_qname(segment, &header, domain, &layout, index as u32).expect("qname");
+        let query = query_for(index as u16, &qname);
+        let decoded = decode_query_with_domains(&query, &[domain]).expect("decode");
+        assert_eq!(decoded.segment, Some(header));
+        assert!(reassembled.is_none());
+        reassembled = reassembler.insert(header, &decoded.payload, index as u64);
+    }
+    assert_eq!(reassembled, Some(packet));
+}
+
+#[test]
+fn unsegmented_queries_have_no_segment_header() {
+    let layout = LabelLayout::default();
+    let qname = build_qname_with_layout(&[1, 2, 3], "example.com", &layout, 0).expect("qname");
+    let decoded =
+        decode_query_with_domains(&query_for(1, &qname), &["example.com"]).expect("decode");
+    assert_eq!(decoded.segment, None);
+    assert_eq!(decoded.payload, vec![1, 2, 3]);
+}
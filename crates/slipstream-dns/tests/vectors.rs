@@ -4,7 +4,7 @@ use std::path::Path;
 use serde::Deserialize;
 use slipstream_dns::{
     decode_query, decode_response, encode_query, encode_response, DecodeQueryError, QueryParams,
-    Question, Rcode, ResponseParams, CLASS_IN, RR_A, RR_TXT,
+    Question, Rcode, ResponseError, ResponseParams, CLASS_IN, RR_A, RR_TXT,
 };
 
 #[derive(Debug, Deserialize)]
@@ -149,8 +149,9 @@ fn vectors_match_codec() {
                 "{}: response_no_data mismatch",
                 vector.name
             );
-            assert!(
-                decode_response(&expected).is_none(),
+            assert_eq!(
+                decode_response(&expected),
+                Err(ResponseError::Rcode(Rcode::NameError.to_u8())),
                 "{}: response_no_data should be ignored",
                 vector.name
             );
@@ -174,8 +175,14 @@ fn vectors_match_codec() {
                 "{}: response_error mismatch",
                 vector.name
             );
-            assert!(
-                decode_response(&expected).is_none(),
+            let expected_err = if rcode == Rcode::Ok {
+                ResponseError::NoData
+            } else {
+                ResponseError::Rcode(rcode.to_u8())
+            };
+            assert_eq!(
+                decode_response(&expected),
+                Err(expected_err),
                 "{}: response_error should be ignored",
                 vector.name
             );
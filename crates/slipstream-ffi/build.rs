@@ -43,12 +43,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cc_src = cc_dir.join("slipstream_server_cc.c");
     let mixed_cc_src = cc_dir.join("slipstream_mixed_cc.c");
     let poll_src = cc_dir.join("slipstream_poll.c");
+    let seed_src = cc_dir.join("slipstream_seed.c");
     let test_helpers_src = cc_dir.join("slipstream_test_helpers.c");
     let picotls_layout_src = cc_dir.join("picotls_layout.c");
     let wincompat_time_src = cc_dir.join("wincompat_time.c");
     println!("cargo:rerun-if-changed={}", cc_src.display());
     println!("cargo:rerun-if-changed={}", mixed_cc_src.display());
     println!("cargo:rerun-if-changed={}", poll_src.display());
+    println!("cargo:rerun-if-changed={}", seed_src.display());
     println!("cargo:rerun-if-changed={}", test_helpers_src.display());
     println!("cargo:rerun-if-changed={}", picotls_layout_src.display());
     println!("cargo:rerun-if-changed={}", wincompat_time_src.display());
@@ -67,6 +69,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .file(&cc_src)
         .file(&mixed_cc_src)
         .file(&poll_src)
+        .file(&seed_src)
         .file(&test_helpers_src)
         .file(&picotls_layout_src)
         .flag_if_supported("-fPIC");
@@ -281,6 +284,7 @@ fn build_picoquic_windows(
         .arg(build_dir)
         .arg("--target")
         .arg("picoquic-core")
+        .arg("picoquic-log")
         .arg("picotls-core")
         .arg("picotls-openssl")
         .arg("picotls-fusion")
@@ -498,7 +502,9 @@ fn candidate_lib_dirs(dir: &Path) -> Vec<PathBuf> {
 }
 
 fn resolve_picoquic_libs_single_dir(dir: &Path) -> Option<Vec<&'static str>> {
-    const REQUIRED: [(&str, &str); 5] = [
+    // Static link order: picoquic-log depends on picoquic-core.
+    const REQUIRED: [(&str, &str); 6] = [
+        ("picoquic_log", "picoquic-log"),
         ("picoquic_core", "picoquic-core"),
         ("picotls_core", "picotls-core"),
         ("picotls_fusion", "picotls-fusion"),
@@ -516,6 +522,7 @@ fn resolve_picoquic_libs_split(
     picoquic_dir: &Path,
     picotls_dir: &Path,
 ) -> Option<Vec<&'static str>> {
+    let picoquic_log = find_lib_variant(picoquic_dir, "picoquic_log", "picoquic-log")?;
     let picoquic_core = find_lib_variant(picoquic_dir, "picoquic_core", "picoquic-core")?;
     let picotls_core = find_lib_variant(picotls_dir, "picotls_core", "picotls-core")?;
     let picotls_fusion = find_lib_variant(picotls_dir, "picotls_fusion", "picotls-fusion")?;
@@ -523,6 +530,7 @@ fn resolve_picoquic_libs_split(
         find_lib_variant(picotls_dir, "picotls_minicrypto", "picotls-minicrypto")?;
     let picotls_openssl = find_lib_variant(picotls_dir, "picotls_openssl", "picotls-openssl")?;
     Some(vec![
+        picoquic_log,
         picoquic_core,
         picotls_core,
         picotls_fusion,
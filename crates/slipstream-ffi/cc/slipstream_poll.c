@@ -67,3 +67,16 @@ int slipstream_get_path_id_from_unique(picoquic_cnx_t *cnx, uint64_t unique_path
     }
     return path_id;
 }
+
+void slipstream_set_path_mtu(picoquic_cnx_t *cnx, int path_id, uint32_t mtu) {
+    if (cnx == NULL || path_id < 0 || path_id >= cnx->nb_paths || mtu == 0) {
+        return;
+    }
+    picoquic_path_t* path_x = cnx->path[path_id];
+    if (path_x == NULL) {
+        return;
+    }
+    path_x->send_mtu = mtu;
+    /* Record the next size up as tried so PMTU probing does not raise it again. */
+    path_x->send_mtu_max_tried = mtu + 1;
+}
@@ -0,0 +1,64 @@
+/* OpenSSL 3 deprecates RAND_METHOD, but still prefers one that is set over its DRBG. */
+#define OPENSSL_SUPPRESS_DEPRECATED
+#include <stdint.h>
+#include <string.h>
+#include <openssl/rand.h>
+
+static uint64_t slipstream_seed_state;
+
+static uint64_t slipstream_seed_next(void) {
+    /* splitmix64 */
+    uint64_t z = (slipstream_seed_state += 0x9e3779b97f4a7c15ull);
+    z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9ull;
+    z = (z ^ (z >> 27)) * 0x94d049bb133111ebull;
+    return z ^ (z >> 31);
+}
+
+static int slipstream_seed_bytes(unsigned char *buf, int num) {
+    while (num > 0) {
+        uint64_t value = slipstream_seed_next();
+        int len = num < (int)sizeof(value) ? num : (int)sizeof(value);
+        memcpy(buf, &value, (size_t)len);
+        buf += len;
+        num -= len;
+    }
+    return 1;
+}
+
+static int slipstream_seed_add(const void *buf, int num, double randomness) {
+    (void)buf;
+    (void)num;
+    (void)randomness;
+    return 1;
+}
+
+static int slipstream_seed_seed(const void *buf, int num) {
+    return slipstream_seed_add(buf, num, 0);
+}
+
+static int slipstream_seed_status(void) {
+    return 1;
+}
+
+static RAND_METHOD slipstream_seed_method = {
+    slipstream_seed_seed,
+    slipstream_seed_bytes,
+    NULL,
+    slipstream_seed_add,
+    slipstream_seed_bytes,
+    slipstream_seed_status,
+};
+
+/*
+ * Replaces OpenSSL's random generator for the whole process with one that
+ * `seed` determines. picotls and picoquic draw their randomness from it: TLS
+ * randoms, key shares, signature salts and nonces, connection IDs and reset
+ * secrets. Must be called before the QUIC context is created.
+ *
+ * Keys become predictable; this is for reproducing recorded sessions only.
+ * Returns 0 on success.
+ */
+int slipstream_seed_tls_random(uint64_t seed) {
+    slipstream_seed_state = seed;
+    return RAND_set_rand_method(&slipstream_seed_method) == 1 ? 0 : -1;
+}
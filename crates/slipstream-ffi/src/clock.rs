@@ -0,0 +1,71 @@
+use crate::picoquic::picoquic_current_time;
+use std::cell::Cell;
+use tokio::time::Instant;
+
+// Simulated time starts here; picoquic treats 0 as unset in places.
+const SIMULATED_START_US: u64 = 1_000_000;
+
+/// Where an event loop takes picoquic's notion of now from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClockSource {
+    /// picoquic's wall clock.
+    #[default]
+    System,
+    /// tokio's clock, so a paused runtime drives QUIC timers as well.
+    Tokio,
+    /// Simulated time that only [`QuicClock::advance_to`] moves, for replaying
+    /// recorded timestamps.
+    Manual,
+}
+
+/// Time source for one QUIC context.
+///
+/// On [`ClockSource::Tokio`] the context is created with simulated time that
+/// [`QuicClock::now`] moves forward to tokio's clock. picoquic reads that value
+/// in place, so the clock must outlive the context.
+pub struct QuicClock {
+    // The origin is tokio's instant at the start, unset on a manual clock.
+    simulated: Option<(Box<Cell<u64>>, Option<Instant>)>,
+}
+
+impl QuicClock {
+    pub fn new(source: ClockSource) -> Self {
+        let simulated = match source {
+            ClockSource::System => None,
+            ClockSource::Tokio => Some((
+                Box::new(Cell::new(SIMULATED_START_US)),
+                Some(Instant::now()),
+            )),
+            ClockSource::Manual => Some((Box::new(Cell::new(SIMULATED_START_US)), None)),
+        };
+        Self { simulated }
+    }
+
+    /// The `p_simulated_time` argument for `picoquic_create`; null on the system clock.
+    pub fn simulated_time_ptr(&self) -> *mut u64 {
+        match self.simulated.as_ref() {
+            Some((time, _)) => time.as_ptr(),
+            None => std::ptr::null_mut(),
+        }
+    }
+
+    /// Current time in microseconds, advancing simulated time first.
+    pub fn now(&self) -> u64 {
+        match self.simulated.as_ref() {
+            Some((time, Some(origin))) => {
+                let now = SIMULATED_START_US + origin.elapsed().as_micros() as u64;
+                time.set(now);
+                now
+            }
+            Some((time, None)) => time.get(),
+            None => unsafe { picoquic_current_time() },
+        }
+    }
+
+    /// Moves a [`ClockSource::Manual`] clock forward to `now`; time never goes back.
+    pub fn advance_to(&self, now: u64) {
+        if let Some((time, None)) = self.simulated.as_ref() {
+            time.set(time.get().max(now));
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use slipstream_core::HostPort;
+use std::net::SocketAddr;
+use std::str::FromStr;
 
+pub mod clock;
 pub mod picoquic;
 pub mod runtime;
 
@@ -13,17 +16,84 @@ pub enum ResolverMode {
     Authoritative = 2,
 }
 
+/// How the client carries DNS queries to its resolvers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DnsTransportKind {
+    /// One UDP datagram per query.
+    #[default]
+    Udp,
+    /// RFC 7766 length-prefixed messages over one TCP connection per resolver.
+    Tcp,
+    /// DNS over TLS (RFC 7858): the TCP framing inside TLS.
+    Tls,
+    /// DNS over HTTPS (RFC 8484): one HTTP/1.1 POST per query.
+    Https,
+}
+
+impl FromStr for DnsTransportKind {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "udp" => Ok(Self::Udp),
+            "tcp" => Ok(Self::Tcp),
+            "dot" => Ok(Self::Tls),
+            "doh" => Ok(Self::Https),
+            other => Err(format!(
+                "Unknown DNS transport {}; expected udp, tcp, dot or doh",
+                other
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ResolverSpec {
     pub resolver: HostPort,
     pub mode: ResolverMode,
 }
 
+#[derive(Debug, Clone)]
+pub struct DomainSpec {
+    pub domain: String,
+    /// Share of queries relative to the other domains.
+    pub weight: u32,
+    /// Restricts the domain to queries sent to this resolver.
+    pub resolver: Option<HostPort>,
+}
+
 #[derive(Debug)]
 pub struct ClientConfig<'a> {
     pub tcp_listen_port: u16,
     pub resolvers: &'a [ResolverSpec],
-    pub domain: &'a str,
+    pub domains: &'a [DomainSpec],
+    pub label_len: usize,
+    pub sequence_label: bool,
+    pub mtu: Option<u32>,
+    pub tolerant_responses: bool,
+    pub path_mtu_discovery: bool,
+    pub poll_jitter_ms: u64,
+    pub pad_bucket: u32,
+    pub cover_interval_ms: u64,
+    pub max_qps: u32,
+    pub idle_after_secs: u64,
+    pub idle_poll_interval_ms: (u64, u64),
+    pub lazy: bool,
+    pub lazy_idle_timeout_secs: u64,
+    pub metrics_listen: Option<SocketAddr>,
+    pub control_socket: Option<&'a str>,
+    pub qlog_dir: Option<&'a str>,
+    pub qlog_max_mb: u64,
+    pub keylog_file: Option<&'a str>,
+    pub pcap: Option<&'a str>,
+    pub pcap_max_mb: u64,
+    pub pcap_files: u64,
+    pub dns_transport: DnsTransportKind,
+    /// Name the DoT or DoH resolvers' certificates must carry; their
+    /// addresses when unset.
+    pub dns_tls_name: Option<&'a str>,
+    /// PEM file of extra CAs to trust for DoT and DoH resolvers.
+    pub dns_tls_ca: Option<&'a str>,
     pub cert: Option<&'a str>,
     pub congestion_control: Option<&'a str>,
     pub gso: bool,
@@ -33,7 +103,7 @@ pub struct ClientConfig<'a> {
 }
 
 pub use runtime::{
-    configure_quic, configure_quic_with_custom, sockaddr_storage_to_socket_addr,
-    socket_addr_to_storage, write_stream_or_reset, QuicGuard, SLIPSTREAM_FILE_CANCEL_ERROR,
-    SLIPSTREAM_INTERNAL_ERROR,
+    cnx_time, configure_quic, configure_quic_tracing, configure_quic_with_custom,
+    sockaddr_storage_to_socket_addr, socket_addr_to_storage, write_stream_or_reset, QuicGuard,
+    SLIPSTREAM_FILE_CANCEL_ERROR, SLIPSTREAM_INTERNAL_ERROR,
 };
@@ -188,6 +188,14 @@ pub type picoquic_connection_id_cb_fn = Option<
 
 extern "C" {
     pub fn picoquic_current_time() -> u64;
+    // Simulated time when the context was created with `p_simulated_time`, else current time.
+    pub fn picoquic_get_quic_time(quic: *mut picoquic_quic_t) -> u64;
+    pub fn picoquic_get_quic(cnx: *mut picoquic_cnx_t) -> *mut picoquic_quic_t;
+    // Seeds the generator behind connection IDs and other public randomness.
+    pub fn picoquic_public_random_seed_64(seed: u64, reset: c_int);
+    // Replaces OpenSSL's random generator, and with it every key and nonce
+    // picotls picks, with one `seed` determines; see cc/slipstream_seed.c.
+    pub fn slipstream_seed_tls_random(seed: u64) -> c_int;
 
     pub fn picoquic_create(
         max_nb_connections: c_uint,
@@ -239,12 +247,21 @@ extern "C" {
     );
     pub fn picoquic_set_max_data_control(quic: *mut picoquic_quic_t, max_data: u64);
     pub fn picoquic_set_mtu_max(quic: *mut picoquic_quic_t, mtu_max: u32);
+    pub fn picoquic_set_padding_policy(
+        quic: *mut picoquic_quic_t,
+        padding_min_size: u32,
+        padding_multiple: u32,
+    );
     pub fn picoquic_set_initial_send_mtu(
         quic: *mut picoquic_quic_t,
         initial_mtu_ipv4: u32,
         initial_mtu_ipv6: u32,
     );
     pub fn picoquic_set_key_log_file_from_env(quic: *mut picoquic_quic_t);
+    pub fn picoquic_set_key_log_file(quic: *mut picoquic_quic_t, keylog_filename: *const c_char);
+    pub fn picoquic_set_log_level(quic: *mut picoquic_quic_t, log_level: c_int);
+    // Defined in picoquic's loglib (libpicoquic-log).
+    pub fn picoquic_set_qlog(quic: *mut picoquic_quic_t, qlog_dir: *const c_char) -> c_int;
     pub fn picoquic_enable_path_callbacks_default(quic: *mut picoquic_quic_t, are_enabled: c_int);
 
     pub fn picoquic_set_verify_certificate_callback(
@@ -313,6 +330,7 @@ extern "C" {
         cnx: *mut picoquic_cnx_t,
         unique_path_id: u64,
     ) -> c_int;
+    pub fn slipstream_set_path_mtu(cnx: *mut picoquic_cnx_t, path_id: c_int, mtu: u32);
     pub fn slipstream_set_cc_override(alg_name: *const c_char);
     pub fn slipstream_set_default_path_mode(mode: c_int);
     pub fn slipstream_set_path_mode(cnx: *mut picoquic_cnx_t, path_id: c_int, mode: c_int);
@@ -383,6 +401,7 @@ extern "C" {
         stream_id: u64,
         local_stream_error: u64,
     ) -> c_int;
+    pub fn picoquic_get_remote_stream_error(cnx: *mut picoquic_cnx_t, stream_id: u64) -> u64;
     pub fn picoquic_stream_data_consumed(
         cnx: *mut picoquic_cnx_t,
         stream_id: u64,
@@ -413,6 +432,14 @@ extern "C" {
         path_id_p: *mut c_int,
     ) -> c_int;
 
+    pub fn picoquic_abandon_path(
+        cnx: *mut picoquic_cnx_t,
+        unique_path_id: u64,
+        reason: u64,
+        phrase: *const c_char,
+        current_time: u64,
+    ) -> c_int;
+
     pub fn picoquic_get_path_addr(
         cnx: *mut picoquic_cnx_t,
         unique_path_id: u64,
@@ -1,14 +1,17 @@
 use crate::picoquic::{
     picoquic_cnx_t, picoquic_congestion_algorithm_t, picoquic_disable_port_blocking, picoquic_free,
+    picoquic_get_quic, picoquic_get_quic_time,
     picoquic_quic_t, picoquic_reset_stream, picoquic_set_cookie_mode,
     picoquic_set_default_congestion_algorithm, picoquic_set_default_congestion_algorithm_by_name,
     picoquic_set_default_multipath_option, picoquic_set_default_priority,
-    picoquic_set_initial_send_mtu, picoquic_set_key_log_file_from_env,
-    picoquic_set_max_data_control, picoquic_set_mtu_max, picoquic_set_preemptive_repeat_policy,
+    picoquic_set_initial_send_mtu, picoquic_set_key_log_file, picoquic_set_key_log_file_from_env,
+    picoquic_set_log_level, picoquic_set_max_data_control, picoquic_set_mtu_max,
+    picoquic_set_preemptive_repeat_policy, picoquic_set_qlog,
     picoquic_set_stream_data_consumption_mode,
 };
 use libc::c_char;
 use slipstream_core::tcp::stream_write_buffer_bytes;
+use std::ffi::CString;
 use std::io::Write;
 use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, SocketAddrV4, SocketAddrV6, TcpStream};
 
@@ -88,6 +91,34 @@ unsafe fn configure_quic_common(quic: *mut picoquic_quic_t, mtu: u32) {
     picoquic_set_key_log_file_from_env(quic);
 }
 
+/// Turns on per-connection qlog traces in `qlog_dir` and TLS key logging to
+/// `keylog_file`. picoquic names each trace after the connection's initial ID.
+///
+/// # Safety
+/// `quic` must be a valid picoquic context.
+pub unsafe fn configure_quic_tracing(
+    quic: *mut picoquic_quic_t,
+    qlog_dir: Option<&str>,
+    keylog_file: Option<&str>,
+) -> Result<(), String> {
+    if let Some(dir) = qlog_dir {
+        std::fs::create_dir_all(dir).map_err(|err| format!("qlog dir {}: {}", dir, err))?;
+        let c_dir = CString::new(dir)
+            .map_err(|_| "qlog dir contains an unexpected null byte".to_string())?;
+        if picoquic_set_qlog(quic, c_dir.as_ptr()) != 0 {
+            return Err(format!("Could not enable qlog in {}", dir));
+        }
+        // Log every packet instead of only the start of each connection.
+        picoquic_set_log_level(quic, 1);
+    }
+    if let Some(path) = keylog_file {
+        let c_path = CString::new(path)
+            .map_err(|_| "Key log file contains an unexpected null byte".to_string())?;
+        picoquic_set_key_log_file(quic, c_path.as_ptr());
+    }
+    Ok(())
+}
+
 #[cfg(not(windows))]
 pub fn socket_addr_to_storage(addr: SocketAddr) -> SockaddrStorage {
     match addr {
@@ -232,6 +263,16 @@ pub fn sockaddr_storage_to_socket_addr(storage: &SockaddrStorage) -> Result<Sock
     }
 }
 
+/// picoquic's notion of now for `cnx`; simulated time if its context has a
+/// [`QuicClock`](crate::clock::QuicClock) on tokio's clock.
+///
+/// # Safety
+/// Caller must ensure `cnx` points to a valid picoquic connection.
+pub unsafe fn cnx_time(cnx: *mut picoquic_cnx_t) -> u64 {
+    // SAFETY: caller guarantees cnx is a valid picoquic connection.
+    unsafe { picoquic_get_quic_time(picoquic_get_quic(cnx)) }
+}
+
 /// # Safety
 /// Caller must ensure `cnx` points to a valid picoquic connection.
 pub unsafe fn write_stream_or_reset(
@@ -0,0 +1,463 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_INTERFACE: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+const IPPROTO_UDP: u8 = 17;
+
+/// One UDP payload pulled out of a capture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Datagram {
+    /// Capture time since the Unix epoch; hex dumps have none.
+    pub(crate) time: Option<Duration>,
+    pub(crate) src: Option<SocketAddr>,
+    pub(crate) dst: Option<SocketAddr>,
+    pub(crate) payload: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Capture {
+    pub(crate) datagrams: Vec<Datagram>,
+    /// Frames that were not unfragmented UDP over IPv4 or IPv6.
+    pub(crate) skipped: usize,
+}
+
+/// Reads a classic pcap, a pcapng or a hex dump, chosen by the leading bytes.
+///
+/// A hex dump holds one DNS message per line; whitespace and `:` are ignored
+/// and `#` starts a comment.
+pub(crate) fn read_capture(data: &[u8]) -> Result<Capture, String> {
+    let magic = data
+        .get(..4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    match magic {
+        Some(PCAPNG_SECTION_HEADER) => read_pcapng(data),
+        Some(magic)
+            if [PCAP_MAGIC_MICROS, PCAP_MAGIC_NANOS].contains(&magic)
+                || [PCAP_MAGIC_MICROS, PCAP_MAGIC_NANOS].contains(&magic.swap_bytes()) =>
+        {
+            read_pcap(data)
+        }
+        _ => read_hex(data),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn slice(&self, offset: usize, len: usize) -> Option<&'a [u8]> {
+        self.data.get(offset..offset.checked_add(len)?)
+    }
+}
+
+fn read_pcap(data: &[u8]) -> Result<Capture, String> {
+    let le_magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let reader = Reader {
+        data,
+        big_endian: ![PCAP_MAGIC_MICROS, PCAP_MAGIC_NANOS].contains(&le_magic),
+    };
+    let nanos = reader.u32(0) == Some(PCAP_MAGIC_NANOS);
+    let linktype = reader.u32(20).ok_or("pcap file header is truncated")? & 0x0fff_ffff;
+    let mut capture = Capture::default();
+    let mut offset = 24;
+    while offset < data.len() {
+        let (Some(secs), Some(frac), Some(len)) = (
+            reader.u32(offset),
+            reader.u32(offset + 4),
+            reader.u32(offset + 8),
+        ) else {
+            return Err(format!("pcap record at byte {} is truncated", offset));
+        };
+        let frame = reader
+            .slice(offset + 16, len as usize)
+            .ok_or_else(|| format!("pcap record at byte {} is truncated", offset))?;
+        let time = if nanos {
+            Duration::new(u64::from(secs), frac)
+        } else {
+            Duration::new(u64::from(secs), 0) + Duration::from_micros(u64::from(frac))
+        };
+        capture.push_frame(linktype, frame, Some(time));
+        offset += 16 + len as usize;
+    }
+    Ok(capture)
+}
+
+struct Interface {
+    linktype: u32,
+    /// Timestamp units per second.
+    units_per_sec: u64,
+}
+
+fn read_pcapng(data: &[u8]) -> Result<Capture, String> {
+    let mut capture = Capture::default();
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut reader = Reader {
+        data,
+        big_endian: false,
+    };
+    let mut offset = 0;
+    while offset < data.len() {
+        let block_type = reader
+            .u32(offset)
+            .ok_or_else(|| format!("pcapng block at byte {} is truncated", offset))?;
+        if block_type == PCAPNG_SECTION_HEADER {
+            // Each section declares its own byte order.
+            reader.big_endian = false;
+            match reader.u32(offset + 8) {
+                Some(PCAPNG_BYTE_ORDER_MAGIC) => {}
+                Some(magic) if magic.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => {
+                    reader.big_endian = true;
+                }
+                _ => return Err(format!("bad pcapng section header at byte {}", offset)),
+            }
+            interfaces.clear();
+        }
+        let block_len = reader
+            .u32(offset + 4)
+            .ok_or_else(|| format!("pcapng block at byte {} is truncated", offset))?
+            as usize;
+        if block_len < 12 || !block_len.is_multiple_of(4) || offset + block_len > data.len() {
+            return Err(format!("bad pcapng block length at byte {}", offset));
+        }
+        let body = Reader {
+            data: &data[offset + 8..offset + block_len - 4],
+            big_endian: reader.big_endian,
+        };
+        match block_type {
+            PCAPNG_INTERFACE => {
+                let linktype = u32::from(body.u16(0).unwrap_or(0));
+                interfaces.push(Interface {
+                    linktype,
+                    units_per_sec: interface_resolution(body),
+                });
+            }
+            PCAPNG_ENHANCED_PACKET => {
+                let (Some(id), Some(high), Some(low), Some(len)) =
+                    (body.u32(0), body.u32(4), body.u32(8), body.u32(12))
+                else {
+                    return Err(format!("pcapng packet at byte {} is truncated", offset));
+                };
+                let interface = interfaces.get(id as usize).ok_or_else(|| {
+                    format!("pcapng packet at byte {} names no interface", offset)
+                })?;
+                let frame = body
+                    .slice(20, len as usize)
+                    .ok_or_else(|| format!("pcapng packet at byte {} is truncated", offset))?;
+                let ticks = (u64::from(high) << 32) | u64::from(low);
+                let units = interface.units_per_sec.max(1);
+                let nanos = u128::from(ticks % units) * 1_000_000_000 / u128::from(units);
+                let time = Duration::new(ticks / units, nanos as u32);
+                capture.push_frame(interface.linktype, frame, Some(time));
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let interface = interfaces.first().ok_or_else(|| {
+                    format!("pcapng packet at byte {} names no interface", offset)
+                })?;
+                let orig_len = body
+                    .u32(0)
+                    .ok_or_else(|| format!("pcapng packet at byte {} is truncated", offset))?;
+                let len = (body.data.len() - 4).min(orig_len as usize);
+                capture.push_frame(interface.linktype, &body.data[4..4 + len], None);
+            }
+            _ => {}
+        }
+        offset += block_len;
+    }
+    Ok(capture)
+}
+
+/// Reads `if_tsresol` from an interface description block; microseconds by default.
+fn interface_resolution(body: Reader<'_>) -> u64 {
+    let mut offset = 8;
+    while let (Some(code), Some(len)) = (body.u16(offset), body.u16(offset + 2)) {
+        if code == 0 {
+            break;
+        }
+        if code == PCAPNG_OPTION_TSRESOL && len >= 1 {
+            if let Some(&value) = body.data.get(offset + 4) {
+                let exponent = u32::from(value & 0x7f);
+                return if value & 0x80 == 0 {
+                    10u64.checked_pow(exponent).unwrap_or(1_000_000)
+                } else {
+                    1u64.checked_shl(exponent).unwrap_or(1_000_000)
+                };
+            }
+        }
+        offset += 4 + usize::from(len).div_ceil(4) * 4;
+    }
+    1_000_000
+}
+
+fn read_hex(data: &[u8]) -> Result<Capture, String> {
+    let text = std::str::from_utf8(data)
+        .map_err(|_| "input is neither pcap, pcapng nor a text hex dump".to_string())?;
+    let mut capture = Capture::default();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let digits: Vec<u8> = line
+            .bytes()
+            .filter(|byte| !byte.is_ascii_whitespace() && *byte != b':')
+            .collect();
+        if digits.is_empty() {
+            continue;
+        }
+        if !digits.len().is_multiple_of(2) {
+            return Err(format!("line {}: odd number of hex digits", index + 1));
+        }
+        let payload = digits
+            .chunks(2)
+            .map(|pair| {
+                let pair = std::str::from_utf8(pair).unwrap_or("");
+                u8::from_str_radix(pair, 16)
+            })
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| format!("line {}: not a hex string", index + 1))?;
+        capture.datagrams.push(Datagram {
+            time: None,
+            src: None,
+            dst: None,
+            payload,
+        });
+    }
+    Ok(capture)
+}
+
+impl Capture {
+    fn push_frame(&mut self, linktype: u32, frame: &[u8], time: Option<Duration>) {
+        match link_payload(linktype, frame).and_then(udp_datagram) {
+            Some((src, dst, payload)) => self.datagrams.push(Datagram {
+                time,
+                src: Some(src),
+                dst: Some(dst),
+                payload: payload.to_vec(),
+            }),
+            None => self.skipped += 1,
+        }
+    }
+}
+
+/// Strips the link-layer header, returning the IP packet.
+fn link_payload(linktype: u32, frame: &[u8]) -> Option<&[u8]> {
+    match linktype {
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(frame),
+        LINKTYPE_NULL | LINKTYPE_LOOP => frame.get(4..),
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += 4;
+                ethertype = u16::from_be_bytes([*frame.get(offset)?, *frame.get(offset + 1)?]);
+            }
+            match ethertype {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => frame.get(offset + 2..),
+                _ => None,
+            }
+        }
+        LINKTYPE_LINUX_SLL => frame.get(16..),
+        LINKTYPE_LINUX_SLL2 => frame.get(20..),
+        _ => None,
+    }
+}
+
+/// Returns the endpoints and payload of an unfragmented UDP packet.
+fn udp_datagram(packet: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (src, dst, udp) = match packet.first()? >> 4 {
+        4 => {
+            let header_len = usize::from(packet[0] & 0x0f) * 4;
+            let total_len = usize::from(u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]));
+            let fragment = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]);
+            // Skip fragments: more-fragments flag or a non-zero offset.
+            if fragment & 0x3fff != 0 || *packet.get(9)? != IPPROTO_UDP {
+                return None;
+            }
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            let end = total_len.min(packet.len());
+            (
+                IpAddr::V4(Ipv4Addr::from(src)),
+                IpAddr::V4(Ipv4Addr::from(dst)),
+                packet.get(header_len..end)?,
+            )
+        }
+        6 => {
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            let payload_len = usize::from(u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]));
+            let end = (40 + payload_len).min(packet.len());
+            let mut next = *packet.get(6)?;
+            let mut offset = 40;
+            // Hop-by-hop, routing and destination options headers.
+            while matches!(next, 0 | 43 | 60) {
+                next = *packet.get(offset)?;
+                offset += (usize::from(*packet.get(offset + 1)?) + 1) * 8;
+            }
+            if next != IPPROTO_UDP {
+                return None;
+            }
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                packet.get(offset..end)?,
+            )
+        }
+        _ => return None,
+    };
+    let src_port = u16::from_be_bytes([*udp.first()?, *udp.get(1)?]);
+    let dst_port = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+    let udp_len = usize::from(u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]));
+    let payload = udp.get(8..udp_len.clamp(8, udp.len()))?;
+    Some((
+        SocketAddr::new(src, src_port),
+        SocketAddr::new(dst, dst_port),
+        payload,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_capture;
+    use std::time::Duration;
+
+    fn ipv4_udp(payload: &[u8]) -> Vec<u8> {
+        let total_len = (28 + payload.len()) as u16;
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&total_len.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+        packet.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 7]);
+        packet.extend_from_slice(&40000u16.to_be_bytes());
+        packet.extend_from_slice(&53u16.to_be_bytes());
+        packet.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    #[test]
+    fn reads_classic_pcap() {
+        let packet = ipv4_udp(b"dns");
+        let mut file = Vec::new();
+        file.extend_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+        file.extend_from_slice(&[2, 0, 4, 0]);
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&65535u32.to_le_bytes());
+        file.extend_from_slice(&101u32.to_le_bytes());
+        for word in [10u32, 250, packet.len() as u32, packet.len() as u32] {
+            file.extend_from_slice(&word.to_le_bytes());
+        }
+        file.extend_from_slice(&packet);
+        // An ARP-sized frame that is not IP.
+        for word in [11u32, 0, 2, 2] {
+            file.extend_from_slice(&word.to_le_bytes());
+        }
+        file.extend_from_slice(&[0, 1]);
+
+        let capture = read_capture(&file).expect("capture");
+        assert_eq!(capture.skipped, 1);
+        assert_eq!(capture.datagrams.len(), 1);
+        let datagram = &capture.datagrams[0];
+        assert_eq!(datagram.payload, b"dns");
+        assert_eq!(datagram.src, Some("192.0.2.1:40000".parse().unwrap()));
+        assert_eq!(datagram.dst, Some("198.51.100.7:53".parse().unwrap()));
+        assert_eq!(datagram.time, Some(Duration::from_micros(10_000_250)));
+    }
+
+    #[test]
+    fn reads_pcapng_with_ethernet() {
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        frame.extend_from_slice(&ipv4_udp(b"abcd"));
+        let mut file = Vec::new();
+        let mut block = |block_type: u32, body: &[u8]| {
+            let padded = body.len().div_ceil(4) * 4;
+            let len = (12 + padded) as u32;
+            file.extend_from_slice(&block_type.to_le_bytes());
+            file.extend_from_slice(&len.to_le_bytes());
+            file.extend_from_slice(body);
+            file.resize(file.len() + padded - body.len(), 0);
+            file.extend_from_slice(&len.to_le_bytes());
+        };
+        let mut section = 0x1a2b_3c4du32.to_le_bytes().to_vec();
+        section.extend_from_slice(&[1, 0, 0, 0]);
+        section.extend_from_slice(&u64::MAX.to_le_bytes());
+        block(0x0a0d_0d0a, &section);
+        // Ethernet with if_tsresol = 10^-9.
+        let mut interface = vec![1, 0, 0, 0, 0, 0, 0, 0];
+        interface.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+        block(1, &interface);
+        let mut packet = Vec::new();
+        let ticks = 1_500_000_000u64;
+        for word in [
+            0u32,
+            (ticks >> 32) as u32,
+            ticks as u32,
+            frame.len() as u32,
+            frame.len() as u32,
+        ] {
+            packet.extend_from_slice(&word.to_le_bytes());
+        }
+        packet.extend_from_slice(&frame);
+        block(6, &packet);
+
+        let capture = read_capture(&file).expect("capture");
+        assert_eq!(capture.skipped, 0);
+        assert_eq!(capture.datagrams.len(), 1);
+        assert_eq!(capture.datagrams[0].payload, b"abcd");
+        assert_eq!(capture.datagrams[0].time, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn reads_hex_dump() {
+        let capture = read_capture(b"# from a bug report\nde ad:BE ef\n\n0001 # trailing\n")
+            .expect("capture");
+        let payloads: Vec<&[u8]> = capture
+            .datagrams
+            .iter()
+            .map(|datagram| datagram.payload.as_slice())
+            .collect();
+        assert_eq!(payloads, vec![&[0xde, 0xad, 0xbe, 0xef][..], &[0, 1][..]]);
+        assert!(read_capture(b"abc\n").is_err());
+        assert!(read_capture(b"zz\n").is_err());
+    }
+}
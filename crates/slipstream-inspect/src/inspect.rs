@@ -0,0 +1,366 @@
+use crate::capture::Datagram;
+use crate::quic::parse_datagram;
+use slipstream_dns::{
+    base32_decode, decode_query_with_domains, decode_response_with_mode, undotify,
+    DecodeQueryError, Question, Rcode, ResponseError, ResponseMode, RR_TXT,
+    SEGMENT_HEADER_LABEL_LEN, SEGMENT_MARKER, SEQUENCE_LABEL_LEN, SEQUENCE_MARKER,
+};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+const DNS_HEADER_LEN: usize = 12;
+
+/// Running totals printed after the last datagram.
+#[derive(Debug, Default)]
+pub(crate) struct Totals {
+    pub(crate) queries: usize,
+    pub(crate) responses: usize,
+    pub(crate) failures: usize,
+    pub(crate) not_dns: usize,
+}
+
+struct PendingQuery {
+    time: Option<Duration>,
+    probe: bool,
+}
+
+/// Decodes datagrams the way the client and server would and describes them.
+pub(crate) struct Inspector<'a> {
+    domains: Vec<&'a str>,
+    response_mode: ResponseMode,
+    cid_len: usize,
+    first_time: Option<Duration>,
+    /// Queries waiting for an answer, by DNS id and client address.
+    pending: HashMap<(u16, Option<SocketAddr>), PendingQuery>,
+    pub(crate) totals: Totals,
+}
+
+impl<'a> Inspector<'a> {
+    pub(crate) fn new(domains: Vec<&'a str>, response_mode: ResponseMode, cid_len: usize) -> Self {
+        Self {
+            domains,
+            response_mode,
+            cid_len,
+            first_time: None,
+            pending: HashMap::new(),
+            totals: Totals::default(),
+        }
+    }
+
+    /// Describes one datagram as a summary line plus indented details.
+    pub(crate) fn describe(&mut self, index: usize, datagram: &Datagram) -> String {
+        let mut out = format!("#{}", index);
+        if let Some(time) = datagram.time {
+            let first = *self.first_time.get_or_insert(time);
+            let _ = write!(out, " {:.6}", time.saturating_sub(first).as_secs_f64());
+        }
+        if let (Some(src), Some(dst)) = (datagram.src, datagram.dst) {
+            let _ = write!(out, " {} > {}", src, dst);
+        }
+        let payload = &datagram.payload;
+        if payload.len() < DNS_HEADER_LEN {
+            self.totals.not_dns += 1;
+            let _ = writeln!(out, " not DNS ({} bytes)", payload.len());
+            return out;
+        }
+        let id = u16::from_be_bytes([payload[0], payload[1]]);
+        if payload[2] & 0x80 == 0 {
+            self.totals.queries += 1;
+            self.describe_query(&mut out, id, datagram);
+        } else {
+            self.totals.responses += 1;
+            self.describe_response(&mut out, id, datagram);
+        }
+        out
+    }
+
+    fn describe_query(&mut self, out: &mut String, id: u16, datagram: &Datagram) {
+        let _ = write!(out, " query id=0x{:04x}", id);
+        match decode_query_with_domains(&datagram.payload, &self.domains) {
+            Ok(query) => {
+                let _ = writeln!(
+                    out,
+                    " {} ({} bytes)",
+                    query.question.name,
+                    datagram.payload.len()
+                );
+                self.pending.insert(
+                    (id, datagram.src),
+                    PendingQuery {
+                        time: datagram.time,
+                        probe: query.probe.is_some(),
+                    },
+                );
+                if let Some(probe) = query.probe {
+                    let _ = writeln!(out, "    doctor probe {:?}", probe.kind);
+                    return;
+                }
+                match query.segment {
+                    Some(segment) => {
+                        let _ = writeln!(
+                            out,
+                            "    segment {}/{} of packet 0x{:06x} in session 0x{:08x}, {} bytes",
+                            segment.index + 1,
+                            segment.count,
+                            segment.packet_id,
+                            segment.session,
+                            query.payload.len()
+                        );
+                        // Only the first segment starts with a QUIC header.
+                        if segment.index == 0 {
+                            self.describe_quic(out, &query.payload);
+                        }
+                    }
+                    None => {
+                        let _ = writeln!(out, "    payload {} bytes", query.payload.len());
+                        self.describe_quic(out, &query.payload);
+                    }
+                }
+            }
+            Err(DecodeQueryError::Drop) => {
+                self.totals.failures += 1;
+                let _ = writeln!(out, " ({} bytes)", datagram.payload.len());
+                let _ = writeln!(
+                    out,
+                    "    decode failed: malformed header or question; the server drops it"
+                );
+            }
+            Err(DecodeQueryError::Reply {
+                question, rcode, ..
+            }) => {
+                self.totals.failures += 1;
+                let name = question
+                    .as_ref()
+                    .map_or("-", |question| question.name.as_str());
+                let _ = writeln!(out, " {} ({} bytes)", name, datagram.payload.len());
+                let reason = explain_query_error(question.as_ref(), rcode, &self.domains);
+                let _ = writeln!(
+                    out,
+                    "    decode failed: {}; the server answers rcode {}",
+                    reason,
+                    rcode.to_u8()
+                );
+            }
+        }
+    }
+
+    fn describe_response(&mut self, out: &mut String, id: u16, datagram: &Datagram) {
+        let payload = &datagram.payload;
+        let _ = write!(out, " response id=0x{:04x} rcode={}", id, payload[3] & 0x0f);
+        if payload[2] & 0x02 != 0 {
+            let _ = write!(out, " tc");
+        }
+        let pending = self.pending.remove(&(id, datagram.dst));
+        if let Some((Some(sent), Some(received))) = pending
+            .as_ref()
+            .map(|pending| (pending.time, datagram.time))
+        {
+            let rtt = received.saturating_sub(sent);
+            let _ = write!(out, " rtt={:.3}ms", rtt.as_secs_f64() * 1000.0);
+        }
+        let _ = writeln!(out, " ({} bytes)", payload.len());
+        match decode_response_with_mode(payload, self.response_mode) {
+            Ok(data) if pending.as_ref().is_some_and(|pending| pending.probe) => {
+                let _ = writeln!(out, "    doctor probe reply, {} bytes", data.len());
+            }
+            Ok(data) => {
+                let _ = writeln!(out, "    payload {} bytes", data.len());
+                self.describe_quic(out, &data);
+            }
+            Err(ResponseError::NoData) => {
+                let _ = writeln!(out, "    no payload (nothing queued for this poll)");
+            }
+            Err(err) => {
+                self.totals.failures += 1;
+                let _ = writeln!(out, "    decode failed: {}", explain_response_error(&err));
+            }
+        }
+    }
+
+    fn describe_quic(&self, out: &mut String, data: &[u8]) {
+        let summary = parse_datagram(data, self.cid_len);
+        for packet in &summary.packets {
+            let _ = writeln!(out, "    quic {}", packet);
+        }
+        if summary.padding > 0 {
+            let _ = writeln!(out, "    padding {} bytes", summary.padding);
+        }
+        if let Some(err) = summary.error {
+            let _ = writeln!(out, "    quic parse stopped at {}", err);
+        }
+    }
+}
+
+/// Spells out why `decode_query_with_domains` turned a query into an error reply.
+pub(crate) fn explain_query_error(
+    question: Option<&Question>,
+    rcode: Rcode,
+    domains: &[&str],
+) -> String {
+    let Some(question) = question else {
+        return "not exactly one question, or the QR bit is set".to_string();
+    };
+    if question.qtype != RR_TXT {
+        return if rcode == Rcode::Ok {
+            format!("doctor probe with qtype {}", question.qtype)
+        } else {
+            format!("qtype {} is not TXT", question.qtype)
+        };
+    }
+    if rcode == Rcode::FormatError {
+        return "not exactly one question".to_string();
+    }
+    let name = question.name.trim_end_matches('.');
+    let lower = name.to_ascii_lowercase();
+    let suffix = domains
+        .iter()
+        .map(|domain| domain.trim_end_matches('.'))
+        .filter(|domain| {
+            let domain = domain.to_ascii_lowercase();
+            lower == domain || lower.ends_with(&format!(".{}", domain))
+        })
+        .max_by_key(|domain| domain.len());
+    let Some(suffix) = suffix else {
+        return format!(
+            "suffix mismatch: {} is not under {}",
+            name,
+            domains.join(", ")
+        );
+    };
+    if name.len() == suffix.len() {
+        return format!("QNAME is the bare domain {} with no payload", suffix);
+    }
+    let subdomain = &name[..name.len() - suffix.len() - 1];
+    let data_labels = match subdomain.split_once('.') {
+        _ if subdomain.as_bytes().first() != Some(&SEQUENCE_MARKER) => subdomain,
+        Some((label, _)) if label.len() != SEQUENCE_LABEL_LEN => {
+            return format!(
+                "bad sequence label {}: {} characters, expected {}",
+                label,
+                label.len(),
+                SEQUENCE_LABEL_LEN
+            );
+        }
+        Some((_, data)) => data,
+        None => return "only a sequence label; no payload labels".to_string(),
+    };
+    let data_labels = match data_labels.split_once('.') {
+        Some((label, rest)) if label.as_bytes().first() == Some(&SEGMENT_MARKER) => {
+            if label.len() != SEGMENT_HEADER_LABEL_LEN {
+                return format!(
+                    "bad segment header label {}: {} characters, expected {}",
+                    label,
+                    label.len(),
+                    SEGMENT_HEADER_LABEL_LEN
+                );
+            }
+            match base32_decode(&label[1..]) {
+                Ok(bytes) if bytes.len() == 9 && bytes[8] > 0 && bytes[7] < bytes[8] => rest,
+                Ok(bytes) if bytes.len() == 9 => {
+                    return format!(
+                        "bad segment header label {}: index {} of {} segments",
+                        label, bytes[7], bytes[8]
+                    )
+                }
+                Ok(_) => return format!("bad segment header label {}", label),
+                Err(err) => return format!("bad segment header label {}: {}", label, err),
+            }
+        }
+        _ => data_labels,
+    };
+    let undotted = undotify(data_labels);
+    if undotted.is_empty() {
+        return "no payload labels".to_string();
+    }
+    match base32_decode(&undotted) {
+        Err(err) => format!("bad base32 in {}: {}", data_labels, err),
+        Ok(_) => format!("rejected with rcode {}", rcode.to_u8()),
+    }
+}
+
+fn explain_response_error(err: &ResponseError) -> String {
+    match err {
+        ResponseError::Rcode(3) => {
+            "rcode 3 (NXDOMAIN): the server did not accept the query or a resolver answered for it"
+                .to_string()
+        }
+        ResponseError::Rcode(2) => {
+            "rcode 2 (SERVFAIL): bad base32 or segment header, or a resolver failed upstream"
+                .to_string()
+        }
+        ResponseError::AnswerCount(_)
+        | ResponseError::AnswerType(_)
+        | ResponseError::OwnerMismatch { .. } => {
+            format!(
+                "{} (try --tolerant-responses if a resolver rewrote the answer)",
+                err
+            )
+        }
+        err => err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::explain_query_error;
+    use slipstream_dns::{base32_encode, Question, Rcode, RR_A, RR_TXT};
+
+    fn question(name: &str, qtype: u16) -> Question {
+        Question {
+            name: name.to_string(),
+            qtype,
+            qclass: 1,
+        }
+    }
+
+    #[test]
+    fn explains_query_failures() {
+        let domains = ["t.example.com", "example.net"];
+        let explain = |name: &str, qtype, rcode| {
+            explain_query_error(Some(&question(name, qtype)), rcode, &domains)
+        };
+        assert_eq!(
+            explain("abc.example.org.", RR_TXT, Rcode::NameError),
+            "suffix mismatch: abc.example.org is not under t.example.com, example.net"
+        );
+        assert_eq!(
+            explain("T.Example.com.", RR_TXT, Rcode::NameError),
+            "QNAME is the bare domain t.example.com with no payload"
+        );
+        assert_eq!(
+            explain("ab!d.t.example.com.", RR_TXT, Rcode::ServerFailure),
+            "bad base32 in ab!d: invalid base32 character"
+        );
+        assert_eq!(
+            explain("0abc.aaaa.example.net.", RR_TXT, Rcode::ServerFailure),
+            "bad segment header label 0abc: 4 characters, expected 16"
+        );
+        let header = format!("0{}", base32_encode(&[0, 0, 0, 7, 0, 0, 1, 3, 2]));
+        assert_eq!(
+            explain(
+                &format!("{}.aaaa.example.net.", header),
+                RR_TXT,
+                Rcode::ServerFailure
+            ),
+            format!("bad segment header label {}: index 3 of 2 segments", header)
+        );
+        assert_eq!(
+            explain("8aab.aaaa.example.net.", RR_TXT, Rcode::ServerFailure),
+            "bad sequence label 8aab: 4 characters, expected 5"
+        );
+        assert_eq!(
+            explain("8aaab.example.net.", RR_TXT, Rcode::NameError),
+            "only a sequence label; no payload labels"
+        );
+        assert_eq!(
+            explain("aaaa.example.net.", RR_A, Rcode::NameError),
+            "qtype 1 is not TXT"
+        );
+        assert_eq!(
+            explain_query_error(None, Rcode::FormatError, &domains),
+            "not exactly one question, or the QR bit is set"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+mod capture;
+mod inspect;
+mod quic;
+
+use capture::read_capture;
+use clap::Parser;
+use inspect::Inspector;
+use slipstream_core::normalize_domain;
+use slipstream_dns::ResponseMode;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "slipstream-inspect",
+    about = "slipstream-inspect - Decode captured slipstream DNS traffic offline"
+)]
+struct Args {
+    #[arg(value_name = "FILE")]
+    input: PathBuf,
+    #[arg(long = "domain", short = 'd', value_parser = parse_domain, required = true)]
+    domains: Vec<String>,
+    #[arg(long = "tolerant-responses")]
+    tolerant_responses: bool,
+    #[arg(long = "port", value_name = "PORT")]
+    port: Option<u16>,
+    #[arg(
+        long = "cid-len",
+        value_name = "BYTES",
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u8).range(0..=20)
+    )]
+    cid_len: u8,
+}
+
+fn main() {
+    let args = Args::parse();
+    let data = match std::fs::read(&args.input) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("{}: {}", args.input.display(), err);
+            std::process::exit(1);
+        }
+    };
+    let capture = match read_capture(&data) {
+        Ok(capture) => capture,
+        Err(err) => {
+            eprintln!("{}: {}", args.input.display(), err);
+            std::process::exit(1);
+        }
+    };
+    let response_mode = if args.tolerant_responses {
+        ResponseMode::Tolerant
+    } else {
+        ResponseMode::Strict
+    };
+    let domains = args.domains.iter().map(String::as_str).collect();
+    let mut inspector = Inspector::new(domains, response_mode, args.cid_len as usize);
+
+    let mut filtered = 0usize;
+    for (index, datagram) in capture.datagrams.iter().enumerate() {
+        if let Some(port) = args.port {
+            let ports = [datagram.src, datagram.dst].map(|addr| addr.map(|addr| addr.port()));
+            if !ports.contains(&Some(port)) {
+                filtered += 1;
+                continue;
+            }
+        }
+        print!("{}", inspector.describe(index + 1, datagram));
+    }
+    let totals = &inspector.totals;
+    println!(
+        "{} queries, {} responses, {} decode failures, {} not DNS; skipped {} non-UDP frames and {} on other ports",
+        totals.queries,
+        totals.responses,
+        totals.failures,
+        totals.not_dns,
+        capture.skipped,
+        filtered
+    );
+}
+
+fn parse_domain(input: &str) -> Result<String, String> {
+    normalize_domain(input).map_err(|err| err.to_string())
+}
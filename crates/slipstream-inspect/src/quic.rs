@@ -0,0 +1,288 @@
+use std::fmt;
+
+const VERSION_NEGOTIATION: u32 = 0;
+const VERSION_1: u32 = 0x0000_0001;
+const VERSION_2: u32 = 0x6b33_43cf;
+const RETRY_TAG_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PacketType {
+    Initial,
+    ZeroRtt,
+    Handshake,
+    Retry,
+    VersionNegotiation,
+    OneRtt,
+}
+
+impl PacketType {
+    fn label(self) -> &'static str {
+        match self {
+            PacketType::Initial => "Initial",
+            PacketType::ZeroRtt => "0-RTT",
+            PacketType::Handshake => "Handshake",
+            PacketType::Retry => "Retry",
+            PacketType::VersionNegotiation => "VersionNegotiation",
+            PacketType::OneRtt => "1-RTT",
+        }
+    }
+}
+
+/// Unprotected header fields of one QUIC packet.
+///
+/// Header protection hides the packet number, its length and the key phase,
+/// so they are not reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PacketHeader {
+    pub(crate) packet_type: PacketType,
+    /// Long headers only.
+    pub(crate) version: Option<u32>,
+    pub(crate) dcid: Vec<u8>,
+    /// Long headers only.
+    pub(crate) scid: Option<Vec<u8>>,
+    /// Initial and Retry only.
+    pub(crate) token_len: Option<usize>,
+    /// Short headers only.
+    pub(crate) spin: Option<bool>,
+    /// Bytes after the header fields shown here, packet number included.
+    pub(crate) payload_len: usize,
+    /// The Length field points past the end of the datagram.
+    pub(crate) truncated: bool,
+}
+
+/// The packets coalesced in one datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DatagramSummary {
+    pub(crate) packets: Vec<PacketHeader>,
+    /// Why parsing stopped before the end of the datagram, if it did.
+    pub(crate) error: Option<String>,
+    /// Zero bytes after the last packet.
+    pub(crate) padding: usize,
+}
+
+/// Walks the QUIC packets in `data`; short headers need the connection ID length.
+pub(crate) fn parse_datagram(data: &[u8], short_cid_len: usize) -> DatagramSummary {
+    let mut summary = DatagramSummary {
+        packets: Vec::new(),
+        error: None,
+        padding: 0,
+    };
+    let mut offset = 0;
+    while offset < data.len() {
+        if !summary.packets.is_empty() && data[offset..].iter().all(|&byte| byte == 0) {
+            summary.padding = data.len() - offset;
+            break;
+        }
+        match parse_packet(&data[offset..], short_cid_len) {
+            Ok((header, len)) => {
+                let truncated = header.truncated;
+                summary.packets.push(header);
+                if truncated {
+                    break;
+                }
+                offset += len;
+            }
+            Err(err) => {
+                summary.error = Some(format!("byte {}: {}", offset, err));
+                break;
+            }
+        }
+    }
+    summary
+}
+
+/// Parses one packet and returns its header and total length.
+fn parse_packet(data: &[u8], short_cid_len: usize) -> Result<(PacketHeader, usize), String> {
+    let first = data[0];
+    if first & 0x80 == 0 {
+        if first & 0x40 == 0 {
+            return Err("fixed bit is clear; not a QUIC packet".to_string());
+        }
+        let dcid = data
+            .get(1..1 + short_cid_len)
+            .ok_or("short header shorter than its connection ID")?;
+        let header = PacketHeader {
+            packet_type: PacketType::OneRtt,
+            version: None,
+            dcid: dcid.to_vec(),
+            scid: None,
+            token_len: None,
+            spin: Some(first & 0x20 != 0),
+            payload_len: data.len() - 1 - short_cid_len,
+            truncated: false,
+        };
+        return Ok((header, data.len()));
+    }
+
+    let mut cursor = Cursor { data, offset: 1 };
+    let version = u32::from_be_bytes(
+        cursor
+            .take(4)
+            .ok_or("long header is truncated")?
+            .try_into()
+            .unwrap_or_default(),
+    );
+    let dcid = cursor
+        .take_prefixed()
+        .ok_or("destination connection ID is truncated")?;
+    let scid = cursor
+        .take_prefixed()
+        .ok_or("source connection ID is truncated")?;
+    let mut header = PacketHeader {
+        packet_type: PacketType::VersionNegotiation,
+        version: Some(version),
+        dcid: dcid.to_vec(),
+        scid: Some(scid.to_vec()),
+        token_len: None,
+        spin: None,
+        payload_len: data.len() - cursor.offset,
+        truncated: false,
+    };
+    if version == VERSION_NEGOTIATION {
+        return Ok((header, data.len()));
+    }
+    if first & 0x40 == 0 {
+        return Err("fixed bit is clear; not a QUIC packet".to_string());
+    }
+    let type_bits = (first >> 4) & 0x03;
+    header.packet_type = match (version == VERSION_2, type_bits) {
+        (false, 0) | (true, 1) => PacketType::Initial,
+        (false, 1) | (true, 2) => PacketType::ZeroRtt,
+        (false, 2) | (true, 3) => PacketType::Handshake,
+        _ => PacketType::Retry,
+    };
+    if header.packet_type == PacketType::Retry {
+        let token_len = header.payload_len.saturating_sub(RETRY_TAG_LEN);
+        header.token_len = Some(token_len);
+        header.payload_len = 0;
+        return Ok((header, data.len()));
+    }
+    if header.packet_type == PacketType::Initial {
+        let token_len = cursor.varint().ok_or("token length is truncated")? as usize;
+        cursor.take(token_len).ok_or("token is truncated")?;
+        header.token_len = Some(token_len);
+    }
+    let length = cursor.varint().ok_or("length field is truncated")? as usize;
+    let remaining = data.len() - cursor.offset;
+    header.payload_len = length;
+    header.truncated = length > remaining;
+    Ok((header, cursor.offset + length.min(remaining)))
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    fn take_prefixed(&mut self) -> Option<&'a [u8]> {
+        let len = usize::from(*self.take(1)?.first()?);
+        self.take(len)
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let first = *self.data.get(self.offset)?;
+        let len = 1usize << (first >> 6);
+        let bytes = self.take(len)?;
+        let mut value = u64::from(first & 0x3f);
+        for &byte in &bytes[1..] {
+            value = (value << 8) | u64::from(byte);
+        }
+        Some(value)
+    }
+}
+
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    if bytes.is_empty() {
+        return write!(f, "-");
+    }
+    for byte in bytes {
+        write!(f, "{:02x}", byte)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for PacketHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.packet_type.label())?;
+        match self.version {
+            Some(VERSION_NEGOTIATION) | None => {}
+            Some(VERSION_1) => write!(f, " v1")?,
+            Some(VERSION_2) => write!(f, " v2")?,
+            Some(version) => write!(f, " version=0x{:08x}", version)?,
+        }
+        write!(f, " dcid=")?;
+        write_hex(f, &self.dcid)?;
+        if let Some(scid) = self.scid.as_ref() {
+            write!(f, " scid=")?;
+            write_hex(f, scid)?;
+        }
+        if let Some(token_len) = self.token_len {
+            write!(f, " token={}", token_len)?;
+        }
+        if let Some(spin) = self.spin {
+            write!(f, " spin={}", u8::from(spin))?;
+        }
+        if self.packet_type != PacketType::Retry {
+            write!(f, " len={}", self.payload_len)?;
+        }
+        if self.truncated {
+            write!(f, " (truncated)")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_datagram, PacketType};
+
+    #[test]
+    fn parses_coalesced_long_headers_and_padding() {
+        let mut datagram = vec![0xc0, 0, 0, 0, 1, 4, 1, 2, 3, 4, 2, 9, 9, 0, 3];
+        datagram.extend_from_slice(&[0xaa; 3]);
+        datagram.extend_from_slice(&[0xe0, 0, 0, 0, 1, 4, 1, 2, 3, 4, 0, 0x40, 2]);
+        datagram.extend_from_slice(&[0xbb; 2]);
+        datagram.extend_from_slice(&[0; 5]);
+
+        let summary = parse_datagram(&datagram, 8);
+        assert_eq!(summary.error, None);
+        assert_eq!(summary.padding, 5);
+        assert_eq!(summary.packets.len(), 2);
+        assert_eq!(summary.packets[0].packet_type, PacketType::Initial);
+        assert_eq!(summary.packets[0].token_len, Some(0));
+        assert_eq!(
+            summary.packets[0].to_string(),
+            "Initial v1 dcid=01020304 scid=0909 token=0 len=3"
+        );
+        assert_eq!(
+            summary.packets[1].to_string(),
+            "Handshake v1 dcid=01020304 scid=- len=2"
+        );
+    }
+
+    #[test]
+    fn parses_short_header_with_configured_cid_length() {
+        let summary = parse_datagram(&[0x60, 1, 2, 3, 4, 0xff, 0xff], 4);
+        assert_eq!(summary.packets.len(), 1);
+        assert_eq!(
+            summary.packets[0].to_string(),
+            "1-RTT dcid=01020304 spin=1 len=2"
+        );
+    }
+
+    #[test]
+    fn reports_truncation_and_garbage() {
+        let summary = parse_datagram(&[0xc0, 0, 0, 0, 1, 0, 0, 0, 0x10, 1], 8);
+        assert!(summary.packets[0].truncated);
+        let summary = parse_datagram(&[0x00, 1, 2], 8);
+        assert!(summary.packets.is_empty());
+        assert!(summary.error.expect("error").contains("fixed bit"));
+    }
+}
@@ -0,0 +1,211 @@
+use crate::stats::LoadStats;
+use crate::transport::MeteredSocket;
+use crate::workload::{run_stream, Workload};
+use slipstream_client::{run_client_with, ClientError};
+use slipstream_ffi::clock::ClockSource;
+use slipstream_ffi::{ClientConfig, DnsTransportKind, DomainSpec, ResolverSpec};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Builder;
+use tokio::task::LocalSet;
+use tokio::time::{sleep, sleep_until, Instant};
+use tracing::{debug, warn};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Who connects, when, and what they do once connected.
+#[derive(Debug)]
+pub(crate) struct LoadPlan {
+    pub(crate) resolvers: Vec<ResolverSpec>,
+    pub(crate) domains: Vec<DomainSpec>,
+    pub(crate) cert: Option<String>,
+    pub(crate) workload: Workload,
+    /// Pause between streams of one client.
+    pub(crate) think: Duration,
+    pub(crate) clients: usize,
+    /// Clients added at the start of each step.
+    pub(crate) ramp_step: usize,
+    pub(crate) step: Duration,
+}
+
+impl LoadPlan {
+    pub(crate) fn steps(&self) -> usize {
+        self.clients.div_ceil(self.ramp_step)
+    }
+
+    /// Clients running during step `step` (zero-based).
+    pub(crate) fn clients_in_step(&self, step: usize) -> usize {
+        ((step + 1) * self.ramp_step).min(self.clients)
+    }
+
+    fn start_delay(&self, client: usize) -> Duration {
+        self.step * (client / self.ramp_step) as u32
+    }
+}
+
+/// Starts every simulated client, spread over `threads` threads that each
+/// run their own runtime. Clients run until the process exits.
+pub(crate) fn spawn_clients(
+    plan: Arc<LoadPlan>,
+    threads: usize,
+    stats: Arc<Mutex<LoadStats>>,
+    started: Instant,
+) -> io::Result<()> {
+    for thread in 0..threads {
+        let plan = plan.clone();
+        let stats = stats.clone();
+        std::thread::Builder::new()
+            .name(format!("loadgen-{}", thread))
+            .spawn(move || {
+                let runtime = Builder::new_current_thread()
+                    .enable_io()
+                    .enable_time()
+                    .build()
+                    .expect("Failed to build Tokio runtime");
+                let local = LocalSet::new();
+                for client in (thread..plan.clients).step_by(threads) {
+                    let start_at = started + plan.start_delay(client);
+                    local.spawn_local(run_simulated_client(
+                        client,
+                        plan.clone(),
+                        stats.clone(),
+                        start_at,
+                    ));
+                }
+                local.block_on(&runtime, std::future::pending::<()>());
+            })?;
+    }
+    Ok(())
+}
+
+async fn run_simulated_client(
+    client: usize,
+    plan: Arc<LoadPlan>,
+    stats: Arc<Mutex<LoadStats>>,
+    start_at: Instant,
+) {
+    sleep_until(start_at).await;
+    loop {
+        match free_tcp_port() {
+            Ok(port) => {
+                stats.lock().expect("stats lock").connects += 1;
+                let config = client_config(port, &plan);
+                let open_transport = || {
+                    let stats = stats.clone();
+                    async move {
+                        MeteredSocket::bind(stats)
+                            .await
+                            .map_err(|err| ClientError::new(err.to_string()))
+                    }
+                };
+                tokio::select! {
+                    result = run_client_with(&config, ClockSource::System, open_transport) => {
+                        match result {
+                            Ok(code) => debug!(client, code, "Client exited"),
+                            Err(err) => warn!(client, "Client failed: {}", err),
+                        }
+                    }
+                    _ = run_streams(client, port, &plan, &stats) => {}
+                }
+            }
+            Err(err) => warn!(client, "No free TCP port: {}", err),
+        }
+        stats.lock().expect("stats lock").disconnects += 1;
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn run_streams(client: usize, port: u16, plan: &LoadPlan, stats: &Mutex<LoadStats>) {
+    if plan.workload == Workload::Idle {
+        return std::future::pending().await;
+    }
+    loop {
+        match run_stream(port, plan.workload).await {
+            Ok(bytes) => {
+                let mut stats = stats.lock().expect("stats lock");
+                stats.streams += 1;
+                stats.bytes_up += bytes.up;
+                stats.bytes_down += bytes.down;
+            }
+            Err(err) => {
+                debug!(client, "Stream failed: {}", err);
+                stats.lock().expect("stats lock").stream_errors += 1;
+            }
+        }
+        sleep(plan.think).await;
+    }
+}
+
+fn client_config(tcp_listen_port: u16, plan: &LoadPlan) -> ClientConfig<'_> {
+    // The CLI defaults, with every side channel off.
+    ClientConfig {
+        tcp_listen_port,
+        resolvers: &plan.resolvers,
+        domains: &plan.domains,
+        label_len: 57,
+        sequence_label: false,
+        mtu: None,
+        tolerant_responses: false,
+        path_mtu_discovery: false,
+        poll_jitter_ms: 0,
+        pad_bucket: 0,
+        cover_interval_ms: 0,
+        max_qps: 0,
+        idle_after_secs: 10,
+        idle_poll_interval_ms: (1000, 30000),
+        lazy: false,
+        lazy_idle_timeout_secs: 300,
+        metrics_listen: None,
+        control_socket: None,
+        qlog_dir: None,
+        qlog_max_mb: 0,
+        keylog_file: None,
+        pcap: None,
+        pcap_max_mb: 0,
+        pcap_files: 0,
+        dns_transport: DnsTransportKind::Udp,
+        dns_tls_name: None,
+        dns_tls_ca: None,
+        cert: plan.cert.as_deref(),
+        congestion_control: None,
+        gso: false,
+        keep_alive_interval: 400,
+        debug_poll: false,
+        debug_streams: false,
+    }
+}
+
+fn free_tcp_port() -> io::Result<u16> {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0))?;
+    Ok(listener.local_addr()?.port())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramps_clients_in_steps() {
+        let plan = LoadPlan {
+            resolvers: Vec::new(),
+            domains: Vec::new(),
+            cert: None,
+            workload: Workload::Idle,
+            think: Duration::ZERO,
+            clients: 25,
+            ramp_step: 10,
+            step: Duration::from_secs(5),
+        };
+        assert_eq!(plan.steps(), 3);
+        assert_eq!(
+            (0..3)
+                .map(|step| plan.clients_in_step(step))
+                .collect::<Vec<_>>(),
+            vec![10, 20, 25]
+        );
+        assert_eq!(plan.start_delay(9), Duration::ZERO);
+        assert_eq!(plan.start_delay(10), Duration::from_secs(5));
+        assert_eq!(plan.start_delay(24), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,197 @@
+mod clients;
+mod stats;
+mod transport;
+mod workload;
+
+use clap::{ArgGroup, Parser};
+use slipstream_core::logging::{init_logging, LogFormat};
+use slipstream_core::{normalize_domain, parse_host_port, AddressKind, HostPort};
+use slipstream_ffi::{DomainSpec, ResolverMode, ResolverSpec};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use clients::{spawn_clients, LoadPlan};
+use stats::{rcode_name, saturation_step, LoadStats, StepReport};
+use workload::Workload;
+
+// Past this share of a core per thread, the generator is likely the bottleneck.
+const BUSY_CPU_SHARE: f64 = 0.9;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "slipstream-loadgen",
+    about = "slipstream-loadgen - run many simulated clients against one server",
+    group(
+        ArgGroup::new("server")
+            .required(true)
+            .args(["resolver", "authoritative"])
+    )
+)]
+struct Args {
+    #[arg(long = "resolver", value_parser = parse_resolver)]
+    resolver: Option<HostPort>,
+    #[arg(long = "authoritative", value_parser = parse_resolver)]
+    authoritative: Option<HostPort>,
+    #[arg(long = "domain", short = 'd', value_parser = parse_domain)]
+    domain: String,
+    #[arg(long = "cert", value_name = "PATH")]
+    cert: Option<String>,
+    #[arg(long = "clients", default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    clients: u32,
+    #[arg(long = "ramp-step", value_parser = clap::value_parser!(u32).range(1..))]
+    ramp_step: Option<u32>,
+    #[arg(long = "step-secs", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    step_secs: u64,
+    #[arg(long = "workload", default_value = "echo:16k")]
+    workload: Workload,
+    #[arg(long = "think-ms", default_value_t = 1000)]
+    think_ms: u64,
+    #[arg(long = "threads", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    threads: u32,
+    #[arg(long = "log-format", value_name = "FORMAT", default_value = "text")]
+    log_format: LogFormat,
+}
+
+fn main() {
+    let args = Args::parse();
+    // Hundreds of clients logging every handshake would drown the report.
+    if std::env::var_os("RUST_LOG").is_none() {
+        std::env::set_var("RUST_LOG", "warn");
+    }
+    init_logging(args.log_format);
+
+    let (resolver, mode) = match (args.resolver, args.authoritative) {
+        (Some(resolver), _) => (resolver, ResolverMode::Recursive),
+        (None, Some(resolver)) => (resolver, ResolverMode::Authoritative),
+        (None, None) => unreachable!("clap requires a resolver"),
+    };
+    let target = format!("{}:{}", resolver.host, resolver.port);
+    let clients = args.clients as usize;
+    let plan = Arc::new(LoadPlan {
+        resolvers: vec![ResolverSpec { resolver, mode }],
+        domains: vec![DomainSpec {
+            domain: args.domain,
+            weight: 1,
+            resolver: None,
+        }],
+        cert: args.cert,
+        workload: args.workload,
+        think: Duration::from_millis(args.think_ms),
+        clients,
+        ramp_step: args.ramp_step.map_or(clients, |step| step as usize),
+        step: Duration::from_secs(args.step_secs),
+    });
+    let threads = (args.threads as usize).min(clients);
+
+    println!(
+        "Load test against {}: {} clients, {} at a time every {} s, workload {}, {} thread(s)",
+        target,
+        plan.clients,
+        plan.ramp_step.min(plan.clients),
+        args.step_secs,
+        plan.workload,
+        threads
+    );
+    println!("{}", StepReport::header());
+
+    let stats = Arc::new(Mutex::new(LoadStats::default()));
+    let started = Instant::now();
+    if let Err(err) = spawn_clients(plan.clone(), threads, stats.clone(), started) {
+        tracing::error!("Failed to start clients: {}", err);
+        std::process::exit(1);
+    }
+
+    let mut steps = Vec::with_capacity(plan.steps());
+    let mut previous = LoadStats::default();
+    let mut previous_cpu = cpu_time();
+    let mut step_started = started;
+    for step in 0..plan.steps() {
+        let step_ends = started + plan.step * (step + 1) as u32;
+        std::thread::sleep(step_ends.saturating_duration_since(Instant::now()));
+        let now = Instant::now();
+        let snapshot = stats.lock().expect("stats lock").clone();
+        let cpu = cpu_time();
+        let elapsed = now - step_started;
+        let report = StepReport {
+            clients: plan.clients_in_step(step),
+            elapsed,
+            stats: snapshot.since(&previous),
+            cpu_share: previous_cpu
+                .zip(cpu)
+                .map(|(before, after)| (after - before).as_secs_f64() / elapsed.as_secs_f64()),
+        };
+        println!("{}", report);
+        steps.push(report);
+        previous = snapshot;
+        previous_cpu = cpu;
+        step_started = now;
+    }
+
+    print_summary(&previous, &steps, threads, plan.workload);
+    // The client threads never finish on their own.
+    std::process::exit(0);
+}
+
+fn print_summary(totals: &LoadStats, steps: &[StepReport], threads: usize, workload: Workload) {
+    println!();
+    println!(
+        "Totals: {} queries, {} answers, {} timeouts, {} streams ({} failed), {} connects, {} restarts",
+        totals.queries,
+        totals.responses,
+        totals.timeouts,
+        totals.streams,
+        totals.stream_errors,
+        totals.connects,
+        totals.disconnects
+    );
+    let rcodes: Vec<String> = totals
+        .rcodes
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .map(|(rcode, count)| format!("{} {}", rcode_name(rcode), count))
+        .collect();
+    if !rcodes.is_empty() {
+        println!("Rcodes: {}", rcodes.join(", "));
+    }
+    if totals.responses == 0 || (workload != Workload::Idle && totals.streams == 0) {
+        println!(
+            "No traffic completed; is the server reachable and running with --enable-diagnostics?"
+        );
+        return;
+    }
+    match saturation_step(steps) {
+        None => println!(
+            "No saturation up to {} clients; raise --clients to push further",
+            steps.last().map_or(0, |step| step.clients)
+        ),
+        Some(step) => println!(
+            "Saturated between {} and {} clients, at about {:.0} answers/s",
+            steps[step - 1].clients,
+            steps[step].clients,
+            steps[step - 1]
+                .responses_per_sec()
+                .max(steps[step].responses_per_sec())
+        ),
+    }
+    if steps
+        .iter()
+        .any(|step| step.cpu_share.unwrap_or(0.0) > BUSY_CPU_SHARE * threads as f64)
+    {
+        println!("The load generator was CPU bound; add --threads or spread it over hosts");
+    }
+}
+
+fn parse_resolver(input: &str) -> Result<HostPort, String> {
+    parse_host_port(input, 53, AddressKind::Resolver).map_err(|err| err.to_string())
+}
+
+fn parse_domain(input: &str) -> Result<String, String> {
+    normalize_domain(input).map_err(|err| err.to_string())
+}
+
+/// User plus system CPU time this process has used so far.
+fn cpu_time() -> Option<Duration> {
+    slipstream_client::cpu_time().map(|(user, system)| user + system)
+}
@@ -0,0 +1,297 @@
+use std::fmt;
+use std::time::Duration;
+
+// Sixteen buckets per doubling keeps each bucket within ~4.5% of its neighbours.
+const BUCKETS_PER_DOUBLING: f64 = 16.0;
+// 2^25 us is about 33 s, well past any query timeout.
+const HISTOGRAM_BUCKETS: usize = 25 * 16;
+// A step is saturated when response throughput grows by less than this share
+// although clients were added.
+const SATURATION_MIN_GROWTH: f64 = 0.10;
+// ... or when median latency exceeds this multiple of the first step's.
+const SATURATION_LATENCY_FACTOR: f64 = 2.0;
+
+/// Response latencies in log-spaced buckets starting at 1 us.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LatencyHistogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; HISTOGRAM_BUCKETS],
+            total: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub(crate) fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros().max(1) as f64;
+        let bucket = (micros.log2() * BUCKETS_PER_DOUBLING) as usize;
+        self.counts[bucket.min(HISTOGRAM_BUCKETS - 1)] += 1;
+        self.total += 1;
+    }
+
+    #[cfg(test)]
+    pub(crate) fn count(&self) -> u64 {
+        self.total
+    }
+
+    /// Upper bound of the bucket holding the `quantile` sample, or `None`
+    /// when nothing was recorded.
+    pub(crate) fn percentile(&self, quantile: f64) -> Option<Duration> {
+        if self.total == 0 {
+            return None;
+        }
+        let rank = ((self.total as f64 * quantile).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0u64;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let micros = 2f64.powf((bucket + 1) as f64 / BUCKETS_PER_DOUBLING);
+                return Some(Duration::from_micros(micros.round() as u64));
+            }
+        }
+        None
+    }
+
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            counts: self
+                .counts
+                .iter()
+                .zip(&earlier.counts)
+                .map(|(now, then)| now - then)
+                .collect(),
+            total: self.total - earlier.total,
+        }
+    }
+}
+
+/// Counters shared by every simulated client; only ever grow.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LoadStats {
+    pub(crate) queries: u64,
+    pub(crate) responses: u64,
+    /// Queries not answered within the query timeout.
+    pub(crate) timeouts: u64,
+    /// Responses by DNS rcode.
+    pub(crate) rcodes: [u64; 16],
+    pub(crate) latency: LatencyHistogram,
+    /// Stream payload bytes, excluding diagnostic prefaces.
+    pub(crate) bytes_up: u64,
+    pub(crate) bytes_down: u64,
+    pub(crate) streams: u64,
+    pub(crate) stream_errors: u64,
+    pub(crate) connects: u64,
+    /// Client loops that ended and had to be restarted.
+    pub(crate) disconnects: u64,
+}
+
+impl LoadStats {
+    pub(crate) fn since(&self, earlier: &Self) -> Self {
+        let mut rcodes = [0u64; 16];
+        for (rcode, slot) in rcodes.iter_mut().enumerate() {
+            *slot = self.rcodes[rcode] - earlier.rcodes[rcode];
+        }
+        Self {
+            queries: self.queries - earlier.queries,
+            responses: self.responses - earlier.responses,
+            timeouts: self.timeouts - earlier.timeouts,
+            rcodes,
+            latency: self.latency.since(&earlier.latency),
+            bytes_up: self.bytes_up - earlier.bytes_up,
+            bytes_down: self.bytes_down - earlier.bytes_down,
+            streams: self.streams - earlier.streams,
+            stream_errors: self.stream_errors - earlier.stream_errors,
+            connects: self.connects - earlier.connects,
+            disconnects: self.disconnects - earlier.disconnects,
+        }
+    }
+
+    /// Responses with any rcode but NOERROR.
+    pub(crate) fn error_responses(&self) -> u64 {
+        self.rcodes[1..].iter().sum()
+    }
+}
+
+/// What one ramp step measured.
+#[derive(Debug, Clone)]
+pub(crate) struct StepReport {
+    pub(crate) clients: usize,
+    pub(crate) elapsed: Duration,
+    pub(crate) stats: LoadStats,
+    /// CPU time the load generator itself used, as a share of one core.
+    pub(crate) cpu_share: Option<f64>,
+}
+
+impl StepReport {
+    fn per_sec(&self, value: u64) -> f64 {
+        value as f64 / self.elapsed.as_secs_f64().max(1e-3)
+    }
+
+    pub(crate) fn responses_per_sec(&self) -> f64 {
+        self.per_sec(self.stats.responses)
+    }
+
+    pub(crate) fn header() -> &'static str {
+        "clients  queries/s  answers/s   p50 ms   p90 ms   p99 ms  timeouts  errors  \
+         up kbit/s  down kbit/s  streams  failed  loadgen cpu"
+    }
+}
+
+impl fmt::Display for StepReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = &self.stats;
+        let ms = |quantile| {
+            stats
+                .latency
+                .percentile(quantile)
+                .map(|latency| format!("{:.1}", latency.as_secs_f64() * 1_000.0))
+                .unwrap_or_else(|| "-".to_string())
+        };
+        write!(
+            f,
+            "{:>7}  {:>9.0}  {:>9.0}  {:>7}  {:>7}  {:>7}  {:>8}  {:>6}  {:>9.1}  {:>11.1}  {:>7}  {:>6}  {:>11}",
+            self.clients,
+            self.per_sec(stats.queries),
+            self.responses_per_sec(),
+            ms(0.50),
+            ms(0.90),
+            ms(0.99),
+            stats.timeouts,
+            stats.error_responses(),
+            self.per_sec(stats.bytes_up) * 8.0 / 1_000.0,
+            self.per_sec(stats.bytes_down) * 8.0 / 1_000.0,
+            stats.streams,
+            stats.stream_errors,
+            self.cpu_share
+                .map(|share| format!("{:.0}%", share * 100.0))
+                .unwrap_or_else(|| "-".to_string()),
+        )
+    }
+}
+
+/// Index of the first step where the server stopped keeping up: adding
+/// clients no longer raised the response rate, or median latency blew up
+/// compared with the lightest step.
+pub(crate) fn saturation_step(steps: &[StepReport]) -> Option<usize> {
+    let baseline = steps
+        .first()
+        .and_then(|step| step.stats.latency.percentile(0.50));
+    for (index, pair) in steps.windows(2).enumerate() {
+        let (previous, step) = (&pair[0], &pair[1]);
+        if step.clients > previous.clients
+            && step.responses_per_sec()
+                < previous.responses_per_sec() * (1.0 + SATURATION_MIN_GROWTH)
+        {
+            return Some(index + 1);
+        }
+        let median = step.stats.latency.percentile(0.50);
+        if let (Some(baseline), Some(median)) = (baseline, median) {
+            if median.as_secs_f64() > baseline.as_secs_f64() * SATURATION_LATENCY_FACTOR {
+                return Some(index + 1);
+            }
+        }
+    }
+    None
+}
+
+pub(crate) fn rcode_name(rcode: usize) -> String {
+    match rcode {
+        0 => "NOERROR".to_string(),
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        other => format!("RCODE{}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(clients: usize, responses: u64, median_ms: u64) -> StepReport {
+        let mut stats = LoadStats {
+            responses,
+            ..LoadStats::default()
+        };
+        stats.latency.record(Duration::from_millis(median_ms));
+        StepReport {
+            clients,
+            elapsed: Duration::from_secs(1),
+            stats,
+            cpu_share: None,
+        }
+    }
+
+    #[test]
+    fn percentiles_land_within_a_bucket() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile(0.5), None);
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms));
+        }
+        assert_eq!(histogram.count(), 100);
+        for (quantile, expected_ms) in [(0.5, 50.0), (0.9, 90.0), (0.99, 99.0)] {
+            let got = histogram.percentile(quantile).unwrap().as_secs_f64() * 1_000.0;
+            assert!(
+                got >= expected_ms && got <= expected_ms * 1.05,
+                "p{} = {} ms",
+                quantile,
+                got
+            );
+        }
+        histogram.record(Duration::from_secs(3600));
+        assert!(histogram.percentile(1.0).unwrap() > Duration::from_secs(30));
+    }
+
+    #[test]
+    fn deltas_subtract_every_counter() {
+        let mut earlier = LoadStats::default();
+        earlier.latency.record(Duration::from_millis(5));
+        earlier.rcodes[2] = 1;
+        let mut later = earlier.clone();
+        later.latency.record(Duration::from_millis(80));
+        later.rcodes[2] = 4;
+        later.responses = 3;
+        let delta = later.since(&earlier);
+        assert_eq!(delta.responses, 3);
+        assert_eq!(delta.error_responses(), 3);
+        assert_eq!(delta.latency.count(), 1);
+        assert!(delta.latency.percentile(0.5).unwrap() >= Duration::from_millis(80));
+    }
+
+    #[test]
+    fn finds_saturation() {
+        let linear = [
+            step(10, 1_000, 20),
+            step(20, 2_000, 22),
+            step(30, 3_000, 25),
+        ];
+        assert_eq!(saturation_step(&linear), None);
+
+        let flat = [
+            step(10, 1_000, 20),
+            step(20, 2_000, 22),
+            step(30, 2_100, 25),
+        ];
+        assert_eq!(saturation_step(&flat), Some(2));
+
+        let queued = [
+            step(10, 1_000, 20),
+            step(20, 2_000, 60),
+            step(30, 3_000, 90),
+        ];
+        assert_eq!(saturation_step(&queued), Some(1));
+
+        // Holding the client count steady is not saturation.
+        let steady = [step(10, 1_000, 20), step(10, 1_000, 20)];
+        assert_eq!(saturation_step(&steady), None);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::stats::LoadStats;
+use slipstream_core::transport::DnsTransport;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+/// A query still unanswered after this long counts as a timeout.
+pub(crate) const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+const DNS_HEADER_LEN: usize = 12;
+
+/// One simulated client's UDP socket; times each response against its query
+/// by DNS id and tallies the result into the shared stats.
+pub(crate) struct MeteredSocket {
+    socket: UdpSocket,
+    sent: RefCell<HashMap<u16, Instant>>,
+    last_sweep: Cell<Instant>,
+    stats: Arc<Mutex<LoadStats>>,
+}
+
+impl MeteredSocket {
+    pub(crate) async fn bind(stats: Arc<Mutex<LoadStats>>) -> io::Result<Self> {
+        // Dual-stack, like the client: resolver addresses arrive V4-mapped.
+        let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await?;
+        Ok(Self {
+            socket,
+            sent: RefCell::new(HashMap::new()),
+            last_sweep: Cell::new(Instant::now()),
+            stats,
+        })
+    }
+
+    fn on_query(&self, packet: &[u8]) {
+        let now = Instant::now();
+        let expired = if now.duration_since(self.last_sweep.get()) >= SWEEP_INTERVAL {
+            self.last_sweep.set(now);
+            let mut sent = self.sent.borrow_mut();
+            let before = sent.len();
+            sent.retain(|_, at| now.duration_since(*at) < QUERY_TIMEOUT);
+            (before - sent.len()) as u64
+        } else {
+            0
+        };
+        if let Some(id) = dns_id(packet) {
+            self.sent.borrow_mut().insert(id, now);
+        }
+        let mut stats = self.stats.lock().expect("stats lock");
+        stats.queries += 1;
+        stats.timeouts += expired;
+    }
+
+    fn on_response(&self, packet: &[u8]) {
+        let Some(id) = dns_id(packet) else {
+            return;
+        };
+        let sent = self.sent.borrow_mut().remove(&id);
+        let mut stats = self.stats.lock().expect("stats lock");
+        stats.responses += 1;
+        stats.rcodes[usize::from(packet[3] & 0x0f)] += 1;
+        if let Some(sent) = sent {
+            stats.latency.record(sent.elapsed());
+        }
+    }
+}
+
+impl Drop for MeteredSocket {
+    fn drop(&mut self) {
+        let now = Instant::now();
+        let expired = self
+            .sent
+            .get_mut()
+            .values()
+            .filter(|at| now.duration_since(**at) >= QUERY_TIMEOUT)
+            .count() as u64;
+        if let Ok(mut stats) = self.stats.lock() {
+            stats.timeouts += expired;
+        }
+    }
+}
+
+impl DnsTransport for MeteredSocket {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    async fn send_to(&self, packet: &[u8], dest: SocketAddr) -> io::Result<()> {
+        self.socket.send_to(packet, dest).await?;
+        self.on_query(packet);
+        Ok(())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (size, peer) = self.socket.recv_from(buf).await?;
+        self.on_response(&buf[..size]);
+        Ok((size, peer))
+    }
+
+    fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (size, peer) = self.socket.try_recv_from(buf)?;
+        self.on_response(&buf[..size]);
+        Ok((size, peer))
+    }
+}
+
+fn dns_id(packet: &[u8]) -> Option<u16> {
+    if packet.len() < DNS_HEADER_LEN {
+        return None;
+    }
+    Some(u16::from_be_bytes([packet[0], packet[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(id: u16, flags: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; DNS_HEADER_LEN];
+        packet[..2].copy_from_slice(&id.to_be_bytes());
+        packet[2..4].copy_from_slice(&flags.to_be_bytes());
+        packet
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn matches_responses_to_queries() {
+        let stats = Arc::new(Mutex::new(LoadStats::default()));
+        let socket = MeteredSocket::bind(stats.clone()).await.expect("bind");
+        socket.on_query(&header(7, 0x0100));
+        socket.on_query(&header(8, 0x0100));
+        socket.on_response(&header(7, 0x8180));
+        socket.on_response(&header(9, 0x8182));
+        socket.on_response(&[0u8; 4]);
+
+        let stats = stats.lock().unwrap();
+        assert_eq!(stats.queries, 2);
+        assert_eq!(stats.responses, 2);
+        assert_eq!(stats.rcodes[0], 1);
+        assert_eq!(stats.rcodes[2], 1);
+        // Only the answer to a query we sent has a latency.
+        assert_eq!(stats.latency.count(), 1);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn counts_unanswered_queries_as_timeouts() {
+        let stats = Arc::new(Mutex::new(LoadStats::default()));
+        let socket = MeteredSocket::bind(stats.clone()).await.expect("bind");
+        socket.on_query(&header(1, 0x0100));
+        socket.on_query(&header(2, 0x0100));
+        socket.on_response(&header(2, 0x8180));
+        tokio::time::advance(QUERY_TIMEOUT + SWEEP_INTERVAL).await;
+        socket.on_query(&header(3, 0x0100));
+        assert_eq!(stats.lock().unwrap().timeouts, 1);
+
+        tokio::time::advance(QUERY_TIMEOUT).await;
+        drop(socket);
+        assert_eq!(stats.lock().unwrap().timeouts, 2);
+    }
+}
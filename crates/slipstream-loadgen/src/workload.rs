@@ -0,0 +1,226 @@
+use slipstream_core::diagnostics::{parse_size, DiagnosticRequest};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Instant};
+
+// The first stream of a connection also waits for the QUIC handshake.
+const STREAM_TIMEOUT: Duration = Duration::from_secs(120);
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(10);
+const CHUNK_LEN: usize = 16 * 1024;
+
+/// What each simulated client does with its tunnel, one stream at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Workload {
+    /// Open no streams; only the client's polling loads the server.
+    Idle,
+    /// Send `bytes` to the server's echo service and read them back.
+    Echo { bytes: u64 },
+    /// Send `bytes` to the discard service.
+    Upload { bytes: u64 },
+    /// Read `bytes` from the chargen service.
+    Download { bytes: u64 },
+}
+
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Workload::Idle => f.write_str("idle"),
+            Workload::Echo { bytes } => write!(f, "echo:{}", bytes),
+            Workload::Upload { bytes } => write!(f, "upload:{}", bytes),
+            Workload::Download { bytes } => write!(f, "download:{}", bytes),
+        }
+    }
+}
+
+/// Parses `idle`, `echo:SIZE`, `upload:SIZE` or `download:SIZE`.
+impl FromStr for Workload {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if input == "idle" {
+            return Ok(Workload::Idle);
+        }
+        let (kind, size) = input
+            .split_once(':')
+            .ok_or_else(|| format!("Workload {:?} needs a size, e.g. {}:16k", input, input))?;
+        let bytes = parse_size(size)?;
+        match kind {
+            "echo" => Ok(Workload::Echo { bytes }),
+            "upload" => Ok(Workload::Upload { bytes }),
+            "download" => Ok(Workload::Download { bytes }),
+            other => Err(format!("Unknown workload {:?}", other)),
+        }
+    }
+}
+
+/// Bytes one completed stream carried each way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct StreamBytes {
+    pub(crate) up: u64,
+    pub(crate) down: u64,
+}
+
+/// Runs one stream of `workload` through the client listening on `port`.
+pub(crate) async fn run_stream(port: u16, workload: Workload) -> io::Result<StreamBytes> {
+    let exchange = async {
+        match workload {
+            Workload::Idle => Ok(StreamBytes::default()),
+            Workload::Echo { bytes } => echo(port, bytes).await,
+            Workload::Upload { bytes } => upload(port, bytes).await,
+            Workload::Download { bytes } => download(port, bytes).await,
+        }
+    };
+    timeout(STREAM_TIMEOUT, exchange)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "stream timed out"))?
+}
+
+async fn open_stream(port: u16, request: DiagnosticRequest) -> io::Result<TcpStream> {
+    let deadline = Instant::now() + LISTEN_TIMEOUT;
+    // The client binds its listener once the resolvers are set up.
+    let mut stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(stream) => break stream,
+            Err(err) if Instant::now() >= deadline => return Err(err),
+            Err(_) => sleep(Duration::from_millis(50)).await,
+        }
+    };
+    let _ = stream.set_nodelay(true);
+    stream.write_all(&request.preface()).await?;
+    Ok(stream)
+}
+
+async fn echo(port: u16, bytes: u64) -> io::Result<StreamBytes> {
+    let stream = open_stream(port, DiagnosticRequest::Echo).await?;
+    let (mut read_half, mut write_half) = stream.into_split();
+    let send = async {
+        send_pattern(&mut write_half, bytes).await?;
+        write_half.shutdown().await
+    };
+    let receive = async {
+        let mut buf = vec![0u8; CHUNK_LEN];
+        let mut received = 0u64;
+        loop {
+            let n = read_half.read(&mut buf).await?;
+            if n == 0 {
+                return Ok::<u64, io::Error>(received);
+            }
+            received += n as u64;
+        }
+    };
+    let (sent, received) = tokio::join!(send, receive);
+    sent?;
+    let received = received?;
+    if received != bytes {
+        return Err(short_stream("echo", received, bytes));
+    }
+    Ok(StreamBytes {
+        up: bytes,
+        down: received,
+    })
+}
+
+async fn upload(port: u16, bytes: u64) -> io::Result<StreamBytes> {
+    let stream = open_stream(port, DiagnosticRequest::Discard).await?;
+    let (read_half, mut write_half) = stream.into_split();
+    send_pattern(&mut write_half, bytes).await?;
+    write_half.shutdown().await?;
+    let mut line = String::new();
+    BufReader::new(read_half).read_line(&mut line).await?;
+    let counted: u64 = line.trim().parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected discard reply {:?}", line),
+        )
+    })?;
+    if counted != bytes {
+        return Err(short_stream("discard", counted, bytes));
+    }
+    Ok(StreamBytes { up: bytes, down: 0 })
+}
+
+async fn download(port: u16, bytes: u64) -> io::Result<StreamBytes> {
+    let request = DiagnosticRequest::Chargen { bytes };
+    let mut stream = open_stream(port, request).await?;
+    let mut buf = vec![0u8; CHUNK_LEN];
+    let mut received = 0u64;
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        received += n as u64;
+    }
+    if received != bytes {
+        return Err(short_stream("chargen", received, bytes));
+    }
+    Ok(StreamBytes {
+        up: 0,
+        down: received,
+    })
+}
+
+async fn send_pattern<W: AsyncWriteExt + Unpin>(writer: &mut W, bytes: u64) -> io::Result<()> {
+    let chunk = vec![0x5au8; CHUNK_LEN];
+    let mut remaining = bytes;
+    while remaining > 0 {
+        let len = chunk.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        writer.write_all(&chunk[..len]).await?;
+        remaining -= len as u64;
+    }
+    Ok(())
+}
+
+fn short_stream(service: &str, got: u64, expected: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("{} carried {} of {} bytes", service, got, expected),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_workloads() {
+        assert_eq!("idle".parse(), Ok(Workload::Idle));
+        assert_eq!("echo:16k".parse(), Ok(Workload::Echo { bytes: 16 << 10 }));
+        assert_eq!("upload:1m".parse(), Ok(Workload::Upload { bytes: 1 << 20 }));
+        assert_eq!(
+            "download:300".parse(),
+            Ok(Workload::Download { bytes: 300 })
+        );
+        assert_eq!(
+            "download:2k".parse::<Workload>().map(|w| w.to_string()),
+            Ok("download:2048".to_string())
+        );
+        assert!("echo".parse::<Workload>().is_err());
+        assert!("flood:1k".parse::<Workload>().is_err());
+        assert!("upload:lots".parse::<Workload>().is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn checks_the_byte_count_the_server_reports() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // A discard service that only heard half of the upload.
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            let payload = received.len() - DiagnosticRequest::Discard.preface().len();
+            let reply = format!("{}\n", payload / 2);
+            stream.write_all(reply.as_bytes()).await.unwrap();
+        });
+        let err = run_stream(port, Workload::Upload { bytes: 4096 })
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use slipstream_core::rng::SimRng;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::scenario::LinkScenario;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct LinkStats {
+    pub(crate) sent: u64,
+    pub(crate) dropped: u64,
+    /// Dropped because the bandwidth queue was full.
+    pub(crate) overflowed: u64,
+    pub(crate) reordered: u64,
+    pub(crate) duplicated: u64,
+}
+
+impl fmt::Display for LinkStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent={} dropped={} overflowed={} reordered={} duplicated={}",
+            self.sent, self.dropped, self.overflowed, self.reordered, self.duplicated
+        )
+    }
+}
+
+/// One direction through the relay: decides when, if ever, each datagram arrives.
+pub(crate) struct Link {
+    config: LinkScenario,
+    /// When each datagram still queued for the bandwidth cap finishes sending.
+    queue: VecDeque<Instant>,
+    pub(crate) stats: LinkStats,
+}
+
+impl Link {
+    pub(crate) fn new(config: LinkScenario) -> Self {
+        Self {
+            config,
+            queue: VecDeque::new(),
+            stats: LinkStats::default(),
+        }
+    }
+
+    /// Delivery times for a datagram of `len` bytes sent at `now`; empty if it is lost.
+    pub(crate) fn schedule(&mut self, len: usize, now: Instant, rng: &mut SimRng) -> Vec<Instant> {
+        self.stats.sent += 1;
+        if rng.chance(self.config.loss) {
+            self.stats.dropped += 1;
+            return Vec::new();
+        }
+        let copies = if rng.chance(self.config.duplicate) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        let mut times = Vec::with_capacity(copies);
+        for _ in 0..copies {
+            let Some(sent_at) = self.transmit(len, now) else {
+                self.stats.overflowed += 1;
+                continue;
+            };
+            let delay = if rng.chance(self.config.reorder) {
+                self.stats.reordered += 1;
+                Duration::ZERO
+            } else {
+                let jitter = rng.below(self.config.jitter().as_micros() as u64);
+                self.config.delay() + Duration::from_micros(jitter)
+            };
+            times.push(sent_at + delay);
+        }
+        times
+    }
+
+    /// When the bandwidth cap lets the datagram out, or `None` if the queue is full.
+    fn transmit(&mut self, len: usize, now: Instant) -> Option<Instant> {
+        if self.config.rate_kbps == 0 {
+            return Some(now);
+        }
+        while self.queue.front().is_some_and(|done| *done <= now) {
+            self.queue.pop_front();
+        }
+        if self.queue.len() >= self.config.queue_packets {
+            return None;
+        }
+        let start = self.queue.back().copied().unwrap_or(now).max(now);
+        let bits = (len as u64) * 8;
+        let done = start + Duration::from_micros(bits * 1000 / self.config.rate_kbps);
+        self.queue.push_back(done);
+        Some(done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Link;
+    use crate::scenario::LinkScenario;
+    use slipstream_core::rng::SimRng;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn delays_with_jitter_inside_the_window() {
+        let mut link = Link::new(LinkScenario {
+            delay_ms: 50,
+            jitter_ms: 10,
+            ..LinkScenario::default()
+        });
+        let mut rng = SimRng::new(1);
+        let now = Instant::now();
+        for _ in 0..100 {
+            let times = link.schedule(100, now, &mut rng);
+            assert_eq!(times.len(), 1);
+            let delay = times[0] - now;
+            assert!(delay >= Duration::from_millis(50) && delay < Duration::from_millis(60));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caps_bandwidth_and_drops_on_a_full_queue() {
+        // 80 kbit/s: a 1000 byte datagram takes 100 ms.
+        let mut link = Link::new(LinkScenario {
+            rate_kbps: 80,
+            queue_packets: 3,
+            ..LinkScenario::default()
+        });
+        let mut rng = SimRng::new(2);
+        let now = Instant::now();
+        let times: Vec<_> = (0..4).map(|_| link.schedule(1000, now, &mut rng)).collect();
+        for (i, times) in times[..3].iter().enumerate() {
+            assert_eq!(times, &[now + Duration::from_millis(100 * (i as u64 + 1))]);
+        }
+        assert!(times[3].is_empty());
+        assert_eq!(link.stats.overflowed, 1);
+
+        // Once the first datagram is out there is room again, behind the others.
+        let later = now + Duration::from_millis(150);
+        assert_eq!(
+            link.schedule(1000, later, &mut rng),
+            [now + Duration::from_millis(400)]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn loses_duplicates_and_reorders() {
+        let mut link = Link::new(LinkScenario {
+            delay_ms: 100,
+            loss: 0.2,
+            duplicate: 0.1,
+            reorder: 0.1,
+            ..LinkScenario::default()
+        });
+        let mut rng = SimRng::new(3);
+        let now = Instant::now();
+        let delivered: usize = (0..1000)
+            .map(|_| link.schedule(100, now, &mut rng).len())
+            .sum();
+        let stats = link.stats;
+        assert_eq!(stats.sent, 1000);
+        assert!((150..250).contains(&stats.dropped), "{:?}", stats);
+        assert!(stats.duplicated > 0 && stats.reordered > 0, "{:?}", stats);
+        assert_eq!(
+            delivered as u64,
+            stats.sent - stats.dropped + stats.duplicated
+        );
+    }
+}
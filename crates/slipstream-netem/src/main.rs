@@ -0,0 +1,61 @@
+mod link;
+mod relay;
+mod resolver;
+mod scenario;
+
+use clap::Parser;
+use slipstream_core::logging::{init_logging, LogFormat};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::runtime::Builder;
+
+use relay::run_relay;
+use scenario::Scenario;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "slipstream-netem",
+    about = "slipstream-netem - UDP DNS relay with network impairments and resolver emulation"
+)]
+struct Args {
+    #[arg(long = "listen", value_name = "ADDR")]
+    listen: SocketAddr,
+    #[arg(long = "upstream", value_name = "ADDR")]
+    upstream: SocketAddr,
+    #[arg(long = "scenario", value_name = "FILE")]
+    scenario: Option<PathBuf>,
+    #[arg(long = "seed")]
+    seed: Option<u64>,
+    #[arg(long = "log-format", value_name = "FORMAT", default_value = "text")]
+    log_format: LogFormat,
+}
+
+fn main() {
+    let args = Args::parse();
+    init_logging(args.log_format);
+
+    let scenario = match args.scenario.as_deref() {
+        Some(path) => Scenario::load(path).unwrap_or_else(|err| {
+            tracing::error!("Scenario {}", err);
+            std::process::exit(2);
+        }),
+        None => Scenario::default(),
+    };
+    let seed = args.seed.or(scenario.seed).unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default()
+    });
+
+    let runtime = Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .expect("Failed to build Tokio runtime");
+    if let Err(err) = runtime.block_on(run_relay(args.listen, args.upstream, &scenario, seed)) {
+        tracing::error!("Relay error: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,104 @@
+use slipstream_core::rng::SimRng;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::{interval_at, sleep_until, Instant, MissedTickBehavior};
+use tracing::{debug, info};
+
+use crate::link::Link;
+use crate::resolver::{QueryAction, Resolver};
+use crate::scenario::Scenario;
+
+const MAX_DATAGRAM_LEN: usize = 65535;
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Relays queries from clients on `listen` to `upstream` and answers back,
+/// impairing both directions as `scenario` describes.
+pub(crate) async fn run_relay(
+    listen: SocketAddr,
+    upstream: SocketAddr,
+    scenario: &Scenario,
+    seed: u64,
+) -> io::Result<()> {
+    let clients = Arc::new(UdpSocket::bind(listen).await?);
+    let upstream_bind = match upstream {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let server = Arc::new(UdpSocket::bind(upstream_bind).await?);
+    info!(
+        "Relaying {} -> {} (seed {})",
+        clients.local_addr()?,
+        upstream,
+        seed
+    );
+
+    let mut rng = SimRng::new(seed);
+    let mut resolver = Resolver::new(scenario.resolver, SimRng::new(rng.next_u64()));
+    let mut queries = Link::new(scenario.query_link());
+    let mut responses = Link::new(scenario.response_link());
+    let mut stats = interval_at(Instant::now() + STATS_INTERVAL, STATS_INTERVAL);
+    stats.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut client_buf = vec![0u8; MAX_DATAGRAM_LEN];
+    let mut server_buf = vec![0u8; MAX_DATAGRAM_LEN];
+
+    loop {
+        tokio::select! {
+            recv = clients.recv_from(&mut client_buf) => {
+                let (len, client) = recv?;
+                let now = Instant::now();
+                match resolver.on_query(client, &client_buf[..len], now) {
+                    QueryAction::Forward(query) => {
+                        let times = queries.schedule(query.len(), now, &mut rng);
+                        deliver(&server, query, upstream, times).await;
+                    }
+                    QueryAction::Answer(answer) => {
+                        let times = responses.schedule(answer.len(), now, &mut rng);
+                        deliver(&clients, answer, client, times).await;
+                    }
+                    QueryAction::Drop => debug!(%client, "Dropping a query without a question"),
+                }
+            }
+            recv = server.recv_from(&mut server_buf) => {
+                let (len, from) = recv?;
+                if from != upstream {
+                    continue;
+                }
+                let now = Instant::now();
+                if let Some((client, answer)) = resolver.on_response(&server_buf[..len], now) {
+                    let times = responses.schedule(answer.len(), now, &mut rng);
+                    deliver(&clients, answer, client, times).await;
+                }
+            }
+            _ = stats.tick() => {
+                info!(
+                    "Queries: {}; responses: {}; resolver: {}",
+                    queries.stats, responses.stats, resolver.stats
+                );
+            }
+        }
+    }
+}
+
+/// Sends `packet` once per delivery time, waiting in the background for future ones.
+async fn deliver(socket: &Arc<UdpSocket>, packet: Vec<u8>, dest: SocketAddr, times: Vec<Instant>) {
+    let now = Instant::now();
+    for at in times {
+        if at <= now {
+            if let Err(err) = socket.send_to(&packet, dest).await {
+                debug!(%dest, error = %err, "Relay send failed");
+            }
+            continue;
+        }
+        let socket = socket.clone();
+        let packet = packet.clone();
+        tokio::spawn(async move {
+            sleep_until(at).await;
+            if let Err(err) = socket.send_to(&packet, dest).await {
+                debug!(%dest, error = %err, "Relay send failed");
+            }
+        });
+    }
+}
@@ -0,0 +1,351 @@
+use slipstream_core::rng::SimRng;
+use slipstream_dns::{
+    question_end, question_key, randomize_case, refused_response, truncate_response,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::scenario::ResolverScenario;
+
+const HEADER_LEN: usize = 12;
+// Forwarded queries with no answer by then are forgotten.
+const PENDING_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ResolverStats {
+    pub(crate) queries: u64,
+    pub(crate) forwarded: u64,
+    pub(crate) responses: u64,
+    /// Responses that matched no forwarded query.
+    pub(crate) unmatched: u64,
+    pub(crate) cache_hits: u64,
+    pub(crate) refused: u64,
+    pub(crate) truncated: u64,
+}
+
+impl fmt::Display for ResolverStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "queries={} forwarded={} responses={} unmatched={} cache_hits={} refused={} truncated={}",
+            self.queries,
+            self.forwarded,
+            self.responses,
+            self.unmatched,
+            self.cache_hits,
+            self.refused,
+            self.truncated
+        )
+    }
+}
+
+/// What to do with a query from a client.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum QueryAction {
+    /// Send this to the upstream server.
+    Forward(Vec<u8>),
+    /// Answer the client with this directly.
+    Answer(Vec<u8>),
+    Drop,
+}
+
+struct Pending {
+    client: SocketAddr,
+    client_id: u16,
+    /// The question as the client sent it, restored in the answer.
+    question: Vec<u8>,
+    sent_at: Instant,
+}
+
+/// Per-client token bucket for the QPS limit.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The resolver half of the relay, without any I/O.
+pub(crate) struct Resolver {
+    config: ResolverScenario,
+    rng: SimRng,
+    /// Forwarded queries by upstream id and the question exactly as forwarded.
+    pending: HashMap<(u16, Vec<u8>), Pending>,
+    /// Responses by lowercased question, with their expiry.
+    cache: HashMap<Vec<u8>, (Vec<u8>, Instant)>,
+    buckets: HashMap<SocketAddr, Bucket>,
+    pub(crate) stats: ResolverStats,
+}
+
+impl Resolver {
+    pub(crate) fn new(config: ResolverScenario, rng: SimRng) -> Self {
+        Self {
+            config,
+            rng,
+            pending: HashMap::new(),
+            cache: HashMap::new(),
+            buckets: HashMap::new(),
+            stats: ResolverStats::default(),
+        }
+    }
+
+    pub(crate) fn on_query(
+        &mut self,
+        client: SocketAddr,
+        query: &[u8],
+        now: Instant,
+    ) -> QueryAction {
+        let Some(end) = question_end(query) else {
+            return QueryAction::Drop;
+        };
+        self.stats.queries += 1;
+        let client_id = u16::from_be_bytes([query[0], query[1]]);
+        let question = query[HEADER_LEN..end].to_vec();
+
+        if !self.admit(client, now) {
+            self.stats.refused += 1;
+            return match refused_response(query) {
+                Some(response) => QueryAction::Answer(response),
+                None => QueryAction::Drop,
+            };
+        }
+        if self.config.cache_ttl_secs > 0 {
+            let key = question_key(query).expect("question checked above");
+            match self.cache.get(&key) {
+                Some((response, expires)) if *expires > now => {
+                    let response = response.clone();
+                    self.stats.cache_hits += 1;
+                    return QueryAction::Answer(self.answer(response, client_id, &question));
+                }
+                Some(_) => {
+                    self.cache.remove(&key);
+                }
+                None => {}
+            }
+        }
+
+        self.pending
+            .retain(|_, pending| now.duration_since(pending.sent_at) < PENDING_TIMEOUT);
+        let mut forwarded = query.to_vec();
+        if self.config.randomize_case {
+            let rng = &mut self.rng;
+            randomize_case(&mut forwarded, || rng.chance(0.5));
+        }
+        let forwarded_question = forwarded[HEADER_LEN..end].to_vec();
+        let upstream_id = if self.config.rewrite_ids {
+            loop {
+                let id = self.rng.below(1 << 16) as u16;
+                if !self.pending.contains_key(&(id, forwarded_question.clone())) {
+                    break id;
+                }
+            }
+        } else {
+            client_id
+        };
+        forwarded[..2].copy_from_slice(&upstream_id.to_be_bytes());
+        self.pending.insert(
+            (upstream_id, forwarded_question),
+            Pending {
+                client,
+                client_id,
+                question,
+                sent_at: now,
+            },
+        );
+        self.stats.forwarded += 1;
+        QueryAction::Forward(forwarded)
+    }
+
+    /// Matches an upstream response to its query; returns the client and its answer.
+    pub(crate) fn on_response(
+        &mut self,
+        response: &[u8],
+        now: Instant,
+    ) -> Option<(SocketAddr, Vec<u8>)> {
+        let matched = question_end(response).and_then(|end| {
+            let id = u16::from_be_bytes([response[0], response[1]]);
+            // The question must come back exactly as sent, case included.
+            self.pending
+                .remove(&(id, response[HEADER_LEN..end].to_vec()))
+        });
+        let Some(pending) = matched else {
+            self.stats.unmatched += 1;
+            return None;
+        };
+        self.stats.responses += 1;
+        if self.config.cache_ttl_secs > 0 {
+            let key = question_key(response).expect("question matched above");
+            let expires = now + Duration::from_secs(self.config.cache_ttl_secs);
+            self.cache.insert(key, (response.to_vec(), expires));
+        }
+        let answer = self.answer(response.to_vec(), pending.client_id, &pending.question);
+        Some((pending.client, answer))
+    }
+
+    /// Readdresses `response` to the client: its id, its question case, and TC if too long.
+    fn answer(&mut self, mut response: Vec<u8>, client_id: u16, question: &[u8]) -> Vec<u8> {
+        response[..2].copy_from_slice(&client_id.to_be_bytes());
+        if question_end(&response) == Some(HEADER_LEN + question.len()) {
+            response[HEADER_LEN..HEADER_LEN + question.len()].copy_from_slice(question);
+        }
+        let max_len = self.config.max_response_len;
+        if max_len > 0 && response.len() > max_len {
+            truncate_response(&mut response);
+            self.stats.truncated += 1;
+        }
+        response
+    }
+
+    fn admit(&mut self, client: SocketAddr, now: Instant) -> bool {
+        let limit = self.config.qps_limit;
+        if limit == 0 {
+            return true;
+        }
+        let bucket = self.buckets.entry(client).or_insert(Bucket {
+            tokens: f64::from(limit),
+            updated: now,
+        });
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * f64::from(limit);
+        bucket.tokens = (bucket.tokens + refill).min(f64::from(limit));
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QueryAction, Resolver};
+    use crate::scenario::ResolverScenario;
+    use slipstream_core::rng::SimRng;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    fn client(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn query(id: u16, name: &[u8]) -> Vec<u8> {
+        let mut query = id.to_be_bytes().to_vec();
+        query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        query.extend_from_slice(name);
+        query.extend_from_slice(&[0, 0x10, 0, 1]);
+        query
+    }
+
+    /// The server's answer: the query echoed with QR set and `pad` bytes appended.
+    fn respond(forwarded: &[u8], pad: usize) -> Vec<u8> {
+        let mut response = forwarded.to_vec();
+        response[2] |= 0x80;
+        response.resize(forwarded.len() + pad, 0);
+        response
+    }
+
+    fn forwarded(action: QueryAction) -> Vec<u8> {
+        match action {
+            QueryAction::Forward(query) => query,
+            other => panic!("expected a forward, got {:?}", other),
+        }
+    }
+
+    const NAME: &[u8] = b"\x04abcd\x07example\x00";
+
+    #[tokio::test(start_paused = true)]
+    async fn plain_relay_preserves_the_query() {
+        let mut resolver = Resolver::new(ResolverScenario::default(), SimRng::new(1));
+        let now = Instant::now();
+        let sent = forwarded(resolver.on_query(client(1), &query(7, NAME), now));
+        assert_eq!(sent, query(7, NAME));
+        let (to, answer) = resolver
+            .on_response(&respond(&sent, 3), now)
+            .expect("match");
+        assert_eq!(to, client(1));
+        assert_eq!(answer, respond(&query(7, NAME), 3));
+        assert!(resolver.on_response(&respond(&sent, 3), now).is_none());
+        assert_eq!(resolver.stats.unmatched, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rewrites_ids_and_case_then_restores_them() {
+        let config = ResolverScenario {
+            rewrite_ids: true,
+            randomize_case: true,
+            ..ResolverScenario::default()
+        };
+        let mut resolver = Resolver::new(config, SimRng::new(2));
+        let now = Instant::now();
+        let sent = forwarded(resolver.on_query(client(1), &query(7, NAME), now));
+        assert_ne!(&sent[..2], &7u16.to_be_bytes());
+        assert_ne!(&sent[12..], &query(7, NAME)[12..]);
+        assert!(sent[12..].eq_ignore_ascii_case(&query(7, NAME)[12..]));
+
+        // A response whose question case differs from what was sent is rejected.
+        let mut spoofed = respond(&sent, 0);
+        for byte in &mut spoofed[13..17] {
+            *byte ^= 0x20;
+        }
+        assert!(resolver.on_response(&spoofed, now).is_none());
+
+        let (_, answer) = resolver
+            .on_response(&respond(&sent, 0), now)
+            .expect("match");
+        assert_eq!(answer, respond(&query(7, NAME), 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caches_and_truncates_answers() {
+        let config = ResolverScenario {
+            cache_ttl_secs: 5,
+            max_response_len: 60,
+            ..ResolverScenario::default()
+        };
+        let mut resolver = Resolver::new(config, SimRng::new(3));
+        let now = Instant::now();
+        let sent = forwarded(resolver.on_query(client(1), &query(1, NAME), now));
+        let (_, answer) = resolver
+            .on_response(&respond(&sent, 100), now)
+            .expect("match");
+        assert_eq!(answer.len(), query(1, NAME).len());
+        assert_ne!(answer[2] & 0x02, 0);
+
+        let upper = b"\x04ABCD\x07example\x00";
+        match resolver.on_query(client(2), &query(2, upper), now + Duration::from_secs(1)) {
+            QueryAction::Answer(answer) => {
+                assert_eq!(&answer[..2], &2u16.to_be_bytes());
+                assert_eq!(&answer[12..12 + upper.len()], upper);
+            }
+            other => panic!("expected a cached answer, got {:?}", other),
+        }
+        let later = now + Duration::from_secs(6);
+        forwarded(resolver.on_query(client(2), &query(3, NAME), later));
+        assert_eq!(resolver.stats.cache_hits, 1);
+        assert_eq!(resolver.stats.truncated, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refuses_clients_over_the_qps_limit() {
+        let config = ResolverScenario {
+            qps_limit: 2,
+            ..ResolverScenario::default()
+        };
+        let mut resolver = Resolver::new(config, SimRng::new(4));
+        let now = Instant::now();
+        for id in 0..2 {
+            forwarded(resolver.on_query(client(1), &query(id, NAME), now));
+        }
+        match resolver.on_query(client(1), &query(2, NAME), now) {
+            QueryAction::Answer(answer) => assert_eq!(answer[3] & 0x0f, 5),
+            other => panic!("expected REFUSED, got {:?}", other),
+        }
+        // Other clients have their own budget, and tokens come back over time.
+        forwarded(resolver.on_query(client(2), &query(3, NAME), now));
+        let later = now + Duration::from_millis(500);
+        forwarded(resolver.on_query(client(1), &query(4, NAME), later));
+        assert_eq!(resolver.stats.refused, 1);
+    }
+}
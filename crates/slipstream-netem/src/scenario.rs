@@ -0,0 +1,205 @@
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+
+const DEFAULT_QUEUE_PACKETS: usize = 1000;
+
+/// A scenario file; see docs/benchmarks.md for the format.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Scenario {
+    pub(crate) seed: Option<u64>,
+    /// Impairments for both directions.
+    pub(crate) link: Option<LinkScenario>,
+    /// Replaces `link` for queries towards the server.
+    pub(crate) queries: Option<LinkScenario>,
+    /// Replaces `link` for responses towards the client.
+    pub(crate) responses: Option<LinkScenario>,
+    #[serde(default)]
+    pub(crate) resolver: ResolverScenario,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LinkScenario {
+    pub(crate) delay_ms: u64,
+    /// Extra delay drawn uniformly from `0..jitter_ms`.
+    pub(crate) jitter_ms: u64,
+    pub(crate) loss: f64,
+    /// Share of datagrams sent without the delay, overtaking earlier ones.
+    pub(crate) reorder: f64,
+    pub(crate) duplicate: f64,
+    /// Bandwidth cap; 0 leaves the link unlimited.
+    pub(crate) rate_kbps: u64,
+    /// Datagrams waiting for the bandwidth cap before new ones are dropped.
+    pub(crate) queue_packets: usize,
+}
+
+impl Default for LinkScenario {
+    fn default() -> Self {
+        Self {
+            delay_ms: 0,
+            jitter_ms: 0,
+            loss: 0.0,
+            reorder: 0.0,
+            duplicate: 0.0,
+            rate_kbps: 0,
+            queue_packets: DEFAULT_QUEUE_PACKETS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ResolverScenario {
+    pub(crate) rewrite_ids: bool,
+    /// Flip the case of QNAME letters at random (0x20 encoding).
+    pub(crate) randomize_case: bool,
+    /// Answer repeated questions from a cache for this long; 0 disables it.
+    pub(crate) cache_ttl_secs: u64,
+    /// Queries per second per client before answering REFUSED; 0 disables it.
+    pub(crate) qps_limit: u32,
+    /// Truncate longer answers with TC; 0 disables it.
+    pub(crate) max_response_len: usize,
+}
+
+impl Scenario {
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Self::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let scenario: Self = toml::from_str(text).map_err(|err| err.message().to_string())?;
+        for (name, link) in [
+            ("link", scenario.link),
+            ("queries", scenario.queries),
+            ("responses", scenario.responses),
+        ] {
+            if let Some(link) = link {
+                link.validate()
+                    .map_err(|err| format!("[{}] {}", name, err))?;
+            }
+        }
+        Ok(scenario)
+    }
+
+    pub(crate) fn query_link(&self) -> LinkScenario {
+        self.queries.or(self.link).unwrap_or_default()
+    }
+
+    pub(crate) fn response_link(&self) -> LinkScenario {
+        self.responses.or(self.link).unwrap_or_default()
+    }
+}
+
+impl LinkScenario {
+    pub(crate) fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms)
+    }
+
+    pub(crate) fn jitter(&self) -> Duration {
+        Duration::from_millis(self.jitter_ms)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("loss", self.loss),
+            ("reorder", self.reorder),
+            ("duplicate", self.duplicate),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("{} must be between 0 and 1, got {}", name, value));
+            }
+        }
+        if self.queue_packets == 0 {
+            return Err("queue_packets must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LinkScenario, ResolverScenario, Scenario};
+
+    #[test]
+    fn parses_a_full_scenario() {
+        let scenario = Scenario::parse(
+            r#"
+            seed = 9
+
+            [link]
+            delay_ms = 40
+            jitter_ms = 10
+            loss = 0.01
+
+            [responses]
+            delay_ms = 40
+            rate_kbps = 256
+            queue_packets = 20
+
+            [resolver]
+            rewrite_ids = true
+            randomize_case = true
+            cache_ttl_secs = 30
+            qps_limit = 50
+            max_response_len = 512
+            "#,
+        )
+        .expect("scenario");
+        assert_eq!(scenario.seed, Some(9));
+        assert_eq!(scenario.query_link().jitter_ms, 10);
+        assert_eq!(
+            scenario.response_link(),
+            LinkScenario {
+                delay_ms: 40,
+                rate_kbps: 256,
+                queue_packets: 20,
+                ..LinkScenario::default()
+            }
+        );
+        assert_eq!(
+            scenario.resolver,
+            ResolverScenario {
+                rewrite_ids: true,
+                randomize_case: true,
+                cache_ttl_secs: 30,
+                qps_limit: 50,
+                max_response_len: 512,
+            }
+        );
+    }
+
+    #[test]
+    fn empty_scenario_is_a_plain_relay() {
+        let scenario = Scenario::parse("").expect("scenario");
+        assert_eq!(scenario.query_link(), LinkScenario::default());
+        assert_eq!(scenario.response_link(), LinkScenario::default());
+        assert_eq!(scenario.resolver, ResolverScenario::default());
+    }
+
+    #[test]
+    fn bench_scenarios_parse() {
+        let dir =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../scripts/bench/scenarios");
+        let mut count = 0;
+        for entry in std::fs::read_dir(&dir).expect("scenario dir") {
+            let path = entry.expect("entry").path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                Scenario::load(&path).expect("scenario");
+                count += 1;
+            }
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let err = Scenario::parse("[link]\nloss = 1.5\n").expect_err("loss");
+        assert!(err.contains("[link] loss"), "{}", err);
+        assert!(Scenario::parse("[link]\ndelay = 5\n").is_err());
+        assert!(Scenario::parse("[queries]\nqueue_packets = 0\n").is_err());
+    }
+}
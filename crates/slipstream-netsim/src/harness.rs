@@ -0,0 +1,254 @@
+use slipstream_client::{run_client_with, ClientError};
+use slipstream_core::diagnostics::DiagnosticRequest;
+use slipstream_core::{AddressFamily, HostPort};
+use slipstream_ffi::clock::ClockSource;
+use slipstream_ffi::picoquic::slipstream_server_cc_algorithm;
+use slipstream_ffi::{ClientConfig, DnsTransportKind, DomainSpec, ResolverMode, ResolverSpec};
+use slipstream_server::{run_server_with, ServerConfig, ServerError, TargetAddress};
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::network::{LinkConfig, SimNetwork};
+use crate::resolver::{ResolverConfig, SimResolver};
+
+pub const CLIENT_ADDR: SocketAddr = sim_addr(2, 40000);
+pub const RESOLVER_ADDR: SocketAddr = sim_addr(53, 53);
+pub const SERVER_ADDR: SocketAddr = sim_addr(1, 53);
+const CONNECT_RETRY: Duration = Duration::from_millis(10);
+
+const fn sim_addr(host: u16, port: u16) -> SocketAddr {
+    SocketAddr::new(
+        std::net::IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, host)),
+        port,
+    )
+}
+
+/// Whether picoquic is linked for real; tests should skip when it is not.
+pub fn picoquic_available() -> bool {
+    unsafe { !slipstream_server_cc_algorithm.is_null() }
+}
+
+/// A client, resolver and server on one [`SimNetwork`].
+#[derive(Debug, Clone)]
+pub struct TunnelConfig {
+    pub seed: u64,
+    /// Between the client and the resolver, both ways.
+    pub access: LinkConfig,
+    /// Between the resolver and the server, both ways.
+    pub upstream: LinkConfig,
+    pub resolver: ResolverConfig,
+    pub domain: String,
+    pub path_mtu_discovery: bool,
+    /// The server's built-in target service.
+    pub target: DiagnosticRequest,
+    /// Runs the client with `--lazy`, closing the tunnel after this many
+    /// seconds without TCP connections.
+    pub lazy_idle_timeout_secs: Option<u64>,
+}
+
+impl Default for TunnelConfig {
+    fn default() -> Self {
+        Self {
+            seed: 1,
+            access: LinkConfig::default(),
+            upstream: LinkConfig::default(),
+            resolver: ResolverConfig::default(),
+            domain: "tunnel.example.com".to_string(),
+            path_mtu_discovery: false,
+            target: DiagnosticRequest::Echo,
+            lazy_idle_timeout_secs: None,
+        }
+    }
+}
+
+/// The client and server event loops running over a simulated network.
+///
+/// Both loops run on tokio's clock, so under a paused runtime QUIC timers,
+/// pacing and link delays all advance together. The TCP side is real: the
+/// client listens on loopback, and virtual time may run ahead while that
+/// socket is waited on. The server's target is one of its built-in services,
+/// `echo` unless [`TunnelConfig::target`] says otherwise.
+pub struct SimTunnel {
+    pub network: SimNetwork,
+    pub resolver: SimResolver,
+    tcp_port: u16,
+    shutdown: Arc<AtomicBool>,
+    server: JoinHandle<Result<i32, ServerError>>,
+    client: JoinHandle<Result<i32, ClientError>>,
+}
+
+impl SimTunnel {
+    /// Starts the tunnel; must be called inside a [`tokio::task::LocalSet`]
+    /// because the event loops hold picoquic pointers across awaits.
+    pub async fn start(config: TunnelConfig) -> io::Result<Self> {
+        let network = SimNetwork::new(config.seed, LinkConfig::default());
+        for (a, b, link) in [
+            (CLIENT_ADDR, RESOLVER_ADDR, config.access),
+            (RESOLVER_ADDR, SERVER_ADDR, config.upstream),
+        ] {
+            network.set_link(a, b, link);
+            network.set_link(b, a, link);
+        }
+        let resolver = SimResolver::spawn(&network, RESOLVER_ADDR, SERVER_ADDR, config.resolver);
+        let tcp_port = pick_tcp_port().await?;
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server_config = ServerConfig {
+            dns_listen_port: SERVER_ADDR.port(),
+            target_address: TargetAddress::Builtin(config.target),
+            cert: fixture("cert.pem"),
+            key: fixture("key.pem"),
+            domains: vec![config.domain.clone()],
+            pad_responses: 0,
+            probe_rate: 100,
+            metrics_listen: None,
+            control_socket: None,
+            audit_log: None,
+            qlog_dir: None,
+            qlog_max_mb: 0,
+            keylog_file: None,
+            pcap: None,
+            pcap_max_mb: 0,
+            pcap_files: 0,
+            record: None,
+            seed: None,
+            workers: 1,
+            enable_diagnostics: true,
+            debug_streams: false,
+            debug_commands: false,
+        };
+        let server = {
+            let endpoint = network.endpoint(SERVER_ADDR);
+            let shutdown = shutdown.clone();
+            tokio::task::spawn_local(async move {
+                run_server_with(&server_config, endpoint, ClockSource::Tokio, &shutdown).await
+            })
+        };
+
+        let client = {
+            let network = network.clone();
+            tokio::task::spawn_local(async move {
+                let resolvers = [ResolverSpec {
+                    resolver: HostPort {
+                        host: RESOLVER_ADDR.ip().to_string(),
+                        port: RESOLVER_ADDR.port(),
+                        family: AddressFamily::V6,
+                    },
+                    mode: ResolverMode::Recursive,
+                }];
+                let domains = [DomainSpec {
+                    domain: config.domain,
+                    weight: 1,
+                    resolver: None,
+                }];
+                let mut client_config =
+                    client_config(tcp_port, &resolvers, &domains, config.path_mtu_discovery);
+                if let Some(secs) = config.lazy_idle_timeout_secs {
+                    client_config.lazy = true;
+                    client_config.lazy_idle_timeout_secs = secs;
+                }
+                run_client_with(&client_config, ClockSource::Tokio, || {
+                    let endpoint = network.endpoint(CLIENT_ADDR);
+                    async move { Ok(endpoint) }
+                })
+                .await
+            })
+        };
+
+        Ok(Self {
+            network,
+            resolver,
+            tcp_port,
+            shutdown,
+            server,
+            client,
+        })
+    }
+
+    /// Opens a TCP connection through the tunnel to the server's target service.
+    pub async fn connect(&self) -> io::Result<TcpStream> {
+        let addr = SocketAddr::from(([127, 0, 0, 1], self.tcp_port));
+        loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                    if self.client.is_finished() {
+                        return Err(err);
+                    }
+                    tokio::time::sleep(CONNECT_RETRY).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Asks the server to shut down, stops the client, and returns the server's exit code.
+    pub async fn shutdown(self) -> Result<i32, ServerError> {
+        self.shutdown.store(true, Ordering::Relaxed);
+        let result = self.server.await.expect("server task");
+        self.client.abort();
+        result
+    }
+}
+
+fn client_config<'a>(
+    tcp_listen_port: u16,
+    resolvers: &'a [ResolverSpec],
+    domains: &'a [DomainSpec],
+    path_mtu_discovery: bool,
+) -> ClientConfig<'a> {
+    // The CLI defaults, with every side channel off.
+    ClientConfig {
+        tcp_listen_port,
+        resolvers,
+        domains,
+        label_len: 57,
+        sequence_label: false,
+        mtu: None,
+        tolerant_responses: false,
+        path_mtu_discovery,
+        poll_jitter_ms: 0,
+        pad_bucket: 0,
+        cover_interval_ms: 0,
+        max_qps: 0,
+        idle_after_secs: 10,
+        idle_poll_interval_ms: (1000, 30000),
+        lazy: false,
+        lazy_idle_timeout_secs: 300,
+        metrics_listen: None,
+        control_socket: None,
+        qlog_dir: None,
+        qlog_max_mb: 0,
+        keylog_file: None,
+        pcap: None,
+        pcap_max_mb: 0,
+        pcap_files: 0,
+        dns_transport: DnsTransportKind::Udp,
+        dns_tls_name: None,
+        dns_tls_ca: None,
+        cert: None,
+        congestion_control: None,
+        gso: false,
+        keep_alive_interval: 400,
+        debug_poll: false,
+        debug_streams: false,
+    }
+}
+
+fn fixture(name: &str) -> String {
+    let path: PathBuf = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../../fixtures/certs")
+        .join(name);
+    path.to_string_lossy().into_owned()
+}
+
+async fn pick_tcp_port() -> io::Result<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    Ok(listener.local_addr()?.port())
+}
@@ -0,0 +1,10 @@
+mod harness;
+mod network;
+mod resolver;
+
+pub use harness::{
+    picoquic_available, SimTunnel, TunnelConfig, CLIENT_ADDR, RESOLVER_ADDR, SERVER_ADDR,
+};
+pub use network::{LinkConfig, NetworkStats, SimEndpoint, SimNetwork};
+pub use resolver::{ResolverConfig, ResolverStats, SimResolver};
+pub use slipstream_core::rng::SimRng;
@@ -0,0 +1,288 @@
+use slipstream_core::rng::SimRng;
+use slipstream_core::transport::DnsTransport;
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+/// Impairments applied to datagrams travelling one way between two endpoints.
+///
+/// The knobs follow `tc netem`: every datagram is delayed by `delay` plus a
+/// uniform share of `jitter`, except the `reorder` share, which is delivered
+/// at once and so overtakes datagrams already in flight.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkConfig {
+    pub delay: Duration,
+    pub jitter: Duration,
+    /// Probability that a datagram is dropped.
+    pub loss: f64,
+    /// Probability that a datagram skips the delay.
+    pub reorder: f64,
+    /// Probability that a datagram is delivered twice, each copy delayed independently.
+    pub duplicate: f64,
+}
+
+/// Datagram counters across the whole network.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetworkStats {
+    pub sent: u64,
+    pub dropped: u64,
+    pub reordered: u64,
+    pub duplicated: u64,
+    /// Sent to an address without an endpoint.
+    pub unroutable: u64,
+}
+
+struct Inner {
+    rng: SimRng,
+    default_link: LinkConfig,
+    links: HashMap<(SocketAddr, SocketAddr), LinkConfig>,
+    endpoints: HashMap<SocketAddr, mpsc::UnboundedSender<Datagram>>,
+    stats: NetworkStats,
+}
+
+/// A simulated datagram network on tokio's clock.
+///
+/// Cloning shares the network. All randomness comes from the seed, so a
+/// single-threaded runtime with a paused clock replays a run exactly.
+#[derive(Clone)]
+pub struct SimNetwork {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl SimNetwork {
+    /// A network whose links all use `default_link` until [`SimNetwork::set_link`] says otherwise.
+    pub fn new(seed: u64, default_link: LinkConfig) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                rng: SimRng::new(seed),
+                default_link,
+                links: HashMap::new(),
+                endpoints: HashMap::new(),
+                stats: NetworkStats::default(),
+            })),
+        }
+    }
+
+    /// Sets the impairments for datagrams sent from `from` to `to`.
+    pub fn set_link(&self, from: SocketAddr, to: SocketAddr, link: LinkConfig) {
+        self.lock().links.insert((from, to), link);
+    }
+
+    /// Attaches an endpoint at `addr`, replacing any earlier one there.
+    pub fn endpoint(&self, addr: SocketAddr) -> SimEndpoint {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.lock().endpoints.insert(addr, tx);
+        SimEndpoint {
+            addr,
+            network: self.clone(),
+            inbox: Mutex::new(rx),
+        }
+    }
+
+    pub fn stats(&self) -> NetworkStats {
+        self.lock().stats
+    }
+
+    /// A generator seeded from the network's, for simulated hosts that need randomness.
+    pub fn fork_rng(&self) -> SimRng {
+        SimRng::new(self.lock().rng.next_u64())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().expect("network lock")
+    }
+
+    fn send(&self, from: SocketAddr, to: SocketAddr, packet: &[u8]) {
+        let mut inner = self.lock();
+        inner.stats.sent += 1;
+        let Some(inbox) = inner.endpoints.get(&to).cloned() else {
+            inner.stats.unroutable += 1;
+            return;
+        };
+        let link = inner
+            .links
+            .get(&(from, to))
+            .copied()
+            .unwrap_or(inner.default_link);
+        if inner.rng.chance(link.loss) {
+            inner.stats.dropped += 1;
+            return;
+        }
+        let copies = if inner.rng.chance(link.duplicate) {
+            inner.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        let now = Instant::now();
+        for _ in 0..copies {
+            let delay = if inner.rng.chance(link.reorder) {
+                inner.stats.reordered += 1;
+                Duration::ZERO
+            } else {
+                let jitter = inner.rng.below(link.jitter.as_micros() as u64);
+                link.delay + Duration::from_micros(jitter)
+            };
+            let datagram = (packet.to_vec(), from);
+            if delay.is_zero() {
+                let _ = inbox.send(datagram);
+                continue;
+            }
+            let inbox = inbox.clone();
+            tokio::spawn(async move {
+                sleep_until(now + delay).await;
+                let _ = inbox.send(datagram);
+            });
+        }
+    }
+}
+
+/// One address on a [`SimNetwork`]; receives what is sent to that address.
+pub struct SimEndpoint {
+    addr: SocketAddr,
+    network: SimNetwork,
+    inbox: Mutex<mpsc::UnboundedReceiver<Datagram>>,
+}
+
+impl DnsTransport for SimEndpoint {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    async fn send_to(&self, packet: &[u8], dest: SocketAddr) -> io::Result<()> {
+        self.network.send(self.addr, dest, packet);
+        Ok(())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let datagram = poll_fn(|cx| self.inbox.lock().expect("inbox lock").poll_recv(cx)).await;
+        // A replacement endpoint at the same address closes this inbox.
+        let (packet, from) = datagram.ok_or(io::ErrorKind::NotConnected)?;
+        Ok((copy_datagram(&packet, buf), from))
+    }
+
+    fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self.inbox.lock().expect("inbox lock").try_recv() {
+            Ok((packet, from)) => Ok((copy_datagram(&packet, buf), from)),
+            Err(mpsc::error::TryRecvError::Empty) => Err(io::ErrorKind::WouldBlock.into()),
+            Err(mpsc::error::TryRecvError::Disconnected) => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+}
+
+/// Copies a datagram into the caller's buffer, truncating it like a UDP socket would.
+fn copy_datagram(packet: &[u8], buf: &mut [u8]) -> usize {
+    let len = packet.len().min(buf.len());
+    buf[..len].copy_from_slice(&packet[..len]);
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LinkConfig, SimNetwork};
+    use slipstream_core::transport::DnsTransport;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([0xfd00, 0, 0, 0, 0, 0, 0, 1], port))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delays_datagrams_on_the_virtual_clock() {
+        let network = SimNetwork::new(
+            1,
+            LinkConfig {
+                delay: Duration::from_millis(40),
+                ..LinkConfig::default()
+            },
+        );
+        let a = network.endpoint(addr(1));
+        let b = network.endpoint(addr(2));
+        let started = Instant::now();
+        a.send_to(b"ping", addr(2)).await.expect("send");
+        let mut buf = [0u8; 16];
+        assert!(b.try_recv_from(&mut buf).is_err());
+        let (len, from) = b.recv_from(&mut buf).await.expect("recv");
+        assert_eq!(&buf[..len], b"ping");
+        assert_eq!(from, addr(1));
+        assert_eq!(started.elapsed(), Duration::from_millis(40));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn applies_per_link_impairments() {
+        let network = SimNetwork::new(2, LinkConfig::default());
+        network.set_link(
+            addr(1),
+            addr(2),
+            LinkConfig {
+                loss: 1.0,
+                ..LinkConfig::default()
+            },
+        );
+        network.set_link(
+            addr(2),
+            addr(1),
+            LinkConfig {
+                duplicate: 1.0,
+                ..LinkConfig::default()
+            },
+        );
+        let a = network.endpoint(addr(1));
+        let b = network.endpoint(addr(2));
+        let mut buf = [0u8; 16];
+
+        a.send_to(b"lost", addr(2)).await.expect("send");
+        b.send_to(b"twice", addr(1)).await.expect("send");
+        b.send_to(b"nowhere", addr(3)).await.expect("send");
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert!(b.try_recv_from(&mut buf).is_err());
+        for _ in 0..2 {
+            let (len, _) = a.try_recv_from(&mut buf).expect("copy");
+            assert_eq!(&buf[..len], b"twice");
+        }
+        assert!(a.try_recv_from(&mut buf).is_err());
+
+        let stats = network.stats();
+        assert_eq!(stats.sent, 3);
+        assert_eq!(stats.dropped, 1);
+        assert_eq!(stats.duplicated, 1);
+        assert_eq!(stats.unroutable, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reordered_datagrams_overtake_delayed_ones() {
+        let network = SimNetwork::new(
+            3,
+            LinkConfig {
+                delay: Duration::from_millis(100),
+                reorder: 0.5,
+                ..LinkConfig::default()
+            },
+        );
+        let a = network.endpoint(addr(1));
+        let b = network.endpoint(addr(2));
+        for seq in 0u8..32 {
+            a.send_to(&[seq], addr(2)).await.expect("send");
+        }
+        let mut buf = [0u8; 4];
+        let mut received = Vec::new();
+        for _ in 0..32 {
+            let (_, _) = b.recv_from(&mut buf).await.expect("recv");
+            received.push(buf[0]);
+        }
+        let reordered = network.stats().reordered;
+        assert!(reordered > 0 && reordered < 32, "reordered={}", reordered);
+        assert_ne!(received, (0u8..32).collect::<Vec<_>>());
+        received.sort_unstable();
+        assert_eq!(received, (0u8..32).collect::<Vec<_>>());
+    }
+}
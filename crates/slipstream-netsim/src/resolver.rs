@@ -0,0 +1,336 @@
+use slipstream_core::rng::SimRng;
+use slipstream_core::transport::DnsTransport;
+use slipstream_dns::{question_key, truncate_response};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::network::{SimEndpoint, SimNetwork};
+
+const MAX_MESSAGE_LEN: usize = 65535;
+// Forwarded queries with no answer by then are forgotten.
+const PENDING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a [`SimResolver`] treats the queries it relays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResolverConfig {
+    /// Forward queries under fresh DNS ids instead of the client's.
+    pub rewrite_ids: bool,
+    /// Answer repeated questions from a cache for this long.
+    pub cache_ttl: Option<Duration>,
+    /// Replace longer responses with a header and question carrying the TC bit.
+    pub max_response_len: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResolverStats {
+    pub queries: u64,
+    pub forwarded: u64,
+    pub responses: u64,
+    pub cache_hits: u64,
+    pub truncated: u64,
+}
+
+/// A recursive resolver relaying queries to one upstream, the tunnel server.
+pub struct SimResolver {
+    stats: Arc<Mutex<ResolverStats>>,
+    task: JoinHandle<()>,
+}
+
+impl SimResolver {
+    /// Attaches the resolver at `addr` and relays queries to `upstream`.
+    pub fn spawn(
+        network: &SimNetwork,
+        addr: SocketAddr,
+        upstream: SocketAddr,
+        config: ResolverConfig,
+    ) -> Self {
+        let stats = Arc::new(Mutex::new(ResolverStats::default()));
+        let relay = Relay {
+            endpoint: network.endpoint(addr),
+            upstream,
+            config,
+            rng: network.fork_rng(),
+            pending: HashMap::new(),
+            cache: HashMap::new(),
+            stats: stats.clone(),
+        };
+        Self {
+            stats,
+            task: tokio::spawn(relay.run()),
+        }
+    }
+
+    pub fn stats(&self) -> ResolverStats {
+        *self.stats.lock().expect("resolver stats lock")
+    }
+}
+
+impl Drop for SimResolver {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+struct Pending {
+    client: SocketAddr,
+    client_id: u16,
+    question: Vec<u8>,
+    sent_at: Instant,
+}
+
+struct Relay {
+    endpoint: SimEndpoint,
+    upstream: SocketAddr,
+    config: ResolverConfig,
+    rng: SimRng,
+    /// Forwarded queries by upstream id.
+    pending: HashMap<u16, Pending>,
+    /// Responses by lowercased question, with their expiry.
+    cache: HashMap<Vec<u8>, (Vec<u8>, Instant)>,
+    stats: Arc<Mutex<ResolverStats>>,
+}
+
+impl Relay {
+    async fn run(mut self) {
+        let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+        loop {
+            let Ok((len, peer)) = self.endpoint.recv_from(&mut buf).await else {
+                return;
+            };
+            let message = &buf[..len];
+            let Some(question) = question_key(message) else {
+                continue;
+            };
+            if peer == self.upstream {
+                self.on_response(message, question).await;
+            } else {
+                self.on_query(message, question, peer).await;
+            }
+        }
+    }
+
+    async fn on_query(&mut self, query: &[u8], question: Vec<u8>, client: SocketAddr) {
+        self.stats().queries += 1;
+        let client_id = u16::from_be_bytes([query[0], query[1]]);
+        let now = Instant::now();
+        if let Some((response, expires)) = self.cache.get(&question) {
+            if *expires > now {
+                let response = response.clone();
+                self.stats().cache_hits += 1;
+                self.answer(response, client_id, client).await;
+                return;
+            }
+            self.cache.remove(&question);
+        }
+
+        self.pending
+            .retain(|_, pending| now.duration_since(pending.sent_at) < PENDING_TIMEOUT);
+        let upstream_id = if self.config.rewrite_ids {
+            loop {
+                let id = self.rng.below(1 << 16) as u16;
+                if !self.pending.contains_key(&id) {
+                    break id;
+                }
+            }
+        } else {
+            client_id
+        };
+        self.pending.insert(
+            upstream_id,
+            Pending {
+                client,
+                client_id,
+                question,
+                sent_at: now,
+            },
+        );
+        let mut forwarded = query.to_vec();
+        forwarded[..2].copy_from_slice(&upstream_id.to_be_bytes());
+        self.stats().forwarded += 1;
+        let _ = self.endpoint.send_to(&forwarded, self.upstream).await;
+    }
+
+    async fn on_response(&mut self, response: &[u8], question: Vec<u8>) {
+        let upstream_id = u16::from_be_bytes([response[0], response[1]]);
+        let Some(pending) = self.pending.remove(&upstream_id) else {
+            return;
+        };
+        if pending.question != question {
+            return;
+        }
+        self.stats().responses += 1;
+        if let Some(ttl) = self.config.cache_ttl {
+            self.cache
+                .insert(question, (response.to_vec(), Instant::now() + ttl));
+        }
+        self.answer(response.to_vec(), pending.client_id, pending.client)
+            .await;
+    }
+
+    async fn answer(&mut self, mut response: Vec<u8>, client_id: u16, client: SocketAddr) {
+        response[..2].copy_from_slice(&client_id.to_be_bytes());
+        if let Some(max_len) = self.config.max_response_len {
+            if response.len() > max_len {
+                truncate_response(&mut response);
+                self.stats().truncated += 1;
+            }
+        }
+        let _ = self.endpoint.send_to(&response, client).await;
+    }
+
+    fn stats(&self) -> std::sync::MutexGuard<'_, ResolverStats> {
+        self.stats.lock().expect("resolver stats lock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResolverConfig, SimResolver};
+    use crate::network::{LinkConfig, SimNetwork};
+    use slipstream_core::transport::DnsTransport;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    const CLIENT: u16 = 1;
+    const RESOLVER: u16 = 53;
+    const SERVER: u16 = 5353;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([0xfd00, 0, 0, 0, 0, 0, 0, 1], port))
+    }
+
+    /// A query for `<label>.example.` with one question.
+    fn query(id: u16, label: &str) -> Vec<u8> {
+        let mut query = id.to_be_bytes().to_vec();
+        query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+        query.extend_from_slice(b"\x07example\x00\x00\x10\x00\x01");
+        query
+    }
+
+    /// Answers each query with the query itself, QR set, and `pad` extra bytes.
+    async fn serve(server: &impl DnsTransport, pad: usize) -> u16 {
+        let mut buf = [0u8; 512];
+        let (len, from) = server.recv_from(&mut buf).await.expect("query");
+        let mut response = buf[..len].to_vec();
+        response[2] |= 0x80;
+        response[6..8].copy_from_slice(&1u16.to_be_bytes());
+        response.resize(len + pad, 0);
+        server.send_to(&response, from).await.expect("respond");
+        u16::from_be_bytes([buf[0], buf[1]])
+    }
+
+    async fn recv(client: &impl DnsTransport) -> Vec<u8> {
+        let mut buf = [0u8; 512];
+        let (len, from) = client.recv_from(&mut buf).await.expect("response");
+        assert_eq!(from, addr(RESOLVER));
+        buf[..len].to_vec()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rewrites_ids_and_restores_them() {
+        let network = SimNetwork::new(4, LinkConfig::default());
+        let client = network.endpoint(addr(CLIENT));
+        let server = network.endpoint(addr(SERVER));
+        let resolver = SimResolver::spawn(
+            &network,
+            addr(RESOLVER),
+            addr(SERVER),
+            ResolverConfig {
+                rewrite_ids: true,
+                ..ResolverConfig::default()
+            },
+        );
+
+        let mut upstream_ids = Vec::new();
+        for id in [0x1111u16, 0x2222] {
+            client
+                .send_to(&query(id, "abc"), addr(RESOLVER))
+                .await
+                .expect("send");
+            upstream_ids.push(serve(&server, 0).await);
+            assert_eq!(&recv(&client).await[..2], &id.to_be_bytes());
+        }
+        assert_ne!(upstream_ids, [0x1111, 0x2222]);
+        assert_eq!(resolver.stats().forwarded, 2);
+        assert_eq!(resolver.stats().cache_hits, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caches_until_the_ttl_expires() {
+        let network = SimNetwork::new(5, LinkConfig::default());
+        let client = network.endpoint(addr(CLIENT));
+        let server = network.endpoint(addr(SERVER));
+        let resolver = SimResolver::spawn(
+            &network,
+            addr(RESOLVER),
+            addr(SERVER),
+            ResolverConfig {
+                cache_ttl: Some(Duration::from_secs(5)),
+                ..ResolverConfig::default()
+            },
+        );
+
+        client
+            .send_to(&query(1, "abc"), addr(RESOLVER))
+            .await
+            .expect("send");
+        serve(&server, 0).await;
+        recv(&client).await;
+        // Same question in another case: answered from the cache under the new id.
+        client
+            .send_to(&query(2, "ABC"), addr(RESOLVER))
+            .await
+            .expect("send");
+        assert_eq!(&recv(&client).await[..2], &2u16.to_be_bytes());
+        assert_eq!(resolver.stats().cache_hits, 1);
+
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        client
+            .send_to(&query(3, "abc"), addr(RESOLVER))
+            .await
+            .expect("send");
+        serve(&server, 0).await;
+        recv(&client).await;
+        assert_eq!(resolver.stats().cache_hits, 1);
+        assert_eq!(resolver.stats().forwarded, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn truncates_long_responses() {
+        let network = SimNetwork::new(6, LinkConfig::default());
+        let client = network.endpoint(addr(CLIENT));
+        let server = network.endpoint(addr(SERVER));
+        let resolver = SimResolver::spawn(
+            &network,
+            addr(RESOLVER),
+            addr(SERVER),
+            ResolverConfig {
+                max_response_len: Some(100),
+                ..ResolverConfig::default()
+            },
+        );
+
+        let short = query(1, "abc");
+        client.send_to(&short, addr(RESOLVER)).await.expect("send");
+        serve(&server, 200).await;
+        let response = recv(&client).await;
+        assert_eq!(response.len(), short.len());
+        assert_ne!(response[2] & 0x02, 0);
+        assert_eq!(&response[6..12], &[0; 6]);
+        assert_eq!(&response[12..], &short[12..]);
+
+        client
+            .send_to(&query(2, "abc"), addr(RESOLVER))
+            .await
+            .expect("send");
+        serve(&server, 10).await;
+        assert_eq!(recv(&client).await[2] & 0x02, 0);
+        assert_eq!(resolver.stats().truncated, 1);
+    }
+}
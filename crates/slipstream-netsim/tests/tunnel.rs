@@ -0,0 +1,245 @@
+use slipstream_core::diagnostics::DiagnosticRequest;
+use slipstream_netsim::{picoquic_available, LinkConfig, ResolverConfig, SimTunnel, TunnelConfig};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::task::LocalSet;
+use tokio::time::{timeout, Instant};
+
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(300);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Sends `len` bytes through the tunnel and returns the virtual time until
+/// the echo came back intact.
+async fn echo_through(tunnel: &SimTunnel, len: usize) -> Duration {
+    let payload: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+    let stream = tunnel.connect().await.expect("connect to client");
+    let (mut reader, mut writer) = stream.into_split();
+    let started = Instant::now();
+    let expected = payload.clone();
+    let write = tokio::spawn(async move {
+        writer.write_all(&payload).await.expect("write");
+        writer
+    });
+    let mut received = vec![0u8; len];
+    timeout(TRANSFER_TIMEOUT, reader.read_exact(&mut received))
+        .await
+        .expect("echo timed out")
+        .expect("read echo");
+    let elapsed = started.elapsed();
+    assert!(received == expected, "echo corrupted");
+    drop(write.await.expect("writer"));
+    elapsed
+}
+
+async fn run(config: TunnelConfig, len: usize, min_bytes_per_sec: f64) {
+    let tunnel = SimTunnel::start(config).await.expect("start tunnel");
+    let elapsed = echo_through(&tunnel, len).await;
+    let throughput = (2 * len) as f64 / elapsed.as_secs_f64().max(1e-3);
+    assert!(
+        throughput >= min_bytes_per_sec,
+        "throughput {:.0} B/s over {:?} ({:?})",
+        throughput,
+        elapsed,
+        tunnel.network.stats()
+    );
+    let code = timeout(SHUTDOWN_TIMEOUT, tunnel.shutdown())
+        .await
+        .expect("server did not shut down")
+        .expect("server error");
+    assert_eq!(code, 0);
+}
+
+fn skip() -> bool {
+    if picoquic_available() {
+        return false;
+    }
+    eprintln!("skipping netsim tunnel test: picoquic is not available");
+    true
+}
+
+#[tokio::test(start_paused = true)]
+async fn delivers_over_a_clean_network() {
+    if skip() {
+        return;
+    }
+    LocalSet::new()
+        .run_until(run(TunnelConfig::default(), 64 * 1024, 8.0 * 1024.0))
+        .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn delivers_through_an_impaired_path() {
+    if skip() {
+        return;
+    }
+    let access = LinkConfig {
+        delay: Duration::from_millis(30),
+        jitter: Duration::from_millis(20),
+        loss: 0.05,
+        reorder: 0.05,
+        duplicate: 0.02,
+    };
+    let config = TunnelConfig {
+        seed: 7,
+        access,
+        upstream: LinkConfig {
+            delay: Duration::from_millis(10),
+            ..LinkConfig::default()
+        },
+        resolver: ResolverConfig {
+            rewrite_ids: true,
+            cache_ttl: Some(Duration::from_secs(30)),
+            max_response_len: None,
+        },
+        ..TunnelConfig::default()
+    };
+    LocalSet::new()
+        .run_until(run(config, 32 * 1024, 1024.0))
+        .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn path_mtu_discovery_avoids_truncation() {
+    if skip() {
+        return;
+    }
+    let config = TunnelConfig {
+        seed: 11,
+        access: LinkConfig {
+            delay: Duration::from_millis(20),
+            ..LinkConfig::default()
+        },
+        resolver: ResolverConfig {
+            max_response_len: Some(512),
+            ..ResolverConfig::default()
+        },
+        path_mtu_discovery: true,
+        ..TunnelConfig::default()
+    };
+    LocalSet::new()
+        .run_until(async {
+            let tunnel = SimTunnel::start(config).await.expect("start tunnel");
+            echo_through(&tunnel, 16 * 1024).await;
+            // Probes above the limit come back truncated; tunnel traffic must not.
+            let truncated = tunnel.resolver.stats().truncated;
+            echo_through(&tunnel, 16 * 1024).await;
+            assert_eq!(tunnel.resolver.stats().truncated, truncated);
+            let code = timeout(SHUTDOWN_TIMEOUT, tunnel.shutdown())
+                .await
+                .expect("server did not shut down")
+                .expect("server error");
+            assert_eq!(code, 0);
+        })
+        .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn serves_diagnostic_discard() {
+    if skip() {
+        return;
+    }
+    LocalSet::new()
+        .run_until(async {
+            let tunnel = SimTunnel::start(TunnelConfig::default())
+                .await
+                .expect("start tunnel");
+            let mut stream = tunnel.connect().await.expect("connect to client");
+            stream
+                .write_all(&DiagnosticRequest::Discard.preface())
+                .await
+                .expect("write preface");
+            stream.write_all(&[7u8; 8192]).await.expect("write");
+            stream.shutdown().await.expect("shutdown");
+            let mut reply = String::new();
+            timeout(TRANSFER_TIMEOUT, stream.read_to_string(&mut reply))
+                .await
+                .expect("discard timed out")
+                .expect("read count");
+            // The preface is stripped before the service counts bytes.
+            assert_eq!(reply, "8192\n");
+            let code = timeout(SHUTDOWN_TIMEOUT, tunnel.shutdown())
+                .await
+                .expect("server did not shut down")
+                .expect("server error");
+            assert_eq!(code, 0);
+        })
+        .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn serves_builtin_chargen_target() {
+    if skip() {
+        return;
+    }
+    let config = TunnelConfig {
+        target: DiagnosticRequest::Chargen { bytes: 10_000 },
+        ..TunnelConfig::default()
+    };
+    LocalSet::new()
+        .run_until(async {
+            let tunnel = SimTunnel::start(config).await.expect("start tunnel");
+            let mut stream = tunnel.connect().await.expect("connect to client");
+            let mut received = Vec::new();
+            timeout(TRANSFER_TIMEOUT, stream.read_to_end(&mut received))
+                .await
+                .expect("chargen timed out")
+                .expect("read chargen");
+            assert_eq!(received.len(), 10_000);
+            assert!(received.starts_with(b" !\"#$%&'()*+,-./0123456789"));
+            assert_eq!(&received[72..76], b"\r\n!\"");
+            drop(stream);
+            let code = timeout(SHUTDOWN_TIMEOUT, tunnel.shutdown())
+                .await
+                .expect("server did not shut down")
+                .expect("server error");
+            assert_eq!(code, 0);
+        })
+        .await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn lazy_client_connects_on_demand_and_reconnects_after_idle() {
+    if skip() {
+        return;
+    }
+    let idle_timeout = Duration::from_secs(5);
+    let config = TunnelConfig {
+        lazy_idle_timeout_secs: Some(idle_timeout.as_secs()),
+        ..TunnelConfig::default()
+    };
+    LocalSet::new()
+        .run_until(async {
+            let tunnel = SimTunnel::start(config).await.expect("start tunnel");
+            // Nothing reaches the resolver until the first TCP connection.
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            assert_eq!(tunnel.resolver.stats().queries, 0);
+            echo_through(&tunnel, 4 * 1024).await;
+            assert!(tunnel.resolver.stats().queries > 0);
+
+            // Without streams the tunnel is torn down and stops polling,
+            // which a live connection never does for this long.
+            tokio::time::sleep(idle_timeout * 2).await;
+            let queries = tunnel.resolver.stats().queries;
+            tokio::time::sleep(Duration::from_secs(120)).await;
+            assert_eq!(tunnel.resolver.stats().queries, queries);
+
+            // The next connection opens a new tunnel.
+            echo_through(&tunnel, 4 * 1024).await;
+            assert!(tunnel.resolver.stats().queries > queries);
+
+            // Connections accepted around the idle deadline, while the
+            // tunnel may be closing, are carried by this or the next tunnel.
+            for offset_ms in [-200i64, -50, 0, 20, 50, 200] {
+                let wait = idle_timeout.as_millis() as i64 + offset_ms;
+                tokio::time::sleep(Duration::from_millis(wait as u64)).await;
+                echo_through(&tunnel, 1024).await;
+            }
+
+            let code = timeout(SHUTDOWN_TIMEOUT, tunnel.shutdown())
+                .await
+                .expect("server did not shut down")
+                .expect("server error");
+            assert_eq!(code, 0);
+        })
+        .await;
+}
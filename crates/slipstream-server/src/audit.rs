@@ -0,0 +1,79 @@
+use serde_json::json;
+use slipstream_core::logging::format_timestamp;
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::net::SocketAddr;
+use std::time::SystemTime;
+use tracing::warn;
+
+/// How a stream ended, as written to `--audit-log`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum CloseReason {
+    /// The target closed and its FIN reached the client.
+    Fin,
+    /// The server reset the stream with this application error code.
+    LocalReset(u64),
+    /// The client reset the stream or asked the server to stop sending.
+    PeerReset(u64),
+    /// The QUIC connection went away with the stream still open.
+    ConnectionClosed,
+}
+
+/// One finished stream.
+pub(crate) struct StreamRecord {
+    pub(crate) started_at: SystemTime,
+    pub(crate) connection: usize,
+    pub(crate) stream_id: u64,
+    pub(crate) source: Option<SocketAddr>,
+    pub(crate) target: String,
+    pub(crate) bytes_from_client: u64,
+    pub(crate) bytes_to_client: u64,
+    pub(crate) reason: CloseReason,
+}
+
+/// Append-only JSON lines file behind `--audit-log`.
+pub(crate) struct AuditLog {
+    file: LineWriter<File>,
+    failed: bool,
+}
+
+impl AuditLog {
+    pub(crate) fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: LineWriter::new(file),
+            failed: false,
+        })
+    }
+
+    pub(crate) fn record(&mut self, record: &StreamRecord) {
+        let (close, reset_code) = match record.reason {
+            CloseReason::Fin => ("fin", None),
+            CloseReason::LocalReset(code) => ("local_reset", Some(code)),
+            CloseReason::PeerReset(code) => ("peer_reset", Some(code)),
+            CloseReason::ConnectionClosed => ("connection_closed", None),
+        };
+        let line = json!({
+            "start": format_timestamp(record.started_at),
+            "end": format_timestamp(SystemTime::now()),
+            "connection": record.connection,
+            "stream_id": record.stream_id,
+            "source": record.source.map(|addr| addr.to_string()),
+            "target": record.target,
+            "bytes_from_client": record.bytes_from_client,
+            "bytes_to_client": record.bytes_to_client,
+            "close": close,
+            "reset_code": reset_code,
+        });
+        match writeln!(self.file, "{}", line) {
+            Ok(()) => self.failed = false,
+            Err(err) => {
+                // Warn once per run of failures rather than once per stream.
+                if !self.failed {
+                    warn!(error = %err, "Failed writing audit log");
+                }
+                self.failed = true;
+            }
+        }
+    }
+}
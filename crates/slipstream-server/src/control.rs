@@ -0,0 +1,113 @@
+use crate::server::{live_connections, StreamKey};
+use crate::streams::ServerState;
+use crate::target::Target;
+use serde_json::{json, Value};
+use slipstream_core::control::ControlRequest;
+use slipstream_ffi::picoquic::{
+    picoquic_get_default_path_quality, picoquic_path_quality_t, picoquic_quic_t,
+};
+use std::time::Instant;
+use tracing::info;
+
+/// Server state a control request may read or change.
+pub(crate) struct ControlContext<'a> {
+    pub(crate) quic: *mut picoquic_quic_t,
+    pub(crate) state: &'a mut ServerState,
+    pub(crate) target: Target,
+    pub(crate) started_at: Instant,
+}
+
+pub(crate) fn handle_control(
+    request: &ControlRequest,
+    ctx: &mut ControlContext<'_>,
+) -> Result<Value, String> {
+    match request.command.as_str() {
+        "status" => Ok(json!({
+            "connections": live_connections(ctx.quic).len(),
+            "streams": ctx.state.streams_len(),
+            "target": ctx.target.to_string(),
+            "uptime_secs": ctx.started_at.elapsed().as_secs(),
+        })),
+        "connections" => {
+            let streams = ctx.state.stream_infos();
+            let connections = live_connections(ctx.quic)
+                .into_iter()
+                .map(|cnx| {
+                    let id = cnx as usize;
+                    let mut quality = picoquic_path_quality_t::default();
+                    unsafe {
+                        picoquic_get_default_path_quality(cnx, &mut quality as *mut _);
+                    }
+                    let own = streams.iter().filter(|info| info.key.cnx == id);
+                    let (count, rx_bytes, tx_bytes) = own
+                        .fold((0usize, 0u64, 0u64), |(count, rx, tx), info| {
+                            (count + 1, rx + info.rx_bytes, tx + info.tx_bytes)
+                        });
+                    json!({
+                        "id": id,
+                        "streams": count,
+                        "rx_bytes": rx_bytes,
+                        "tx_bytes": tx_bytes,
+                        "rtt_us": quality.rtt,
+                        "cwnd": quality.cwin,
+                        "bytes_in_transit": quality.bytes_in_transit,
+                        "packets_sent": quality.sent,
+                        "packets_lost": quality.lost,
+                    })
+                })
+                .collect();
+            Ok(Value::Array(connections))
+        }
+        "streams" => {
+            let streams = ctx
+                .state
+                .stream_infos()
+                .into_iter()
+                .map(|info| {
+                    json!({
+                        "connection": info.key.cnx,
+                        "id": info.key.stream_id,
+                        "state": info.state,
+                        "rx_bytes": info.rx_bytes,
+                        "tx_bytes": info.tx_bytes,
+                        "queued_bytes": info.queued_bytes,
+                        "age_secs": info.age.as_secs(),
+                    })
+                })
+                .collect();
+            Ok(Value::Array(streams))
+        }
+        "close-stream" => {
+            let key = StreamKey {
+                cnx: request.u64_arg("connection")? as usize,
+                stream_id: request.u64_arg("id")?,
+            };
+            if !ctx.state.close_stream(key) {
+                return Err(format!(
+                    "No open stream {} on connection {}",
+                    key.stream_id, key.cnx
+                ));
+            }
+            info!(
+                "Closed stream {} on connection {} from the control socket",
+                key.stream_id, key.cnx
+            );
+            Ok(Value::Null)
+        }
+        "kick" => {
+            let id = request.u64_arg("connection")? as usize;
+            // Only ids of live connections are ever turned back into pointers.
+            let cnx = live_connections(ctx.quic)
+                .into_iter()
+                .find(|cnx| *cnx as usize == id)
+                .ok_or_else(|| format!("No connection {}", id))?;
+            ctx.state.close_connection(cnx);
+            info!("Closed connection {} from the control socket", id);
+            Ok(Value::Null)
+        }
+        other => Err(format!(
+            "Unknown command {}; expected status, connections, streams, close-stream or kick",
+            other
+        )),
+    }
+}
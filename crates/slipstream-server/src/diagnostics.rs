@@ -0,0 +1,174 @@
+use crate::server::{
+    Command, StreamKey, DEFAULT_TCP_RCVBUF_BYTES, STREAM_READ_CHUNK_BYTES,
+    TARGET_WRITE_COALESCE_DEFAULT_BYTES,
+};
+use crate::target::attach_target;
+use slipstream_core::diagnostics::DiagnosticRequest;
+use slipstream_core::rng::SimRng;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{
+    AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream,
+};
+use tokio::sync::{mpsc, watch};
+use tracing::debug;
+
+// Source payload, repeated; random so compression along the path cannot help.
+const SOURCE_PATTERN_BYTES: usize = 64 * 1024;
+// RFC 864: 72 printable characters per line, each line shifted by one.
+const CHARGEN_LINE_CHARS: usize = 72;
+const CHARGEN_PRINTABLE: std::ops::RangeInclusive<u8> = b' '..=b'~';
+const MAX_LATENCY_LINE_BYTES: u64 = 1024;
+
+/// Serves `request` in-process, through the same stream plumbing as a target.
+pub(crate) fn spawn_diagnostic_service(
+    key: StreamKey,
+    request: DiagnosticRequest,
+    command_tx: mpsc::UnboundedSender<Command>,
+    debug_streams: bool,
+    shutdown_rx: watch::Receiver<bool>,
+) {
+    if debug_streams {
+        debug!(stream_id = key.stream_id, service = %request, "diagnostic stream");
+    }
+    let (target, service) = tokio::io::duplex(DEFAULT_TCP_RCVBUF_BYTES);
+    let (read_half, write_half) = tokio::io::split(target);
+    let service_shutdown = shutdown_rx.clone();
+    attach_target(
+        key,
+        read_half,
+        write_half,
+        DEFAULT_TCP_RCVBUF_BYTES / STREAM_READ_CHUNK_BYTES,
+        TARGET_WRITE_COALESCE_DEFAULT_BYTES,
+        command_tx,
+        debug_streams,
+        shutdown_rx,
+    );
+    tokio::spawn(run_service(request, service, service_shutdown));
+}
+
+async fn run_service(
+    request: DiagnosticRequest,
+    stream: DuplexStream,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    let service = async {
+        match request {
+            DiagnosticRequest::Discard => discard(stream).await,
+            DiagnosticRequest::Source { bytes } => source(stream, bytes).await,
+            DiagnosticRequest::Echo => echo(stream).await,
+            DiagnosticRequest::Chargen { bytes } => chargen(stream, bytes).await,
+            DiagnosticRequest::Latency => latency(stream).await,
+        }
+    };
+    tokio::select! {
+        _ = shutdown_rx.wait_for(|shutdown| *shutdown) => {}
+        _ = service => {}
+    }
+}
+
+async fn discard(mut stream: DuplexStream) -> std::io::Result<()> {
+    let mut buf = vec![0u8; STREAM_READ_CHUNK_BYTES];
+    let mut total = 0u64;
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        total = total.saturating_add(n as u64);
+    }
+    stream.write_all(format!("{}\n", total).as_bytes()).await?;
+    stream.shutdown().await
+}
+
+async fn source(mut stream: DuplexStream, limit: u64) -> std::io::Result<()> {
+    let mut rng = SimRng::new(0);
+    let pattern: Vec<u8> = (0..SOURCE_PATTERN_BYTES)
+        .map(|_| rng.next_u64() as u8)
+        .collect();
+    write_repeated(&mut stream, &pattern, limit).await?;
+    stream.shutdown().await
+}
+
+async fn echo(stream: DuplexStream) -> std::io::Result<()> {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    tokio::io::copy(&mut reader, &mut writer).await?;
+    writer.shutdown().await
+}
+
+async fn chargen(stream: DuplexStream, limit: u64) -> std::io::Result<()> {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let pattern = chargen_pattern();
+    let generate = async {
+        write_repeated(&mut writer, &pattern, limit).await?;
+        writer.shutdown().await
+    };
+    // Input is read and dropped, as in RFC 864.
+    let drain = async {
+        let mut buf = vec![0u8; STREAM_READ_CHUNK_BYTES];
+        while reader.read(&mut buf).await? > 0 {}
+        Ok::<(), std::io::Error>(())
+    };
+    let (generated, _) = tokio::join!(generate, drain);
+    generated
+}
+
+/// Writes `pattern` over and over until `limit` bytes are out.
+async fn write_repeated<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    pattern: &[u8],
+    limit: u64,
+) -> std::io::Result<()> {
+    let mut remaining = limit;
+    while remaining > 0 {
+        let len = pattern
+            .len()
+            .min(remaining.try_into().unwrap_or(usize::MAX));
+        writer.write_all(&pattern[..len]).await?;
+        remaining -= len as u64;
+    }
+    Ok(())
+}
+
+/// One full cycle of chargen lines; repeating it continues the pattern.
+fn chargen_pattern() -> Vec<u8> {
+    let printable: Vec<u8> = CHARGEN_PRINTABLE.collect();
+    let mut pattern = Vec::with_capacity(printable.len() * (CHARGEN_LINE_CHARS + 2));
+    for start in 0..printable.len() {
+        for offset in 0..CHARGEN_LINE_CHARS {
+            pattern.push(printable[(start + offset) % printable.len()]);
+        }
+        pattern.extend_from_slice(b"\r\n");
+    }
+    pattern
+}
+
+async fn latency(stream: DuplexStream) -> std::io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let n = (&mut reader)
+            .take(MAX_LATENCY_LINE_BYTES)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if n == 0 {
+            break;
+        }
+        let received_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_micros())
+            .unwrap_or(0);
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        } else if n as u64 == MAX_LATENCY_LINE_BYTES {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "latency probe line too long",
+            ));
+        }
+        line.extend_from_slice(format!(" {}\n", received_us).as_bytes());
+        writer.write_all(&line).await?;
+    }
+    writer.shutdown().await
+}
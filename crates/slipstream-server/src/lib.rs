@@ -0,0 +1,17 @@
+mod audit;
+mod control;
+mod diagnostics;
+mod metrics;
+mod path_mtu;
+mod probes;
+mod replay;
+mod server;
+mod session;
+mod streams;
+mod target;
+mod workers;
+
+pub use replay::{run_replay, ReplayConfig};
+pub use server::{run_server, run_server_with, ServerConfig, ServerError};
+pub use target::TargetAddress;
+pub use workers::run_workers;
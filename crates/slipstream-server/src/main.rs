@@ -1,12 +1,13 @@
-mod server;
-mod streams;
-mod target;
-
-use clap::Parser;
-use server::{run_server, ServerConfig};
-use slipstream_core::{normalize_domain, parse_host_port, AddressKind, HostPort};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use slipstream_core::control::run_control_cli;
+use slipstream_core::logging::{init_logging, LogFormat};
+use slipstream_core::{normalize_domain, parse_host_port, AddressKind};
+use slipstream_server::{
+    run_replay, run_server, run_workers, ReplayConfig, ServerConfig, TargetAddress,
+};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use tokio::runtime::Builder;
-use tracing_subscriber::EnvFilter;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -22,22 +23,117 @@ struct Args {
         default_value = "127.0.0.1:5201",
         value_parser = parse_target_address
     )]
-    target_address: HostPort,
+    target_address: TargetAddress,
     #[arg(long = "cert", short = 'c', value_name = "PATH")]
     cert: String,
     #[arg(long = "key", short = 'k', value_name = "PATH")]
     key: String,
     #[arg(long = "domain", short = 'd', value_parser = parse_domain, required = true)]
     domains: Vec<String>,
+    #[arg(long = "pad-responses", value_name = "BYTES", default_value_t = 0)]
+    pad_responses: u16,
+    #[arg(long = "probe-rate", value_name = "PER_SEC", default_value_t = 100)]
+    probe_rate: u32,
+    #[arg(long = "metrics-listen", value_name = "ADDR")]
+    metrics_listen: Option<SocketAddr>,
+    #[arg(long = "control-socket", value_name = "PATH")]
+    control_socket: Option<String>,
+    #[arg(long = "audit-log", value_name = "FILE")]
+    audit_log: Option<String>,
+    #[arg(long = "qlog-dir", value_name = "DIR")]
+    qlog_dir: Option<String>,
+    #[arg(long = "qlog-max-mb", value_name = "MB", default_value_t = 256)]
+    qlog_max_mb: u64,
+    #[arg(long = "keylog-file", value_name = "FILE")]
+    keylog_file: Option<String>,
+    #[arg(long = "pcap", value_name = "FILE")]
+    pcap: Option<String>,
+    #[arg(long = "pcap-max-mb", value_name = "MB", default_value_t = 0)]
+    pcap_max_mb: u64,
+    #[arg(long = "pcap-files", value_name = "N", default_value_t = 0)]
+    pcap_files: u64,
+    #[arg(long = "record", value_name = "FILE")]
+    record: Option<String>,
+    #[arg(long = "seed", value_name = "N")]
+    seed: Option<u64>,
+    #[arg(
+        long = "workers",
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..=256)
+    )]
+    workers: u16,
+    #[arg(long = "enable-diagnostics")]
+    enable_diagnostics: bool,
+    #[arg(long = "log-format", value_name = "FORMAT", default_value = "text")]
+    log_format: LogFormat,
     #[arg(long = "debug-streams")]
     debug_streams: bool,
     #[arg(long = "debug-commands")]
     debug_commands: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Send a command to a running server's --control-socket
+    Ctl(CtlArgs),
+    /// Replay a --record file against a fresh server on a virtual clock
+    Replay(ReplayArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct CtlArgs {
+    #[arg(long = "socket", value_name = "PATH")]
+    socket: PathBuf,
+    /// status, connections, streams, close-stream or kick
+    command: String,
+    #[arg(value_name = "KEY=VALUE")]
+    args: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReplayArgs {
+    #[arg(long = "session", value_name = "FILE")]
+    session: String,
+    #[arg(
+        long = "target-address",
+        short = 'a',
+        default_value = "127.0.0.1:5201",
+        value_parser = parse_target_address
+    )]
+    target_address: TargetAddress,
+    #[arg(long = "cert", short = 'c', value_name = "PATH")]
+    cert: String,
+    #[arg(long = "key", short = 'k', value_name = "PATH")]
+    key: String,
+    #[arg(long = "domain", short = 'd', value_parser = parse_domain, required = true)]
+    domains: Vec<String>,
+    #[arg(long = "pad-responses", value_name = "BYTES", default_value_t = 0)]
+    pad_responses: u16,
+    #[arg(long = "seed", default_value_t = 0)]
+    seed: u64,
+}
+
+fn cli() -> clap::Command {
+    Command::augment_subcommands(Args::command())
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+}
+
 fn main() {
-    init_logging();
-    let args = Args::parse();
+    let matches = cli().get_matches();
+    if matches.subcommand().is_some() {
+        init_logging(LogFormat::Text);
+        let command = Command::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+        match command {
+            Command::Ctl(args) => {
+                std::process::exit(run_control_cli(&args.socket, &args.command, &args.args))
+            }
+            Command::Replay(args) => std::process::exit(replay_cli(args)),
+        }
+    }
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    init_logging(args.log_format);
 
     let config = ServerConfig {
         dns_listen_port: args.dns_listen_port,
@@ -45,16 +141,37 @@ fn main() {
         cert: args.cert,
         key: args.key,
         domains: args.domains,
+        pad_responses: args.pad_responses,
+        probe_rate: args.probe_rate,
+        metrics_listen: args.metrics_listen,
+        control_socket: args.control_socket,
+        audit_log: args.audit_log,
+        qlog_dir: args.qlog_dir,
+        qlog_max_mb: args.qlog_max_mb,
+        keylog_file: args.keylog_file,
+        pcap: args.pcap,
+        pcap_max_mb: args.pcap_max_mb,
+        pcap_files: args.pcap_files,
+        record: args.record,
+        seed: args.seed,
+        workers: args.workers as usize,
+        enable_diagnostics: args.enable_diagnostics,
         debug_streams: args.debug_streams,
         debug_commands: args.debug_commands,
     };
 
-    let runtime = Builder::new_current_thread()
-        .enable_io()
-        .enable_time()
-        .build()
-        .expect("Failed to build Tokio runtime");
-    match runtime.block_on(run_server(&config)) {
+    let result = if config.workers > 1 {
+        // Each worker builds a runtime of its own; this thread only waits.
+        run_workers(&config)
+    } else {
+        let runtime = Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .expect("Failed to build Tokio runtime");
+        runtime.block_on(run_server(&config))
+    };
+    match result {
         Ok(code) => std::process::exit(code),
         Err(err) => {
             tracing::error!("Server error: {}", err);
@@ -63,19 +180,48 @@ fn main() {
     }
 }
 
-fn init_logging() {
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .without_time()
-        .try_init();
+fn replay_cli(args: ReplayArgs) -> i32 {
+    let config = ReplayConfig {
+        session: args.session,
+        target_address: args.target_address,
+        cert: args.cert,
+        key: args.key,
+        domains: args.domains,
+        pad_responses: args.pad_responses,
+        seed: args.seed,
+    };
+    let runtime = Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .expect("Failed to build Tokio runtime");
+    match runtime.block_on(run_replay(&config)) {
+        Ok(report) => {
+            let pretty =
+                serde_json::to_string_pretty(&report).unwrap_or_else(|_| report.to_string());
+            println!("{}", pretty);
+            0
+        }
+        Err(err) => {
+            eprintln!("Replay failed: {}", err);
+            1
+        }
+    }
 }
 
 fn parse_domain(input: &str) -> Result<String, String> {
     normalize_domain(input).map_err(|err| err.to_string())
 }
 
-fn parse_target_address(input: &str) -> Result<HostPort, String> {
-    parse_host_port(input, 5201, AddressKind::Target).map_err(|err| err.to_string())
+fn parse_target_address(input: &str) -> Result<TargetAddress, String> {
+    // Built-in service names take precedence over host names.
+    match input.parse() {
+        Ok(request) => return Ok(TargetAddress::Builtin(request)),
+        // The services that generate data stay reserved without their size.
+        Err(err) if matches!(input, "chargen" | "source") => return Err(err),
+        Err(_) => {}
+    }
+    parse_host_port(input, 5201, AddressKind::Target)
+        .map(TargetAddress::Remote)
+        .map_err(|err| err.to_string())
 }
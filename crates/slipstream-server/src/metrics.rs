@@ -0,0 +1,138 @@
+use slipstream_core::metrics::{Histogram, MetricKind, MetricsEncoder};
+use slipstream_dns::Rcode;
+
+// Slot latency buckets in seconds, from decode to response send.
+const SLOT_LATENCY_BOUNDS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1,
+];
+const RCODES: [Rcode; 4] = [
+    Rcode::Ok,
+    Rcode::FormatError,
+    Rcode::ServerFailure,
+    Rcode::NameError,
+];
+
+/// Server counters behind `--metrics-listen`.
+#[derive(Clone)]
+pub(crate) struct ServerMetrics {
+    responses_by_rcode: [u64; RCODES.len()],
+    pub(crate) streams_opened: u64,
+    pub(crate) target_connect_failures: u64,
+    pub(crate) bytes_to_target: u64,
+    pub(crate) bytes_from_target: u64,
+    slot_latency: Histogram,
+}
+
+impl ServerMetrics {
+    pub(crate) fn new() -> Self {
+        Self {
+            responses_by_rcode: [0; RCODES.len()],
+            streams_opened: 0,
+            target_connect_failures: 0,
+            bytes_to_target: 0,
+            bytes_from_target: 0,
+            slot_latency: Histogram::new(&SLOT_LATENCY_BOUNDS),
+        }
+    }
+
+    /// Counts one answered query and how long its slot waited for the response.
+    pub(crate) fn record_response(&mut self, rcode: Rcode, latency_us: u64) {
+        let index = RCODES.iter().position(|known| *known == rcode).unwrap_or(0);
+        self.responses_by_rcode[index] = self.responses_by_rcode[index].saturating_add(1);
+        self.slot_latency.observe(latency_us as f64 / 1_000_000.0);
+    }
+
+    /// Adds another worker's counters to these.
+    pub(crate) fn merge(&mut self, other: &ServerMetrics) {
+        for (count, other) in self
+            .responses_by_rcode
+            .iter_mut()
+            .zip(other.responses_by_rcode)
+        {
+            *count = count.saturating_add(other);
+        }
+        self.streams_opened = self.streams_opened.saturating_add(other.streams_opened);
+        self.target_connect_failures = self
+            .target_connect_failures
+            .saturating_add(other.target_connect_failures);
+        self.bytes_to_target = self.bytes_to_target.saturating_add(other.bytes_to_target);
+        self.bytes_from_target = self
+            .bytes_from_target
+            .saturating_add(other.bytes_from_target);
+        self.slot_latency.merge(&other.slot_latency);
+    }
+
+    pub(crate) fn render(&self, connections: usize, streams: usize) -> String {
+        let mut encoder = MetricsEncoder::new();
+        encoder.family(
+            "slipstream_server_queries",
+            MetricKind::Counter,
+            "DNS queries answered, by response code.",
+        );
+        for (rcode, count) in RCODES.iter().zip(self.responses_by_rcode) {
+            encoder.counter(
+                "slipstream_server_queries",
+                &[("rcode", rcode_label(*rcode))],
+                count,
+            );
+        }
+        encoder.family(
+            "slipstream_server_connections",
+            MetricKind::Gauge,
+            "Open QUIC connections.",
+        );
+        encoder.gauge("slipstream_server_connections", &[], connections as f64);
+        encoder.family(
+            "slipstream_server_streams",
+            MetricKind::Gauge,
+            "Open QUIC streams.",
+        );
+        encoder.gauge("slipstream_server_streams", &[], streams as f64);
+        let counters = [
+            (
+                "slipstream_server_streams_opened",
+                "QUIC streams opened by clients.",
+                self.streams_opened,
+            ),
+            (
+                "slipstream_server_target_connect_failures",
+                "Streams reset because the target connection failed.",
+                self.target_connect_failures,
+            ),
+            (
+                "slipstream_server_target_sent_bytes",
+                "Stream bytes written to the target.",
+                self.bytes_to_target,
+            ),
+            (
+                "slipstream_server_target_received_bytes",
+                "Stream bytes read from the target.",
+                self.bytes_from_target,
+            ),
+        ];
+        for (name, help, value) in counters {
+            encoder.family(name, MetricKind::Counter, help);
+            encoder.counter(name, &[], value);
+        }
+        encoder.family(
+            "slipstream_server_slot_latency_seconds",
+            MetricKind::Histogram,
+            "Time from decoding a query to sending its response.",
+        );
+        encoder.histogram(
+            "slipstream_server_slot_latency_seconds",
+            &[],
+            &self.slot_latency,
+        );
+        encoder.finish()
+    }
+}
+
+fn rcode_label(rcode: Rcode) -> &'static str {
+    match rcode {
+        Rcode::Ok => "NOERROR",
+        Rcode::FormatError => "FORMERR",
+        Rcode::ServerFailure => "SERVFAIL",
+        Rcode::NameError => "NXDOMAIN",
+    }
+}
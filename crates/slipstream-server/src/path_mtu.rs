@@ -0,0 +1,117 @@
+use slipstream_dns::{max_response_payload_len, MAX_UDP_RESPONSE_LEN, PROBE_CNX_ID_LEN};
+use slipstream_ffi::picoquic::{picoquic_cnx_t, PICOQUIC_MAX_PACKET_SIZE};
+use std::collections::HashMap;
+
+// Bounds for response sizes reported by client MTU discovery; see docs/config.md for details.
+const MIN_REPORTED_RESPONSE_LEN: usize = 512;
+const MAX_PENDING_REPORTS: usize = 1024;
+const PENDING_REPORT_TTL_US: u64 = 10_000_000;
+const MAX_TRACKED_PATHS: usize = 4096;
+
+type CnxId = [u8; PROBE_CNX_ID_LEN];
+
+struct Limit {
+    response_len: usize,
+    updated_at: u64,
+}
+
+/// Largest DNS response each QUIC path's resolver passes through, as reported by clients.
+///
+/// A report names the connection ID the client sends on the path. It waits until a
+/// QUIC packet with that ID is routed, then applies to that connection and path only,
+/// so one client cannot change how others sharing its resolver are answered.
+pub(crate) struct DownstreamLimits {
+    pending: HashMap<CnxId, Limit>,
+    limits: HashMap<(usize, libc::c_int), Limit>,
+}
+
+impl DownstreamLimits {
+    pub(crate) fn new() -> Self {
+        Self {
+            pending: HashMap::new(),
+            limits: HashMap::new(),
+        }
+    }
+
+    /// Holds a reported limit until a packet for `cnx_id` is routed.
+    pub(crate) fn record(&mut self, cnx_id: CnxId, response_len: usize, now: u64) {
+        if response_len < MIN_REPORTED_RESPONSE_LEN {
+            return;
+        }
+        self.pending
+            .retain(|_, limit| now.saturating_sub(limit.updated_at) < PENDING_REPORT_TTL_US);
+        let limit = Limit {
+            response_len: response_len.min(MAX_UDP_RESPONSE_LEN),
+            updated_at: now,
+        };
+        insert_bounded(&mut self.pending, cnx_id, limit, MAX_PENDING_REPORTS);
+    }
+
+    /// Applies a pending report to the path `packet` was just routed to.
+    pub(crate) fn bind(&mut self, packet: &[u8], cnx: *mut picoquic_cnx_t, path_id: libc::c_int) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let Some(cnx_id) = short_header_dcid(packet) else {
+            return;
+        };
+        if let Some(limit) = self.pending.remove(&cnx_id) {
+            let key = (cnx as usize, path_id);
+            insert_bounded(&mut self.limits, key, limit, MAX_TRACKED_PATHS);
+        }
+    }
+
+    /// Drops the limits of a closed connection.
+    pub(crate) fn forget_connection(&mut self, cnx: usize) {
+        self.limits.retain(|(limit_cnx, _), _| *limit_cnx != cnx);
+    }
+
+    /// Largest DNS response reported for the path.
+    pub(crate) fn response_len(
+        &self,
+        cnx: *mut picoquic_cnx_t,
+        path_id: libc::c_int,
+    ) -> Option<usize> {
+        self.limits
+            .get(&(cnx as usize, path_id))
+            .map(|limit| limit.response_len)
+    }
+
+    /// Send MTU for answering `qname` on the path, if its client reported a limit.
+    pub(crate) fn path_mtu(
+        &self,
+        cnx: *mut picoquic_cnx_t,
+        path_id: libc::c_int,
+        qname: &str,
+    ) -> Option<u32> {
+        let response_len = self.response_len(cnx, path_id)?;
+        let mtu = max_response_payload_len(response_len, qname).min(PICOQUIC_MAX_PACKET_SIZE);
+        (mtu > 0).then_some(mtu as u32)
+    }
+}
+
+fn insert_bounded<K: Copy + Eq + std::hash::Hash>(
+    map: &mut HashMap<K, Limit>,
+    key: K,
+    limit: Limit,
+    max_len: usize,
+) {
+    if map.len() >= max_len && !map.contains_key(&key) {
+        if let Some(oldest) = map
+            .iter()
+            .min_by_key(|(_, limit)| limit.updated_at)
+            .map(|(key, _)| *key)
+        {
+            map.remove(&oldest);
+        }
+    }
+    map.insert(key, limit);
+}
+
+/// Destination connection ID of a short-header QUIC packet.
+fn short_header_dcid(packet: &[u8]) -> Option<CnxId> {
+    if packet.first()? & 0x80 != 0 {
+        return None;
+    }
+    packet.get(1..1 + PROBE_CNX_ID_LEN)?.try_into().ok()
+}
@@ -0,0 +1,36 @@
+/// Token bucket for probe replies, which answer without QUIC and so without any
+/// proof that the source address is real. Holds up to one second of replies.
+///
+/// Times are in microseconds and only need to be monotonic.
+pub(crate) struct ProbeBudget {
+    per_sec: u32,
+    tokens: f64,
+    updated_at: u64,
+}
+
+impl ProbeBudget {
+    /// A budget of `per_sec` replies a second; `0` refuses every probe.
+    pub(crate) fn new(per_sec: u32) -> Self {
+        Self {
+            per_sec,
+            tokens: f64::from(per_sec),
+            updated_at: 0,
+        }
+    }
+
+    /// Takes one reply from the budget, or returns false when it is spent.
+    pub(crate) fn take(&mut self, now: u64) -> bool {
+        if self.per_sec == 0 {
+            return false;
+        }
+        let elapsed = now.saturating_sub(self.updated_at);
+        self.updated_at = self.updated_at.max(now);
+        let per_sec = f64::from(self.per_sec);
+        self.tokens = (self.tokens + elapsed as f64 * per_sec / 1_000_000.0).min(per_sec);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
@@ -0,0 +1,142 @@
+use serde_json::{json, Value};
+use slipstream_ffi::clock::{ClockSource, QuicClock};
+use slipstream_ffi::picoquic::{picoquic_get_cnx_state, PICOQUIC_MAX_PACKET_SIZE};
+use slipstream_ffi::socket_addr_to_storage;
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use tokio::sync::mpsc;
+
+use crate::path_mtu::DownstreamLimits;
+use crate::probes::ProbeBudget;
+use crate::server::{
+    create_server_quic, decode_slot, forget_closed_connections, live_connections, respond,
+    seed_randomness, segment_reassembler, ServerError,
+};
+use crate::session::read_session;
+use crate::streams::{drain_commands, ServerState};
+use crate::target::TargetAddress;
+
+pub struct ReplayConfig {
+    pub session: String,
+    pub target_address: TargetAddress,
+    pub cert: String,
+    pub key: String,
+    pub domains: Vec<String>,
+    pub pad_responses: u16,
+    pub seed: u64,
+}
+
+/// Feeds a `--record` file to a fresh server on a virtual clock and reports
+/// how each query was answered and the connections left at the end.
+///
+/// Each query is answered before the next is fed in, at its recorded time.
+/// With the `--seed` the recording server ran with, the server picks the same
+/// keys, so the recorded client packets decrypt past the handshake.
+pub async fn run_replay(config: &ReplayConfig) -> Result<Value, ServerError> {
+    let queries = read_session(&config.session).map_err(ServerError::new)?;
+    let target = config.target_address.resolve()?;
+    let domains: Vec<&str> = config.domains.iter().map(String::as_str).collect();
+    let mut reassembler = segment_reassembler();
+    let mut downstream_limits = DownstreamLimits::new();
+    // Recorded probes were already admitted by the live server.
+    let mut probe_budget = ProbeBudget::new(u32::MAX);
+
+    let (command_tx, mut command_rx) = mpsc::unbounded_channel();
+    let mut state = Box::new(ServerState::new(
+        target, command_tx, false, false, false, None,
+    ));
+    let state_ptr: *mut ServerState = &mut *state;
+    let _state = state;
+    seed_randomness(config.seed)?;
+    let clock = QuicClock::new(ClockSource::Manual);
+    let start = clock.now();
+    let (_quic_guard, quic) = create_server_quic(
+        &config.cert,
+        &config.key,
+        state_ptr,
+        start,
+        clock.simulated_time_ptr(),
+        std::ptr::null(),
+    )?;
+    let local_addr = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 53, 0, 0));
+    let local_addr_storage = socket_addr_to_storage(local_addr);
+    let mut send_buf = vec![0u8; PICOQUIC_MAX_PACKET_SIZE];
+
+    let mut responses = Vec::with_capacity(queries.len());
+    for (index, query) in queries.iter().enumerate() {
+        clock.advance_to(start + query.at_us);
+        let now = clock.now();
+        // Let stream tasks spawned by earlier packets run before the next one.
+        tokio::task::yield_now().await;
+        drain_commands(state_ptr, &mut command_rx);
+        forget_closed_connections(state_ptr, &mut downstream_limits);
+        reassembler.expire(now);
+        // Like the server, a query that fails to decode or answer goes unanswered.
+        let response = decode_slot(
+            &query.packet,
+            query.peer,
+            &domains,
+            &mut reassembler,
+            &mut downstream_limits,
+            &mut probe_budget,
+            quic,
+            now,
+            &local_addr_storage,
+            None,
+        )
+        .and_then(|slot| {
+            slot.map(|slot| {
+                respond(
+                    &slot,
+                    &mut send_buf,
+                    now,
+                    &downstream_limits,
+                    config.pad_responses,
+                )
+            })
+            .transpose()
+        });
+        let response = match response {
+            Ok(Some(response)) => response,
+            Ok(None) => {
+                responses.push(json!({
+                    "index": index,
+                    "at_us": query.at_us,
+                    "answered": false,
+                }));
+                continue;
+            }
+            Err(err) => {
+                responses.push(json!({
+                    "index": index,
+                    "at_us": query.at_us,
+                    "answered": false,
+                    "error": err.to_string(),
+                }));
+                continue;
+            }
+        };
+        responses.push(json!({
+            "index": index,
+            "at_us": query.at_us,
+            "answered": true,
+            "rcode": response.rcode.to_u8(),
+            "payload_len": response.payload_len,
+            "response_len": response.packet.len(),
+        }));
+    }
+    drain_commands(state_ptr, &mut command_rx);
+
+    let connections: Vec<Value> = live_connections(quic)
+        .into_iter()
+        .map(|cnx| {
+            let state = format!("{:?}", unsafe { picoquic_get_cnx_state(cnx) });
+            Value::String(state.trim_start_matches("picoquic_state_").to_string())
+        })
+        .collect();
+    Ok(json!({
+        "queries": queries.len(),
+        "responses": responses,
+        "connections": connections,
+        "streams": unsafe { &*state_ptr }.streams_len(),
+    }))
+}
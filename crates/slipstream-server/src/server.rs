@@ -1,39 +1,64 @@
-use slipstream_core::{resolve_host_port, HostPort};
+use slipstream_core::control::ControlHandle;
+use slipstream_core::metrics::serve_metrics;
+use slipstream_core::pcap::{CapturedUdpSocket, PcapConfig, PcapSink};
+use slipstream_core::qlog::spawn_qlog_pruner;
+use slipstream_core::transport::DnsTransport;
+use slipstream_core::udp_batch::{RecvBatch, SendBatch};
 use slipstream_dns::{
-    decode_query_with_domains, encode_response, DecodeQueryError, Question, Rcode, ResponseParams,
+    decode_query_with_domains, encode_response_padded, max_response_payload_len, DecodeQueryError,
+    ProbeKind, ProbeReply, Question, Rcode, ResponseParams, SegmentHeader, SegmentReassembler,
+    EDNS_UDP_PAYLOAD, PROBE_CNX_ID_LEN,
 };
+use slipstream_ffi::clock::{ClockSource, QuicClock};
 use slipstream_ffi::picoquic::{
-    picoquic_cnx_t, picoquic_create, picoquic_current_time, picoquic_incoming_packet_ex,
-    picoquic_prepare_packet_ex, picoquic_quic_t, slipstream_disable_ack_delay,
-    slipstream_server_cc_algorithm, PICOQUIC_MAX_PACKET_SIZE, PICOQUIC_PACKET_LOOP_RECV_MAX,
+    picoquic_cnx_t, picoquic_create, picoquic_get_first_cnx, picoquic_get_next_cnx,
+    picoquic_incoming_packet_ex, picoquic_prepare_packet_ex, picoquic_public_random_seed_64,
+    picoquic_quic_t, slipstream_disable_ack_delay, slipstream_seed_tls_random,
+    slipstream_server_cc_algorithm, slipstream_set_path_mtu, PICOQUIC_MAX_PACKET_SIZE,
+    PICOQUIC_PACKET_LOOP_RECV_MAX,
+};
+use slipstream_ffi::{
+    configure_quic_tracing, configure_quic_with_custom, socket_addr_to_storage, QuicGuard,
 };
-use slipstream_ffi::{configure_quic_with_custom, socket_addr_to_storage, QuicGuard};
 use std::ffi::CString;
 use std::fmt;
 use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::net::UdpSocket as TokioUdpSocket;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener as TokioTcpListener, UdpSocket as TokioUdpSocket};
 use tokio::sync::mpsc;
 use tokio::time::sleep;
 
+use crate::audit::AuditLog;
+use crate::control::{handle_control, ControlContext};
+use crate::path_mtu::DownstreamLimits;
+use crate::probes::ProbeBudget;
+use crate::session::SessionRecorder;
 use crate::streams::{
     drain_commands, handle_command, handle_shutdown, maybe_report_command_stats, server_callback,
     ServerState,
 };
+use crate::target::TargetAddress;
+use crate::workers::{prefix_cnx_id, Handoff, Worker};
 
 // Protocol defaults; see docs/config.md for details.
 const SLIPSTREAM_ALPN: &str = "picoquic_sample";
 const DNS_MAX_QUERY_SIZE: usize = 512;
 const IDLE_SLEEP_MS: u64 = 10;
+const METRICS_PUBLISH_INTERVAL_US: u64 = 1_000_000;
 // Default QUIC MTU for server packets; see docs/config.md for details.
 const QUIC_MTU: u32 = 900;
+// Limits for reassembling QUIC packets the client split across several queries.
+const SEGMENT_REASSEMBLY_TIMEOUT_US: u64 = 2_000_000;
+const SEGMENT_REASSEMBLY_MAX_BYTES: usize = 4 * 1024 * 1024;
+const SEGMENT_REASSEMBLY_MAX_PACKETS: usize = 4096;
 pub(crate) const STREAM_READ_CHUNK_BYTES: usize = 4096;
 pub(crate) const DEFAULT_TCP_RCVBUF_BYTES: usize = 256 * 1024;
 pub(crate) const TARGET_WRITE_COALESCE_DEFAULT_BYTES: usize = 256 * 1024;
 
-static SHOULD_SHUTDOWN: AtomicBool = AtomicBool::new(false);
+pub(crate) static SHOULD_SHUTDOWN: AtomicBool = AtomicBool::new(false);
 
 extern "C" fn handle_sigterm(_signum: libc::c_int) {
     SHOULD_SHUTDOWN.store(true, Ordering::Relaxed);
@@ -45,7 +70,7 @@ pub struct ServerError {
 }
 
 impl ServerError {
-    fn new(message: impl Into<String>) -> Self {
+    pub(crate) fn new(message: impl Into<String>) -> Self {
         Self {
             message: message.into(),
         }
@@ -62,10 +87,29 @@ impl std::error::Error for ServerError {}
 
 pub struct ServerConfig {
     pub dns_listen_port: u16,
-    pub target_address: HostPort,
+    pub target_address: TargetAddress,
     pub cert: String,
     pub key: String,
     pub domains: Vec<String>,
+    pub pad_responses: u16,
+    /// Probe replies per second and worker; `0` ignores probes.
+    pub probe_rate: u32,
+    pub metrics_listen: Option<SocketAddr>,
+    pub control_socket: Option<String>,
+    pub audit_log: Option<String>,
+    pub qlog_dir: Option<String>,
+    pub qlog_max_mb: u64,
+    pub keylog_file: Option<String>,
+    pub pcap: Option<String>,
+    pub pcap_max_mb: u64,
+    pub pcap_files: u64,
+    pub record: Option<String>,
+    /// Seeds every random choice of the QUIC and TLS stack, so a recorded
+    /// session can be replayed with the same keys. Makes keys predictable.
+    pub seed: Option<u64>,
+    /// Event loops sharing the DNS port; see [`run_server`].
+    pub workers: usize,
+    pub enable_diagnostics: bool,
     pub debug_streams: bool,
     pub debug_commands: bool,
 }
@@ -117,91 +161,200 @@ pub(crate) enum Command {
     },
 }
 
-struct Slot {
+pub(crate) struct Slot {
     peer: SocketAddr,
     id: u16,
     rd: bool,
     cd: bool,
     question: Question,
     rcode: Option<Rcode>,
+    // Answered directly without QUIC (doctor probes).
+    reply: Option<Vec<u8>>,
     cnx: *mut picoquic_cnx_t,
     path_id: libc::c_int,
+    // Send MTU from the downstream limit the client reported for this resolver.
+    path_mtu: Option<u32>,
+    // When the query was decoded, for the slot latency metric.
+    received_at: u64,
 }
 
+/// Binds the DNS port and serves until SIGTERM.
+///
+/// Serves with one event loop; [`run_workers`] runs several.
+///
+/// [`run_workers`]: crate::run_workers
 pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
-    let target_addr = resolve_host_port(&config.target_address)
-        .map_err(|err| ServerError::new(err.to_string()))?;
+    if config.workers > 1 {
+        return Err(ServerError::new(
+            "More than one worker needs run_workers, outside any runtime",
+        ));
+    }
+    let pcap = match config.pcap.as_deref() {
+        Some(path) => {
+            let sink = PcapSink::open(PcapConfig {
+                path: path.into(),
+                max_bytes: config.pcap_max_mb.saturating_mul(1024 * 1024),
+                max_files: config.pcap_files,
+            })
+            .map_err(|err| ServerError::new(format!("pcap {}: {}", path, err)))?;
+            tracing::info!("Capturing DNS traffic to {}", path);
+            Some(sink)
+        }
+        None => None,
+    };
+    let udp = CapturedUdpSocket::new(bind_udp_socket(config.dns_listen_port).await?, pcap)
+        .map_err(map_io)?;
+
+    install_sigterm_handler();
+
+    run_server_with(config, udp, ClockSource::System, &SHOULD_SHUTDOWN).await
+}
+
+// Newer compilers lint the direct cast of the handler; older ones do not know the lint.
+#[allow(unknown_lints, function_casts_as_integer)]
+pub(crate) fn install_sigterm_handler() {
+    unsafe {
+        libc::signal(libc::SIGTERM, handle_sigterm as usize);
+    }
+}
+
+/// Runs the server loop, answering queries that arrive on `transport`.
+///
+/// `config.dns_listen_port` and the `--pcap` options are ignored. The loop
+/// starts a graceful shutdown once `shutdown` is set.
+pub async fn run_server_with<T: DnsTransport>(
+    config: &ServerConfig,
+    transport: T,
+    clock: ClockSource,
+    shutdown: &AtomicBool,
+) -> Result<i32, ServerError> {
+    serve(config, transport, clock, shutdown, None).await
+}
+
+/// The event loop behind [`run_server_with`]; with `worker` set it is one of
+/// several, and hands queries for other workers' connections over to them.
+pub(crate) async fn serve<T: DnsTransport>(
+    config: &ServerConfig,
+    transport: T,
+    clock: ClockSource,
+    shutdown: &AtomicBool,
+    mut worker: Option<Worker>,
+) -> Result<i32, ServerError> {
+    let target = config.target_address.resolve()?;
+    let mut reassembler = segment_reassembler();
+    let mut downstream_limits = DownstreamLimits::new();
+    let mut probe_budget = ProbeBudget::new(config.probe_rate);
+    // Worker 0 stands in for the whole server where only one may act.
+    let leader = worker.as_ref().is_none_or(|worker| worker.index == 0);
 
-    let alpn = CString::new(SLIPSTREAM_ALPN)
-        .map_err(|_| ServerError::new("ALPN contains an unexpected null byte"))?;
-    let cert = CString::new(config.cert.clone())
-        .map_err(|_| ServerError::new("Cert path contains an unexpected null byte"))?;
-    let key = CString::new(config.key.clone())
-        .map_err(|_| ServerError::new("Key path contains an unexpected null byte"))?;
     let (command_tx, mut command_rx) = mpsc::unbounded_channel();
     let debug_streams = config.debug_streams;
     let debug_commands = config.debug_commands;
+    let audit = match config.audit_log.as_deref() {
+        Some(path) => Some(
+            AuditLog::open(path)
+                .map_err(|err| ServerError::new(format!("Audit log {}: {}", path, err)))?,
+        ),
+        None => None,
+    };
     let mut state = Box::new(ServerState::new(
-        target_addr,
+        target,
         command_tx,
+        config.enable_diagnostics,
         debug_streams,
         debug_commands,
+        audit,
     ));
     let state_ptr: *mut ServerState = &mut *state;
     let _state = state;
 
-    let current_time = unsafe { picoquic_current_time() };
-    let quic = unsafe {
-        picoquic_create(
-            8,
-            cert.as_ptr(),
-            key.as_ptr(),
-            std::ptr::null(),
-            alpn.as_ptr(),
-            Some(server_callback),
-            state_ptr as *mut _,
-            None,
-            std::ptr::null_mut(),
-            std::ptr::null(),
-            current_time,
-            std::ptr::null_mut(),
-            std::ptr::null(),
-            std::ptr::null(),
-            0,
-        )
-    };
-    if quic.is_null() {
-        return Err(ServerError::new("Could not create QUIC context"));
+    if let Some(seed) = config.seed {
+        tracing::warn!(
+            "Seeding QUIC and TLS randomness with {}; keys are predictable",
+            seed
+        );
+        seed_randomness(seed)?;
     }
-    let _quic_guard = QuicGuard::new(quic);
+    let clock = QuicClock::new(clock);
+    let (_quic_guard, quic) = create_server_quic(
+        &config.cert,
+        &config.key,
+        state_ptr,
+        clock.now(),
+        clock.simulated_time_ptr(),
+        worker
+            .as_ref()
+            .map_or(std::ptr::null(), Worker::cnx_id_prefix),
+    )?;
     unsafe {
-        if slipstream_server_cc_algorithm.is_null() {
-            return Err(ServerError::new(
-                "Slipstream server congestion algorithm is unavailable",
-            ));
+        configure_quic_tracing(
+            quic,
+            config.qlog_dir.as_deref(),
+            config.keylog_file.as_deref(),
+        )
+        .map_err(ServerError::new)?;
+    }
+    if let Some(dir) = config.qlog_dir.as_deref() {
+        if leader {
+            spawn_qlog_pruner(dir.into(), config.qlog_max_mb.saturating_mul(1024 * 1024));
+            tracing::info!("Writing qlog traces to {}", dir);
         }
-        configure_quic_with_custom(quic, slipstream_server_cc_algorithm, QUIC_MTU);
     }
 
-    let udp = bind_udp_socket(config.dns_listen_port).await?;
-    let local_addr_storage = socket_addr_to_storage(udp.local_addr().map_err(map_io)?);
-    warn_overlapping_domains(&config.domains);
+    let mut recorder = match config.record.as_deref() {
+        Some(path) => {
+            let recorder = SessionRecorder::create(path)
+                .map_err(|err| ServerError::new(format!("Session recording {}: {}", path, err)))?;
+            tracing::info!("Recording inbound queries to {}", path);
+            Some(recorder)
+        }
+        None => None,
+    };
+    let local_addr_storage = socket_addr_to_storage(transport.local_addr().map_err(map_io)?);
+    if leader {
+        warn_overlapping_domains(&config.domains);
+    }
     let domains: Vec<&str> = config.domains.iter().map(String::as_str).collect();
     if domains.is_empty() {
         return Err(ServerError::new("At least one domain must be configured"));
     }
 
-    unsafe {
-        libc::signal(libc::SIGTERM, handle_sigterm as usize);
-    }
+    let metrics = match config.metrics_listen {
+        Some(addr) => {
+            let snapshot = worker
+                .as_ref()
+                .and_then(Worker::metrics_snapshot)
+                .unwrap_or_default();
+            if leader {
+                let listener = TokioTcpListener::bind(addr).await.map_err(map_io)?;
+                tokio::spawn(serve_metrics(listener, snapshot.clone()));
+                tracing::info!("Serving metrics on http://{}/metrics", addr);
+            }
+            Some(snapshot)
+        }
+        None => None,
+    };
+    let mut last_metrics_at = 0u64;
+    let mut control = match config.control_socket.as_deref() {
+        Some(path) => {
+            let control = ControlHandle::bind(Path::new(path))
+                .map_err(|err| ServerError::new(format!("Control socket {}: {}", path, err)))?;
+            tracing::info!("Control socket listening on {}", path);
+            control
+        }
+        None => ControlHandle::disabled(),
+    };
+    let started_at = Instant::now();
 
-    let mut recv_buf = vec![0u8; DNS_MAX_QUERY_SIZE];
+    let mut recv_batch = RecvBatch::new(PICOQUIC_PACKET_LOOP_RECV_MAX, DNS_MAX_QUERY_SIZE);
+    let mut send_batch = SendBatch::new();
     let mut send_buf = vec![0u8; PICOQUIC_MAX_PACKET_SIZE];
 
     loop {
         drain_commands(state_ptr, &mut command_rx);
+        forget_closed_connections(state_ptr, &mut downstream_limits);
 
-        if SHOULD_SHUTDOWN.load(Ordering::Relaxed) {
+        if shutdown.load(Ordering::Relaxed) {
             let state = unsafe { &mut *state_ptr };
             if handle_shutdown(quic, state) {
                 break;
@@ -216,174 +369,591 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
                     handle_command(state_ptr, command);
                 }
             }
-            recv = udp.recv_from(&mut recv_buf) => {
-                let (size, peer) = recv.map_err(map_io)?;
-                let loop_time = unsafe { picoquic_current_time() };
-                if let Some(slot) = decode_slot(
-                    &recv_buf[..size],
-                    peer,
-                    &domains,
-                    quic,
-                    loop_time,
-                    &local_addr_storage,
-                )? {
-                    slots.push(slot);
+            recv = transport.recv_batch(&mut recv_batch) => {
+                recv.map_err(map_io)?;
+                let loop_time = clock.now();
+                for (packet, peer) in recv_batch.iter() {
+                    if let Some(recorder) = recorder.as_mut() {
+                        recorder.record(peer, packet);
+                    }
+                    match decode_slot(
+                        packet,
+                        peer,
+                        &domains,
+                        &mut reassembler,
+                        &mut downstream_limits,
+                        &mut probe_budget,
+                        quic,
+                        loop_time,
+                        &local_addr_storage,
+                        worker.as_ref(),
+                    ) {
+                        Ok(Some(slot)) => slots.push(slot),
+                        Ok(None) => {}
+                        Err(err) => tracing::debug!("Dropping query from {}: {}", peer, err),
+                    }
                 }
-                for _ in 1..PICOQUIC_PACKET_LOOP_RECV_MAX {
-                    match udp.try_recv_from(&mut recv_buf) {
-                        Ok((size, peer)) => {
-                            if let Some(slot) = decode_slot(
-                                &recv_buf[..size],
-                                peer,
-                                &domains,
+            }
+            handoff = next_handoff(worker.as_mut()) => {
+                let loop_time = clock.now();
+                let mut next = handoff;
+                let mut handled = 0;
+                while let Some(handoff) = next {
+                    match handoff {
+                        Handoff::Query(query) => {
+                            let peer = query.peer;
+                            match route_query(
+                                query,
+                                &mut reassembler,
+                                &mut downstream_limits,
                                 quic,
                                 loop_time,
                                 &local_addr_storage,
-                            )? {
-                                slots.push(slot);
+                                worker.as_ref(),
+                            ) {
+                                Ok(Some(slot)) => slots.push(slot),
+                                Ok(None) => {}
+                                Err(err) => {
+                                    tracing::debug!("Dropping query from {}: {}", peer, err)
+                                }
                             }
                         }
-                        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
-                        Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
-                        Err(err) => return Err(map_io(err)),
+                        Handoff::DownstreamLimit { cnx_id, response_len, reported_at } => {
+                            downstream_limits.record(cnx_id, response_len, reported_at);
+                        }
+                    }
+                    handled += 1;
+                    if handled == PICOQUIC_PACKET_LOOP_RECV_MAX {
+                        break;
                     }
+                    next = worker.as_mut().and_then(Worker::try_next_handoff);
                 }
             }
+            call = control.next() => {
+                let mut ctx = ControlContext {
+                    quic,
+                    state: unsafe { &mut *state_ptr },
+                    target,
+                    started_at,
+                };
+                let result = handle_control(&call.request, &mut ctx);
+                call.respond(result);
+            }
             _ = sleep(Duration::from_millis(IDLE_SLEEP_MS)) => {}
         }
 
         drain_commands(state_ptr, &mut command_rx);
+        forget_closed_connections(state_ptr, &mut downstream_limits);
         maybe_report_command_stats(state_ptr);
+        reassembler.expire(clock.now());
+        if let Some(metrics) = metrics.as_ref() {
+            let now = clock.now();
+            if now.saturating_sub(last_metrics_at) >= METRICS_PUBLISH_INTERVAL_US {
+                last_metrics_at = now;
+                let state = unsafe { &*state_ptr };
+                let connections = live_connections(quic).len();
+                match worker.as_ref() {
+                    Some(worker) => {
+                        worker.publish_metrics(&state.metrics, connections, state.streams_len())
+                    }
+                    None => metrics.publish(state.metrics.render(connections, state.streams_len())),
+                }
+            }
+        }
 
         if slots.is_empty() {
             continue;
         }
 
-        let loop_time = unsafe { picoquic_current_time() };
+        let loop_time = clock.now();
 
-        for slot in slots.iter_mut() {
-            let mut send_length = 0usize;
-            let mut addr_to: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
-            let mut addr_from: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
-            let mut if_index: libc::c_int = 0;
-
-            if slot.rcode.is_none() && !slot.cnx.is_null() {
-                let ret = unsafe {
-                    picoquic_prepare_packet_ex(
-                        slot.cnx,
-                        slot.path_id,
-                        loop_time,
-                        send_buf.as_mut_ptr(),
-                        send_buf.len(),
-                        &mut send_length,
-                        &mut addr_to,
-                        &mut addr_from,
-                        &mut if_index,
-                        std::ptr::null_mut(),
-                    )
-                };
-                if ret < 0 {
-                    return Err(ServerError::new("Failed to prepare QUIC packet"));
+        send_batch.clear();
+        let mut answered = Vec::with_capacity(slots.len());
+        for slot in slots.iter() {
+            // One bad query must not take the server down; drop its answer instead.
+            let response = match respond(
+                slot,
+                &mut send_buf,
+                loop_time,
+                &downstream_limits,
+                config.pad_responses,
+            ) {
+                Ok(response) => response,
+                Err(err) => {
+                    tracing::debug!("Dropping response to {}: {}", slot.peer, err);
+                    continue;
                 }
+            };
+            send_batch.push(&response.packet, response.peer);
+            answered.push((slot.received_at, response.rcode));
+        }
+        if let Err(err) = transport.send_batch(&send_batch).await {
+            tracing::warn!("Failed to send DNS responses: {}", err);
+        }
+        if metrics.is_some() {
+            let state = unsafe { &mut *state_ptr };
+            let sent_at = clock.now();
+            for (received_at, rcode) in answered {
+                let latency = sent_at.saturating_sub(received_at);
+                state.metrics.record_response(rcode, latency);
             }
+        }
+    }
 
-            let (payload, rcode) = if send_length > 0 {
-                (Some(&send_buf[..send_length]), slot.rcode)
-            } else if slot.rcode.is_none() {
-                // No QUIC payload ready; still answer the poll with NOERROR and empty payload to clear it.
-                (None, Some(slipstream_dns::Rcode::Ok))
+    Ok(0)
+}
+
+async fn next_handoff(worker: Option<&mut Worker>) -> Option<Handoff> {
+    match worker {
+        Some(worker) => worker.next_handoff().await,
+        None => std::future::pending().await,
+    }
+}
+
+pub(crate) fn segment_reassembler() -> SegmentReassembler {
+    SegmentReassembler::new(
+        SEGMENT_REASSEMBLY_TIMEOUT_US,
+        SEGMENT_REASSEMBLY_MAX_BYTES,
+        SEGMENT_REASSEMBLY_MAX_PACKETS,
+    )
+}
+
+/// Creates the server QUIC context; `current_time` is picoquic's notion of now
+/// and `simulated_time`, when not null, where picoquic reads it from afterwards.
+/// When `cnx_id_prefix` is not null, every local connection ID starts with the
+/// byte it points to.
+/// Makes the random choices of QUIC contexts created afterwards, TLS keys
+/// included, follow from `seed`; see `--seed`.
+pub(crate) fn seed_randomness(seed: u64) -> Result<(), ServerError> {
+    unsafe {
+        picoquic_public_random_seed_64(seed, 1);
+        if slipstream_seed_tls_random(seed) != 0 {
+            return Err(ServerError::new("Could not seed TLS randomness"));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn create_server_quic(
+    cert: &str,
+    key: &str,
+    state_ptr: *mut ServerState,
+    current_time: u64,
+    simulated_time: *mut u64,
+    cnx_id_prefix: *const u8,
+) -> Result<(QuicGuard, *mut picoquic_quic_t), ServerError> {
+    let alpn = CString::new(SLIPSTREAM_ALPN)
+        .map_err(|_| ServerError::new("ALPN contains an unexpected null byte"))?;
+    let cert = CString::new(cert)
+        .map_err(|_| ServerError::new("Cert path contains an unexpected null byte"))?;
+    let key = CString::new(key)
+        .map_err(|_| ServerError::new("Key path contains an unexpected null byte"))?;
+    let quic = unsafe {
+        picoquic_create(
+            8,
+            cert.as_ptr(),
+            key.as_ptr(),
+            std::ptr::null(),
+            alpn.as_ptr(),
+            Some(server_callback),
+            state_ptr as *mut _,
+            if cnx_id_prefix.is_null() {
+                None
             } else {
-                (None, slot.rcode)
-            };
-            let response = encode_response(&ResponseParams {
-                id: slot.id,
-                rd: slot.rd,
-                cd: slot.cd,
-                question: &slot.question,
-                payload,
-                rcode,
-            })
-            .map_err(|err| ServerError::new(err.to_string()))?;
-            let peer = normalize_dual_stack_addr(slot.peer);
-            udp.send_to(&response, peer).await.map_err(map_io)?;
+                Some(prefix_cnx_id)
+            },
+            cnx_id_prefix as *mut _,
+            std::ptr::null(),
+            current_time,
+            simulated_time,
+            std::ptr::null(),
+            std::ptr::null(),
+            0,
+        )
+    };
+    if quic.is_null() {
+        return Err(ServerError::new("Could not create QUIC context"));
+    }
+    let guard = QuicGuard::new(quic);
+    unsafe {
+        if slipstream_server_cc_algorithm.is_null() {
+            return Err(ServerError::new(
+                "Slipstream server congestion algorithm is unavailable",
+            ));
         }
+        configure_quic_with_custom(quic, slipstream_server_cc_algorithm, QUIC_MTU);
     }
+    Ok((guard, quic))
+}
 
-    Ok(0)
+/// DNS answer for one slot.
+pub(crate) struct SlotResponse {
+    pub(crate) peer: SocketAddr,
+    pub(crate) packet: Vec<u8>,
+    pub(crate) rcode: Rcode,
+    /// Bytes of QUIC packet or probe reply carried in the answer.
+    pub(crate) payload_len: usize,
+}
+
+/// Encodes the answer to `slot`, pulling the next QUIC packet for its path if any.
+pub(crate) fn respond(
+    slot: &Slot,
+    send_buf: &mut [u8],
+    current_time: u64,
+    downstream_limits: &DownstreamLimits,
+    pad_responses: u16,
+) -> Result<SlotResponse, ServerError> {
+    let mut send_length = 0usize;
+    let mut addr_to: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut addr_from: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut if_index: libc::c_int = 0;
+
+    if slot.rcode.is_none() && !slot.cnx.is_null() {
+        if let Some(path_mtu) = slot.path_mtu {
+            unsafe {
+                slipstream_set_path_mtu(slot.cnx, slot.path_id, path_mtu);
+            }
+        }
+        let ret = unsafe {
+            picoquic_prepare_packet_ex(
+                slot.cnx,
+                slot.path_id,
+                current_time,
+                send_buf.as_mut_ptr(),
+                send_buf.len(),
+                &mut send_length,
+                &mut addr_to,
+                &mut addr_from,
+                &mut if_index,
+                std::ptr::null_mut(),
+            )
+        };
+        if ret < 0 {
+            return Err(ServerError::new("Failed to prepare QUIC packet"));
+        }
+    }
+
+    let (payload, rcode) = if let Some(reply) = &slot.reply {
+        (Some(reply.as_slice()), None)
+    } else if send_length > 0 {
+        (Some(&send_buf[..send_length]), slot.rcode)
+    } else if slot.rcode.is_none() {
+        // No QUIC payload ready; still answer the poll with NOERROR and empty payload to clear it.
+        (None, Some(slipstream_dns::Rcode::Ok))
+    } else {
+        (None, slot.rcode)
+    };
+    let peer = normalize_dual_stack_addr(slot.peer);
+    // Padding stays within what the path's resolver is known to pass through.
+    let max_response_len = downstream_limits
+        .response_len(slot.cnx, slot.path_id)
+        .unwrap_or(EDNS_UDP_PAYLOAD as usize);
+    let packet = encode_response_padded(
+        &ResponseParams {
+            id: slot.id,
+            rd: slot.rd,
+            cd: slot.cd,
+            question: &slot.question,
+            payload,
+            rcode,
+        },
+        pad_responses as usize,
+        max_response_len,
+    )
+    .map_err(|err| ServerError::new(err.to_string()))?;
+    Ok(SlotResponse {
+        peer,
+        packet,
+        rcode: rcode.unwrap_or(Rcode::Ok),
+        payload_len: payload.map_or(0, <[u8]>::len),
+    })
+}
+
+/// A decoded query carrying a QUIC packet, or one segment of it.
+pub(crate) struct QuicQuery {
+    peer: SocketAddr,
+    id: u16,
+    rd: bool,
+    cd: bool,
+    question: Question,
+    payload: Vec<u8>,
+    segment: Option<SegmentHeader>,
+    received_at: u64,
 }
 
-fn decode_slot(
+impl QuicQuery {
+    pub(crate) fn segment(&self) -> Option<SegmentHeader> {
+        self.segment
+    }
+
+    pub(crate) fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    fn into_slot(
+        self,
+        rcode: Option<Rcode>,
+        cnx: *mut picoquic_cnx_t,
+        path_id: libc::c_int,
+        path_mtu: Option<u32>,
+    ) -> Slot {
+        Slot {
+            peer: self.peer,
+            id: self.id,
+            rd: self.rd,
+            cd: self.cd,
+            question: self.question,
+            rcode,
+            reply: None,
+            cnx,
+            path_id,
+            path_mtu,
+            received_at: self.received_at,
+        }
+    }
+}
+
+enum Decoded {
+    /// Answered without QUIC: probes and malformed queries.
+    Answer(Slot),
+    /// A downstream probe; the answer plus the limit the client reported
+    /// for the path using `cnx_id`.
+    Report {
+        slot: Slot,
+        cnx_id: [u8; PROBE_CNX_ID_LEN],
+        response_len: usize,
+    },
+    Quic(QuicQuery),
+    Drop,
+}
+
+/// Decodes one query into the slot to answer, feeding any QUIC packet it
+/// carries to `quic`. With `worker` set, packets of other workers'
+/// connections are handed over instead and yield no slot here.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn decode_slot(
     packet: &[u8],
     peer: SocketAddr,
     domains: &[&str],
+    reassembler: &mut SegmentReassembler,
+    downstream_limits: &mut DownstreamLimits,
+    probe_budget: &mut ProbeBudget,
     quic: *mut picoquic_quic_t,
     current_time: u64,
     local_addr_storage: &libc::sockaddr_storage,
+    worker: Option<&Worker>,
 ) -> Result<Option<Slot>, ServerError> {
+    match decode_query(packet, peer, domains, probe_budget, current_time) {
+        Decoded::Answer(slot) => Ok(Some(slot)),
+        Decoded::Report {
+            slot,
+            cnx_id,
+            response_len,
+        } => {
+            let handed_off = worker.is_some_and(|worker| {
+                worker.hand_off_downstream_limit(cnx_id, response_len, current_time)
+            });
+            if !handed_off {
+                downstream_limits.record(cnx_id, response_len, current_time);
+            }
+            Ok(Some(slot))
+        }
+        Decoded::Quic(query) => route_query(
+            query,
+            reassembler,
+            downstream_limits,
+            quic,
+            current_time,
+            local_addr_storage,
+            worker,
+        ),
+        Decoded::Drop => Ok(None),
+    }
+}
+
+fn decode_query(
+    packet: &[u8],
+    peer: SocketAddr,
+    domains: &[&str],
+    probe_budget: &mut ProbeBudget,
+    current_time: u64,
+) -> Decoded {
+    let peer = normalize_dual_stack_addr(peer);
     match decode_query_with_domains(packet, domains) {
         Ok(query) => {
-            let mut peer_storage = dummy_sockaddr_storage();
-            let mut local_storage = unsafe { std::ptr::read(local_addr_storage) };
-            let mut first_cnx: *mut picoquic_cnx_t = std::ptr::null_mut();
-            let mut first_path: libc::c_int = -1;
-            let ret = unsafe {
-                picoquic_incoming_packet_ex(
-                    quic,
-                    query.payload.as_ptr() as *mut u8,
-                    query.payload.len(),
-                    &mut peer_storage as *mut _ as *mut libc::sockaddr,
-                    &mut local_storage as *mut _ as *mut libc::sockaddr,
-                    0,
-                    0,
-                    &mut first_cnx,
-                    &mut first_path,
-                    current_time,
-                )
-            };
-            if ret < 0 {
-                return Err(ServerError::new("Failed to process QUIC packet"));
-            }
-            if first_cnx.is_null() {
-                return Ok(None);
-            }
-            unsafe {
-                slipstream_disable_ack_delay(first_cnx);
+            if let Some(probe) = query.probe {
+                // Probe replies need no handshake, so they are rationed and
+                // never larger than the requester accepts.
+                if !probe_budget.take(current_time) {
+                    return Decoded::Drop;
+                }
+                let max_reply_len =
+                    max_response_payload_len(query.max_response_len, &query.question.name);
+                let (response_len, reported) = match probe.kind {
+                    ProbeKind::Echo => ((probe.response_len as usize).min(max_reply_len), None),
+                    // Reports that name no connection are answered but ignored.
+                    ProbeKind::ReportDownstream => (
+                        0,
+                        probe
+                            .cnx_id
+                            .map(|cnx_id| (cnx_id, probe.response_len as usize)),
+                    ),
+                };
+                let reply = ProbeReply {
+                    id: query.id,
+                    nonce: probe.nonce,
+                    stamp: current_time,
+                    qname: query.question.name.clone(),
+                };
+                let slot = Slot {
+                    peer,
+                    id: query.id,
+                    rd: query.rd,
+                    cd: query.cd,
+                    question: query.question,
+                    rcode: None,
+                    reply: Some(reply.encode(response_len)),
+                    cnx: std::ptr::null_mut(),
+                    path_id: -1,
+                    path_mtu: None,
+                    received_at: current_time,
+                };
+                return match reported {
+                    Some((cnx_id, response_len)) => Decoded::Report {
+                        slot,
+                        cnx_id,
+                        response_len,
+                    },
+                    None => Decoded::Answer(slot),
+                };
             }
-            Ok(Some(Slot {
-                peer: normalize_dual_stack_addr(peer),
+            Decoded::Quic(QuicQuery {
+                peer,
                 id: query.id,
                 rd: query.rd,
                 cd: query.cd,
                 question: query.question,
-                rcode: None,
-                cnx: first_cnx,
-                path_id: first_path,
-            }))
+                payload: query.payload,
+                segment: query.segment,
+                received_at: current_time,
+            })
         }
-        Err(DecodeQueryError::Drop) => Ok(None),
+        Err(DecodeQueryError::Drop) => Decoded::Drop,
         Err(DecodeQueryError::Reply {
             id,
             rd,
             cd,
             question,
             rcode,
-        }) => {
-            let question = match question {
-                Some(question) => question,
-                None => return Ok(None),
-            };
-            Ok(Some(Slot {
-                peer: normalize_dual_stack_addr(peer),
+        }) => match question {
+            Some(question) => Decoded::Answer(Slot {
+                peer,
                 id,
                 rd,
                 cd,
                 question,
                 rcode: Some(rcode),
+                reply: None,
                 cnx: std::ptr::null_mut(),
                 path_id: -1,
-            }))
+                path_mtu: None,
+                received_at: current_time,
+            }),
+            None => Decoded::Drop,
+        },
+    }
+}
+
+/// Reassembles `query` if it is a segment and feeds the packet to `quic`,
+/// or hands it to the worker that owns it.
+pub(crate) fn route_query(
+    mut query: QuicQuery,
+    reassembler: &mut SegmentReassembler,
+    downstream_limits: &mut DownstreamLimits,
+    quic: *mut picoquic_quic_t,
+    current_time: u64,
+    local_addr_storage: &libc::sockaddr_storage,
+    worker: Option<&Worker>,
+) -> Result<Option<Slot>, ServerError> {
+    if let Some(worker) = worker {
+        if let Some(owner) = worker.other_owner(&query) {
+            worker.hand_off(owner, query);
+            return Ok(None);
+        }
+    }
+    if let Some(segment) = query.segment.take() {
+        match reassembler.insert(segment, &query.payload, current_time) {
+            Some(payload) => query.payload = payload,
+            // Acknowledge partial packets with an empty NOERROR answer.
+            None => {
+                return Ok(Some(query.into_slot(
+                    Some(Rcode::Ok),
+                    std::ptr::null_mut(),
+                    -1,
+                    None,
+                )))
+            }
         }
+        // The whole packet may belong to another worker than its segments.
+        if let Some(worker) = worker {
+            if let Some(owner) = worker.other_owner(&query) {
+                worker.hand_off(owner, query);
+                return Ok(None);
+            }
+        }
+    }
+
+    let mut peer_storage = dummy_sockaddr_storage();
+    let mut local_storage = unsafe { std::ptr::read(local_addr_storage) };
+    let mut first_cnx: *mut picoquic_cnx_t = std::ptr::null_mut();
+    let mut first_path: libc::c_int = -1;
+    let ret = unsafe {
+        picoquic_incoming_packet_ex(
+            quic,
+            query.payload.as_ptr() as *mut u8,
+            query.payload.len(),
+            &mut peer_storage as *mut _ as *mut libc::sockaddr,
+            &mut local_storage as *mut _ as *mut libc::sockaddr,
+            0,
+            0,
+            &mut first_cnx,
+            &mut first_path,
+            current_time,
+        )
+    };
+    if ret < 0 {
+        return Err(ServerError::new("Failed to process QUIC packet"));
+    }
+    if first_cnx.is_null() {
+        return Ok(None);
+    }
+    unsafe {
+        slipstream_disable_ack_delay(first_cnx);
+    }
+    downstream_limits.bind(&query.payload, first_cnx, first_path);
+    let path_mtu = downstream_limits.path_mtu(first_cnx, first_path, &query.question.name);
+    Ok(Some(query.into_slot(None, first_cnx, first_path, path_mtu)))
+}
+
+/// Drops per-connection state the serve loop keeps for connections picoquic closed.
+pub(crate) fn forget_closed_connections(
+    state_ptr: *mut ServerState,
+    downstream_limits: &mut DownstreamLimits,
+) {
+    let state = unsafe { &mut *state_ptr };
+    for cnx in state.take_closed_connections() {
+        downstream_limits.forget_connection(cnx);
+    }
+}
+
+pub(crate) fn live_connections(quic: *mut picoquic_quic_t) -> Vec<*mut picoquic_cnx_t> {
+    let mut connections = Vec::new();
+    let mut cnx = unsafe { picoquic_get_first_cnx(quic) };
+    while !cnx.is_null() {
+        connections.push(cnx);
+        cnx = unsafe { picoquic_get_next_cnx(cnx) };
     }
+    connections
 }
 
 async fn bind_udp_socket(port: u16) -> Result<TokioUdpSocket, ServerError> {
@@ -417,7 +987,7 @@ fn dummy_sockaddr_storage() -> libc::sockaddr_storage {
     storage
 }
 
-fn map_io(err: std::io::Error) -> ServerError {
+pub(crate) fn map_io(err: std::io::Error) -> ServerError {
     ServerError::new(err.to_string())
 }
 
@@ -0,0 +1,106 @@
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::net::SocketAddr;
+use std::time::Instant;
+use tracing::warn;
+
+/// One inbound query from a `--record` file.
+pub(crate) struct RecordedQuery {
+    /// Microseconds since the recording started.
+    pub(crate) at_us: u64,
+    pub(crate) peer: SocketAddr,
+    pub(crate) packet: Vec<u8>,
+}
+
+/// JSON lines file behind `--record`: `{"at_us": .., "peer": "..", "query": "<hex>"}`.
+pub(crate) struct SessionRecorder {
+    file: LineWriter<File>,
+    started_at: Instant,
+    failed: bool,
+}
+
+impl SessionRecorder {
+    pub(crate) fn create(path: &str) -> io::Result<Self> {
+        Ok(Self {
+            file: LineWriter::new(File::create(path)?),
+            started_at: Instant::now(),
+            failed: false,
+        })
+    }
+
+    pub(crate) fn record(&mut self, peer: SocketAddr, packet: &[u8]) {
+        let line = json!({
+            "at_us": self.started_at.elapsed().as_micros() as u64,
+            "peer": peer.to_string(),
+            "query": encode_hex(packet),
+        });
+        match writeln!(self.file, "{}", line) {
+            Ok(()) => self.failed = false,
+            Err(err) => {
+                if !self.failed {
+                    warn!(error = %err, "Failed writing session recording");
+                }
+                self.failed = true;
+            }
+        }
+    }
+}
+
+/// Reads a `--record` file; blank lines are skipped.
+pub(crate) fn read_session(path: &str) -> Result<Vec<RecordedQuery>, String> {
+    let file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+    let mut queries = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| format!("{}: {}", path, err))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let query = parse_line(&line).map_err(|err| format!("{}:{}: {}", path, index + 1, err))?;
+        queries.push(query);
+    }
+    Ok(queries)
+}
+
+fn parse_line(line: &str) -> Result<RecordedQuery, String> {
+    let value: Value = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let at_us = value
+        .get("at_us")
+        .and_then(Value::as_u64)
+        .ok_or("missing at_us")?;
+    let peer = value
+        .get("peer")
+        .and_then(Value::as_str)
+        .ok_or("missing peer")?
+        .parse()
+        .map_err(|_| "peer is not an IP:PORT address")?;
+    let packet = value
+        .get("query")
+        .and_then(Value::as_str)
+        .ok_or("missing query")
+        .and_then(decode_hex)?;
+    Ok(RecordedQuery {
+        at_us,
+        peer,
+        packet,
+    })
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(input: &str) -> Result<Vec<u8>, &'static str> {
+    if !input.len().is_multiple_of(2) {
+        return Err("query has an odd number of hex digits");
+    }
+    (0..input.len())
+        .step_by(2)
+        .map(|index| {
+            input
+                .get(index..index + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or("query is not a hex string")
+        })
+        .collect()
+}
@@ -1,47 +1,124 @@
+use crate::audit::{AuditLog, CloseReason, StreamRecord};
+use crate::metrics::ServerMetrics;
 use crate::server::{Command, StreamKey, StreamWrite};
-use crate::target::spawn_target_connector;
+use crate::target::{spawn_target_connector, Target};
+use slipstream_core::diagnostics::{parse_preface, DiagnosticRequest, Preface, MAX_PREFACE_LEN};
 use slipstream_ffi::picoquic::{
     picoquic_call_back_event_t, picoquic_close, picoquic_close_immediate, picoquic_cnx_t,
-    picoquic_get_first_cnx, picoquic_get_next_cnx, picoquic_mark_active_stream,
+    picoquic_get_first_cnx, picoquic_get_next_cnx, picoquic_get_path_addr,
+    picoquic_get_remote_stream_error, picoquic_mark_active_stream,
     picoquic_provide_stream_data_buffer, picoquic_quic_t, picoquic_reset_stream,
     picoquic_stream_data_consumed,
 };
-use slipstream_ffi::{SLIPSTREAM_FILE_CANCEL_ERROR, SLIPSTREAM_INTERNAL_ERROR};
+use slipstream_ffi::{
+    sockaddr_storage_to_socket_addr, SLIPSTREAM_FILE_CANCEL_ERROR, SLIPSTREAM_INTERNAL_ERROR,
+};
 use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::{mpsc, watch};
 use tracing::{debug, error, warn};
 
 pub(crate) struct ServerState {
-    target_addr: SocketAddr,
+    target: Target,
     streams: HashMap<StreamKey, ServerStream>,
     command_tx: mpsc::UnboundedSender<Command>,
+    diagnostics: bool,
     debug_streams: bool,
     debug_commands: bool,
     command_counts: CommandCounts,
     last_command_report: Instant,
+    pub(crate) metrics: ServerMetrics,
+    audit: Option<AuditLog>,
+    closed_connections: Vec<usize>,
 }
 
 impl ServerState {
     pub(crate) fn new(
-        target_addr: SocketAddr,
+        target: Target,
         command_tx: mpsc::UnboundedSender<Command>,
+        diagnostics: bool,
         debug_streams: bool,
         debug_commands: bool,
+        audit: Option<AuditLog>,
     ) -> Self {
         Self {
-            target_addr,
+            target,
             streams: HashMap::new(),
             command_tx,
+            diagnostics,
             debug_streams,
             debug_commands,
             command_counts: CommandCounts::default(),
             last_command_report: Instant::now(),
+            metrics: ServerMetrics::new(),
+            audit,
+            closed_connections: Vec::new(),
         }
     }
+
+    /// Connections closed since the last call, for state kept outside picoquic.
+    pub(crate) fn take_closed_connections(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.closed_connections)
+    }
+
+    pub(crate) fn streams_len(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Snapshot of the open streams for the control socket.
+    pub(crate) fn stream_infos(&self) -> Vec<StreamInfo> {
+        let mut infos: Vec<_> = self
+            .streams
+            .iter()
+            .map(|(key, stream)| StreamInfo {
+                key: *key,
+                rx_bytes: stream.rx_bytes,
+                tx_bytes: stream.tx_bytes,
+                queued_bytes: stream.queued_bytes,
+                age: stream.opened_at.elapsed(),
+                state: if stream.write_tx.is_none() {
+                    "connecting"
+                } else if stream.fin_offset.is_some() {
+                    "half_closed_remote"
+                } else if stream.close_after_flush {
+                    "half_closed_local"
+                } else {
+                    "open"
+                },
+            })
+            .collect();
+        infos.sort_by_key(|info| (info.key.cnx, info.key.stream_id));
+        infos
+    }
+
+    /// Resets a stream and drops its target connection; false if it is not open.
+    pub(crate) fn close_stream(&mut self, key: StreamKey) -> bool {
+        let reason = CloseReason::LocalReset(SLIPSTREAM_FILE_CANCEL_ERROR);
+        if shutdown_stream(self, key, reason).is_none() {
+            return false;
+        }
+        let cnx = key.cnx as *mut picoquic_cnx_t;
+        let _ = unsafe { picoquic_reset_stream(cnx, key.stream_id, SLIPSTREAM_FILE_CANCEL_ERROR) };
+        true
+    }
+
+    /// Closes a connection and its streams; the caller checks `cnx` is live.
+    pub(crate) fn close_connection(&mut self, cnx: *mut picoquic_cnx_t) {
+        remove_connection_streams(self, cnx as usize);
+        let _ = unsafe { picoquic_close(cnx, 0) };
+    }
+}
+
+pub(crate) struct StreamInfo {
+    pub(crate) key: StreamKey,
+    pub(crate) rx_bytes: u64,
+    pub(crate) tx_bytes: u64,
+    pub(crate) queued_bytes: usize,
+    pub(crate) age: Duration,
+    pub(crate) state: &'static str,
 }
 
 #[derive(Default)]
@@ -99,6 +176,13 @@ struct ServerStream {
     pending_data: VecDeque<Vec<u8>>,
     pending_fin: bool,
     fin_enqueued: bool,
+    // Waiting for enough bytes to tell a diagnostic preface from target data.
+    sniffing: bool,
+    service: Option<DiagnosticRequest>,
+    opened_at: Instant,
+    // Wall-clock start and QUIC peer for the audit log.
+    started_at: SystemTime,
+    source: Option<SocketAddr>,
 }
 
 pub(crate) unsafe extern "C" fn server_callback(
@@ -140,27 +224,26 @@ pub(crate) unsafe extern "C" fn server_callback(
                 cnx: cnx as usize,
                 stream_id,
             };
-            if let Some(stream) = shutdown_stream(state, key) {
+            let code = picoquic_get_remote_stream_error(cnx, stream_id);
+            if let Some(stream) = shutdown_stream(state, key, CloseReason::PeerReset(code)) {
                 warn!(
-                    "stream {:?}: reset event={} tx_bytes={} rx_bytes={} consumed_offset={} queued={} pending_chunks={} pending_fin={} fin_enqueued={} fin_offset={:?} target_fin_pending={} close_after_flush={}",
-                    key.stream_id,
-                    reason,
-                    stream.tx_bytes,
-                    stream.rx_bytes,
-                    stream.consumed_offset,
-                    stream.queued_bytes,
-                    stream.pending_data.len(),
-                    stream.pending_fin,
-                    stream.fin_enqueued,
-                    stream.fin_offset,
-                    stream.target_fin_pending,
-                    stream.close_after_flush
+                    stream_id,
+                    event = reason,
+                    code,
+                    tx_bytes = stream.tx_bytes,
+                    rx_bytes = stream.rx_bytes,
+                    consumed_offset = stream.consumed_offset,
+                    queued = stream.queued_bytes,
+                    pending_chunks = stream.pending_data.len(),
+                    pending_fin = stream.pending_fin,
+                    fin_enqueued = stream.fin_enqueued,
+                    fin_offset = ?stream.fin_offset,
+                    target_fin_pending = stream.target_fin_pending,
+                    close_after_flush = stream.close_after_flush,
+                    "stream reset"
                 );
             } else {
-                warn!(
-                    "stream {:?}: reset event={} (unknown stream)",
-                    stream_id, reason
-                );
+                warn!(stream_id, event = reason, "stream reset (unknown stream)");
             }
             let _ = picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_FILE_CANCEL_ERROR);
         }
@@ -237,19 +320,20 @@ pub(crate) unsafe extern "C" fn server_callback(
                     let buffer =
                         picoquic_provide_stream_data_buffer(bytes as *mut _, send_len, 0, 1);
                     if buffer.is_null() {
-                        if let Some(stream) = shutdown_stream(state, key) {
+                        let reason = CloseReason::LocalReset(SLIPSTREAM_INTERNAL_ERROR);
+                        if let Some(stream) = shutdown_stream(state, key, reason) {
                             error!(
-                                "stream {:?}: provide_stream_data_buffer returned null send_len={} queued={} pending_chunks={} tx_bytes={}",
-                                key.stream_id,
+                                stream_id,
                                 send_len,
-                                stream.queued_bytes,
-                                stream.pending_data.len(),
-                                stream.tx_bytes
+                                queued = stream.queued_bytes,
+                                pending_chunks = stream.pending_data.len(),
+                                tx_bytes = stream.tx_bytes,
+                                "provide_stream_data_buffer returned null"
                             );
                         } else {
                             error!(
-                                "stream {:?}: provide_stream_data_buffer returned null send_len={}",
-                                key.stream_id, send_len
+                                stream_id,
+                                send_len, "provide_stream_data_buffer returned null"
                             );
                         }
                         let _ = picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR);
@@ -259,6 +343,10 @@ pub(crate) unsafe extern "C" fn server_callback(
                         std::ptr::copy_nonoverlapping(data.as_ptr(), buffer, data.len());
                     }
                     stream.tx_bytes = stream.tx_bytes.saturating_add(data.len() as u64);
+                    state.metrics.bytes_from_target = state
+                        .metrics
+                        .bytes_from_target
+                        .saturating_add(data.len() as u64);
                 } else if stream.target_fin_pending {
                     stream.target_fin_pending = false;
                     if stream.close_after_flush {
@@ -279,7 +367,7 @@ pub(crate) unsafe extern "C" fn server_callback(
             }
 
             if remove_stream {
-                shutdown_stream(state, key);
+                shutdown_stream(state, key, CloseReason::Fin);
             }
         }
         _ => {}
@@ -306,15 +394,18 @@ fn handle_stream_data(
         let stream = state.streams.entry(key).or_insert_with(|| {
             let (shutdown_tx, shutdown_rx) = watch::channel(false);
             if debug_streams {
-                debug!("stream {:?}: connecting", key.stream_id);
+                debug!(stream_id = key.stream_id, "stream connecting");
+            }
+            state.metrics.streams_opened = state.metrics.streams_opened.saturating_add(1);
+            if !state.diagnostics {
+                spawn_target_connector(
+                    key,
+                    state.target,
+                    state.command_tx.clone(),
+                    debug_streams,
+                    shutdown_rx,
+                );
             }
-            spawn_target_connector(
-                key,
-                state.target_addr,
-                state.command_tx.clone(),
-                debug_streams,
-                shutdown_rx,
-            );
             ServerStream {
                 write_tx: None,
                 data_rx: None,
@@ -331,6 +422,11 @@ fn handle_stream_data(
                 pending_data: VecDeque::new(),
                 pending_fin: false,
                 fin_enqueued: false,
+                sniffing: state.diagnostics,
+                service: None,
+                opened_at: Instant::now(),
+                started_at: SystemTime::now(),
+                source: peer_addr(cnx),
             }
         });
 
@@ -349,6 +445,56 @@ fn handle_stream_data(
             }
         }
 
+        if stream.sniffing {
+            let head: Vec<u8> = stream
+                .pending_data
+                .iter()
+                .flatten()
+                .take(MAX_PREFACE_LEN)
+                .copied()
+                .collect();
+            match parse_preface(&head) {
+                Preface::Incomplete if !fin => {}
+                Preface::Absent | Preface::Incomplete => {
+                    stream.sniffing = false;
+                    spawn_target_connector(
+                        key,
+                        state.target,
+                        state.command_tx.clone(),
+                        debug_streams,
+                        stream.shutdown_tx.subscribe(),
+                    );
+                }
+                Preface::Request { request, len } => {
+                    stream.sniffing = false;
+                    stream.service = Some(request);
+                    // The preface never reaches a target, so credit it right away.
+                    drain_front(&mut stream.pending_data, len);
+                    stream.queued_bytes = stream.queued_bytes.saturating_sub(len);
+                    stream.consumed_offset = len as u64;
+                    let ret = unsafe {
+                        picoquic_stream_data_consumed(cnx, stream_id, stream.consumed_offset)
+                    };
+                    if ret < 0 {
+                        warn!(stream_id, ret, "stream_data_consumed failed");
+                        reset_stream = true;
+                    } else {
+                        spawn_target_connector(
+                            key,
+                            Target::Builtin(request),
+                            state.command_tx.clone(),
+                            debug_streams,
+                            stream.shutdown_tx.subscribe(),
+                        );
+                    }
+                }
+                Preface::Invalid(reason) => {
+                    warn!(stream_id, reason, "rejected diagnostic stream");
+                    reset_stream = true;
+                }
+            }
+        }
+
         if fin {
             if stream.fin_offset.is_none() {
                 stream.fin_offset = Some(stream.rx_bytes);
@@ -372,16 +518,35 @@ fn handle_stream_data(
 
     if reset_stream {
         if debug_streams {
-            debug!("stream {:?}: resetting", stream_id);
+            debug!(stream_id, "stream resetting");
         }
-        shutdown_stream(state, key);
+        shutdown_stream(
+            state,
+            key,
+            CloseReason::LocalReset(SLIPSTREAM_INTERNAL_ERROR),
+        );
         unsafe {
             let _ = picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR);
         }
     }
 }
 
+fn drain_front(chunks: &mut VecDeque<Vec<u8>>, mut len: usize) {
+    while len > 0 {
+        let Some(front) = chunks.front_mut() else {
+            return;
+        };
+        if front.len() > len {
+            front.drain(..len);
+            return;
+        }
+        len -= front.len();
+        chunks.pop_front();
+    }
+}
+
 fn remove_connection_streams(state: &mut ServerState, cnx: usize) {
+    state.closed_connections.push(cnx);
     let keys: Vec<StreamKey> = state
         .streams
         .keys()
@@ -389,18 +554,54 @@ fn remove_connection_streams(state: &mut ServerState, cnx: usize) {
         .cloned()
         .collect();
     for key in keys {
-        shutdown_stream(state, key);
+        shutdown_stream(state, key, CloseReason::ConnectionClosed);
     }
 }
 
-fn shutdown_stream(state: &mut ServerState, key: StreamKey) -> Option<ServerStream> {
+fn shutdown_stream(
+    state: &mut ServerState,
+    key: StreamKey,
+    reason: CloseReason,
+) -> Option<ServerStream> {
     if let Some(stream) = state.streams.remove(&key) {
         let _ = stream.shutdown_tx.send(true);
+        if let Some(audit) = state.audit.as_mut() {
+            audit.record(&StreamRecord {
+                started_at: stream.started_at,
+                connection: key.cnx,
+                stream_id: key.stream_id,
+                source: stream.source,
+                target: match stream.service {
+                    Some(request) => format!("diagnostics:{}", request),
+                    None => state.target.to_string(),
+                },
+                bytes_from_client: stream.rx_bytes,
+                bytes_to_client: stream.tx_bytes,
+                reason,
+            });
+        }
         return Some(stream);
     }
     None
 }
 
+/// Address of the connection's first path; a resolver unless clients query directly.
+fn peer_addr(cnx: *mut picoquic_cnx_t) -> Option<SocketAddr> {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let ret = unsafe { picoquic_get_path_addr(cnx, 0, 0, &mut storage) };
+    if ret != 0 {
+        return None;
+    }
+    let addr = sockaddr_storage_to_socket_addr(&storage).ok()?;
+    match addr {
+        SocketAddr::V6(v6) => Some(match v6.ip().to_ipv4_mapped() {
+            Some(v4) => SocketAddr::new(v4.into(), v6.port()),
+            None => addr,
+        }),
+        SocketAddr::V4(_) => Some(addr),
+    }
+}
+
 pub(crate) fn drain_commands(
     state_ptr: *mut ServerState,
     command_rx: &mut mpsc::UnboundedReceiver<Command>,
@@ -433,7 +634,7 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                     return;
                 };
                 if state.debug_streams {
-                    debug!("stream {:?}: target connected", stream_id);
+                    debug!(stream_id, "target connected");
                 }
                 stream.write_tx = Some(write_tx);
                 stream.data_rx = Some(data_rx);
@@ -442,11 +643,11 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                     while let Some(chunk) = stream.pending_data.pop_front() {
                         if write_tx.send(StreamWrite::Data(chunk)).is_err() {
                             warn!(
-                                "stream {:?}: pending write flush failed queued={} pending_chunks={} tx_bytes={}",
                                 stream_id,
-                                stream.queued_bytes,
-                                stream.pending_data.len(),
-                                stream.tx_bytes
+                                queued = stream.queued_bytes,
+                                pending_chunks = stream.pending_data.len(),
+                                tx_bytes = stream.tx_bytes,
+                                "pending write flush failed"
                             );
                             reset_stream = true;
                             break;
@@ -455,11 +656,11 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                     if !reset_stream && stream.pending_fin && !stream.fin_enqueued {
                         if write_tx.send(StreamWrite::Fin).is_err() {
                             warn!(
-                                "stream {:?}: pending fin flush failed queued={} pending_chunks={} tx_bytes={}",
                                 stream_id,
-                                stream.queued_bytes,
-                                stream.pending_data.len(),
-                                stream.tx_bytes
+                                queued = stream.queued_bytes,
+                                pending_chunks = stream.pending_data.len(),
+                                tx_bytes = stream.tx_bytes,
+                                "pending fin flush failed"
                             );
                             reset_stream = true;
                         } else {
@@ -471,19 +672,26 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
             }
             if reset_stream {
                 let cnx = cnx_id as *mut picoquic_cnx_t;
-                shutdown_stream(state, key);
+                shutdown_stream(
+                    state,
+                    key,
+                    CloseReason::LocalReset(SLIPSTREAM_INTERNAL_ERROR),
+                );
                 let _ = unsafe { picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR) };
             }
         }
         Command::StreamConnectError { cnx_id, stream_id } => {
+            state.metrics.target_connect_failures =
+                state.metrics.target_connect_failures.saturating_add(1);
             let cnx = cnx_id as *mut picoquic_cnx_t;
             let key = StreamKey {
                 cnx: cnx_id,
                 stream_id,
             };
-            if shutdown_stream(state, key).is_some() {
+            let reason = CloseReason::LocalReset(SLIPSTREAM_INTERNAL_ERROR);
+            if shutdown_stream(state, key, reason).is_some() {
                 let _ = unsafe { picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR) };
-                warn!("stream {:?}: target connect failed", stream_id);
+                warn!(stream_id, "target connect failed");
             }
         }
         Command::StreamClosed { cnx_id, stream_id } => {
@@ -495,10 +703,7 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                 stream.target_fin_pending = true;
                 stream.close_after_flush = true;
                 if state.debug_streams {
-                    debug!(
-                        "stream {:?}: closed by target tx_bytes={}",
-                        stream_id, stream.tx_bytes
-                    );
+                    debug!(stream_id, tx_bytes = stream.tx_bytes, "closed by target");
                 }
                 if let Some(pending) = stream.send_pending.as_ref() {
                     let was_pending = pending.swap(true, Ordering::SeqCst);
@@ -508,10 +713,7 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                             picoquic_mark_active_stream(cnx, stream_id, 1, std::ptr::null_mut())
                         };
                         if ret != 0 && state.debug_streams {
-                            debug!(
-                                "stream {:?}: mark_active_stream fin failed ret={}",
-                                stream_id, ret
-                            );
+                            debug!(stream_id, ret, "mark_active_stream fin failed");
                         }
                     }
                 }
@@ -522,10 +724,7 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
             let ret =
                 unsafe { picoquic_mark_active_stream(cnx, stream_id, 1, std::ptr::null_mut()) };
             if ret != 0 && state.debug_streams {
-                debug!(
-                    "stream {:?}: mark_active_stream readable failed ret={}",
-                    stream_id, ret
-                );
+                debug!(stream_id, ret, "mark_active_stream readable failed");
             }
         }
         Command::StreamReadError { cnx_id, stream_id } => {
@@ -534,15 +733,16 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                 cnx: cnx_id,
                 stream_id,
             };
-            if let Some(stream) = shutdown_stream(state, key) {
+            let reason = CloseReason::LocalReset(SLIPSTREAM_INTERNAL_ERROR);
+            if let Some(stream) = shutdown_stream(state, key, reason) {
                 warn!(
-                    "stream {:?}: target read error tx_bytes={} rx_bytes={} consumed_offset={} queued={} fin_offset={:?}",
                     stream_id,
-                    stream.tx_bytes,
-                    stream.rx_bytes,
-                    stream.consumed_offset,
-                    stream.queued_bytes,
-                    stream.fin_offset
+                    tx_bytes = stream.tx_bytes,
+                    rx_bytes = stream.rx_bytes,
+                    consumed_offset = stream.consumed_offset,
+                    queued = stream.queued_bytes,
+                    fin_offset = ?stream.fin_offset,
+                    "target read error"
                 );
                 let _ = unsafe { picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR) };
             }
@@ -553,15 +753,16 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                 cnx: cnx_id,
                 stream_id,
             };
-            if let Some(stream) = shutdown_stream(state, key) {
+            let reason = CloseReason::LocalReset(SLIPSTREAM_INTERNAL_ERROR);
+            if let Some(stream) = shutdown_stream(state, key, reason) {
                 warn!(
-                    "stream {:?}: target write failed tx_bytes={} rx_bytes={} consumed_offset={} queued={} fin_offset={:?}",
                     stream_id,
-                    stream.tx_bytes,
-                    stream.rx_bytes,
-                    stream.consumed_offset,
-                    stream.queued_bytes,
-                    stream.fin_offset
+                    tx_bytes = stream.tx_bytes,
+                    rx_bytes = stream.rx_bytes,
+                    consumed_offset = stream.consumed_offset,
+                    queued = stream.queued_bytes,
+                    fin_offset = ?stream.fin_offset,
+                    "target write failed"
                 );
                 let _ = unsafe { picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR) };
             }
@@ -576,6 +777,8 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                 stream_id,
             };
             let mut reset_stream = false;
+            state.metrics.bytes_to_target =
+                state.metrics.bytes_to_target.saturating_add(bytes as u64);
             if let Some(stream) = state.streams.get_mut(&key) {
                 stream.queued_bytes = stream.queued_bytes.saturating_sub(bytes);
                 stream.consumed_offset = stream.consumed_offset.saturating_add(bytes as u64);
@@ -593,14 +796,20 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                 };
                 if ret < 0 {
                     warn!(
-                        "stream {:?}: stream_data_consumed failed ret={} consumed_offset={}",
-                        stream_id, ret, stream.consumed_offset
+                        stream_id,
+                        ret,
+                        consumed_offset = stream.consumed_offset,
+                        "stream_data_consumed failed"
                     );
                     reset_stream = true;
                 }
             }
             if reset_stream {
-                shutdown_stream(state, key);
+                shutdown_stream(
+                    state,
+                    key,
+                    CloseReason::LocalReset(SLIPSTREAM_INTERNAL_ERROR),
+                );
                 let _ = unsafe {
                     picoquic_reset_stream(
                         cnx_id as *mut picoquic_cnx_t,
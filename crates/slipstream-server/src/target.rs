@@ -1,23 +1,68 @@
+use crate::diagnostics::spawn_diagnostic_service;
 use crate::server::{
-    Command, StreamKey, StreamWrite, DEFAULT_TCP_RCVBUF_BYTES, STREAM_READ_CHUNK_BYTES,
-    TARGET_WRITE_COALESCE_DEFAULT_BYTES,
+    Command, ServerError, StreamKey, StreamWrite, DEFAULT_TCP_RCVBUF_BYTES,
+    STREAM_READ_CHUNK_BYTES, TARGET_WRITE_COALESCE_DEFAULT_BYTES,
 };
+use slipstream_core::diagnostics::DiagnosticRequest;
 use slipstream_core::tcp::{stream_read_limit_chunks, tcp_send_buffer_bytes};
+use slipstream_core::{resolve_host_port, HostPort};
+use std::fmt;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream as TokioTcpStream;
 use tokio::sync::{mpsc, watch};
 use tracing::{debug, warn};
 
+/// `--target-address`: a TCP target or a built-in service such as `echo`.
+#[derive(Debug, Clone)]
+pub enum TargetAddress {
+    Remote(HostPort),
+    Builtin(DiagnosticRequest),
+}
+
+impl TargetAddress {
+    pub(crate) fn resolve(&self) -> Result<Target, ServerError> {
+        match self {
+            TargetAddress::Remote(address) => resolve_host_port(address)
+                .map(Target::Tcp)
+                .map_err(|err| ServerError::new(err.to_string())),
+            TargetAddress::Builtin(request) => Ok(Target::Builtin(*request)),
+        }
+    }
+}
+
+/// Where new streams go once `--target-address` is resolved.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Target {
+    Tcp(SocketAddr),
+    Builtin(DiagnosticRequest),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Tcp(addr) => write!(f, "{}", addr),
+            Target::Builtin(request) => write!(f, "{}", request),
+        }
+    }
+}
+
 pub(crate) fn spawn_target_connector(
     key: StreamKey,
-    target_addr: SocketAddr,
+    target: Target,
     command_tx: mpsc::UnboundedSender<Command>,
     debug_streams: bool,
     mut shutdown_rx: watch::Receiver<bool>,
 ) {
+    let target_addr = match target {
+        Target::Tcp(addr) => addr,
+        Target::Builtin(request) => {
+            spawn_diagnostic_service(key, request, command_tx, debug_streams, shutdown_rx);
+            return;
+        }
+    };
     tokio::spawn(async move {
         if *shutdown_rx.borrow() {
             return;
@@ -40,44 +85,27 @@ pub(crate) fn spawn_target_connector(
                     DEFAULT_TCP_RCVBUF_BYTES,
                     STREAM_READ_CHUNK_BYTES,
                 );
-                let (data_tx, data_rx) = mpsc::channel(read_limit);
                 let send_buffer_bytes = tcp_send_buffer_bytes(&stream)
                     .filter(|bytes| *bytes > 0)
                     .unwrap_or(TARGET_WRITE_COALESCE_DEFAULT_BYTES);
                 let (read_half, write_half) = stream.into_split();
-                let (write_tx, write_rx) = mpsc::unbounded_channel();
-                let send_pending = Arc::new(AtomicBool::new(false));
-                spawn_target_reader(
+                attach_target(
                     key,
                     read_half,
-                    data_tx,
-                    command_tx.clone(),
-                    send_pending.clone(),
-                    debug_streams,
-                    shutdown_rx.clone(),
-                );
-                spawn_target_writer(
-                    key,
                     write_half,
-                    write_rx,
-                    command_tx.clone(),
-                    shutdown_rx,
+                    read_limit,
                     send_buffer_bytes,
+                    command_tx,
+                    debug_streams,
+                    shutdown_rx,
                 );
-                let _ = command_tx.send(Command::StreamConnected {
-                    cnx_id: key.cnx,
-                    stream_id: key.stream_id,
-                    write_tx,
-                    data_rx,
-                    send_pending,
-                });
             }
             Err(err) => {
                 warn!(
-                    "stream {:?}: target connect failed err={} kind={:?}",
-                    key.stream_id,
-                    err,
-                    err.kind()
+                    stream_id = key.stream_id,
+                    error = %err,
+                    kind = ?err.kind(),
+                    "target connect failed"
                 );
                 let _ = command_tx.send(Command::StreamConnectError {
                     cnx_id: key.cnx,
@@ -88,9 +116,53 @@ pub(crate) fn spawn_target_connector(
     });
 }
 
-pub(crate) fn spawn_target_reader(
+/// Wires a connected target into the stream and reports it to the event loop.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn attach_target<R, W>(
+    key: StreamKey,
+    read_half: R,
+    write_half: W,
+    read_limit: usize,
+    send_buffer_bytes: usize,
+    command_tx: mpsc::UnboundedSender<Command>,
+    debug_streams: bool,
+    shutdown_rx: watch::Receiver<bool>,
+) where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (data_tx, data_rx) = mpsc::channel(read_limit);
+    let (write_tx, write_rx) = mpsc::unbounded_channel();
+    let send_pending = Arc::new(AtomicBool::new(false));
+    spawn_target_reader(
+        key,
+        read_half,
+        data_tx,
+        command_tx.clone(),
+        send_pending.clone(),
+        debug_streams,
+        shutdown_rx.clone(),
+    );
+    spawn_target_writer(
+        key,
+        write_half,
+        write_rx,
+        command_tx.clone(),
+        shutdown_rx,
+        send_buffer_bytes,
+    );
+    let _ = command_tx.send(Command::StreamConnected {
+        cnx_id: key.cnx,
+        stream_id: key.stream_id,
+        write_tx,
+        data_rx,
+        send_pending,
+    });
+}
+
+pub(crate) fn spawn_target_reader<R: AsyncRead + Unpin + Send + 'static>(
     key: StreamKey,
-    mut read_half: tokio::net::tcp::OwnedReadHalf,
+    mut read_half: R,
     data_tx: mpsc::Sender<Vec<u8>>,
     command_tx: mpsc::UnboundedSender<Command>,
     send_pending: Arc<AtomicBool>,
@@ -112,8 +184,9 @@ pub(crate) fn spawn_target_reader(
                         Ok(0) => {
                             if debug_streams {
                                 debug!(
-                                    "stream {:?}: target eof read_bytes={}",
-                                    key.stream_id, total
+                                    stream_id = key.stream_id,
+                                    read_bytes = total,
+                                    "target eof"
                                 );
                             }
                             let _ = command_tx.send(Command::StreamClosed {
@@ -141,11 +214,11 @@ pub(crate) fn spawn_target_reader(
                         Err(err) => {
                             if debug_streams {
                                 debug!(
-                                    "stream {:?}: target read error after {} bytes (kind={:?} err={})",
-                                    key.stream_id,
-                                    total,
-                                    err.kind(),
-                                    err
+                                    stream_id = key.stream_id,
+                                    read_bytes = total,
+                                    kind = ?err.kind(),
+                                    error = %err,
+                                    "target read error"
                                 );
                             }
                             let _ = command_tx.send(Command::StreamReadError {
@@ -162,9 +235,9 @@ pub(crate) fn spawn_target_reader(
     });
 }
 
-pub(crate) fn spawn_target_writer(
+pub(crate) fn spawn_target_writer<W: AsyncWrite + Unpin + Send + 'static>(
     key: StreamKey,
-    mut write_half: tokio::net::tcp::OwnedWriteHalf,
+    mut write_half: W,
     mut write_rx: mpsc::UnboundedReceiver<StreamWrite>,
     command_tx: mpsc::UnboundedSender<Command>,
     mut shutdown_rx: watch::Receiver<bool>,
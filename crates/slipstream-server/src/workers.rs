@@ -0,0 +1,534 @@
+use crate::metrics::ServerMetrics;
+use crate::server::{
+    install_sigterm_handler, map_io, serve, QuicQuery, ServerConfig, ServerError, SHOULD_SHUTDOWN,
+};
+use slipstream_core::metrics::MetricsSnapshot;
+use slipstream_core::pcap::CapturedUdpSocket;
+use slipstream_dns::PROBE_CNX_ID_LEN;
+use slipstream_ffi::clock::ClockSource;
+use slipstream_ffi::picoquic::{picoquic_connection_id_t, picoquic_quic_t};
+use std::ffi::c_void;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket as TokioUdpSocket;
+use tokio::runtime::Builder;
+use tokio::sync::mpsc;
+
+/// Workers a one-byte connection ID prefix can tell apart.
+pub(crate) const MAX_WORKERS: usize = 256;
+// Handoffs queued per worker before more are dropped, like a full socket buffer.
+const HANDOFF_QUEUE_LEN: usize = 4096;
+
+pub(crate) enum Handoff {
+    /// A query whose connection, or segmented packet, this worker owns.
+    Query(QuicQuery),
+    /// A downstream limit a client reported through another worker for a
+    /// connection this worker owns.
+    DownstreamLimit {
+        cnx_id: [u8; PROBE_CNX_ID_LEN],
+        response_len: usize,
+        reported_at: u64,
+    },
+}
+
+/// One of several event loops sharing the DNS port.
+///
+/// The kernel spreads queries over the workers' sockets by resolver address,
+/// which says nothing about the QUIC connection inside. Every worker starts
+/// its connection IDs with its index, so whichever worker decodes a query can
+/// tell the owner from the packet's destination connection ID and hand it
+/// over. A new connection's first packets carry an ID the client picked; its
+/// first byte picks the worker the same way.
+pub(crate) struct Worker {
+    pub(crate) index: usize,
+    // Boxed so picoquic can keep a pointer to it.
+    cnx_id_prefix: Box<u8>,
+    peers: Arc<[mpsc::Sender<Handoff>]>,
+    handoff_rx: mpsc::Receiver<Handoff>,
+    metrics: Option<SharedMetrics>,
+}
+
+impl Worker {
+    pub(crate) fn cnx_id_prefix(&self) -> *const u8 {
+        &*self.cnx_id_prefix
+    }
+
+    /// The worker that owns `query`, unless it is this one. Segments go by
+    /// session and packet ID so one worker collects them all; whole packets go
+    /// by connection ID.
+    pub(crate) fn other_owner(&self, query: &QuicQuery) -> Option<usize> {
+        let key = match query.segment() {
+            Some(segment) => segment.session.wrapping_add(segment.packet_id) as usize,
+            None => usize::from(dcid_prefix(query.payload())?),
+        };
+        let owner = key % self.peers.len();
+        (owner != self.index).then_some(owner)
+    }
+
+    pub(crate) fn hand_off(&self, owner: usize, query: QuicQuery) {
+        match self.peers[owner].try_send(Handoff::Query(query)) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::debug!(worker = owner, "Dropping query for a busy worker");
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                tracing::debug!(worker = owner, "Dropping query for a stopped worker");
+            }
+        }
+    }
+
+    /// Passes a downstream limit to the worker owning the connection `cnx_id`
+    /// belongs to; false if that is this worker.
+    pub(crate) fn hand_off_downstream_limit(
+        &self,
+        cnx_id: [u8; PROBE_CNX_ID_LEN],
+        response_len: usize,
+        reported_at: u64,
+    ) -> bool {
+        let owner = usize::from(cnx_id[0]) % self.peers.len();
+        if owner == self.index {
+            return false;
+        }
+        match self.peers[owner].try_send(Handoff::DownstreamLimit {
+            cnx_id,
+            response_len,
+            reported_at,
+        }) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::debug!(
+                    worker = owner,
+                    "Dropping downstream limit for a busy worker"
+                );
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                tracing::debug!(
+                    worker = owner,
+                    "Dropping downstream limit for a stopped worker"
+                );
+            }
+        }
+        true
+    }
+
+    pub(crate) async fn next_handoff(&mut self) -> Option<Handoff> {
+        self.handoff_rx.recv().await
+    }
+
+    pub(crate) fn try_next_handoff(&mut self) -> Option<Handoff> {
+        self.handoff_rx.try_recv().ok()
+    }
+
+    pub(crate) fn metrics_snapshot(&self) -> Option<MetricsSnapshot> {
+        self.metrics.as_ref().map(|shared| shared.snapshot.clone())
+    }
+
+    /// Publishes this worker's counters, summed with the others'.
+    pub(crate) fn publish_metrics(
+        &self,
+        metrics: &ServerMetrics,
+        connections: usize,
+        streams: usize,
+    ) {
+        if let Some(shared) = self.metrics.as_ref() {
+            shared.publish(self.index, metrics, connections, streams);
+        }
+    }
+}
+
+/// Latest counters of every worker, behind one `--metrics-listen` endpoint.
+#[derive(Clone)]
+struct SharedMetrics {
+    snapshot: MetricsSnapshot,
+    workers: Arc<Mutex<Vec<Option<WorkerMetrics>>>>,
+}
+
+struct WorkerMetrics {
+    metrics: ServerMetrics,
+    connections: usize,
+    streams: usize,
+}
+
+impl SharedMetrics {
+    fn new(workers: usize) -> Self {
+        Self {
+            snapshot: MetricsSnapshot::new(),
+            workers: Arc::new(Mutex::new((0..workers).map(|_| None).collect())),
+        }
+    }
+
+    fn publish(&self, index: usize, metrics: &ServerMetrics, connections: usize, streams: usize) {
+        let Ok(mut workers) = self.workers.lock() else {
+            return;
+        };
+        workers[index] = Some(WorkerMetrics {
+            metrics: metrics.clone(),
+            connections,
+            streams,
+        });
+        let mut total = ServerMetrics::new();
+        let (mut connections, mut streams) = (0, 0);
+        for worker in workers.iter().flatten() {
+            total.merge(&worker.metrics);
+            connections += worker.connections;
+            streams += worker.streams;
+        }
+        self.snapshot.publish(total.render(connections, streams));
+    }
+}
+
+/// Binds the DNS port and runs `config.workers` event loops until SIGTERM,
+/// each on a thread of its own with its own runtime, QUIC context and
+/// `SO_REUSEPORT` socket on the port.
+///
+/// Blocks the calling thread, so it must not run inside a Tokio runtime.
+pub fn run_workers(config: &ServerConfig) -> Result<i32, ServerError> {
+    let count = config.workers;
+    if count > MAX_WORKERS {
+        return Err(ServerError::new(format!(
+            "At most {} workers are supported",
+            MAX_WORKERS
+        )));
+    }
+    for (flag, set) in [
+        ("--control-socket", config.control_socket.is_some()),
+        ("--pcap", config.pcap.is_some()),
+        ("--record", config.record.is_some()),
+        // The workers' threads would draw from the seeded generator in no set order.
+        ("--seed", config.seed.is_some()),
+        // Every worker would open the file and interleave its lines.
+        ("--audit-log", config.audit_log.is_some()),
+        ("--keylog-file", config.keylog_file.is_some()),
+    ] {
+        if set {
+            return Err(ServerError::new(format!(
+                "{} is not supported with more than one worker",
+                flag
+            )));
+        }
+    }
+    let sockets = bind_reuseport_sockets(config.dns_listen_port, count).map_err(map_io)?;
+    install_sigterm_handler();
+
+    let (senders, receivers): (Vec<_>, Vec<_>) =
+        (0..count).map(|_| mpsc::channel(HANDOFF_QUEUE_LEN)).unzip();
+    let peers: Arc<[_]> = senders.into();
+    let metrics = config.metrics_listen.map(|_| SharedMetrics::new(count));
+    tracing::info!("Starting {} workers", count);
+
+    std::thread::scope(|scope| {
+        let mut handles = Vec::with_capacity(count);
+        for (index, (socket, handoff_rx)) in sockets.into_iter().zip(receivers).enumerate() {
+            let worker = Worker {
+                index,
+                cnx_id_prefix: Box::new(index as u8),
+                peers: peers.clone(),
+                handoff_rx,
+                metrics: metrics.clone(),
+            };
+            let spawned = std::thread::Builder::new()
+                .name(format!("slipstream-worker-{}", index))
+                .spawn_scoped(scope, move || run_worker(config, socket, worker));
+            match spawned {
+                Ok(handle) => handles.push(handle),
+                Err(err) => {
+                    SHOULD_SHUTDOWN.store(true, Ordering::Relaxed);
+                    return Err(map_io(err));
+                }
+            }
+        }
+        let mut result = Ok(0);
+        for handle in handles {
+            let outcome = handle
+                .join()
+                .unwrap_or_else(|_| Err(ServerError::new("A worker panicked")));
+            if result.is_ok() {
+                result = outcome;
+            }
+        }
+        result
+    })
+}
+
+fn run_worker(
+    config: &ServerConfig,
+    socket: std::net::UdpSocket,
+    worker: Worker,
+) -> Result<i32, ServerError> {
+    let runtime = Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .map_err(map_io)?;
+    let result = runtime.block_on(async {
+        let socket = TokioUdpSocket::from_std(socket).map_err(map_io)?;
+        let transport = CapturedUdpSocket::new(socket, None).map_err(map_io)?;
+        serve(
+            config,
+            transport,
+            ClockSource::System,
+            &SHOULD_SHUTDOWN,
+            Some(worker),
+        )
+        .await
+    });
+    if result.is_err() {
+        // The others could not reach this worker's connections anyway.
+        SHOULD_SHUTDOWN.store(true, Ordering::Relaxed);
+    }
+    result
+}
+
+#[cfg(unix)]
+fn bind_reuseport_sockets(port: u16, count: usize) -> io::Result<Vec<std::net::UdpSocket>> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    use std::net::{Ipv6Addr, SocketAddrV6};
+
+    let mut port = port;
+    let mut sockets = Vec::with_capacity(count);
+    for _ in 0..count {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(false)?;
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        let addr = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0));
+        socket.bind(&addr.into())?;
+        // With port 0, the first socket picks the port the others join.
+        if let Some(local) = socket.local_addr()?.as_socket() {
+            port = local.port();
+        }
+        sockets.push(socket.into());
+    }
+    Ok(sockets)
+}
+
+#[cfg(not(unix))]
+fn bind_reuseport_sockets(_port: u16, _count: usize) -> io::Result<Vec<std::net::UdpSocket>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Workers need SO_REUSEPORT, which this platform lacks",
+    ))
+}
+
+/// First byte of the destination connection ID of the first QUIC packet in
+/// `packet`, if it has one.
+fn dcid_prefix(packet: &[u8]) -> Option<u8> {
+    let first = *packet.first()?;
+    if first & 0x80 == 0 {
+        // Short header: the connection ID follows the first byte.
+        return packet.get(1).copied();
+    }
+    // Long header: a 4-byte version, then the ID length and the ID.
+    match packet.get(5) {
+        Some(0) | None => None,
+        Some(_) => packet.get(6).copied(),
+    }
+}
+
+/// picoquic connection ID callback; stamps the worker index pointed to by
+/// `cnx_id_cb_data` over the first byte of every new local connection ID.
+pub(crate) unsafe extern "C" fn prefix_cnx_id(
+    _quic: *mut picoquic_quic_t,
+    cnx_id_local: picoquic_connection_id_t,
+    _cnx_id_remote: picoquic_connection_id_t,
+    cnx_id_cb_data: *mut c_void,
+    cnx_id_returned: *mut picoquic_connection_id_t,
+) {
+    let mut cnx_id = cnx_id_local;
+    if cnx_id.id_len > 0 {
+        cnx_id.id[0] = *(cnx_id_cb_data as *const u8);
+    }
+    *cnx_id_returned = cnx_id;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path_mtu::DownstreamLimits;
+    use crate::probes::ProbeBudget;
+    use crate::server::{decode_slot, segment_reassembler};
+    use slipstream_dns::{
+        build_qname, build_segment_qname, encode_query, LabelLayout, QueryParams, SegmentHeader,
+        SegmentReassembler, CLASS_IN, RR_TXT,
+    };
+
+    const DOMAIN: &str = "test.example";
+
+    struct Harness {
+        workers: Vec<Worker>,
+        reassembler: SegmentReassembler,
+        downstream_limits: DownstreamLimits,
+        probe_budget: ProbeBudget,
+    }
+
+    impl Harness {
+        fn new(count: usize) -> Self {
+            let (senders, receivers): (Vec<_>, Vec<_>) =
+                (0..count).map(|_| mpsc::channel(HANDOFF_QUEUE_LEN)).unzip();
+            let peers: Arc<[_]> = senders.into();
+            let workers = receivers
+                .into_iter()
+                .enumerate()
+                .map(|(index, handoff_rx)| Worker {
+                    index,
+                    cnx_id_prefix: Box::new(index as u8),
+                    peers: peers.clone(),
+                    handoff_rx,
+                    metrics: None,
+                })
+                .collect();
+            Self {
+                workers,
+                reassembler: segment_reassembler(),
+                downstream_limits: DownstreamLimits::new(),
+                probe_budget: ProbeBudget::new(100),
+            }
+        }
+
+        /// Decodes `packet` on worker `index`; true if it produced a slot there.
+        /// Only queries that never reach QUIC may be fed here.
+        fn decode(&mut self, index: usize, packet: &[u8]) -> bool {
+            let local: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+            decode_slot(
+                packet,
+                "192.0.2.1:53".parse().unwrap(),
+                &[DOMAIN],
+                &mut self.reassembler,
+                &mut self.downstream_limits,
+                &mut self.probe_budget,
+                std::ptr::null_mut(),
+                0,
+                &local,
+                Some(&self.workers[index]),
+            )
+            .expect("decode")
+            .is_some()
+        }
+
+        /// Payload of the query handed to worker `index`, if any.
+        fn handed_to(&mut self, index: usize) -> Option<Vec<u8>> {
+            match self.workers[index].try_next_handoff()? {
+                Handoff::Query(query) => Some(query.payload().to_vec()),
+                Handoff::DownstreamLimit { .. } => panic!("unexpected downstream limit"),
+            }
+        }
+    }
+
+    fn query(qname: &str) -> Vec<u8> {
+        encode_query(&QueryParams {
+            id: 7,
+            qname,
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+        })
+        .expect("encode query")
+    }
+
+    fn packet_query(payload: &[u8]) -> Vec<u8> {
+        query(&build_qname(payload, DOMAIN).expect("qname"))
+    }
+
+    fn segment_query(data: &[u8], session: u32, packet_id: u32, index: u8, count: u8) -> Vec<u8> {
+        let header = SegmentHeader {
+            session,
+            packet_id,
+            index,
+            count,
+        };
+        let qname = build_segment_qname(data, &header, DOMAIN, &LabelLayout::default(), 0)
+            .expect("segment qname");
+        query(&qname)
+    }
+
+    fn short_header_packet(cnx_id_prefix: u8) -> Vec<u8> {
+        let mut packet = vec![0x40, cnx_id_prefix];
+        packet.extend((0..30).map(|byte| byte as u8));
+        packet
+    }
+
+    #[test]
+    fn dcid_prefix_reads_short_and_long_headers() {
+        assert_eq!(dcid_prefix(&[0x40, 3, 9, 9]), Some(3));
+        assert_eq!(dcid_prefix(&[0xc0, 0, 0, 0, 1, 8, 5, 1, 2]), Some(5));
+        // A long header with an empty connection ID names no worker.
+        assert_eq!(dcid_prefix(&[0xc0, 0, 0, 0, 1, 0, 5]), None);
+        assert_eq!(dcid_prefix(&[0x40]), None);
+        assert_eq!(dcid_prefix(&[]), None);
+    }
+
+    #[test]
+    fn packets_go_to_the_worker_named_by_their_connection_id() {
+        let mut harness = Harness::new(4);
+        let packet = short_header_packet(6);
+
+        assert!(!harness.decode(0, &packet_query(&packet)));
+        assert_eq!(harness.handed_to(2), Some(packet.clone()));
+        for index in [0, 1, 3] {
+            assert!(harness.handed_to(index).is_none());
+        }
+
+        let mut initial = vec![0xc0, 0, 0, 0, 1, 8, 3];
+        initial.extend([1u8; 7]);
+        initial.extend([0u8; 20]);
+        assert!(!harness.decode(1, &packet_query(&initial)));
+        assert_eq!(harness.handed_to(3), Some(initial));
+    }
+
+    #[test]
+    fn segments_go_to_the_worker_named_by_session_and_packet_id() {
+        let mut harness = Harness::new(4);
+        // 5 + 2 picks worker 3, wherever each segment lands.
+        let first = segment_query(b"first half", 5, 2, 0, 2);
+        let second = segment_query(b"other half", 5, 2, 1, 2);
+
+        assert!(!harness.decode(0, &first));
+        assert!(!harness.decode(1, &second));
+        assert_eq!(harness.handed_to(3), Some(b"first half".to_vec()));
+        assert_eq!(harness.handed_to(3), Some(b"other half".to_vec()));
+
+        // The owner keeps the segment and acknowledges it as a partial packet.
+        let mut harness = Harness::new(4);
+        assert!(harness.decode(3, &first));
+        for index in 0..4 {
+            assert!(harness.handed_to(index).is_none());
+        }
+        assert_eq!(harness.reassembler.pending_packets(), 1);
+    }
+
+    #[test]
+    fn reassembled_packets_go_to_the_worker_owning_their_connection() {
+        let mut harness = Harness::new(4);
+        let packet = short_header_packet(1);
+        let (head, tail) = packet.split_at(packet.len() / 2);
+
+        // Session 1 and packet 2 put the segments on worker 3, but the whole
+        // packet belongs to worker 1.
+        assert!(harness.decode(3, &segment_query(head, 1, 2, 0, 2)));
+        assert!(!harness.decode(3, &segment_query(tail, 1, 2, 1, 2)));
+        assert_eq!(harness.handed_to(1), Some(packet));
+        assert_eq!(harness.reassembler.pending_packets(), 0);
+        for index in [0, 2, 3] {
+            assert!(harness.handed_to(index).is_none());
+        }
+    }
+
+    #[test]
+    fn downstream_limits_go_to_the_worker_owning_the_connection() {
+        let harness = Harness::new(2);
+        let cnx_id = [1u8; PROBE_CNX_ID_LEN];
+        assert!(!harness.workers[1].hand_off_downstream_limit(cnx_id, 900, 0));
+        assert!(harness.workers[0].hand_off_downstream_limit(cnx_id, 900, 0));
+
+        // A stopped owner drops the report instead of keeping it here.
+        let Harness { mut workers, .. } = harness;
+        let owner = workers.remove(1);
+        drop(owner);
+        assert!(workers[0].hand_off_downstream_limit(cnx_id, 900, 0));
+    }
+}
@@ -0,0 +1,206 @@
+use serde_json::{json, Value};
+use slipstream_ffi::picoquic::slipstream_server_cc_algorithm;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DOMAIN: &str = "test.example.com";
+const SEED: u64 = 7;
+const ECHO_TIMEOUT: Duration = Duration::from_secs(20);
+
+struct ChildGuard {
+    child: Child,
+}
+
+impl ChildGuard {
+    fn has_exited(&mut self) -> bool {
+        !matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join("..")
+}
+
+fn server_bin() -> PathBuf {
+    PathBuf::from(env!("CARGO_BIN_EXE_slipstream-server"))
+}
+
+fn ensure_client_bin(root: &Path) -> PathBuf {
+    let status = Command::new("cargo")
+        .arg("build")
+        .arg("-p")
+        .arg("slipstream-client")
+        .current_dir(root)
+        .status()
+        .expect("failed to invoke cargo build for slipstream-client");
+    assert!(status.success(), "cargo build -p slipstream-client failed");
+    let mut path = root.join("target").join("debug").join("slipstream-client");
+    if cfg!(windows) {
+        path.set_extension("exe");
+    }
+    path
+}
+
+fn pick_udp_port() -> u16 {
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("bind udp");
+    socket.local_addr().expect("udp addr").port()
+}
+
+fn pick_tcp_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind tcp");
+    listener.local_addr().expect("tcp addr").port()
+}
+
+/// Sends `probe` through the client on `port` and reads it back.
+fn echo_through(port: u16, probe: &[u8]) {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let deadline = Instant::now() + ECHO_TIMEOUT;
+    let mut stream = loop {
+        match TcpStream::connect_timeout(&addr, Duration::from_millis(200)) {
+            Ok(stream) => break stream,
+            Err(err) if Instant::now() >= deadline => panic!("connect to client: {}", err),
+            Err(_) => thread::sleep(Duration::from_millis(50)),
+        }
+    };
+    stream
+        .set_read_timeout(Some(deadline.saturating_duration_since(Instant::now())))
+        .expect("read timeout");
+    stream.write_all(probe).expect("write probe");
+    let mut reply = vec![0u8; probe.len()];
+    stream.read_exact(&mut reply).expect("read echo");
+    assert_eq!(reply, probe);
+}
+
+/// Records a client's handshake and one echoed stream with `--seed`.
+fn record_session(root: &Path, session: &Path) {
+    let client_bin = ensure_client_bin(root);
+    let dns_port = pick_udp_port();
+    let mut server = ChildGuard {
+        child: Command::new(server_bin())
+            .arg("--dns-listen-port")
+            .arg(dns_port.to_string())
+            .arg("--target-address")
+            .arg("echo")
+            .arg("--domain")
+            .arg(DOMAIN)
+            .arg("--cert")
+            .arg(root.join("fixtures/certs/cert.pem"))
+            .arg("--key")
+            .arg(root.join("fixtures/certs/key.pem"))
+            .arg("--record")
+            .arg(session)
+            .arg("--seed")
+            .arg(SEED.to_string())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("start slipstream-server"),
+    };
+    thread::sleep(Duration::from_millis(300));
+    assert!(!server.has_exited(), "server failed to start");
+
+    let tcp_port = pick_tcp_port();
+    let _client = ChildGuard {
+        child: Command::new(client_bin)
+            .arg("--tcp-listen-port")
+            .arg(tcp_port.to_string())
+            .arg("--resolver")
+            .arg(format!("127.0.0.1:{}", dns_port))
+            .arg("--domain")
+            .arg(DOMAIN)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("start slipstream-client"),
+    };
+    echo_through(tcp_port, b"replayed probe\n");
+    assert!(!server.has_exited(), "server exited while recording");
+}
+
+fn replay(root: &Path, session: &Path, seed: u64) -> Value {
+    let output = Command::new(server_bin())
+        .arg("replay")
+        .arg("--session")
+        .arg(session)
+        .arg("--target-address")
+        .arg("echo")
+        .arg("--domain")
+        .arg(DOMAIN)
+        .arg("--cert")
+        .arg(root.join("fixtures/certs/cert.pem"))
+        .arg("--key")
+        .arg(root.join("fixtures/certs/key.pem"))
+        .arg("--seed")
+        .arg(seed.to_string())
+        .output()
+        .expect("run slipstream-server replay");
+    assert!(
+        output.status.success(),
+        "replay failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    serde_json::from_slice(&output.stdout).expect("report JSON")
+}
+
+#[test]
+fn replay_with_the_recording_seed_completes_the_handshake() {
+    if unsafe { slipstream_server_cc_algorithm.is_null() } {
+        eprintln!("skipping replay test: picoquic is not available");
+        return;
+    }
+    let root = workspace_root();
+    let session = std::env::temp_dir().join(format!(
+        "slipstream-replay-test-{}.jsonl",
+        std::process::id()
+    ));
+    record_session(&root, &session);
+
+    let report = replay(&root, &session, SEED);
+    let again = replay(&root, &session, SEED);
+    let other_seed = replay(&root, &session, SEED + 1);
+    let _ = std::fs::remove_file(&session);
+
+    // The same file and seed give the same report.
+    assert_eq!(report, again);
+
+    let responses = report["responses"].as_array().expect("responses");
+    assert_eq!(report["queries"], json!(responses.len()));
+    assert!(
+        responses.len() > 2,
+        "recorded only {} queries",
+        responses.len()
+    );
+    for response in responses {
+        // Every recorded query is a tunnel query the server answers NOERROR.
+        assert_eq!(response["answered"], true, "{}", response);
+        assert_eq!(response["rcode"], 0, "{}", response);
+    }
+    let payload_lens: Vec<u64> = responses
+        .iter()
+        .map(|response| {
+            let payload_len = response["payload_len"].as_u64().expect("payload_len");
+            let response_len = response["response_len"].as_u64().expect("response_len");
+            assert!(payload_len < response_len, "{}", response);
+            payload_len
+        })
+        .collect();
+    // Past the handshake, the client's packets still decrypt and get answers.
+    let carrying = payload_lens.iter().filter(|len| **len > 0).count();
+    assert!(carrying > 2, "{:?}", payload_lens);
+    // The client's Finished verified, so the connection is established.
+    assert_eq!(report["connections"], json!(["ready"]));
+
+    // Keys from another seed cannot read the client's handshake packets.
+    assert_ne!(other_seed["connections"], json!(["ready"]));
+}
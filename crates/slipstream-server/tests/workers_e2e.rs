@@ -0,0 +1,151 @@
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WORKERS: usize = 4;
+const CLIENTS: usize = 6;
+const ECHO_TIMEOUT: Duration = Duration::from_secs(20);
+
+struct ChildGuard {
+    child: Child,
+}
+
+impl ChildGuard {
+    fn has_exited(&mut self) -> bool {
+        !matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join("..")
+}
+
+fn ensure_client_bin(root: &Path) -> PathBuf {
+    let status = Command::new("cargo")
+        .arg("build")
+        .arg("-p")
+        .arg("slipstream-client")
+        .current_dir(root)
+        .status()
+        .expect("failed to invoke cargo build for slipstream-client");
+    assert!(status.success(), "cargo build -p slipstream-client failed");
+    let mut path = root.join("target").join("debug").join("slipstream-client");
+    if cfg!(windows) {
+        path.set_extension("exe");
+    }
+    path
+}
+
+fn pick_udp_port() -> u16 {
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("bind udp");
+    socket.local_addr().expect("udp addr").port()
+}
+
+fn pick_tcp_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind tcp");
+    listener.local_addr().expect("tcp addr").port()
+}
+
+/// Sends `probe` through the client on `port` and reads it back.
+fn echo_through(port: u16, probe: &[u8]) -> Result<(), String> {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let deadline = Instant::now() + ECHO_TIMEOUT;
+    let mut stream = loop {
+        match TcpStream::connect_timeout(&addr, Duration::from_millis(200)) {
+            Ok(stream) => break stream,
+            Err(err) if Instant::now() >= deadline => return Err(err.to_string()),
+            Err(_) => thread::sleep(Duration::from_millis(50)),
+        }
+    };
+    let _ = stream.set_nodelay(true);
+    stream
+        .set_read_timeout(Some(deadline.saturating_duration_since(Instant::now())))
+        .map_err(|err| err.to_string())?;
+    stream.write_all(probe).map_err(|err| err.to_string())?;
+    let mut reply = vec![0u8; probe.len()];
+    stream
+        .read_exact(&mut reply)
+        .map_err(|err| err.to_string())?;
+    if reply != probe {
+        return Err(format!("echoed {:?}", String::from_utf8_lossy(&reply)));
+    }
+    Ok(())
+}
+
+#[test]
+fn workers_serve_clients_on_every_socket() {
+    let root = workspace_root();
+    let client_bin = ensure_client_bin(&root);
+    let server_bin = PathBuf::from(env!("CARGO_BIN_EXE_slipstream-server"));
+    let dns_port = pick_udp_port();
+    let domain = "test.example.com";
+
+    let mut server = ChildGuard {
+        child: Command::new(server_bin)
+            .arg("--dns-listen-port")
+            .arg(dns_port.to_string())
+            .arg("--target-address")
+            .arg("echo")
+            .arg("--domain")
+            .arg(domain)
+            .arg("--cert")
+            .arg(root.join("fixtures/certs/cert.pem"))
+            .arg("--key")
+            .arg(root.join("fixtures/certs/key.pem"))
+            .arg("--workers")
+            .arg(WORKERS.to_string())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("start slipstream-server"),
+    };
+    thread::sleep(Duration::from_millis(300));
+    if server.has_exited() {
+        eprintln!("skipping workers e2e test: server failed to start");
+        return;
+    }
+
+    // Each client queries from its own source port, so the kernel spreads
+    // them over the workers' sockets independently of who owns each connection.
+    let clients: Vec<(u16, ChildGuard)> = (0..CLIENTS)
+        .map(|_| {
+            let tcp_port = pick_tcp_port();
+            let child = Command::new(&client_bin)
+                .arg("--tcp-listen-port")
+                .arg(tcp_port.to_string())
+                .arg("--resolver")
+                .arg(format!("127.0.0.1:{}", dns_port))
+                .arg("--domain")
+                .arg(domain)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("start slipstream-client");
+            (tcp_port, ChildGuard { child })
+        })
+        .collect();
+
+    let probes: Vec<thread::JoinHandle<Result<(), String>>> = clients
+        .iter()
+        .enumerate()
+        .map(|(index, (port, _))| {
+            let port = *port;
+            thread::spawn(move || echo_through(port, format!("probe {}\n", index).as_bytes()))
+        })
+        .collect();
+    for (index, probe) in probes.into_iter().enumerate() {
+        let result = probe.join().expect("probe thread");
+        assert!(result.is_ok(), "client {}: {:?}", index, result);
+    }
+    assert!(!server.has_exited(), "server exited while serving");
+}
@@ -35,15 +35,20 @@ PROXY_DIST="${PROXY_DIST:-normal}"
 PROXY_PORT="${PROXY_PORT:-}"
 PROXY_REORDER_PROB="${PROXY_REORDER_PROB:-}"
 PROXY_BURST_CORRELATION="${PROXY_BURST_CORRELATION:-}"
+PROXY_SCENARIO="${PROXY_SCENARIO:-}"
 DEBUG_WAIT_SECS="${DEBUG_WAIT_SECS:-2}"
 DEBUG_LOG_WAIT_SECS="${DEBUG_LOG_WAIT_SECS:-5}"
 CLIENT_ARGS="${CLIENT_ARGS:-}"
 RESOLVER_MODE="${RESOLVER_MODE:-resolver}"
+SLIPSTREAM_GSO="${SLIPSTREAM_GSO:-0}"
 
 client_extra_args=()
 if [[ -n "${CLIENT_ARGS}" ]]; then
   read -r -a client_extra_args <<< "${CLIENT_ARGS}"
 fi
+if [[ "${SLIPSTREAM_GSO}" != "0" ]]; then
+  client_extra_args+=(--gso)
+fi
 
 case "${RESOLVER_MODE}" in
   resolver|authoritative|mixed) ;;
@@ -575,7 +580,11 @@ run_client_bench() {
   extract_e2e_mib_s "${start_path}" "${end_path}" "${TRANSFER_BYTES}"
 }
 
-cargo build -p slipstream-server -p slipstream-client --release
+build_packages=(-p slipstream-server -p slipstream-client)
+if [[ -n "${PROXY_SCENARIO}" ]]; then
+  build_packages+=(-p slipstream-netem)
+fi
+cargo build "${build_packages[@]}" --release
 
 run_case() {
   local case_name="$1"
@@ -594,7 +603,20 @@ run_case() {
     target_preface_args=(--preface-bytes "${PREFACE_BYTES}")
   fi
 
-  if [[ -n "${PROXY_DELAY_MS}" ]]; then
+  if [[ -n "${PROXY_SCENARIO}" ]]; then
+    local proxy_port="${PROXY_PORT:-$((DNS_LISTEN_PORT + 1))}"
+    if [[ "${proxy_port}" -eq "${DNS_LISTEN_PORT}" ]]; then
+      echo "Proxy port ${proxy_port} conflicts with DNS_LISTEN_PORT." >&2
+      return 1
+    fi
+    "${ROOT_DIR}/target/release/slipstream-netem" \
+      --listen "127.0.0.1:${proxy_port}" \
+      --upstream "127.0.0.1:${DNS_LISTEN_PORT}" \
+      --scenario "${PROXY_SCENARIO}" \
+      >"${case_dir}/dns_proxy.log" 2>&1 &
+    PROXY_PID=$!
+    resolver_port="${proxy_port}"
+  elif [[ -n "${PROXY_DELAY_MS}" ]]; then
     local proxy_port="${PROXY_PORT:-$((DNS_LISTEN_PORT + 1))}"
     if [[ "${proxy_port}" -eq "${DNS_LISTEN_PORT}" ]]; then
       echo "Proxy port ${proxy_port} conflicts with DNS_LISTEN_PORT." >&2